@@ -36,6 +36,7 @@
 //!
 use std::collections::HashMap;
 use std::fs::File;
+use std::net::IpAddr;
 use std::path::Path;
 use std::process::exit;
 use std::sync::atomic::Ordering;
@@ -51,7 +52,7 @@ use tracing::{error, info, warn};
 use url::Url;
 
 use compute_api::responses::ComputeStatus;
-use compute_api::spec::ComputeSpec;
+use compute_api::spec::{ComputeSpec, KNOWN_COMPUTE_FEATURES, SPEC_FORMAT_VERSIONS};
 
 use compute_tools::compute::{
     forward_termination_signal, ComputeNode, ComputeState, ParsedSpec, PG_PID,
@@ -72,6 +73,15 @@ const BUILD_TAG_DEFAULT: &str = "latest";
 fn main() -> Result<()> {
     let (build_tag, clap_args) = init()?;
 
+    if clap_args.get_flag("supported-spec-versions") {
+        println!("{}", serde_json::to_string(&SPEC_FORMAT_VERSIONS)?);
+        return Ok(());
+    }
+    if clap_args.get_flag("supported-features") {
+        println!("{}", serde_json::to_string(KNOWN_COMPUTE_FEATURES)?);
+        return Ok(());
+    }
+
     let (pg_handle, start_pg_result) = {
         // Enter startup tracing context
         let _startup_context_guard = startup_context_from_env();
@@ -138,6 +148,9 @@ fn process_cli(matches: &clap::ArgMatches) -> Result<ProcessCliResult> {
     let http_port = *matches
         .get_one::<u16>("http-port")
         .expect("http-port is required");
+    let http_addr = *matches
+        .get_one::<IpAddr>("http-addr")
+        .expect("http-addr has a default");
     let pgdata = matches
         .get_one::<String>("pgdata")
         .expect("PGDATA path is required");
@@ -154,6 +167,7 @@ fn process_cli(matches: &clap::ArgMatches) -> Result<ProcessCliResult> {
         pgbin,
         ext_remote_storage,
         http_port,
+        http_addr,
         spec_json,
         spec_path,
         resize_swap_on_bind,
@@ -166,6 +180,7 @@ struct ProcessCliResult<'clap> {
     pgbin: &'clap str,
     ext_remote_storage: Option<&'clap str>,
     http_port: u16,
+    http_addr: IpAddr,
     spec_json: Option<&'clap String>,
     spec_path: Option<&'clap String>,
     resize_swap_on_bind: bool,
@@ -290,6 +305,7 @@ fn wait_spec(
         ext_remote_storage,
         resize_swap_on_bind,
         http_port,
+        http_addr,
         ..
     }: ProcessCliResult,
     CliSpecParams {
@@ -332,8 +348,8 @@ fn wait_spec(
 
     // Launch http service first, so that we can serve control-plane requests
     // while configuration is still in progress.
-    let _http_handle =
-        launch_http_server(http_port, &compute).expect("cannot launch http endpoint thread");
+    let _http_handle = launch_http_server(http_addr, http_port, &compute)
+        .expect("cannot launch http endpoint thread");
 
     if !spec_set {
         // No spec provided, hang waiting for it.
@@ -657,6 +673,20 @@ fn cli() -> clap::Command {
     let version = option_env!("CARGO_PKG_VERSION").unwrap_or("unknown");
     clap::Command::new("compute_ctl")
         .version(version)
+        .arg(
+            Arg::new("supported-spec-versions")
+                .long("supported-spec-versions")
+                .help("Print the range of spec format versions this compute_ctl supports as JSON, and exit")
+                .action(clap::ArgAction::SetTrue)
+                .exclusive(true),
+        )
+        .arg(
+            Arg::new("supported-features")
+                .long("supported-features")
+                .help("Print the compute features this compute_ctl knows as JSON, and exit")
+                .action(clap::ArgAction::SetTrue)
+                .exclusive(true),
+        )
         .arg(
             Arg::new("http-port")
                 .long("http-port")
@@ -665,6 +695,17 @@ fn cli() -> clap::Command {
                 .value_parser(clap::value_parser!(u16))
                 .required(false),
         )
+        .arg(
+            // The default usually binds to both IPv4 and IPv6 on linux,
+            // see e.g. https://github.com/rust-lang/rust/pull/34440
+            Arg::new("http-addr")
+                .long("http-addr")
+                .value_name("HTTP_ADDR")
+                .help("Address the HTTP API listens on")
+                .default_value("::")
+                .value_parser(clap::value_parser!(IpAddr))
+                .required(false),
+        )
         .arg(
             Arg::new("connstr")
                 .short('C')
@@ -391,6 +391,7 @@ impl ComputeNode {
         state.metrics.pageserver_connect_micros = pageserver_connect_micros;
         state.metrics.basebackup_bytes = measured_reader.get_byte_count() as u64;
         state.metrics.basebackup_ms = start_time.elapsed().as_millis() as u64;
+        state.metrics.basebackup_lsn = (lsn != Lsn(0)).then_some(lsn);
         Ok(())
     }
 
@@ -1,6 +1,5 @@
 use std::convert::Infallible;
 use std::net::IpAddr;
-use std::net::Ipv6Addr;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread;
@@ -399,11 +398,7 @@ async fn handle_terminate_request(compute: &Arc<ComputeNode>) -> Result<(), (Str
 
 // Main Hyper HTTP server function that runs it and blocks waiting on it forever.
 #[tokio::main]
-async fn serve(port: u16, state: Arc<ComputeNode>) {
-    // this usually binds to both IPv4 and IPv6 on linux
-    // see e.g. https://github.com/rust-lang/rust/pull/34440
-    let addr = SocketAddr::new(IpAddr::from(Ipv6Addr::UNSPECIFIED), port);
-
+async fn serve(addr: SocketAddr, state: Arc<ComputeNode>) {
     let make_service = make_service_fn(move |_conn| {
         let state = state.clone();
         async move {
@@ -436,11 +431,17 @@ async fn serve(port: u16, state: Arc<ComputeNode>) {
     }
 }
 
-/// Launch a separate Hyper HTTP API server thread and return its `JoinHandle`.
-pub fn launch_http_server(port: u16, state: &Arc<ComputeNode>) -> Result<thread::JoinHandle<()>> {
+/// Launch a separate Hyper HTTP API server thread listening on `addr`:`port`, and
+/// return its `JoinHandle`.
+pub fn launch_http_server(
+    addr: IpAddr,
+    port: u16,
+    state: &Arc<ComputeNode>,
+) -> Result<thread::JoinHandle<()>> {
     let state = Arc::clone(state);
+    let addr = SocketAddr::new(addr, port);
 
     Ok(thread::Builder::new()
         .name("http-endpoint".into())
-        .spawn(move || serve(port, state))?)
+        .spawn(move || serve(addr, state))?)
 }
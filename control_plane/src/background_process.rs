@@ -17,7 +17,7 @@ use std::io::Write;
 use std::os::unix::prelude::AsRawFd;
 use std::os::unix::process::CommandExt;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::time::Duration;
 use std::{fs, io, thread};
 
@@ -51,6 +51,16 @@ pub enum InitialPidFile {
     Expect(Utf8PathBuf),
 }
 
+/// Settings of [`start_process_with_options`] that most processes leave at their defaults.
+#[derive(Default)]
+pub struct ProcessOptions {
+    /// Name of the log file in the datadir, `{process_name}.log` if not set.
+    pub log_file_name: Option<String>,
+    /// Pass on the whole environment of neon_local, rather than only the variables
+    /// that the Neon storage binaries need.
+    pub inherit_env: bool,
+}
+
 /// Start a background child process using the parameters given.
 #[allow(clippy::too_many_arguments)]
 pub async fn start_process<F, Fut, AI, A, EI>(
@@ -70,12 +80,53 @@ where
     A: AsRef<OsStr>,
     // Not generic AsRef<OsStr>, otherwise empty `envs` prevents type inference
     EI: IntoIterator<Item = (String, String)>,
+{
+    start_process_with_options(
+        process_name,
+        datadir,
+        command,
+        args,
+        envs,
+        initial_pid_file,
+        retry_timeout,
+        process_status_check,
+        ProcessOptions::default(),
+    )
+    .await
+}
+
+/// Like [`start_process`], with [`ProcessOptions`] for processes that aren't Neon storage
+/// binaries, like `compute_ctl`.
+///
+/// `process_status_check` is polled until it returns `Ok(true)`. An error fails the
+/// start right away. In both the error and the timeout case, the child is killed.
+#[allow(clippy::too_many_arguments)]
+pub async fn start_process_with_options<F, Fut, AI, A, EI>(
+    process_name: &str,
+    datadir: &Path,
+    command: &Path,
+    args: AI,
+    envs: EI,
+    initial_pid_file: InitialPidFile,
+    retry_timeout: &Duration,
+    process_status_check: F,
+    options: ProcessOptions,
+) -> anyhow::Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<bool>>,
+    AI: IntoIterator<Item = A>,
+    A: AsRef<OsStr>,
+    EI: IntoIterator<Item = (String, String)>,
 {
     let retries: u128 = retry_timeout.as_millis() / RETRY_INTERVAL.as_millis();
     if !datadir.metadata().context("stat datadir")?.is_dir() {
         anyhow::bail!("`datadir` must be a directory when calling this function: {datadir:?}");
     }
-    let log_path = datadir.join(format!("{process_name}.log"));
+    let log_file_name = options
+        .log_file_name
+        .unwrap_or_else(|| format!("{process_name}.log"));
+    let log_path = datadir.join(log_file_name);
     let process_log_file = fs::OpenOptions::new()
         .create(true)
         .append(true)
@@ -89,6 +140,7 @@ where
 
     let mut command = Command::new(command);
     let background_command = command
+        .stdin(Stdio::null())
         .stdout(process_log_file)
         .stderr(same_file_for_stderr)
         .args(args)
@@ -99,9 +151,13 @@ where
         // ```
         .current_dir(datadir);
 
-    let filled_cmd = fill_env_vars_prefixed_neon(fill_remote_storage_secrets_vars(
-        fill_rust_env_vars(background_command),
-    ));
+    let filled_cmd = if options.inherit_env {
+        background_command
+    } else {
+        fill_env_vars_prefixed_neon(fill_remote_storage_secrets_vars(fill_rust_env_vars(
+            background_command,
+        )))
+    };
     filled_cmd.envs(envs);
 
     let pid_file_to_check = match &initial_pid_file {
@@ -172,34 +228,38 @@ pub fn stop_process(
     process_name: &str,
     pid_file: &Utf8Path,
 ) -> anyhow::Result<()> {
-    let pid = match pid_file::read(pid_file)
-        .with_context(|| format!("read pid_file {pid_file:?}"))?
-    {
-        PidFileRead::NotExist => {
-            println!("{process_name} is already stopped: no pid file present at {pid_file:?}");
-            return Ok(());
-        }
-        PidFileRead::NotHeldByAnyProcess(_) => {
-            // Don't try to kill according to file contents beacuse the pid might have been re-used by another process.
-            // Don't delete the file either, it can race with new pid file creation.
-            // Read `pid_file` module comment for details.
-            println!(
-                "No process is holding the pidfile. The process must have already exited. Leave in place to avoid race conditions: {pid_file:?}"
-            );
-            return Ok(());
-        }
-        PidFileRead::LockedByOtherProcess(pid) => pid,
+    let sig = if immediate {
+        Signal::SIGQUIT
+    } else {
+        Signal::SIGTERM
+    };
+    signal_and_wait(process_name, pid_file, Some(sig))
+}
+
+/// Wait for the process of the pid file given to exit, after sending it `signal` if any.
+/// Without a signal, the process is expected to exit on its own, e.g. `compute_ctl` after
+/// its Postgres has been stopped. Returns Ok also if the process is already not running.
+pub fn signal_and_wait(
+    process_name: &str,
+    pid_file: &Utf8Path,
+    signal: Option<Signal>,
+) -> anyhow::Result<()> {
+    let Some(pid) = running_pid(process_name, pid_file)? else {
+        return Ok(());
     };
     // XXX the pid could become invalid (and recycled) at any time before the kill() below.
 
     // send signal
-    let sig = if immediate {
+    let Some(sig) = signal else {
+        print!("Waiting for {process_name} with pid {pid} to exit..");
+        io::stdout().flush().unwrap();
+        return wait_until_stopped(process_name, pid);
+    };
+    if sig == Signal::SIGQUIT {
         print!("Stopping {process_name} with pid {pid} immediately..");
-        Signal::SIGQUIT
     } else {
         print!("Stopping {process_name} with pid {pid} gracefully..");
-        Signal::SIGTERM
-    };
+    }
     io::stdout().flush().unwrap();
     match kill(pid, sig) {
         Ok(()) => (),
@@ -218,6 +278,83 @@ pub fn stop_process(
     Ok(())
 }
 
+/// The pid of the process holding the pid file, or None if no process does, i.e. the
+/// process has already stopped.
+pub fn running_pid(process_name: &str, pid_file: &Utf8Path) -> anyhow::Result<Option<Pid>> {
+    match pid_file::read(pid_file).with_context(|| format!("read pid_file {pid_file:?}"))? {
+        PidFileRead::NotExist => {
+            println!("{process_name} is already stopped: no pid file present at {pid_file:?}");
+            Ok(None)
+        }
+        PidFileRead::NotHeldByAnyProcess(_) => {
+            // Don't try to kill according to file contents beacuse the pid might have been re-used by another process.
+            // Don't delete the file either, it can race with new pid file creation.
+            // Read `pid_file` module comment for details.
+            println!(
+                "No process is holding the pidfile. The process must have already exited. Leave in place to avoid race conditions: {pid_file:?}"
+            );
+            Ok(None)
+        }
+        PidFileRead::LockedByOtherProcess(pid) => Ok(Some(pid)),
+    }
+}
+
+/// Error of [`wait_until_stopped_timeout`].
+#[derive(Debug, thiserror::Error)]
+pub enum WaitError {
+    /// The process is still running after the timeout. It is up to the caller to escalate,
+    /// e.g. with SIGKILL.
+    #[error("{process_name} with pid {pid} did not stop in {timeout:?}")]
+    TimedOut {
+        process_name: String,
+        pid: Pid,
+        timeout: Duration,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Like [`wait_until_stopped`], but with a custom timeout, and calling `progress` every
+/// `progress_interval` with the time waited so far and the state of the process. The state
+/// is the one-letter code of `/proc/<pid>/stat`, e.g. 'S' for sleeping or 'D' for waiting
+/// for I/O, and None where /proc isn't available.
+pub fn wait_until_stopped_timeout(
+    process_name: &str,
+    pid: Pid,
+    timeout: Duration,
+    progress_interval: Duration,
+    mut progress: impl FnMut(Duration, Option<char>),
+) -> Result<(), WaitError> {
+    let started_at = std::time::Instant::now();
+    let mut next_progress = progress_interval;
+    loop {
+        if process_has_stopped(pid)? {
+            return Ok(());
+        }
+        let elapsed = started_at.elapsed();
+        if elapsed >= timeout {
+            return Err(WaitError::TimedOut {
+                process_name: process_name.to_string(),
+                pid,
+                timeout,
+            });
+        }
+        if elapsed >= next_progress {
+            progress(elapsed, process_state(pid));
+            next_progress += progress_interval;
+        }
+        thread::sleep(RETRY_INTERVAL.min(timeout - elapsed));
+    }
+}
+
+/// State of the process as shown in `/proc/<pid>/stat`, if available.
+fn process_state(pid: Pid) -> Option<char> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The state follows the command name, which is in parentheses and may contain spaces
+    let (_, after_comm) = stat.rsplit_once(')')?;
+    after_comm.trim_start().chars().next()
+}
+
 pub fn wait_until_stopped(process_name: &str, pid: Pid) -> anyhow::Result<()> {
     for retries in 0..STOP_RETRIES {
         match process_has_stopped(pid) {
@@ -388,3 +525,123 @@ fn process_has_stopped(pid: Pid) -> anyhow::Result<bool> {
         Err(err) => anyhow::bail!("Failed to send signal to process with pid {pid}: {err}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start_sleep(
+        datadir: &Utf8Path,
+        pid_file: &Utf8Path,
+        timeout: Duration,
+        ready: bool,
+    ) -> anyhow::Result<()> {
+        start_process_with_options(
+            "sleep",
+            datadir.as_std_path(),
+            Path::new("sleep"),
+            ["60"],
+            [],
+            InitialPidFile::Create(pid_file.to_owned()),
+            &timeout,
+            || async move { anyhow::Ok(ready) },
+            ProcessOptions {
+                log_file_name: Some("sleep.out".to_string()),
+                inherit_env: true,
+            },
+        )
+        .await
+    }
+
+    fn pid_in_file(pid_file: &Utf8Path) -> Pid {
+        Pid::from_raw(
+            fs::read_to_string(pid_file)
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn startup_timeout_kills_child() {
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let pid_file = dir.join("sleep.pid");
+
+        let err = start_sleep(dir, &pid_file, Duration::from_millis(500), false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("did not start"), "{err}");
+
+        // The scopeguard killed and reaped the child
+        assert!(process_has_stopped(pid_in_file(&pid_file)).unwrap());
+        assert!(dir.join("sleep.out").exists());
+    }
+
+    #[tokio::test]
+    async fn sigterm_then_wait() {
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let pid_file = dir.join("sleep.pid");
+
+        start_sleep(dir, &pid_file, Duration::from_secs(10), true)
+            .await
+            .unwrap();
+        let pid = pid_in_file(&pid_file);
+        assert!(!process_has_stopped(pid).unwrap());
+
+        // The child outlives start_process. neon_local would exit and leave it to init,
+        // here we have to reap it ourselves, or it stays around as a zombie.
+        let reaper = thread::spawn(move || nix::sys::wait::waitpid(pid, None));
+        signal_and_wait("sleep", &pid_file, Some(Signal::SIGTERM)).unwrap();
+        reaper.join().unwrap().unwrap();
+        assert!(process_has_stopped(pid).unwrap());
+
+        // Nothing to do once the process is gone
+        signal_and_wait("sleep", &pid_file, None).unwrap();
+    }
+
+    #[test]
+    fn wait_timeout_for_child_ignoring_sigterm() {
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; exec sleep 60"])
+            .spawn()
+            .unwrap();
+        let pid = Pid::from_raw(child.id() as i32);
+        // Give sh a moment to set up the trap
+        thread::sleep(Duration::from_millis(200));
+        kill(pid, Signal::SIGTERM).unwrap();
+
+        let mut reports = Vec::new();
+        let err = wait_until_stopped_timeout(
+            "sleep",
+            pid,
+            Duration::from_millis(1000),
+            Duration::from_millis(300),
+            |elapsed, state| reports.push((elapsed, state)),
+        )
+        .unwrap_err();
+        assert!(matches!(err, WaitError::TimedOut { .. }), "{err}");
+        assert!((2..=4).contains(&reports.len()), "{reports:?}");
+        assert!(reports.windows(2).all(|w| w[0].0 < w[1].0), "{reports:?}");
+        if Path::new("/proc/self/stat").exists() {
+            assert!(
+                reports.iter().all(|(_, state)| *state == Some('S')),
+                "{reports:?}"
+            );
+        }
+
+        // Escalating works
+        child.kill().unwrap();
+        child.wait().unwrap();
+        wait_until_stopped_timeout(
+            "sleep",
+            pid,
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+            |_, _| {},
+        )
+        .unwrap();
+    }
+}
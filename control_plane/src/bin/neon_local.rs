@@ -7,16 +7,20 @@
 //!
 use anyhow::{anyhow, bail, Context, Result};
 use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueEnum};
-use compute_api::spec::ComputeMode;
-use control_plane::endpoint::ComputeControlPlane;
+use compute_api::spec::{ComputeMode, Database, Role};
+use control_plane::endpoint::{
+    validate_log_filter, ComputeControlPlane, ConnectivityCheck, DumpFormat, Endpoint,
+    EndpointSample, EndpointStartArgs, EndpointStatus, GenerateWalOptions, WalWorkload,
+};
 use control_plane::local_env::{
-    InitForceMode, LocalEnv, NeonBroker, NeonLocalInitConf, NeonLocalInitPageserverConf,
-    SafekeeperConf,
+    AuthComponent, EndpointDefaults, InitForceMode, LocalEnv, NeonBroker, NeonLocalInitConf,
+    NeonLocalInitPageserverConf, SafekeeperConf,
 };
 use control_plane::pageserver::PageServerNode;
 use control_plane::safekeeper::SafekeeperNode;
 use control_plane::storage_controller::StorageController;
 use control_plane::{broker, local_env};
+use futures::StreamExt;
 use pageserver_api::config::{
     DEFAULT_HTTP_LISTEN_PORT as DEFAULT_PAGESERVER_HTTP_PORT,
     DEFAULT_PG_LISTEN_PORT as DEFAULT_PAGESERVER_PG_PORT,
@@ -25,12 +29,12 @@ use pageserver_api::controller_api::{PlacementPolicy, TenantCreateRequest};
 use pageserver_api::models::{ShardParameters, TimelineCreateRequest, TimelineInfo};
 use pageserver_api::shard::{ShardCount, ShardStripeSize, TenantShardId};
 use postgres_backend::AuthType;
-use postgres_connection::parse_host_port;
 use safekeeper_api::{
     DEFAULT_HTTP_LISTEN_PORT as DEFAULT_SAFEKEEPER_HTTP_PORT,
     DEFAULT_PG_LISTEN_PORT as DEFAULT_SAFEKEEPER_PG_PORT,
 };
 use std::collections::{BTreeSet, HashMap};
+use std::io::IsTerminal;
 use std::path::PathBuf;
 use std::process::exit;
 use std::str::FromStr;
@@ -38,11 +42,12 @@ use std::time::Duration;
 use storage_broker::DEFAULT_LISTEN_ADDR as DEFAULT_BROKER_ADDR;
 use url::Host;
 use utils::{
-    auth::{Claims, Scope},
+    auth::{attenuate, Claims, Scope},
     id::{NodeId, TenantId, TenantTimelineId, TimelineId},
     lsn::Lsn,
     project_git_version,
 };
+use uuid::Uuid;
 
 // Default id of a safekeeper node, if not specified on the command line.
 const DEFAULT_SAFEKEEPER_ID: NodeId = NodeId(1);
@@ -54,6 +59,13 @@ const DEFAULT_PG_VERSION: &str = "15";
 
 const DEFAULT_PAGESERVER_CONTROL_PLANE_API: &str = "http://127.0.0.1:1234/upcall/v1/";
 
+/// How many endpoints `endpoint start --all` starts at the same time.
+const START_ALL_PARALLELISM: usize = 4;
+
+/// How many endpoints `endpoint list` checks at the same time, and for how long.
+const STATUS_PARALLELISM: usize = 16;
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
 ///
 /// Timelines tree element used as a value in the HashMap.
 ///
@@ -105,6 +117,7 @@ fn main() -> Result<()> {
             "safekeeper" => rt.block_on(handle_safekeeper(sub_args, &env)),
             "endpoint" => rt.block_on(handle_endpoint(sub_args, &env)),
             "mappings" => handle_mappings(sub_args, &mut env),
+            "token" => handle_token(sub_args, &env),
             "pg" => bail!("'pg' subcommand has been renamed to 'endpoint'"),
             _ => bail!("unexpected subcommand {sub_name}"),
         };
@@ -359,13 +372,18 @@ fn handle_init(init_match: &ArgMatches) -> anyhow::Result<LocalEnv> {
             default_tenant_id: TenantId::from_array(std::array::from_fn(|_| 0)),
             storage_controller: None,
             control_plane_compute_hook_api: None,
+            endpoint_defaults: None,
         }
     };
 
     LocalEnv::init(init_conf, force)
         .context("materialize initial neon_local environment on disk")?;
-    Ok(LocalEnv::load_config(&local_env::base_path())
-        .expect("freshly written config should be loadable"))
+    let env = LocalEnv::load_config(&local_env::base_path())
+        .expect("freshly written config should be loadable");
+    if let Err(e) = env.validate(local_env::DEFAULT_PG_VERSION) {
+        eprintln!("Warning: {e:#}");
+    }
+    Ok(env)
 }
 
 /// The default pageserver is the one where CLI tenant/timeline operations are sent by default.
@@ -683,6 +701,141 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
     Ok(())
 }
 
+/// Resolve the pageservers and auth token for starting an endpoint of `tenant_id`.
+#[allow(clippy::too_many_arguments)]
+async fn endpoint_start_args(
+    env: &local_env::LocalEnv,
+    tenant_id: TenantId,
+    vanilla: bool,
+    pageserver_id: Option<NodeId>,
+    safekeepers: Vec<NodeId>,
+    remote_ext_config: Option<&String>,
+    create_test_user: bool,
+    suspend_timeout: Option<Duration>,
+    skip_conf_validation: bool,
+    verify_pageserver_connectivity: ConnectivityCheck,
+    ignore_unsupported_features: bool,
+    log_level: Option<&String>,
+    stripe_size_override: Option<ShardStripeSize>,
+) -> Result<EndpointStartArgs> {
+    // Vanilla endpoints don't talk to the storage
+    if vanilla {
+        return Ok(EndpointStartArgs {
+            auth_token: None,
+            safekeepers: vec![],
+            pageservers: vec![],
+            remote_ext_config: None,
+            shard_stripe_size: 0,
+            create_test_user,
+            suspend_timeout,
+            skip_conf_validation,
+            verify_pageserver_connectivity: ConnectivityCheck::Skip,
+            ignore_unsupported_features,
+            log_level: log_level.cloned(),
+            stripe_size_override,
+        });
+    }
+    let (pageservers, stripe_size) = if let Some(pageserver_id) = pageserver_id {
+        let conf = env.get_pageserver_conf(pageserver_id)?;
+        (
+            vec![conf.pg_host_port()?],
+            // If caller is telling us what pageserver to use, this is not a tenant which is
+            // full managed by storage controller, therefore not sharded.
+            ShardParameters::DEFAULT_STRIPE_SIZE,
+        )
+    } else {
+        // Look up the currently attached location of the tenant, and its striping metadata,
+        // to pass these on to postgres.
+        let storage_controller = StorageController::from_env(env);
+        let locate_result = storage_controller.tenant_locate(tenant_id).await?;
+        let pageservers = locate_result
+            .shards
+            .into_iter()
+            .map(|shard| {
+                (
+                    Host::parse(&shard.listen_pg_addr)
+                        .expect("Storage controller reported bad hostname"),
+                    shard.listen_pg_port,
+                )
+            })
+            .collect::<Vec<_>>();
+        let stripe_size = locate_result.shard_params.stripe_size;
+
+        (pageservers, stripe_size)
+    };
+    assert!(!pageservers.is_empty());
+
+    let ps_conf = env.endpoint_pageserver_conf(pageserver_id)?;
+    let auth_token = if matches!(ps_conf.pg_auth_type, AuthType::NeonJWT) {
+        Some(env.generate_scoped_token(Scope::Tenant, Some(tenant_id), None)?)
+    } else {
+        None
+    };
+
+    Ok(EndpointStartArgs {
+        auth_token,
+        safekeepers,
+        pageservers,
+        remote_ext_config: remote_ext_config.cloned(),
+        shard_stripe_size: stripe_size.0 as usize,
+        create_test_user,
+        suspend_timeout,
+        skip_conf_validation,
+        verify_pageserver_connectivity,
+        ignore_unsupported_features,
+        log_level: log_level.cloned(),
+        stripe_size_override,
+    })
+}
+
+/// A tick of 'neon_local endpoint top'.
+fn samples_table(samples: &[EndpointSample]) -> comfy_table::Table {
+    let mut table = comfy_table::Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header([
+        "ENDPOINT",
+        "STATUS",
+        "CONNECTIONS",
+        "OLDEST XACT",
+        "WAL RATE",
+        "LFC HIT RATIO",
+    ]);
+    for sample in samples {
+        let status = match &sample.status {
+            Ok(info) => match info.compute_status {
+                Some(compute_status) => format!("{}, {compute_status:?}", info.status),
+                None => info.status.to_string(),
+            },
+            Err(_) => "unknown".to_string(),
+        };
+        // Stopped endpoints are sampled as idle, with nothing else to show
+        let (connections, oldest_xact, wal_rate) = match &sample.activity {
+            Some(activity) if activity.running => (
+                activity.client_backends.to_string(),
+                activity
+                    .oldest_xact_age_secs
+                    .map_or_else(|| "-".to_string(), |secs| format!("{secs:.0}s")),
+                activity
+                    .wal_bytes_per_sec
+                    .map_or_else(|| "?".to_string(), |rate| format!("{rate:.0} B/s")),
+            ),
+            Some(_) => ("-".to_string(), "-".to_string(), "-".to_string()),
+            None => ("?".to_string(), "?".to_string(), "?".to_string()),
+        };
+        table.add_row([
+            sample.endpoint_id.clone(),
+            status,
+            connections,
+            oldest_xact,
+            wal_rate,
+            sample
+                .lfc_hit_ratio
+                .map_or_else(|| "-".to_string(), |ratio| format!("{ratio:.2}%")),
+        ]);
+    }
+    table
+}
+
 async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
     let (sub_name, sub_args) = match ep_match.subcommand() {
         Some(ep_subcommand_data) => ep_subcommand_data,
@@ -714,14 +867,47 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 "TIMELINE",
                 "BRANCH NAME",
                 "LSN",
+                "BASEBACKUP LSN",
                 "STATUS",
             ]);
 
-            for (endpoint_id, endpoint) in cplane
-                .endpoints
-                .iter()
-                .filter(|(_, endpoint)| endpoint.tenant_id == tenant_shard_id.tenant_id)
+            let statuses = cplane.statuses(STATUS_PARALLELISM, STATUS_TIMEOUT).await;
+            let idle = if sub_args.get_flag("idle") {
+                let wal_rate = *sub_args
+                    .get_one::<u64>("idle-wal-rate")
+                    .expect("has a default");
+                Some(cplane.idle_endpoints(wal_rate as f64).await)
+            } else {
+                None
+            };
+            for (endpoint_id, endpoint) in
+                cplane.endpoints.iter().filter(|(endpoint_id, endpoint)| {
+                    (endpoint.vanilla || endpoint.tenant_id == tenant_shard_id.tenant_id)
+                        && idle
+                            .as_ref()
+                            .map_or(true, |idle| idle.contains_key(endpoint_id.as_str()))
+                })
             {
+                let status = match &statuses[endpoint_id] {
+                    Ok(info) => Some(info.status),
+                    Err(e) => {
+                        eprintln!("Failed to get the status of endpoint {endpoint_id}: {e:#}");
+                        None
+                    }
+                };
+                let status_str = status.map_or_else(|| "unknown".to_string(), |s| s.to_string());
+                if endpoint.vanilla {
+                    table.add_row([
+                        endpoint_id.as_str(),
+                        &endpoint.pg_address.to_string(),
+                        "-",
+                        "-",
+                        "-",
+                        "-",
+                        &format!("{status_str}, vanilla"),
+                    ]);
+                    continue;
+                }
                 let lsn_str = match endpoint.mode {
                     ComputeMode::Static(lsn) => {
                         // -> read-only endpoint
@@ -746,19 +932,67 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                     .map(|name| name.as_str())
                     .unwrap_or("?");
 
+                // Only meaningful while the compute started from that basebackup runs
+                let basebackup_lsn_str = if status == Some(EndpointStatus::Running) {
+                    match endpoint.basebackup_lsn() {
+                        Ok(Some(lsn)) => lsn.to_string(),
+                        Ok(None) => "?".to_string(),
+                        Err(e) => {
+                            eprintln!("Failed to read the basebackup LSN of {endpoint_id}: {e:#}");
+                            "?".to_string()
+                        }
+                    }
+                } else {
+                    "-".to_string()
+                };
+
                 table.add_row([
                     endpoint_id.as_str(),
                     &endpoint.pg_address.to_string(),
                     &endpoint.timeline_id.to_string(),
                     branch_name,
                     lsn_str.as_str(),
-                    &format!("{}", endpoint.status()),
+                    basebackup_lsn_str.as_str(),
+                    &if endpoint.is_read_only() {
+                        format!("{status_str}, read-only")
+                    } else {
+                        status_str
+                    },
                 ]);
             }
 
             println!("{table}");
         }
         "create" => {
+            if sub_args.get_flag("show-defaults") {
+                let defaults = &env.endpoint_defaults;
+                let effective = EndpointDefaults {
+                    pg_conf: defaults.pg_conf.clone(),
+                    skip_pg_catalog_updates: Some(defaults.skip_pg_catalog_updates(None)),
+                    base_port: Some(defaults.base_port()),
+                    port_reuse_cooldown: Some(defaults.port_reuse_cooldown()),
+                    expose_external_http: Some(defaults.expose_external_http()),
+                    rewrite_legacy_endpoint_conf: Some(defaults.rewrite_legacy_endpoint_conf()),
+                };
+                print!("{}", toml::to_string_pretty(&effective)?);
+                return Ok(());
+            }
+            let profiles: Vec<String> = sub_args
+                .get_many::<String>("profile")
+                .map(|profiles| profiles.cloned().collect())
+                .unwrap_or_default();
+            if sub_args.get_flag("vanilla") {
+                let endpoint_id = sub_args
+                    .get_one::<String>("endpoint_id")
+                    .context("vanilla endpoints need an explicit endpoint id")?;
+                let pg_port = sub_args.get_one::<u16>("pg-port").copied();
+                let pg_version = sub_args
+                    .get_one::<u32>("pg-version")
+                    .copied()
+                    .context("Failed to parse postgres version from the argument string")?;
+                cplane.new_vanilla_endpoint(endpoint_id, pg_port, pg_version, profiles)?;
+                return Ok(());
+            }
             let tenant_id = get_tenant_id(sub_args, env)?;
             let branch_name = sub_args
                 .get_one::<String>("branch-name")
@@ -768,10 +1002,8 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 .get_one::<String>("endpoint_id")
                 .map(String::to_string)
                 .unwrap_or_else(|| format!("ep-{branch_name}"));
-            let update_catalog = sub_args
-                .get_one::<bool>("update-catalog")
-                .cloned()
-                .unwrap_or_default();
+            let update_catalog = sub_args.get_one::<bool>("update-catalog").cloned();
+            let suspend_timeout = get_suspend_timeout(sub_args);
 
             let lsn = sub_args
                 .get_one::<String>("lsn")
@@ -816,6 +1048,11 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
             if !allow_multiple {
                 cplane.check_conflicting_endpoints(mode, tenant_id, timeline_id)?;
             }
+            if !sub_args.get_flag("force") {
+                cplane
+                    .check_static_lsn(mode, tenant_id, timeline_id)
+                    .await?;
+            }
 
             cplane.new_endpoint(
                 &endpoint_id,
@@ -825,14 +1062,14 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 http_port,
                 pg_version,
                 mode,
-                !update_catalog,
+                update_catalog.map(|update_catalog| !update_catalog),
+                suspend_timeout,
+                profiles,
+                sub_args.get_one::<String>("stream-from").cloned(),
+                sub_args.get_flag("split-logs"),
             )?;
         }
         "start" => {
-            let endpoint_id = sub_args
-                .get_one::<String>("endpoint_id")
-                .ok_or_else(|| anyhow!("No endpoint ID was provided to start"))?;
-
             let pageserver_id =
                 if let Some(id_str) = sub_args.get_one::<String>("endpoint-pageserver-id") {
                     Some(NodeId(
@@ -846,83 +1083,119 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
 
             let allow_multiple = sub_args.get_flag("allow-multiple");
 
-            // If --safekeepers argument is given, use only the listed
-            // safekeeper nodes; otherwise all from the env.
-            let safekeepers = if let Some(safekeepers) = parse_safekeepers(sub_args)? {
+            // If --safekeepers argument is given, use only the listed safekeeper
+            // nodes; otherwise those of the last reconfiguration, or all from the env.
+            let safekeepers = parse_safekeepers(sub_args)?;
+            let endpoint_safekeepers = |endpoint: &Endpoint| -> Vec<NodeId> {
                 safekeepers
-            } else {
-                env.safekeepers.iter().map(|sk| sk.id).collect()
+                    .clone()
+                    .or_else(|| endpoint.last_safekeepers.clone())
+                    .unwrap_or_else(|| env.safekeepers.iter().map(|sk| sk.id).collect())
             };
 
-            let endpoint = cplane
-                .endpoints
-                .get(endpoint_id.as_str())
-                .ok_or_else(|| anyhow::anyhow!("endpoint {endpoint_id} not found"))?;
-
             let create_test_user = sub_args
                 .get_one::<bool>("create-test-user")
                 .cloned()
                 .unwrap_or_default();
 
-            if !allow_multiple {
+            let suspend_timeout = get_suspend_timeout(sub_args);
+            let skip_conf_validation = sub_args.get_flag("skip-conf-validation");
+            let verify_pageserver_connectivity = sub_args
+                .get_one::<String>("verify-pageserver-connectivity")
+                .map(|mode| ConnectivityCheck::from_str(mode))
+                .transpose()?
+                .unwrap_or_default();
+            let ignore_unsupported_features = sub_args.get_flag("ignore-unsupported-features");
+            let log_level = sub_args.get_one::<String>("compute-log-level");
+            if let Some(log_level) = log_level {
+                validate_log_filter(log_level)?;
+            }
+            let stripe_size_override = sub_args
+                .get_one::<u32>("stripe-size-override")
+                .copied()
+                .map(ShardStripeSize);
+
+            if sub_args.get_flag("all") {
+                let results = cplane
+                    .start_all(
+                        |_| true,
+                        |endpoint| {
+                            endpoint_start_args(
+                                env,
+                                endpoint.tenant_id,
+                                endpoint.vanilla,
+                                pageserver_id,
+                                endpoint_safekeepers(endpoint),
+                                remote_ext_config,
+                                create_test_user,
+                                suspend_timeout,
+                                skip_conf_validation,
+                                verify_pageserver_connectivity,
+                                ignore_unsupported_features,
+                                log_level,
+                                stripe_size_override,
+                            )
+                        },
+                        START_ALL_PARALLELISM,
+                    )
+                    .await;
+                let mut failed = 0;
+                for (endpoint_id, result) in &results {
+                    match result {
+                        Ok(()) => println!("Started endpoint {endpoint_id}"),
+                        Err(e) => {
+                            failed += 1;
+                            eprintln!("Failed to start endpoint {endpoint_id}: {e:#}");
+                        }
+                    }
+                }
+                if failed > 0 {
+                    bail!("{failed} of {} endpoints failed to start", results.len());
+                }
+                return Ok(());
+            }
+
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided to start"))?;
+
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .ok_or_else(|| anyhow::anyhow!("endpoint {endpoint_id} not found"))?;
+
+            if !allow_multiple && !endpoint.vanilla {
                 cplane.check_conflicting_endpoints(
                     endpoint.mode,
                     endpoint.tenant_id,
                     endpoint.timeline_id,
                 )?;
             }
+            if !sub_args.get_flag("force") && !endpoint.vanilla {
+                cplane
+                    .check_static_lsn(endpoint.mode, endpoint.tenant_id, endpoint.timeline_id)
+                    .await?;
+            }
 
-            let (pageservers, stripe_size) = if let Some(pageserver_id) = pageserver_id {
-                let conf = env.get_pageserver_conf(pageserver_id).unwrap();
-                let parsed = parse_host_port(&conf.listen_pg_addr).expect("Bad config");
-                (
-                    vec![(parsed.0, parsed.1.unwrap_or(5432))],
-                    // If caller is telling us what pageserver to use, this is not a tenant which is
-                    // full managed by storage controller, therefore not sharded.
-                    ShardParameters::DEFAULT_STRIPE_SIZE,
-                )
-            } else {
-                // Look up the currently attached location of the tenant, and its striping metadata,
-                // to pass these on to postgres.
-                let storage_controller = StorageController::from_env(env);
-                let locate_result = storage_controller.tenant_locate(endpoint.tenant_id).await?;
-                let pageservers = locate_result
-                    .shards
-                    .into_iter()
-                    .map(|shard| {
-                        (
-                            Host::parse(&shard.listen_pg_addr)
-                                .expect("Storage controller reported bad hostname"),
-                            shard.listen_pg_port,
-                        )
-                    })
-                    .collect::<Vec<_>>();
-                let stripe_size = locate_result.shard_params.stripe_size;
-
-                (pageservers, stripe_size)
-            };
-            assert!(!pageservers.is_empty());
-
-            let ps_conf = env.get_pageserver_conf(DEFAULT_PAGESERVER_ID)?;
-            let auth_token = if matches!(ps_conf.pg_auth_type, AuthType::NeonJWT) {
-                let claims = Claims::new(Some(endpoint.tenant_id), Scope::Tenant);
-
-                Some(env.generate_auth_token(&claims)?)
-            } else {
-                None
-            };
+            let args = endpoint_start_args(
+                env,
+                endpoint.tenant_id,
+                endpoint.vanilla,
+                pageserver_id,
+                endpoint_safekeepers(endpoint),
+                remote_ext_config,
+                create_test_user,
+                suspend_timeout,
+                skip_conf_validation,
+                verify_pageserver_connectivity,
+                ignore_unsupported_features,
+                log_level,
+                stripe_size_override,
+            )
+            .await?;
 
             println!("Starting existing endpoint {endpoint_id}...");
-            endpoint
-                .start(
-                    &auth_token,
-                    safekeepers,
-                    pageservers,
-                    remote_ext_config,
-                    stripe_size.0 as usize,
-                    create_test_user,
-                )
-                .await?;
+            endpoint.start(&args).await?;
         }
         "reconfigure" => {
             let endpoint_id = sub_args
@@ -932,6 +1205,7 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 .endpoints
                 .get(endpoint_id.as_str())
                 .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            endpoint.check_not_vanilla("reconfiguration")?;
             let pageservers =
                 if let Some(id_str) = sub_args.get_one::<String>("endpoint-pageserver-id") {
                     let ps_id = NodeId(id_str.parse().context("while parsing pageserver id")?);
@@ -959,7 +1233,66 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
             // If --safekeepers argument is given, use only the listed
             // safekeeper nodes; otherwise all from the env.
             let safekeepers = parse_safekeepers(sub_args)?;
-            endpoint.reconfigure(pageservers, None, safekeepers).await?;
+            endpoint
+                .reconfigure_with_progress(
+                    pageservers,
+                    None,
+                    safekeepers,
+                    sub_args.get_flag("skip-conf-validation"),
+                    |status| println!("Compute status: {status:?}"),
+                )
+                .await?;
+        }
+        "add-database" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID provided to add a database to"))?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            let name = sub_args.get_one::<String>("name").expect("required");
+            let owner = sub_args.get_one::<String>("owner").expect("required");
+            let add_roles = if sub_args.get_flag("create-owner") {
+                vec![Role {
+                    name: owner.clone(),
+                    encrypted_password: None,
+                    options: None,
+                }]
+            } else {
+                Vec::new()
+            };
+            let database = Database {
+                name: name.clone(),
+                owner: owner.clone(),
+                options: None,
+                restrict_conn: false,
+                invalid: false,
+            };
+            endpoint
+                .apply_cluster_delta(add_roles, vec![database], Vec::new())
+                .await?;
+            println!("Added database {name} to endpoint {endpoint_id}");
+        }
+        "rotate-token" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID provided to rotate the token of"))?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            let ttl = sub_args
+                .get_one::<humantime::Duration>("ttl")
+                .map(|d| *d.as_ref());
+            let ps_conf = env.endpoint_pageserver_conf(None)?;
+            let token = if matches!(ps_conf.pg_auth_type, AuthType::NeonJWT) {
+                Some(env.generate_scoped_token(Scope::Tenant, Some(endpoint.tenant_id), ttl)?)
+            } else {
+                None
+            };
+            endpoint.rotate_storage_auth_token(token).await?;
+            println!("Rotated the storage auth token of endpoint {endpoint_id}");
         }
         "stop" => {
             let endpoint_id = sub_args
@@ -967,12 +1300,243 @@ async fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Re
                 .ok_or_else(|| anyhow!("No endpoint ID was provided to stop"))?;
             let destroy = sub_args.get_flag("destroy");
             let mode = sub_args.get_one::<String>("mode").expect("has a default");
+            // Destroyed endpoints have no use for a final sync
+            let skip_safekeeper_sync = sub_args.get_flag("skip-safekeeper-sync") || destroy;
 
             let endpoint = cplane
                 .endpoints
                 .get(endpoint_id.as_str())
                 .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
-            endpoint.stop(mode, destroy)?;
+            endpoint.stop(mode, destroy, skip_safekeeper_sync)?;
+        }
+        "verify-availability" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            endpoint.verify_availability_objects().await?;
+            println!("Endpoint {endpoint_id} has its availability check objects");
+        }
+        "upgrade" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let pg_version = sub_args
+                .get_one::<u32>("pg-version")
+                .copied()
+                .expect("required argument");
+            cplane.upgrade_pg_version(endpoint_id, pg_version)?;
+        }
+        "describe" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&endpoint.describe().await)?
+            );
+        }
+        "inspect" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&endpoint.inspect_pgdata()?)?
+            );
+        }
+        "top" => {
+            let interval = *sub_args
+                .get_one::<humantime::Duration>("interval")
+                .expect("has a default")
+                .as_ref();
+            if interval.is_zero() {
+                bail!("the interval must be longer than zero");
+            }
+            let count = sub_args.get_one::<u64>("count").copied();
+            let stream = cplane.status_stream(interval);
+            let mut stream = std::pin::pin!(stream);
+            let mut ticks = 0;
+            while let Some(samples) = stream.next().await {
+                if std::io::stdout().is_terminal() {
+                    // Clear the screen
+                    print!("\x1b[2J\x1b[H");
+                }
+                println!(
+                    "{}\n{}\n",
+                    humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
+                    samples_table(&samples)
+                );
+                ticks += 1;
+                if count.is_some_and(|count| ticks >= count) {
+                    break;
+                }
+            }
+        }
+        "kill-orphans" => {
+            let orphans = cplane.find_orphans();
+            if orphans.is_empty() {
+                println!("No orphaned Postgres processes found");
+            }
+            for orphan in &orphans {
+                println!(
+                    "Endpoint {}: Postgres with pid {} runs without compute_ctl",
+                    orphan.endpoint_id, orphan.pid
+                );
+                if sub_args.get_flag("terminate") {
+                    cplane.kill_orphan(orphan)?;
+                }
+            }
+        }
+        "dump" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let dest = sub_args
+                .get_one::<PathBuf>("dest")
+                .expect("dest argument missing");
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            std::fs::create_dir_all(dest)?;
+            let dump = if sub_args.get_flag("globals") {
+                endpoint.dump_all(dest)?
+            } else {
+                let db = sub_args.get_one::<String>("db").expect("has a default");
+                let format = DumpFormat::from_str(
+                    sub_args.get_one::<String>("format").expect("has a default"),
+                )?;
+                endpoint.dump(db, dest, format)?
+            };
+            println!("{} {}", dump.sha256, dump.path.display());
+        }
+        "generate-wal" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let mb = *sub_args.get_one::<u64>("mb").expect("has a default");
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            let opts = GenerateWalOptions {
+                workload: if sub_args.get_flag("small-transactions") {
+                    WalWorkload::SmallTransactions
+                } else {
+                    WalWorkload::Bulk
+                },
+                ..Default::default()
+            };
+            let cancel = tokio_util::sync::CancellationToken::new();
+            let on_ctrl_c = cancel.clone();
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    on_ctrl_c.cancel();
+                }
+            });
+            let wal = endpoint
+                .generate_wal(mb * 1024 * 1024, &opts, &cancel)
+                .await?;
+            println!(
+                "Generated {} bytes of WAL from {} to {} in {:?}",
+                wal.end_lsn.0 - wal.start_lsn.0,
+                wal.start_lsn,
+                wal.end_lsn,
+                wal.elapsed
+            );
+        }
+        "safekeepers" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            let membership = endpoint.safekeeper_membership().await?;
+            println!("{}", serde_json::to_string_pretty(&membership)?);
+        }
+        "stats" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            let mut previous = endpoint.neon_stats().await?;
+            println!("{}", serde_json::to_string_pretty(&previous)?);
+            if let Some(interval) = sub_args.get_one::<u64>("watch") {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(*interval)).await;
+                    let current = endpoint.neon_stats().await?;
+                    println!("last {interval}s: {}", current.since(&previous));
+                    previous = current;
+                }
+            }
+        }
+        "set-read-only" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided"))?;
+            let read_only = *sub_args
+                .get_one::<bool>("read-only")
+                .expect("read-only argument missing");
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            endpoint
+                .set_read_only(read_only, sub_args.get_flag("terminate-writers"))
+                .await?;
+        }
+        "snapshot" => {
+            let endpoint_id = sub_args
+                .get_one::<String>("endpoint_id")
+                .ok_or_else(|| anyhow!("No endpoint ID was provided to snapshot"))?;
+            let output = sub_args
+                .get_one::<PathBuf>("output")
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from(format!("{endpoint_id}.tar")));
+            let endpoint = cplane
+                .endpoints
+                .get(endpoint_id.as_str())
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            endpoint.snapshot(&output, sub_args.get_flag("include-pgdata"))?;
+            println!("Saved endpoint {endpoint_id} to {}", output.display());
+        }
+        "restore" => {
+            let snapshot = sub_args
+                .get_one::<PathBuf>("snapshot")
+                .expect("snapshot argument missing");
+            let (endpoint, mapping) = cplane.restore_snapshot(
+                snapshot,
+                sub_args
+                    .get_one::<String>("endpoint_id")
+                    .map(String::as_str),
+                sub_args.get_flag("force"),
+                sub_args.get_flag("keep-ports"),
+            )?;
+            for port in mapping {
+                println!("Moved {} port {} -> {}", port.name, port.old, port.new);
+            }
+            println!(
+                "Restored endpoint to {}, postgres at {}",
+                endpoint.endpoint_path().display(),
+                endpoint.pg_address
+            );
         }
 
         _ => bail!("Unexpected endpoint subcommand '{sub_name}'"),
@@ -998,6 +1562,36 @@ fn parse_safekeepers(sub_args: &ArgMatches) -> Result<Option<Vec<NodeId>>> {
     }
 }
 
+fn handle_token(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
+    let scope: Scope = sub_match
+        .get_one::<String>("scope")
+        .expect("scope argument missing")
+        .parse()?;
+    let tenant_id = parse_tenant_id(sub_match)?;
+    let endpoint_id = sub_match
+        .get_one::<String>("endpoint-uuid")
+        .map(|endpoint_id| Uuid::parse_str(endpoint_id))
+        .transpose()
+        .context("Failed to parse endpoint uuid from the argument string")?;
+
+    let claims = match sub_match.get_one::<String>("attenuate-from") {
+        Some(token) => {
+            let auth = env.jwt_auth_for(AuthComponent::Pageserver)?;
+            let parent = auth
+                .decode(token)
+                .context("Failed to validate --attenuate-from token")?
+                .claims;
+            attenuate(&parent, scope, tenant_id)?
+        }
+        None => Claims {
+            endpoint_id,
+            ..Claims::new(tenant_id, scope)
+        },
+    };
+    println!("{}", env.generate_auth_token(&claims)?);
+    Ok(())
+}
+
 fn handle_mappings(sub_match: &ArgMatches, env: &mut local_env::LocalEnv) -> Result<()> {
     let (sub_name, sub_args) = match sub_match.subcommand() {
         Some(ep_subcommand_data) => ep_subcommand_data,
@@ -1050,6 +1644,11 @@ fn get_start_timeout(args: &ArgMatches) -> &Duration {
     humantime_duration.as_ref()
 }
 
+fn get_suspend_timeout(args: &ArgMatches) -> Option<Duration> {
+    args.get_one::<humantime::Duration>("suspend-timeout")
+        .map(|d| *d.as_ref())
+}
+
 async fn handle_pageserver(sub_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
     match sub_match.subcommand() {
         Some(("start", subcommand_args)) => {
@@ -1267,7 +1866,9 @@ async fn try_stop_all(env: &local_env::LocalEnv, immediate: bool) {
     match ComputeControlPlane::load(env.clone()) {
         Ok(cplane) => {
             for (_k, node) in cplane.endpoints {
-                if let Err(e) = node.stop(if immediate { "immediate" } else { "fast" }, false) {
+                if let Err(e) =
+                    node.stop(if immediate { "immediate" } else { "fast" }, false, false)
+                {
                     eprintln!("postgres stop failed: {e:#}");
                 }
             }
@@ -1426,6 +2027,24 @@ fn cli() -> Command {
         .help("If set, will set up the catalog for neon_superuser")
         .required(false);
 
+    let suspend_timeout_arg = Arg::new("suspend-timeout")
+        .long("suspend-timeout")
+        .help("Suspend the compute after it has been idle this long, e.g. 60s. Never by default")
+        .value_parser(value_parser!(humantime::Duration))
+        .required(false);
+
+    let skip_conf_validation_arg = Arg::new("skip-conf-validation")
+        .long("skip-conf-validation")
+        .help("Don't check the values of known settings in the endpoint's postgresql.conf")
+        .action(ArgAction::SetTrue)
+        .required(false);
+
+    let force_lsn_arg = Arg::new("force")
+        .long("force")
+        .help("Don't check that the LSN of a static endpoint exists on its branch")
+        .action(ArgAction::SetTrue)
+        .required(false);
+
     let create_test_user = Arg::new("create-test-user")
         .value_parser(value_parser!(bool))
         .long("create-test-user")
@@ -1577,7 +2196,21 @@ fn cli() -> Command {
             Command::new("endpoint")
                 .arg_required_else_help(true)
                 .about("Manage postgres instances")
-                .subcommand(Command::new("list").arg(tenant_id_arg.clone()))
+                .subcommand(Command::new("list")
+                    .arg(tenant_id_arg.clone())
+                    .arg(
+                        Arg::new("idle")
+                            .long("idle")
+                            .help("Only list the running endpoints without client sessions, that write less WAL than --idle-wal-rate")
+                            .action(ArgAction::SetTrue)
+                            .required(false))
+                    .arg(
+                        Arg::new("idle-wal-rate")
+                            .long("idle-wal-rate")
+                            .help("Bytes of WAL per second under which an endpoint counts as idle, measured since the previous 'list --idle'")
+                            .value_parser(value_parser!(u64))
+                            .default_value("1024")
+                            .required(false)))
                 .subcommand(Command::new("create")
                     .about("Create a compute endpoint")
                     .arg(endpoint_id_arg.clone())
@@ -1595,15 +2228,85 @@ fn cli() -> Command {
                     .arg(pg_version_arg.clone())
                     .arg(hot_standby_arg.clone())
                     .arg(update_catalog)
+                    .arg(force_lsn_arg.clone())
+                    .arg(suspend_timeout_arg.clone())
                     .arg(allow_multiple.clone())
+                    .arg(
+                        Arg::new("show-defaults")
+                            .help("Print the effective endpoint defaults of this environment and exit")
+                            .long("show-defaults")
+                            .action(ArgAction::SetTrue)
+                            .required(false))
+                    .arg(
+                        Arg::new("vanilla")
+                            .help("Run vanilla Postgres without the neon extension and storage, for comparison runs")
+                            .long("vanilla")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with_all(["lsn", "hot-standby", "branch-name", "tenant-id"])
+                            .required(false))
+                    .arg(
+                        Arg::new("split-logs")
+                            .help("Have Postgres write its log to postgres.log and compute_ctl its own to compute_ctl.log, instead of both to compute.log")
+                            .long("split-logs")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("vanilla")
+                            .required(false))
+                    .arg(
+                        Arg::new("stream-from")
+                            .help("Stream WAL of the hot standby directly from this primary endpoint, instead of the safekeepers")
+                            .long("stream-from")
+                            .requires("hot-standby")
+                            .conflicts_with("vanilla")
+                            .required(false))
+                    .arg(
+                        Arg::new("profile")
+                            .help("Apply the settings of profiles/<PROFILE>.conf in the neon_local directory. Can be repeated, later profiles override earlier ones")
+                            .long("profile")
+                            .action(ArgAction::Append)
+                            .required(false))
                 )
                 .subcommand(Command::new("start")
                     .about("Start postgres.\n If the endpoint doesn't exist yet, it is created.")
                     .arg(endpoint_id_arg.clone())
+                    .arg(
+                        Arg::new("all")
+                            .help("Start all stopped endpoints, primaries before replicas")
+                            .long("all")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("endpoint_id")
+                            .required(false))
                     .arg(endpoint_pageserver_id_arg.clone())
                     .arg(safekeepers_arg.clone())
                     .arg(remote_ext_config_args)
                     .arg(create_test_user)
+                    .arg(suspend_timeout_arg)
+                    .arg(force_lsn_arg)
+                    .arg(skip_conf_validation_arg.clone())
+                    .arg(
+                        Arg::new("verify-pageserver-connectivity")
+                            .long("verify-pageserver-connectivity")
+                            .help("Once started, check that the pageserver of each shard is reachable, and fail or only warn if not")
+                            .value_parser(["warn", "fail"])
+                            .num_args(0..=1)
+                            .default_missing_value("fail")
+                            .required(false))
+                    .arg(
+                        Arg::new("ignore-unsupported-features")
+                            .long("ignore-unsupported-features")
+                            .help("Start without the features of the endpoint that compute_ctl doesn't support, rather than failing")
+                            .action(ArgAction::SetTrue)
+                            .required(false))
+                    .arg(
+                        Arg::new("compute-log-level")
+                            .long("compute-log-level")
+                            .help("Log level of compute_ctl for this start, e.g. 'debug', or a RUST_LOG filter such as 'info,compute_ctl=debug'")
+                            .required(false))
+                    .arg(
+                        Arg::new("stripe-size-override")
+                            .long("stripe-size-override")
+                            .help("Give the compute this stripe size in pages instead of the tenant's, to test it before the tenant is split. Only for unsharded tenants")
+                            .value_parser(value_parser!(u32))
+                            .required(false))
                     .arg(allow_multiple.clone())
                     .arg(timeout_arg.clone())
                 )
@@ -1613,6 +2316,222 @@ fn cli() -> Command {
                             .arg(safekeepers_arg)
                             .arg(endpoint_id_arg.clone())
                             .arg(tenant_id_arg.clone())
+                            .arg(skip_conf_validation_arg)
+                )
+                .subcommand(Command::new("add-database")
+                            .about("Create a database on a running primary, through compute_ctl")
+                            .arg(endpoint_id_arg.clone())
+                            .arg(Arg::new("name").long("name").help("Name of the database").required(true))
+                            .arg(
+                                Arg::new("owner")
+                                    .long("owner")
+                                    .help("Role owning the database: one of the endpoint's roles, or cloud_admin")
+                                    .required(true))
+                            .arg(
+                                Arg::new("create-owner")
+                                    .long("create-owner")
+                                    .help("Also create the owner role")
+                                    .action(ArgAction::SetTrue)
+                                    .required(false))
+                )
+                .subcommand(Command::new("rotate-token")
+                            .about("Give a running endpoint a new storage auth token, leaving the rest of its spec alone")
+                            .arg(endpoint_id_arg.clone())
+                            .arg(
+                                Arg::new("ttl")
+                                    .long("ttl")
+                                    .help("Make the new token expire after this long, e.g. '1h'. Doesn't expire by default")
+                                    .value_parser(value_parser!(humantime::Duration))
+                                    .required(false))
+                )
+                .subcommand(
+                    Command::new("upgrade")
+                    .about("Move a stopped endpoint to a newer Postgres major version. The data is not migrated")
+                    .arg(endpoint_id_arg.clone())
+                    .arg(
+                        Arg::new("pg-version")
+                            .help("Postgres major version to upgrade to")
+                            .long("pg-version")
+                            .value_parser(value_parser!(u32))
+                            .required(true)
+                    )
+                )
+                .subcommand(
+                    Command::new("describe")
+                    .about("Print the configuration, spec, status and last run of an endpoint as JSON")
+                    .arg(endpoint_id_arg.clone())
+                )
+                .subcommand(
+                    Command::new("inspect")
+                    .about("Print the control file and neon.signal of an endpoint's data directory as JSON, without starting it")
+                    .arg(endpoint_id_arg.clone())
+                )
+                .subcommand(
+                    Command::new("top")
+                    .about("Show the status and activity of all endpoints, refreshed every --interval")
+                    .arg(
+                        Arg::new("interval")
+                            .long("interval")
+                            .help("Time between refreshes, e.g. '2s'")
+                            .value_parser(value_parser!(humantime::Duration))
+                            .default_value("2s")
+                            .required(false))
+                    .arg(
+                        Arg::new("count")
+                            .long("count")
+                            .help("Exit after this many refreshes, rather than running until interrupted")
+                            .value_parser(value_parser!(u64))
+                            .required(false))
+                )
+                .subcommand(
+                    Command::new("kill-orphans")
+                    .about("List the Postgres processes left running without compute_ctl by previous runs")
+                    .arg(
+                        Arg::new("terminate")
+                            .help("Stop them with an immediate shutdown")
+                            .long("terminate")
+                            .action(ArgAction::SetTrue)
+                            .required(false))
+                )
+                .subcommand(
+                    Command::new("verify-availability")
+                    .about("Check that a running endpoint created with --update-catalog has the objects of compute_ctl's availability checks")
+                    .arg(endpoint_id_arg.clone())
+                )
+                .subcommand(
+                    Command::new("dump")
+                    .about("Dump a database of a running endpoint with pg_dump, and print its hash and path")
+                    .arg(endpoint_id_arg.clone())
+                    .arg(
+                        Arg::new("dest")
+                            .help("Directory to write the dump into")
+                            .long("dest")
+                            .value_parser(value_parser!(PathBuf))
+                            .required(true)
+                    )
+                    .arg(
+                        Arg::new("db")
+                            .help("Database to dump")
+                            .long("db")
+                            .default_value("postgres")
+                    )
+                    .arg(
+                        Arg::new("format")
+                            .help("pg_dump output format")
+                            .long("format")
+                            .value_parser(["plain", "custom", "directory", "tar"])
+                            .default_value("plain")
+                    )
+                    .arg(
+                        Arg::new("globals")
+                            .help("Dump roles and tablespaces with pg_dumpall instead")
+                            .long("globals")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
+                )
+                .subcommand(
+                    Command::new("generate-wal")
+                    .about("Write at least the given amount of WAL on a running primary, e.g. for benchmarks")
+                    .arg(endpoint_id_arg.clone())
+                    .arg(
+                        Arg::new("mb")
+                            .help("Megabytes of WAL to write")
+                            .long("mb")
+                            .value_parser(value_parser!(u64))
+                            .default_value("16")
+                    )
+                    .arg(
+                        Arg::new("small-transactions")
+                            .help("Insert each row in its own transaction, instead of in bulk")
+                            .long("small-transactions")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
+                )
+                .subcommand(
+                    Command::new("safekeepers")
+                    .about("Print the safekeepers the endpoint is configured with and those it uses, as JSON")
+                    .arg(endpoint_id_arg.clone())
+                )
+                .subcommand(
+                    Command::new("stats")
+                    .about("Print the statistics of the neon extension of a running endpoint as JSON")
+                    .arg(endpoint_id_arg.clone())
+                    .arg(
+                        Arg::new("watch")
+                            .help("Then print what changed every SECONDS seconds, until interrupted")
+                            .long("watch")
+                            .value_name("SECONDS")
+                            .value_parser(value_parser!(u64).range(1..))
+                            .required(false))
+                )
+                .subcommand(
+                    Command::new("set-read-only")
+                    .about("Make a running primary reject writes, until it is restarted")
+                    .arg(endpoint_id_arg.clone())
+                    .arg(
+                        Arg::new("read-only")
+                            .help("true to reject writes, false to accept them again")
+                            .value_parser(value_parser!(bool))
+                            .required(true)
+                    )
+                    .arg(
+                        Arg::new("terminate-writers")
+                            .help("Terminate the sessions in the middle of a write transaction")
+                            .long("terminate-writers")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
+                )
+                .subcommand(
+                    Command::new("snapshot")
+                    .about("Save a stopped endpoint's directory to a tar file")
+                    .arg(endpoint_id_arg.clone())
+                    .arg(
+                        Arg::new("output")
+                            .help("Path of the tar file, <endpoint_id>.tar by default")
+                            .long("output")
+                            .value_parser(value_parser!(PathBuf))
+                            .required(false)
+                    )
+                    .arg(
+                        Arg::new("include-pgdata")
+                            .help("Also save the postgres data directory")
+                            .long("include-pgdata")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
+                )
+                .subcommand(
+                    Command::new("restore")
+                    .about("Create an endpoint from a snapshot, with new ports unless --keep-ports is given")
+                    .arg(
+                        Arg::new("snapshot")
+                            .help("Tar file made by 'endpoint snapshot'")
+                            .value_parser(value_parser!(PathBuf))
+                            .required(true)
+                    )
+                    .arg(
+                        Arg::new("endpoint_id")
+                            .long("endpoint-id")
+                            .help("Id of the restored endpoint, the one in the snapshot by default")
+                            .required(false)
+                    )
+                    .arg(
+                        Arg::new("force")
+                            .help("Replace an existing stopped endpoint with the same id")
+                            .long("force")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
+                    .arg(
+                        Arg::new("keep-ports")
+                            .help("Keep the ports of the snapshot, failing if they aren't free")
+                            .long("keep-ports")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
                 )
                 .subcommand(
                     Command::new("stop")
@@ -1633,6 +2552,13 @@ fn cli() -> Command {
                             .value_parser(["smart", "fast", "immediate"])
                             .default_value("fast")
                     )
+                    .arg(
+                        Arg::new("skip-safekeeper-sync")
+                            .help("Don't let compute_ctl sync the safekeepers after postgres stops, e.g. when a majority of them is down. Without the flag, the sync is only skipped with --destroy, as before the flag existed")
+                            .long("skip-safekeeper-sync")
+                            .action(ArgAction::SetTrue)
+                            .required(false)
+                    )
                 )
 
         )
@@ -1648,6 +2574,24 @@ fn cli() -> Command {
                         .arg(timeline_id_arg.clone())
                 )
         )
+        .subcommand(
+            Command::new("token")
+                .about("Print a JWT token signed with the environment's private key")
+                .arg(Arg::new("scope").long("scope").help("Token scope, e.g. tenant or pageserverapi").required(true))
+                .arg(tenant_id_arg.clone())
+                .arg(
+                    Arg::new("endpoint-uuid")
+                        .long("endpoint-uuid")
+                        .help("Endpoint the token is for, required by the tenant_endpoint scope")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("attenuate-from")
+                        .long("attenuate-from")
+                        .help("Existing token to narrow down. The new token may not grant more than this one")
+                        .required(false),
+                )
+        )
         // Obsolete old name for 'endpoint'. We now just print an error if it's used.
         .subcommand(
             Command::new("pg")
@@ -17,18 +17,24 @@
 //! until it exits.
 //!
 //! When an endpoint is created, a `postgresql.conf` file is also created in
-//! the endpoint's directory. The file can be modified before starting PostgreSQL.
-//! However, the `postgresql.conf` file in the endpoint directory is not used directly
-//! by PostgreSQL. It is passed to `compute_ctl`, and `compute_ctl` writes another
-//! copy of it in the data directory.
+//! the endpoint's directory. It includes `neon_managed.conf` with the settings
+//! generated by neon_local, which is rewritten on every start, and can be
+//! modified to override them before starting PostgreSQL. However, the
+//! `postgresql.conf` file in the endpoint directory is not used directly by
+//! PostgreSQL. It is passed to `compute_ctl` with the includes resolved, and
+//! `compute_ctl` writes another copy of it in the data directory.
 //!
 //! Directory contents:
 //!
 //! ```text
 //! .neon/endpoints/main/
 //!     compute.log               - log output of `compute_ctl` and `postgres`
+//!     compute_ctl.log           - output of `compute_ctl` instead, with split logs
+//!     postgres.log              - log of `postgres` instead, with split logs
 //!     endpoint.json             - serialized `EndpointConf` struct
-//!     postgresql.conf           - postgresql settings
+//!     neon_managed.conf         - postgresql settings generated by neon_local, with
+//!                                 the endpoint's profiles applied
+//!     postgresql.conf           - postgresql settings, editable
 //!     spec.json                 - passed to `compute_ctl`
 //!     pgdata/
 //!         postgresql.conf       - copy of postgresql.conf created by `compute_ctl`
@@ -36,85 +42,339 @@
 //!         <other PostgreSQL files>
 //! ```
 //!
-use std::collections::BTreeMap;
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
 use std::net::TcpStream;
-use std::path::PathBuf;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
 use compute_api::spec::Database;
 use compute_api::spec::PgIdent;
 use compute_api::spec::RemoteExtSpec;
 use compute_api::spec::Role;
-use nix::sys::signal::kill;
-use nix::sys::signal::Signal;
+use futures::StreamExt;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
 use pageserver_api::shard::ShardStripeSize;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
 use url::Host;
+use utils::auth;
 use utils::id::{NodeId, TenantId, TimelineId};
+use utils::lsn::Lsn;
+use utils::pid_file::{self, PidFileRead};
+use utils::project_git_version;
 
-use crate::local_env::LocalEnv;
+use crate::background_process;
+use crate::endpoint_events::{EndpointEventKind, EndpointEventSink, EventSinks};
+use crate::endpoints_lock;
+use crate::http_hooks::{self, HttpCall, HttpHooks};
+use crate::local_env::{EndpointDefaults, LocalEnv, PageServerConf};
+use crate::pageserver::PageServerNode;
+use crate::port_registry::PortRegistry;
 use crate::postgresql_conf::PostgresConf;
 use crate::storage_controller::StorageController;
 
 use compute_api::responses::{ComputeState, ComputeStatus};
-use compute_api::spec::{Cluster, ComputeFeature, ComputeMode, ComputeSpec};
+use compute_api::spec::{
+    Cluster, ComputeFeature, ComputeMode, ComputeSpec, DeltaOp, SpecFormatVersions,
+    SPEC_FORMAT_VERSIONS,
+};
+
+project_git_version!(GIT_VERSION);
+
+/// Settings generated by neon_local, included by the endpoint's postgresql.conf.
+const MANAGED_PG_CONF: &str = "neon_managed.conf";
+
+const COMPUTE_CTL_START_TIMEOUT: Duration = Duration::from_secs(90);
+/// How long `compute_ctl` may take to exit on stop, e.g. to sync safekeepers, before it
+/// gets killed.
+const COMPUTE_CTL_STOP_TIMEOUT: Duration = Duration::from_secs(60);
+const COMPUTE_CTL_STOP_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+const COMPUTE_CTL_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const COMPUTE_CTL_CONFIGURE_TIMEOUT: Duration = Duration::from_secs(120);
+const COMPUTE_CTL_CONFIGURE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const COMPUTE_CTL_STATUS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MIN_SUSPEND_TIMEOUT: Duration = Duration::from_secs(5);
+const PAGESERVER_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const PG_CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+/// How many endpoints [`ComputeControlPlane::sample`] queries at a time.
+const SAMPLE_PARALLELISM: usize = 16;
+/// How long [`ComputeControlPlane::sample`] waits for the status of one endpoint.
+const SAMPLE_STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+/// The last [`WalSample`] of an endpoint.
+const WAL_SAMPLE: &str = "wal_sample.json";
+/// The [`TerminateLsn`]s of an endpoint, oldest first.
+const TERMINATE_LSNS: &str = "terminate_lsns.json";
+/// How many [`TerminateLsn`]s are kept.
+const TERMINATE_LSN_HISTORY: usize = 100;
+/// Marker file of an endpoint made read-only, see [`Endpoint::set_read_only`].
+const READ_ONLY_MARKER: &str = "read_only";
+/// The spec of a reconfiguration in progress, see [`Endpoint::reconfigure`].
+const PENDING_SPEC: &str = "spec.json.pending";
+/// The Postgres version the endpoint last started with, see [`Endpoint::check_pg_version`].
+const PG_VERSION_STAMP: &str = "pg_version.stamp";
+/// The combined log of `compute_ctl` and Postgres, or of Postgres alone for vanilla
+/// endpoints.
+pub const COMPUTE_LOG: &str = "compute.log";
+/// With split logs, the output of `compute_ctl`, without the Postgres log.
+pub const COMPUTE_CTL_LOG: &str = "compute_ctl.log";
+/// With split logs, the log Postgres writes itself with its logging collector, rather
+/// than to its stderr, where `compute_ctl` would forward it.
+pub const POSTGRES_LOG: &str = "postgres.log";
+/// How many lines of the logs are shown when `compute_ctl` fails to start.
+const START_FAILURE_LOG_LINES: usize = 20;
+/// Longest endpoint id, so that it still fits in a Postgres identifier.
+const MAX_ENDPOINT_ID_LEN: usize = 63;
+
+/// Check that `endpoint_id` is safe to use as a directory name, compute id, and in
+/// tokens and Postgres identifiers: lowercase letters, digits, '-' and '_' only.
+pub fn validate_endpoint_id(endpoint_id: &str) -> Result<()> {
+    if endpoint_id.is_empty() {
+        bail!("endpoint id must not be empty");
+    }
+    if endpoint_id == "." || endpoint_id == ".." {
+        bail!("endpoint id '{endpoint_id}' is reserved");
+    }
+    if endpoint_id.len() > MAX_ENDPOINT_ID_LEN {
+        bail!("endpoint id '{endpoint_id}' is longer than {MAX_ENDPOINT_ID_LEN} characters");
+    }
+    if let Some(c) = endpoint_id
+        .chars()
+        .find(|c| !matches!(c, 'a'..='z' | '0'..='9' | '-' | '_'))
+    {
+        bail!(
+            "endpoint id '{endpoint_id}' contains {c:?}, only lowercase letters, digits, '-' and '_' are allowed"
+        );
+    }
+    Ok(())
+}
+
+fn port_registry(env: &LocalEnv) -> PortRegistry {
+    PortRegistry::new(
+        &env.endpoints_path(),
+        env.endpoint_defaults.base_port(),
+        env.endpoint_defaults.port_reuse_cooldown(),
+    )
+}
+
+/// The address of `primary_id`, for a replica of `tenant_id`/`timeline_id` to stream WAL
+/// from. Read from its endpoint.json, as it can be created by another neon_local.
+fn stream_source_address(
+    env: &LocalEnv,
+    primary_id: &str,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+) -> Result<SocketAddr> {
+    let path = env.endpoints_path().join(primary_id).join("endpoint.json");
+    let (conf, _) = parse_endpoint_conf(
+        &std::fs::read(&path)
+            .with_context(|| format!("no endpoint {primary_id} to stream WAL from"))?,
+    )
+    .with_context(|| format!("failed to parse {}", path.display()))?;
+    if conf.vanilla || conf.mode != ComputeMode::Primary {
+        bail!(
+            "endpoint {primary_id} is not a primary, replicas can only stream WAL from primaries"
+        );
+    }
+    if conf.tenant_id != Some(tenant_id) || conf.timeline_id != Some(timeline_id) {
+        bail!("endpoint {primary_id} runs on another tenant or timeline");
+    }
+    Ok(SocketAddr::new(
+        IpAddr::from(Ipv4Addr::LOCALHOST),
+        conf.pg_port,
+    ))
+}
 
 // contents of a endpoint.json file
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct EndpointConf {
     endpoint_id: String,
-    tenant_id: TenantId,
-    timeline_id: TimelineId,
+    // None for vanilla endpoints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant_id: Option<TenantId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeline_id: Option<TimelineId>,
     mode: ComputeMode,
     pg_port: u16,
     http_port: u16,
     pg_version: u32,
     skip_pg_catalog_updates: bool,
     features: Vec<ComputeFeature>,
+    #[serde(default, with = "humantime_serde")]
+    suspend_timeout: Option<Duration>,
+    #[serde(default)]
+    vanilla: bool,
+    // The safekeepers of the last successful reconfiguration that changed them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_safekeepers: Option<Vec<NodeId>>,
+    // Named postgresql.conf fragments layered over the generated settings, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    profiles: Vec<String>,
+    // None for endpoints created before neon_local recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by: Option<BinaryVersion>,
+    // The primary endpoint a replica streams WAL from, instead of the safekeepers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream_from: Option<String>,
+    // Whether compute_ctl and Postgres log to separate files
+    #[serde(default)]
+    split_logs: bool,
+}
+
+/// Fields of endpoint.json that older neon_local versions wrote under another name, as
+/// (legacy, current) pairs.
+const LEGACY_CONF_FIELDS: [(&str, &str); 2] = [("name", "endpoint_id"), ("port", "pg_port")];
+
+/// Parse an endpoint.json, including the layouts written by older neon_local versions:
+/// legacy field names are translated and fields they did not write yet get the values
+/// those versions behaved as. Returns the conf and a description of each translation.
+fn parse_endpoint_conf(contents: &[u8]) -> Result<(EndpointConf, Vec<String>)> {
+    endpoint_conf_from_value(serde_json::from_slice(contents)?)
+}
+
+/// [`parse_endpoint_conf`] for an endpoint.json that was already parsed as JSON.
+fn endpoint_conf_from_value(mut value: serde_json::Value) -> Result<(EndpointConf, Vec<String>)> {
+    let mut translated = Vec::new();
+    if let Some(fields) = value.as_object_mut() {
+        for (legacy, current) in LEGACY_CONF_FIELDS {
+            let Some(v) = fields.remove(legacy) else {
+                continue;
+            };
+            if fields.contains_key(current) {
+                translated.push(format!("dropped {legacy}, {current} is set"));
+            } else {
+                fields.insert(current.to_string(), v);
+                translated.push(format!("renamed {legacy} to {current}"));
+            }
+        }
+        let missing = [
+            (
+                "skip_pg_catalog_updates",
+                serde_json::json!(EndpointDefaults::DEFAULT_SKIP_PG_CATALOG_UPDATES),
+            ),
+            ("features", serde_json::json!([])),
+        ];
+        for (field, default) in missing {
+            if !fields.contains_key(field) {
+                translated.push(format!("set missing {field} to {default}"));
+                fields.insert(field.to_string(), default);
+            }
+        }
+    }
+    Ok((serde_json::from_value(value)?, translated))
+}
+
+/// A Postgres process that runs in the data directory of an endpoint without the
+/// `compute_ctl` that started it, e.g. because neon_local or `compute_ctl` were killed.
+/// See [`ComputeControlPlane::find_orphans`].
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct OrphanProcess {
+    pub endpoint_id: String,
+    pub pid: i32,
+}
+
+/// Returned for operations that need neon storage or `compute_ctl`, when called on a
+/// vanilla endpoint.
+#[derive(Debug, thiserror::Error)]
+#[error("endpoint {endpoint_id} runs vanilla Postgres, {operation} is not supported")]
+pub struct VanillaEndpointError {
+    pub endpoint_id: String,
+    pub operation: &'static str,
+}
+
+/// Returned when configuring a replica that would stream WAL from the safekeepers, in an
+/// environment without any.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "replica endpoint {endpoint_id} needs safekeepers to stream WAL from, add safekeepers \
+     to the environment or stream directly from a primary endpoint with --stream-from"
+)]
+pub struct NoSafekeepersError {
+    pub endpoint_id: String,
 }
 
 //
 // ComputeControlPlane
 //
 pub struct ComputeControlPlane {
-    base_port: u16,
+    ports: PortRegistry,
 
     // endpoint ID is the key
     pub endpoints: BTreeMap<String, Arc<Endpoint>>,
 
     env: LocalEnv,
+
+    /// Shared with the endpoints, see the endpoint_events module
+    events: EventSinks,
 }
 
 impl ComputeControlPlane {
     // Load current endpoints from the endpoints/ subdirectories
     pub fn load(env: LocalEnv) -> Result<ComputeControlPlane> {
         let mut endpoints = BTreeMap::default();
+        let events = EventSinks::default();
         for endpoint_dir in std::fs::read_dir(env.endpoints_path())
             .with_context(|| format!("failed to list {}", env.endpoints_path().display()))?
         {
-            let ep = Endpoint::from_dir_entry(endpoint_dir?, &env)?;
+            let endpoint_dir = endpoint_dir?;
+            // Skip the port registry and lock files, and snapshots being restored
+            if !endpoint_dir.file_type()?.is_dir()
+                || endpoint_dir.file_name().to_string_lossy().starts_with('.')
+            {
+                continue;
+            }
+            if let Err(e) = validate_endpoint_id(&endpoint_dir.file_name().to_string_lossy()) {
+                eprintln!(
+                    "Skipping endpoint directory {}: {e}. Endpoints created before endpoint ids \
+                     were checked can be kept by renaming the directory, and the endpoint_id in \
+                     its endpoint.json, to a valid id while the endpoint is stopped.",
+                    endpoint_dir.path().display()
+                );
+                continue;
+            }
+            let mut ep = Endpoint::from_dir_entry(endpoint_dir, &env)?;
+            ep.events = events.clone();
             endpoints.insert(ep.endpoint_id.clone(), Arc::new(ep));
         }
 
         Ok(ComputeControlPlane {
-            base_port: 55431,
+            ports: port_registry(&env),
             endpoints,
             env,
+            events,
         })
     }
 
-    fn get_port(&mut self) -> u16 {
-        1 + self
+    /// Have `sink` told about the transitions of all endpoints of this control plane.
+    pub fn add_event_sink(&self, sink: Arc<dyn EndpointEventSink>) {
+        self.events.add(sink);
+    }
+
+    /// An endpoint reporting to the event sinks of this control plane.
+    fn endpoint_from_conf(&self, endpoint_id: String, conf: EndpointConf) -> Result<Arc<Endpoint>> {
+        let mut ep = Endpoint::from_conf(endpoint_id, conf, &self.env)?;
+        ep.events = self.events.clone();
+        Ok(Arc::new(ep))
+    }
+
+    /// Allocate a port for a new endpoint, avoiding those still used by existing or
+    /// recently destroyed endpoints.
+    fn get_port(&self, endpoint_id: &str) -> Result<u16> {
+        let live: Vec<u16> = self
             .endpoints
             .values()
-            .map(|ep| std::cmp::max(ep.pg_address.port(), ep.http_address.port()))
-            .max()
-            .unwrap_or(self.base_port)
+            .flat_map(|ep| [ep.pg_address.port(), ep.http_address.port()])
+            .collect();
+        self.ports.allocate(endpoint_id, &live)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -127,18 +387,62 @@ impl ComputeControlPlane {
         http_port: Option<u16>,
         pg_version: u32,
         mode: ComputeMode,
-        skip_pg_catalog_updates: bool,
+        skip_pg_catalog_updates: Option<bool>,
+        suspend_timeout: Option<Duration>,
+        profiles: Vec<String>,
+        stream_from: Option<String>,
+        split_logs: bool,
     ) -> Result<Arc<Endpoint>> {
-        let pg_port = pg_port.unwrap_or_else(|| self.get_port());
-        let http_port = http_port.unwrap_or_else(|| self.get_port() + 1);
-        let ep = Arc::new(Endpoint {
-            endpoint_id: endpoint_id.to_owned(),
-            pg_address: SocketAddr::new("127.0.0.1".parse().unwrap(), pg_port),
-            http_address: SocketAddr::new("127.0.0.1".parse().unwrap(), http_port),
-            env: self.env.clone(),
-            timeline_id,
+        validate_endpoint_id(endpoint_id)?;
+        validate_suspend_timeout(suspend_timeout)?;
+        match (mode, &stream_from) {
+            (ComputeMode::Replica, Some(primary_id)) => {
+                stream_source_address(&self.env, primary_id, tenant_id, timeline_id)?;
+            }
+            (ComputeMode::Replica, None) if self.env.safekeepers.is_empty() => {
+                return Err(NoSafekeepersError {
+                    endpoint_id: endpoint_id.to_string(),
+                }
+                .into());
+            }
+            (_, Some(_)) => bail!("only replicas can stream from another endpoint"),
+            _ => {}
+        }
+        let available_pg_versions = self.env.available_pg_versions();
+        if !available_pg_versions.contains(&pg_version) {
+            bail!(
+                "Postgres {pg_version} is not installed in '{}', available versions: {available_pg_versions:?}",
+                self.env.pg_distrib_dir_raw().display()
+            );
+        }
+        self.check_profiles(&profiles)?;
+        let skip_pg_catalog_updates = self
+            .env
+            .endpoint_defaults
+            .skip_pg_catalog_updates(skip_pg_catalog_updates);
+        let _lock = endpoints_lock::lock(&self.env.endpoints_path())?;
+        self.check_new_endpoint_id(endpoint_id)?;
+        let pg_port = match pg_port {
+            Some(port) => {
+                self.ports.reserve(endpoint_id, port)?;
+                port
+            }
+            None => self.get_port(endpoint_id)?,
+        };
+        let http_port = match http_port {
+            Some(port) => {
+                self.ports.reserve(endpoint_id, port)?;
+                port
+            }
+            None => self.get_port(endpoint_id)?,
+        };
+        self.create_endpoint(EndpointConf {
+            endpoint_id: endpoint_id.to_string(),
+            tenant_id: Some(tenant_id),
+            timeline_id: Some(timeline_id),
             mode,
-            tenant_id,
+            http_port,
+            pg_port,
             pg_version,
             // We don't setup roles and databases in the spec locally, so we don't need to
             // do catalog updates. Catalog updates also include check availability
@@ -148,26 +452,100 @@ impl ComputeControlPlane {
             // we also skip catalog updates in the cloud.
             skip_pg_catalog_updates,
             features: vec![],
-        });
+            suspend_timeout,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles,
+            created_by: None,
+            stream_from,
+            split_logs,
+        })
+    }
+
+    /// Create an endpoint that runs vanilla Postgres on a local data directory, without
+    /// neon storage or `compute_ctl`, e.g. to compare performance with the same harness.
+    pub fn new_vanilla_endpoint(
+        &mut self,
+        endpoint_id: &str,
+        pg_port: Option<u16>,
+        pg_version: u32,
+        profiles: Vec<String>,
+    ) -> Result<Arc<Endpoint>> {
+        validate_endpoint_id(endpoint_id)?;
+        let available_pg_versions = self.env.available_pg_versions();
+        if !available_pg_versions.contains(&pg_version) {
+            bail!(
+                "Postgres {pg_version} is not installed in '{}', available versions: {available_pg_versions:?}",
+                self.env.pg_distrib_dir_raw().display()
+            );
+        }
+        self.check_profiles(&profiles)?;
+        let _lock = endpoints_lock::lock(&self.env.endpoints_path())?;
+        self.check_new_endpoint_id(endpoint_id)?;
+        let pg_port = match pg_port {
+            Some(port) => {
+                self.ports.reserve(endpoint_id, port)?;
+                port
+            }
+            None => self.get_port(endpoint_id)?,
+        };
+        self.create_endpoint(EndpointConf {
+            endpoint_id: endpoint_id.to_string(),
+            tenant_id: None,
+            timeline_id: None,
+            mode: ComputeMode::Primary,
+            pg_port,
+            // There is no compute_ctl to talk to
+            http_port: 0,
+            pg_version,
+            skip_pg_catalog_updates: true,
+            features: vec![],
+            suspend_timeout: None,
+            vanilla: true,
+            last_safekeepers: None,
+            profiles,
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        })
+    }
+
+    /// Fail if `endpoint_id` is taken, also by endpoints created by other neon_local
+    /// invocations since this control plane was loaded. Called with the endpoints lock held.
+    fn check_new_endpoint_id(&self, endpoint_id: &str) -> Result<()> {
+        if self.env.endpoints_path().join(endpoint_id).exists() {
+            bail!("endpoint {endpoint_id} already exists");
+        }
+        Ok(())
+    }
+
+    /// Check that all `profiles` exist in the environment's profiles directory.
+    fn check_profiles(&self, profiles: &[String]) -> Result<()> {
+        let available_profiles = self.env.available_profiles();
+        for profile in profiles {
+            if !available_profiles.contains(profile) {
+                bail!(
+                    "unknown profile '{profile}' in '{}', available profiles: {available_profiles:?}",
+                    self.env.profiles_path().display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn create_endpoint(&mut self, mut conf: EndpointConf) -> Result<Arc<Endpoint>> {
+        conf.created_by = Some(BinaryVersion::current());
+        let ep = self.endpoint_from_conf(conf.endpoint_id.clone(), conf.clone())?;
 
         ep.create_endpoint_dir()?;
         std::fs::write(
             ep.endpoint_path().join("endpoint.json"),
-            serde_json::to_string_pretty(&EndpointConf {
-                endpoint_id: endpoint_id.to_string(),
-                tenant_id,
-                timeline_id,
-                mode,
-                http_port,
-                pg_port,
-                pg_version,
-                skip_pg_catalog_updates,
-                features: vec![],
-            })?,
+            serde_json::to_string_pretty(&conf)?,
         )?;
+        ep.write_managed_pg_conf()?;
         std::fs::write(
             ep.endpoint_path().join("postgresql.conf"),
-            ep.setup_pg_conf()?.to_string(),
+            Endpoint::setup_user_pg_conf(&self.env.endpoint_defaults.pg_conf).to_string(),
         )?;
 
         self.endpoints
@@ -176,6 +554,303 @@ impl ComputeControlPlane {
         Ok(ep)
     }
 
+    /// Move a stopped endpoint to a newer Postgres major version. Only the endpoint's
+    /// metadata is updated: endpoint.json, and the generated settings that depend on the
+    /// version. The data is not migrated.
+    pub fn upgrade_pg_version(&mut self, endpoint_id: &str, to: u32) -> Result<Arc<Endpoint>> {
+        let ep = self
+            .endpoints
+            .get(endpoint_id)
+            .with_context(|| format!("endpoint {endpoint_id} not found"))?;
+        if ep.status() != EndpointStatus::Stopped {
+            bail!("endpoint {endpoint_id} must be stopped to upgrade it");
+        }
+        if to <= ep.pg_version {
+            bail!(
+                "endpoint {endpoint_id} runs Postgres {}, it can only be upgraded to a newer version",
+                ep.pg_version
+            );
+        }
+        let available_pg_versions = self.env.available_pg_versions();
+        if !available_pg_versions.contains(&to) {
+            bail!(
+                "Postgres {to} is not installed in '{}', available versions: {available_pg_versions:?}",
+                self.env.pg_distrib_dir_raw().display()
+            );
+        }
+
+        let conf_path = ep.endpoint_path().join("endpoint.json");
+        let (mut conf, _) = parse_endpoint_conf(&std::fs::read(&conf_path)?)
+            .with_context(|| format!("failed to parse {}", conf_path.display()))?;
+        conf.pg_version = to;
+        let upgraded = self.endpoint_from_conf(endpoint_id.to_string(), conf.clone())?;
+        std::fs::write(&conf_path, serde_json::to_string_pretty(&conf)?)
+            .with_context(|| format!("failed to write {}", conf_path.display()))?;
+        upgraded.write_managed_pg_conf()?;
+        println!(
+            "Upgraded endpoint {endpoint_id} from Postgres {} to {to}",
+            ep.pg_version
+        );
+        self.endpoints
+            .insert(endpoint_id.to_string(), Arc::clone(&upgraded));
+        Ok(upgraded)
+    }
+
+    /// Restore an endpoint from a snapshot made by [`Endpoint::snapshot`], as `new_id` or
+    /// under its original id. The endpoint gets new ports, and its config files are
+    /// rewritten to match, unless `keep_ports` is set, and then they must be free. Returns
+    /// the ports that were moved. An existing, stopped endpoint with the same id is only
+    /// replaced if `force` is set, and is kept if the restore fails.
+    pub fn restore_snapshot(
+        &mut self,
+        snapshot: &Path,
+        new_id: Option<&str>,
+        force: bool,
+        keep_ports: bool,
+    ) -> Result<(Arc<Endpoint>, Vec<PortMapping>)> {
+        let endpoints_path = self.env.endpoints_path();
+        let _lock = endpoints_lock::lock(&endpoints_path)?;
+        let staging_path = endpoints_path.join(format!(".restore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&staging_path);
+        let staging = scopeguard::guard(staging_path, |path| {
+            let _ = std::fs::remove_dir_all(path);
+        });
+        let file = std::fs::File::open(snapshot)
+            .with_context(|| format!("failed to open snapshot {}", snapshot.display()))?;
+        tar::Archive::new(file)
+            .unpack(&*staging)
+            .with_context(|| format!("failed to unpack snapshot {}", snapshot.display()))?;
+
+        let conf_path = staging.join("endpoint.json");
+        let (mut conf, _) = parse_endpoint_conf(
+            &std::fs::read(&conf_path).context("snapshot has no endpoint.json")?,
+        )?;
+        let endpoint_id = new_id.unwrap_or(&conf.endpoint_id).to_string();
+        validate_endpoint_id(&endpoint_id)?;
+
+        // The replaced endpoint is only removed once the restored one is in place
+        let replaced_ports = match self.endpoints.get(&endpoint_id) {
+            Some(existing) => {
+                if !force {
+                    bail!("endpoint {endpoint_id} already exists, use --force to replace it");
+                }
+                if existing.status() != EndpointStatus::Stopped {
+                    bail!("endpoint {endpoint_id} is not stopped, cannot replace it");
+                }
+                endpoint_ports(existing)
+            }
+            None => {
+                // Possibly created by another neon_local since this one loaded the endpoints
+                self.check_new_endpoint_id(&endpoint_id)?;
+                Vec::new()
+            }
+        };
+
+        conf.endpoint_id = endpoint_id.clone();
+        let staged = self.stage_restore(&staging, &mut conf, keep_ports);
+        // Whatever the restore got to allocate, to release it if it fails
+        let restored_ports: Vec<u16> = [conf.pg_port, conf.http_port]
+            .into_iter()
+            .filter(|port| *port != 0 && !replaced_ports.contains(port))
+            .collect();
+        let installed = staged.and_then(|mapping| {
+            let ep = self.endpoint_from_conf(endpoint_id.clone(), conf)?;
+            self.install_restored(&staging, &ep, !replaced_ports.is_empty())?;
+            Ok((ep, mapping))
+        });
+        let (ep, mapping) = match installed {
+            Ok(installed) => installed,
+            Err(e) => {
+                if let Err(release_err) = self.ports.release_ports(&endpoint_id, &restored_ports) {
+                    eprintln!("Failed to release the ports of the failed restore: {release_err:#}");
+                }
+                return Err(e);
+            }
+        };
+        let kept_ports = endpoint_ports(&ep);
+        let stale_ports: Vec<u16> = replaced_ports
+            .into_iter()
+            .filter(|port| !kept_ports.contains(port))
+            .collect();
+        self.ports.release_ports(&endpoint_id, &stale_ports)?;
+        self.endpoints
+            .insert(ep.endpoint_id.clone(), Arc::clone(&ep));
+        Ok((ep, mapping))
+    }
+
+    /// Prepare the unpacked snapshot in `staging` for restoring as `conf.endpoint_id`: give
+    /// it its ports and clear the redacted secrets. Returns the ports that were moved.
+    fn stage_restore(
+        &self,
+        staging: &Path,
+        conf: &mut EndpointConf,
+        keep_ports: bool,
+    ) -> Result<Vec<PortMapping>> {
+        // The original ports may well be taken, here or on the machine of the snapshot
+        let mapping = if keep_ports {
+            self.check_ports_free(conf)?;
+            Vec::new()
+        } else {
+            self.remap_ports(staging, conf)?
+        };
+        let conf_path = staging.join("endpoint.json");
+        std::fs::write(&conf_path, serde_json::to_string_pretty(conf)?)
+            .with_context(|| format!("failed to write {}", conf_path.display()))?;
+        for name in ["spec.json", PENDING_SPEC] {
+            let path = staging.join(name);
+            if !path.exists() {
+                continue;
+            }
+            let mut spec: serde_json::Value = serde_json::from_slice(&std::fs::read(&path)?)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            clear_redacted_secrets(&mut spec);
+            std::fs::write(&path, serde_json::to_string_pretty(&spec)?)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+        Ok(mapping)
+    }
+
+    /// Move the restored endpoint from `staging` to the directory of `ep`. With `replace`,
+    /// the directory of the endpoint being replaced is moved aside first, and put back if
+    /// anything fails.
+    fn install_restored(&self, staging: &Path, ep: &Endpoint, replace: bool) -> Result<()> {
+        let path = ep.endpoint_path();
+        let aside = replace.then(|| {
+            self.env
+                .endpoints_path()
+                .join(format!(".replaced-{}", std::process::id()))
+        });
+        if let Some(aside) = &aside {
+            let _ = std::fs::remove_dir_all(aside);
+            std::fs::rename(&path, aside)
+                .with_context(|| format!("failed to move {} aside", path.display()))?;
+        }
+        let installed = std::fs::rename(staging, &path)
+            .with_context(|| format!("failed to move the restored endpoint to {}", path.display()))
+            .and_then(|()| {
+                ep.write_managed_pg_conf().inspect_err(|_| {
+                    let _ = std::fs::rename(&path, staging);
+                })
+            });
+        match (installed, aside) {
+            (Ok(()), Some(aside)) => {
+                if let Err(e) = std::fs::remove_dir_all(&aside) {
+                    eprintln!(
+                        "Failed to remove {} of the replaced endpoint: {e}",
+                        aside.display()
+                    );
+                }
+                Ok(())
+            }
+            (Ok(()), None) => Ok(()),
+            (Err(e), Some(aside)) => {
+                std::fs::rename(&aside, &path).with_context(|| {
+                    format!(
+                        "failed to put the replaced endpoint back from {}, after: {e:#}",
+                        aside.display()
+                    )
+                })?;
+                Err(e)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// Give the endpoint of `conf`, whose files are in `dir`, newly allocated ports. The
+    /// `port` and `listen_addresses` lines of its postgresql.conf, of the copy in the data
+    /// directory and of the config in its specs are rewritten to match. The generated
+    /// settings are left to [`Endpoint::write_managed_pg_conf`].
+    fn remap_ports(&self, dir: &Path, conf: &mut EndpointConf) -> Result<Vec<PortMapping>> {
+        let mut mapping = Vec::new();
+        let old_pg_port = conf.pg_port;
+        conf.pg_port = self.get_port(&conf.endpoint_id)?;
+        mapping.push(PortMapping {
+            name: "pg",
+            old: old_pg_port,
+            new: conf.pg_port,
+        });
+        // Vanilla endpoints have no compute_ctl to listen on a port
+        if conf.http_port != 0 {
+            let old_http_port = conf.http_port;
+            conf.http_port = self.get_port(&conf.endpoint_id)?;
+            mapping.push(PortMapping {
+                name: "http",
+                old: old_http_port,
+                new: conf.http_port,
+            });
+        }
+
+        for path in [
+            dir.join("postgresql.conf"),
+            dir.join("pgdata").join("postgresql.conf"),
+        ] {
+            if !path.exists() {
+                continue;
+            }
+            // Not PostgresConf::load, which would inline the includes
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let mut pg_conf = PostgresConf::parse(&text)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            if remap_pg_conf_port(&mut pg_conf, conf.pg_port) {
+                std::fs::write(&path, pg_conf.to_string())
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+        }
+        for name in ["spec.json", PENDING_SPEC] {
+            let path = dir.join(name);
+            if !path.exists() {
+                continue;
+            }
+            let mut spec: serde_json::Value = serde_json::from_slice(&std::fs::read(&path)?)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            let Some(serde_json::Value::String(text)) =
+                spec.pointer_mut("/cluster/postgresql_conf")
+            else {
+                continue;
+            };
+            let mut pg_conf = PostgresConf::parse(text)
+                .with_context(|| format!("failed to parse the config in {}", path.display()))?;
+            if remap_pg_conf_port(&mut pg_conf, conf.pg_port) {
+                *text = pg_conf.to_string();
+                std::fs::write(&path, serde_json::to_string_pretty(&spec)?)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// For restoring with the ports of `conf`: fail if another endpoint has them or they
+    /// can't be bound, and record them in the port registry otherwise. An endpoint with
+    /// the same id is being replaced, so its ports don't count.
+    fn check_ports_free(&self, conf: &EndpointConf) -> Result<()> {
+        let ports = [
+            (conf.pg_port, IpAddr::from(Ipv4Addr::LOCALHOST)),
+            (conf.http_port, self.env.endpoint_defaults.http_bind_ip()),
+        ];
+        for (port, ip) in ports {
+            // The HTTP port of vanilla endpoints
+            if port == 0 {
+                continue;
+            }
+            if let Some(ep) = self
+                .endpoints
+                .values()
+                .find(|ep| ep.endpoint_id != conf.endpoint_id && endpoint_ports(ep).contains(&port))
+            {
+                bail!("port {port} is used by endpoint {}", ep.endpoint_id);
+            }
+            std::net::TcpListener::bind(SocketAddr::new(ip, port))
+                .with_context(|| format!("port {port} is not free"))?;
+        }
+        for (port, _) in ports {
+            if port != 0 {
+                self.ports.reserve(&conf.endpoint_id, port)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn check_conflicting_endpoints(
         &self,
         mode: ComputeMode,
@@ -187,7 +862,8 @@ impl ComputeControlPlane {
             // creating another primary, both reading the state before checking it here,
             // but it's better than nothing.
             let mut duplicates = self.endpoints.iter().filter(|(_k, v)| {
-                v.tenant_id == tenant_id
+                !v.vanilla
+                    && v.tenant_id == tenant_id
                     && v.timeline_id == timeline_id
                     && v.mode == mode
                     && v.status() != EndpointStatus::Stopped
@@ -199,653 +875,6747 @@ impl ComputeControlPlane {
         }
         Ok(())
     }
-}
 
-///////////////////////////////////////////////////////////////////////////////
+    /// For a static endpoint, check that its LSN exists on the branch, so that a typo
+    /// doesn't show up as a basebackup error in compute.log. The check is skipped if
+    /// the branch head can't be looked up.
+    pub async fn check_static_lsn(
+        &self,
+        mode: ComputeMode,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> Result<()> {
+        let ComputeMode::Static(lsn) = mode else {
+            return Ok(());
+        };
+        match branch_head(&self.env, tenant_id, timeline_id).await {
+            Ok(head) => check_lsn_within_branch(lsn, head),
+            Err(e) => {
+                eprintln!("Not checking LSN {lsn}, failed to get the branch head: {e:#}");
+                Ok(())
+            }
+        }
+    }
 
-#[derive(Debug)]
-pub struct Endpoint {
-    /// used as the directory name
-    endpoint_id: String,
-    pub tenant_id: TenantId,
-    pub timeline_id: TimelineId,
-    pub mode: ComputeMode,
+    /// Check the status of all endpoints, up to `parallelism` at a time. Endpoints without
+    /// a postmaster.pid are not asked for their `compute_ctl` status. A check that fails
+    /// or takes longer than `per_endpoint_timeout` is an error for that endpoint only.
+    pub async fn statuses(
+        &self,
+        parallelism: usize,
+        per_endpoint_timeout: Duration,
+    ) -> BTreeMap<String, Result<EndpointStatusInfo>> {
+        futures::stream::iter(self.endpoints.values())
+            .map(|ep| async move {
+                let info = tokio::time::timeout(per_endpoint_timeout, ep.status_info())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(anyhow!(
+                            "status check timed out after {per_endpoint_timeout:?}"
+                        ))
+                    });
+                if matches!(&info, Ok(info) if info.status == EndpointStatus::Crashed) {
+                    self.events
+                        .emit(&ep.endpoint_id, EndpointEventKind::Crashed);
+                }
+                (ep.endpoint_id.clone(), info)
+            })
+            .buffer_unordered(parallelism.max(1))
+            .collect()
+            .await
+    }
 
-    // port and address of the Postgres server and `compute_ctl`'s HTTP API
-    pub pg_address: SocketAddr,
-    pub http_address: SocketAddr,
+    /// The endpoints that are running but idle, see [`EndpointActivity::is_idle`]. Endpoints
+    /// whose activity can't be told are reported and left out.
+    pub async fn idle_endpoints(
+        &self,
+        wal_bytes_per_sec: f64,
+    ) -> BTreeMap<String, EndpointActivity> {
+        let mut idle = BTreeMap::new();
+        for (endpoint_id, ep) in &self.endpoints {
+            match ep.activity().await {
+                Ok(activity) if activity.is_idle(wal_bytes_per_sec) => {
+                    idle.insert(endpoint_id.clone(), activity);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Failed to get the activity of endpoint {endpoint_id}: {e:#}"),
+            }
+        }
+        idle
+    }
 
-    // postgres major version in the format: 14, 15, etc.
-    pg_version: u32,
+    /// The status and activity of all endpoints. Those that don't run are sampled as
+    /// idle, and what can't be told for a running endpoint is left out of its sample.
+    pub async fn sample(&self) -> Vec<EndpointSample> {
+        let statuses = self
+            .statuses(SAMPLE_PARALLELISM, SAMPLE_STATUS_TIMEOUT)
+            .await;
+        futures::stream::iter(statuses)
+            .map(|(endpoint_id, status)| async move {
+                let ep = &self.endpoints[&endpoint_id];
+                let running = matches!(&status, Ok(info) if info.status == EndpointStatus::Running);
+                let (activity, lfc_hit_ratio) = if running {
+                    let activity = ep.activity().await.ok();
+                    let lfc_hit_ratio = if ep.vanilla {
+                        None
+                    } else {
+                        ep.lfc_hit_ratio().await.ok().flatten()
+                    };
+                    (activity, lfc_hit_ratio)
+                } else {
+                    (Some(EndpointActivity::default()), None)
+                };
+                EndpointSample {
+                    endpoint_id,
+                    status,
+                    activity,
+                    lfc_hit_ratio,
+                }
+            })
+            .buffered(SAMPLE_PARALLELISM)
+            .collect()
+            .await
+    }
 
-    // These are not part of the endpoint as such, but the environment
-    // the endpoint runs in.
-    pub env: LocalEnv,
+    /// [`Self::sample`] right away, then every `interval`, e.g. for a live view of the
+    /// endpoints. The WAL rates are those since the previous tick. Panics if `interval`
+    /// is zero.
+    pub fn status_stream(
+        &self,
+        interval: Duration,
+    ) -> impl futures::Stream<Item = Vec<EndpointSample>> + '_ {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        futures::stream::unfold(ticks, move |mut ticks| async move {
+            ticks.tick().await;
+            Some((self.sample().await, ticks))
+        })
+    }
 
-    // Optimizations
-    skip_pg_catalog_updates: bool,
+    /// Find the Postgres processes left running by previous runs, see [`OrphanProcess`].
+    pub fn find_orphans(&self) -> Vec<OrphanProcess> {
+        self.endpoints
+            .values()
+            .filter(|ep| !ep.vanilla)
+            .filter_map(|ep| {
+                let pid = ep.live_postmaster()?;
+                if ep.compute_ctl_running() {
+                    return None;
+                }
+                Some(OrphanProcess {
+                    endpoint_id: ep.endpoint_id.clone(),
+                    pid: pid.as_raw(),
+                })
+            })
+            .collect()
+    }
 
-    // Feature flags
-    features: Vec<ComputeFeature>,
+    /// Stop `orphan` with an immediate shutdown, and wait for it to exit. Fails without
+    /// sending a signal if the process no longer runs in the data directory of its
+    /// endpoint.
+    pub fn kill_orphan(&self, orphan: &OrphanProcess) -> Result<()> {
+        let ep = self
+            .endpoints
+            .get(&orphan.endpoint_id)
+            .with_context(|| format!("endpoint {} not found", orphan.endpoint_id))?;
+        // The process may have exited since it was found, and its pid been reused
+        let pid = Pid::from_raw(orphan.pid);
+        if ep.live_postmaster() != Some(pid) {
+            bail!(
+                "process {pid} does not run in the data directory of endpoint {}",
+                orphan.endpoint_id
+            );
+        }
+        kill(pid, Signal::SIGQUIT)
+            .with_context(|| format!("failed to stop Postgres with pid {pid}"))?;
+        background_process::wait_until_stopped("postgres", pid)
+    }
+
+    /// Start all stopped or crashed endpoints selected by `filter`, up to `parallelism`
+    /// at a time. `args_factory` provides the arguments for each endpoint's start.
+    ///
+    /// Primaries are started before any replicas, so that the replicas find them
+    /// running. A failure doesn't prevent starting the other endpoints; the result
+    /// has the outcome for each endpoint by id.
+    pub async fn start_all<P, A, Fut>(
+        &self,
+        filter: P,
+        args_factory: A,
+        parallelism: usize,
+    ) -> BTreeMap<String, Result<()>>
+    where
+        P: Fn(&Endpoint) -> bool,
+        A: Fn(&Endpoint) -> Fut,
+        Fut: Future<Output = Result<EndpointStartArgs>>,
+    {
+        let selected = self
+            .endpoints
+            .values()
+            .filter(|ep| {
+                matches!(
+                    ep.status(),
+                    EndpointStatus::Stopped | EndpointStatus::Crashed
+                ) && filter(ep)
+            })
+            .cloned()
+            .collect();
+        start_in_phases(selected, parallelism, |ep| {
+            let args = args_factory(&ep);
+            async move {
+                let args = args.await?;
+                println!("Starting existing endpoint {}...", ep.endpoint_id);
+                ep.start(&args).await
+            }
+        })
+        .await
+    }
 }
 
-#[derive(PartialEq, Eq)]
-pub enum EndpointStatus {
-    Running,
-    Stopped,
-    Crashed,
-    RunningNoPidfile,
+/// Startup metrics reported by `compute_ctl` on `/metrics.json`, saved in last_run.json.
+/// Fields that `compute_ctl` doesn't report are None.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StartMetrics {
+    #[serde(default)]
+    pub basebackup_bytes: Option<u64>,
+    #[serde(default)]
+    pub basebackup_ms: Option<u64>,
+    /// For static endpoints, the pinned LSN even if `compute_ctl` doesn't report it.
+    #[serde(default)]
+    pub basebackup_lsn: Option<Lsn>,
+    #[serde(default)]
+    pub sync_safekeepers_ms: Option<u64>,
+    #[serde(default)]
+    pub total_startup_ms: Option<u64>,
+    /// Whether the stop that followed this start skipped sync-safekeepers, None while
+    /// the endpoint runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_safekeeper_sync: Option<bool>,
+    /// The neon_local that did the start, None in files written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_by: Option<BinaryVersion>,
+    /// Measured by neon_local, None in files written before it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StartTimings>,
 }
 
-impl std::fmt::Display for EndpointStatus {
-    fn fmt(&self, writer: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let s = match self {
-            Self::Running => "running",
-            Self::Stopped => "stopped",
-            Self::Crashed => "crashed",
-            Self::RunningNoPidfile => "running, no pidfile",
-        };
-        write!(writer, "{}", s)
+impl std::fmt::Display for StartMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn show(value: Option<u64>) -> String {
+            value.map_or_else(|| "?".to_string(), |v| v.to_string())
+        }
+        write!(
+            f,
+            "basebackup {} bytes at LSN {} in {}ms, sync-safekeepers {}ms, total startup {}ms",
+            show(self.basebackup_bytes),
+            self.basebackup_lsn
+                .map_or_else(|| "?".to_string(), |lsn| lsn.to_string()),
+            show(self.basebackup_ms),
+            show(self.sync_safekeepers_ms),
+            show(self.total_startup_ms)
+        )
     }
 }
 
-impl Endpoint {
-    fn from_dir_entry(entry: std::fs::DirEntry, env: &LocalEnv) -> Result<Endpoint> {
-        if !entry.file_type()?.is_dir() {
-            anyhow::bail!(
-                "Endpoint::from_dir_entry failed: '{}' is not a directory",
-                entry.path().display()
-            );
+/// How long the phases of a start took as seen by neon_local, in milliseconds, to tell
+/// where the time went without reading compute.log.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StartTimings {
+    /// Checks, config files and clearing the data directory
+    pub prepare_ms: u64,
+    /// Building and writing spec.json
+    pub spec_ms: u64,
+    /// Until the `compute_ctl` process runs
+    pub spawn_ms: u64,
+    /// Until the HTTP API of `compute_ctl` answers
+    pub wait_for_init_ms: u64,
+    /// Until `compute_ctl` reports that Postgres is running
+    pub wait_for_running_ms: u64,
+    /// From the start of [`Endpoint::start`], including the time between the phases
+    pub total_ms: u64,
+}
+
+impl std::fmt::Display for StartTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "prepare {}ms, spec {}ms, spawn {}ms, wait for init {}ms, wait for running {}ms, total {}ms",
+            self.prepare_ms,
+            self.spec_ms,
+            self.spawn_ms,
+            self.wait_for_init_ms,
+            self.wait_for_running_ms,
+            self.total_ms
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum StartPhase {
+    Prepare,
+    Spec,
+    Spawn,
+    WaitForInit,
+    WaitForRunning,
+}
+
+impl StartPhase {
+    fn name(self) -> &'static str {
+        match self {
+            StartPhase::Prepare => "prepare",
+            StartPhase::Spec => "spec",
+            StartPhase::Spawn => "spawn",
+            StartPhase::WaitForInit => "wait_for_init",
+            StartPhase::WaitForRunning => "wait_for_running",
         }
+    }
+}
 
-        // parse data directory name
-        let fname = entry.file_name();
-        let endpoint_id = fname.to_str().unwrap().to_string();
+/// Times the phases of a start into [`StartTimings`]. Each phase is also a tracing span,
+/// entered while the phase runs and closed when it ends.
+struct StartTimer {
+    started: Instant,
+    phase: StartPhase,
+    phase_started: Instant,
+    /// The span current when the start began, the parent of the phase spans
+    parent: tracing::Span,
+    span: tracing::Span,
+    timings: StartTimings,
+}
 
-        // Read the endpoint.json file
-        let conf: EndpointConf =
-            serde_json::from_slice(&std::fs::read(entry.path().join("endpoint.json"))?)?;
+impl StartTimer {
+    fn new(endpoint_id: &str) -> Self {
+        let started = Instant::now();
+        let parent = tracing::Span::current();
+        StartTimer {
+            started,
+            phase: StartPhase::Prepare,
+            phase_started: started,
+            span: Self::phase_span(&parent, endpoint_id, StartPhase::Prepare),
+            parent,
+            timings: StartTimings::default(),
+        }
+    }
 
-        Ok(Endpoint {
-            pg_address: SocketAddr::new("127.0.0.1".parse().unwrap(), conf.pg_port),
-            http_address: SocketAddr::new("127.0.0.1".parse().unwrap(), conf.http_port),
-            endpoint_id,
-            env: env.clone(),
-            timeline_id: conf.timeline_id,
-            mode: conf.mode,
-            tenant_id: conf.tenant_id,
-            pg_version: conf.pg_version,
-            skip_pg_catalog_updates: conf.skip_pg_catalog_updates,
-            features: conf.features,
-        })
+    fn phase_span(parent: &tracing::Span, endpoint_id: &str, phase: StartPhase) -> tracing::Span {
+        tracing::info_span!(parent: parent, "endpoint_start", endpoint_id, phase = phase.name())
     }
 
-    fn create_endpoint_dir(&self) -> Result<()> {
-        std::fs::create_dir_all(self.endpoint_path()).with_context(|| {
-            format!(
-                "could not create endpoint directory {}",
-                self.endpoint_path().display()
-            )
-        })
+    /// The span of the current phase, to enter while running it.
+    fn span(&self) -> tracing::Span {
+        self.span.clone()
     }
 
-    // Generate postgresql.conf with default configuration
-    fn setup_pg_conf(&self) -> Result<PostgresConf> {
-        let mut conf = PostgresConf::new();
-        conf.append("max_wal_senders", "10");
-        conf.append("wal_log_hints", "off");
-        conf.append("max_replication_slots", "10");
-        conf.append("hot_standby", "on");
-        conf.append("shared_buffers", "1MB");
-        conf.append("fsync", "off");
-        conf.append("max_connections", "100");
-        conf.append("wal_level", "logical");
-        // wal_sender_timeout is the maximum time to wait for WAL replication.
-        // It also defines how often the walreciever will send a feedback message to the wal sender.
-        conf.append("wal_sender_timeout", "5s");
-        conf.append("listen_addresses", &self.pg_address.ip().to_string());
-        conf.append("port", &self.pg_address.port().to_string());
-        conf.append("wal_keep_size", "0");
-        // walproposer panics when basebackup is invalid, it is pointless to restart in this case.
-        conf.append("restart_after_crash", "off");
+    /// Move on to `phase`, unless the start already got there.
+    fn advance(&mut self, endpoint_id: &str, phase: StartPhase) {
+        if phase <= self.phase {
+            return;
+        }
+        self.record();
+        self.phase = phase;
+        self.phase_started = Instant::now();
+        // Replacing the span closes the one of the previous phase, once it is exited
+        self.span = Self::phase_span(&self.parent, endpoint_id, phase);
+    }
 
-        // Load the 'neon' extension
-        conf.append("shared_preload_libraries", "neon");
+    fn record(&mut self) {
+        let elapsed = self.phase_started.elapsed().as_millis() as u64;
+        let field = match self.phase {
+            StartPhase::Prepare => &mut self.timings.prepare_ms,
+            StartPhase::Spec => &mut self.timings.spec_ms,
+            StartPhase::Spawn => &mut self.timings.spawn_ms,
+            StartPhase::WaitForInit => &mut self.timings.wait_for_init_ms,
+            StartPhase::WaitForRunning => &mut self.timings.wait_for_running_ms,
+        };
+        *field = elapsed;
+    }
 
-        conf.append_line("");
-        // Replication-related configurations, such as WAL sending
-        match &self.mode {
-            ComputeMode::Primary => {
-                // Configure backpressure
-                // - Replication write lag depends on how fast the walreceiver can process incoming WAL.
-                //   This lag determines latency of get_page_at_lsn. Speed of applying WAL is about 10MB/sec,
-                //   so to avoid expiration of 1 minute timeout, this lag should not be larger than 600MB.
-                //   Actually latency should be much smaller (better if < 1sec). But we assume that recently
-                //   updates pages are not requested from pageserver.
-                // - Replication flush lag depends on speed of persisting data by checkpointer (creation of
-                //   delta/image layers) and advancing disk_consistent_lsn. Safekeepers are able to
-                //   remove/archive WAL only beyond disk_consistent_lsn. Too large a lag can cause long
-                //   recovery time (in case of pageserver crash) and disk space overflow at safekeepers.
-                // - Replication apply lag depends on speed of uploading changes to S3 by uploader thread.
-                //   To be able to restore database in case of pageserver node crash, safekeeper should not
-                //   remove WAL beyond this point. Too large lag can cause space exhaustion in safekeepers
-                //   (if they are not able to upload WAL to S3).
-                conf.append("max_replication_write_lag", "15MB");
-                conf.append("max_replication_flush_lag", "10GB");
+    fn finish(mut self) -> StartTimings {
+        self.record();
+        self.timings.total_ms = self.started.elapsed().as_millis() as u64;
+        tracing::info!(parent: &self.span, "endpoint started: {}", self.timings);
+        self.timings
+    }
+}
 
-                if !self.env.safekeepers.is_empty() {
-                    // Configure Postgres to connect to the safekeepers
-                    conf.append("synchronous_standby_names", "walproposer");
+/// The neon_local build that created or started an endpoint, to make sense of endpoint
+/// directories used by several versions.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BinaryVersion {
+    /// Version of the control_plane crate
+    pub version: String,
+    /// The commit, `git-env:` prefixed if it came from the GIT_VERSION variable of the build
+    pub git_version: String,
+}
 
-                    let safekeepers = self
-                        .env
-                        .safekeepers
-                        .iter()
-                        .map(|sk| format!("localhost:{}", sk.get_compute_port()))
-                        .collect::<Vec<String>>()
-                        .join(",");
-                    conf.append("neon.safekeepers", &safekeepers);
-                } else {
-                    // We only use setup without safekeepers for tests,
-                    // and don't care about data durability on pageserver,
-                    // so set more relaxed synchronous_commit.
-                    conf.append("synchronous_commit", "remote_write");
+impl BinaryVersion {
+    /// The version of this neon_local.
+    pub fn current() -> Self {
+        BinaryVersion {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_version: GIT_VERSION.to_string(),
+        }
+    }
 
-                    // Configure the node to stream WAL directly to the pageserver
-                    // This isn't really a supported configuration, but can be useful for
-                    // testing.
-                    conf.append("synchronous_standby_names", "pageserver");
-                }
-            }
-            ComputeMode::Static(lsn) => {
-                conf.append("recovery_target_lsn", &lsn.to_string());
-            }
-            ComputeMode::Replica => {
-                assert!(!self.env.safekeepers.is_empty());
+    /// The part of the version that changes with incompatible releases: the major
+    /// version, or the minor version before 1.0. None if the version isn't semver.
+    fn breaking_version(&self) -> Option<(u64, u64)> {
+        let mut parts = self.version.split('.').map(str::parse::<u64>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        Some(if major == 0 { (0, minor) } else { (major, 0) })
+    }
+}
 
-                // TODO: use future host field from safekeeper spec
-                // Pass the list of safekeepers to the replica so that it can connect to any of them,
-                // whichever is available.
-                let sk_ports = self
-                    .env
-                    .safekeepers
-                    .iter()
-                    .map(|x| x.get_compute_port().to_string())
-                    .collect::<Vec<_>>()
-                    .join(",");
-                let sk_hosts = vec!["localhost"; self.env.safekeepers.len()].join(",");
-
-                let connstr = format!(
-                    "host={} port={} options='-c timeline_id={} tenant_id={}' application_name=replica replication=true",
-                    sk_hosts,
-                    sk_ports,
-                    &self.timeline_id.to_string(),
-                    &self.tenant_id.to_string(),
-                );
+impl std::fmt::Display for BinaryVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.version, self.git_version)
+    }
+}
 
-                let slot_name = format!("repl_{}_", self.timeline_id);
-                conf.append("primary_conninfo", connstr.as_str());
-                conf.append("primary_slot_name", slot_name.as_str());
-                conf.append("hot_standby", "on");
-                // prefetching of blocks referenced in WAL doesn't make sense for us
-                // Neon hot standby ignores pages that are not in the shared_buffers
-                if self.pg_version >= 15 {
-                    conf.append("recovery_prefetch", "off");
+/// A warning about using `endpoint_id` with `current` if it was created by an
+/// incompatible version of neon_local.
+fn version_skew_warning(
+    endpoint_id: &str,
+    created_by: Option<&BinaryVersion>,
+    current: &BinaryVersion,
+) -> Option<String> {
+    let created_by = created_by?;
+    let (created, current_breaking) = (created_by.breaking_version()?, current.breaking_version()?);
+    (created != current_breaking).then(|| {
+        format!(
+            "endpoint {endpoint_id} was created by neon_local {created_by}, this is neon_local {current}"
+        )
+    })
+}
+
+/// A part of an [`EndpointDescription`], or why it could not be loaded.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Described<T> {
+    Loaded(T),
+    Failed { error: String },
+}
+
+impl<T> From<Result<T>> for Described<T> {
+    fn from(result: Result<T>) -> Self {
+        match result {
+            Ok(value) => Described::Loaded(value),
+            Err(e) => Described::Failed {
+                error: format!("{e:#}"),
+            },
+        }
+    }
+}
+
+/// Everything neon_local knows about an endpoint, see [`Endpoint::describe`].
+#[derive(Serialize)]
+pub struct EndpointDescription {
+    pub endpoint_id: String,
+    /// endpoint.json
+    pub conf: Described<EndpointConf>,
+    /// spec.json, with its secrets redacted
+    pub spec: Described<serde_json::Value>,
+    pub status: String,
+    /// The address `compute_ctl` listens on
+    pub http_bind_address: SocketAddr,
+    /// Reported by `compute_ctl`, only asked for while Postgres runs
+    pub compute_state: Option<Described<ComputeState>>,
+    /// last_run.json, None if the endpoint was never started
+    pub last_run: Described<Option<StartMetrics>>,
+    /// The latest of [`Endpoint::terminate_lsns`], None if there is none
+    pub last_terminate: Described<Option<TerminateLsn>>,
+    /// Size of the files in the endpoint directory, including the data directory
+    pub disk_usage_bytes: Described<u64>,
+}
+
+/// The fields of a spec.json that hold secrets, as paths into it where `*` stands for
+/// every element of an array. Every field of [`ComputeSpec`] and the types in it that
+/// looks like a secret must be listed here, or in [`SPEC_NOT_SECRETS`].
+const SPEC_SECRETS: &[&str] = &["storage_auth_token", "cluster/roles/*/encrypted_password"];
+
+/// Fields with a secret-like name that are not secrets.
+#[cfg(test)]
+const SPEC_NOT_SECRETS: &[&str] = &[];
+
+const REDACTED_PREFIX: &str = "<redacted:";
+
+/// Call `f` on every field of `value` at `path`, see [`SPEC_SECRETS`].
+fn for_each_field(
+    value: &mut serde_json::Value,
+    path: &[&str],
+    f: &mut impl FnMut(&mut serde_json::Value),
+) {
+    match path {
+        [] => f(value),
+        ["*", rest @ ..] => {
+            if let Some(elements) = value.as_array_mut() {
+                for element in elements {
+                    for_each_field(element, rest, f);
                 }
             }
         }
-
-        Ok(conf)
+        [field, rest @ ..] => {
+            if let Some(value) = value.get_mut(*field) {
+                for_each_field(value, rest, f);
+            }
+        }
     }
+}
 
-    pub fn endpoint_path(&self) -> PathBuf {
-        self.env.endpoints_path().join(&self.endpoint_id)
+/// Replace the secrets in the contents of a spec.json with a prefix of their SHA-256, so
+/// that redacted specs can still be compared with each other.
+fn redact_spec(spec: &mut serde_json::Value) {
+    for path in SPEC_SECRETS {
+        let path: Vec<&str> = path.split('/').collect();
+        for_each_field(spec, &path, &mut |value: &mut serde_json::Value| {
+            let secret = match value {
+                serde_json::Value::Null => return,
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            let hash = hex::encode(Sha256::digest(secret.as_bytes()));
+            *value = format!("{REDACTED_PREFIX}{}>", &hash[..16]).into();
+        });
     }
+}
 
-    pub fn pgdata(&self) -> PathBuf {
-        self.endpoint_path().join("pgdata")
+/// Clear the secrets that [`redact_spec`] replaced, so they aren't taken for real ones.
+fn clear_redacted_secrets(spec: &mut serde_json::Value) {
+    for path in SPEC_SECRETS {
+        let path: Vec<&str> = path.split('/').collect();
+        for_each_field(spec, &path, &mut |value: &mut serde_json::Value| {
+            if value
+                .as_str()
+                .is_some_and(|s| s.starts_with(REDACTED_PREFIX))
+            {
+                *value = serde_json::Value::Null;
+            }
+        });
     }
+}
 
-    pub fn status(&self) -> EndpointStatus {
-        let timeout = Duration::from_millis(300);
-        let has_pidfile = self.pgdata().join("postmaster.pid").exists();
-        let can_connect = TcpStream::connect_timeout(&self.pg_address, timeout).is_ok();
+/// The contents of a spec.json with `token` as the storage auth token, and everything
+/// else as it was, including fields this version of neon_local doesn't know about.
+fn patch_storage_auth_token(
+    mut spec: serde_json::Value,
+    token: Option<String>,
+) -> Result<serde_json::Value> {
+    spec.as_object_mut()
+        .context("spec is not a JSON object")?
+        .insert(
+            "storage_auth_token".to_string(),
+            token.map_or(serde_json::Value::Null, serde_json::Value::String),
+        );
+    Ok(spec)
+}
 
-        match (has_pidfile, can_connect) {
-            (true, true) => EndpointStatus::Running,
-            (false, false) => EndpointStatus::Stopped,
-            (true, false) => EndpointStatus::Crashed,
-            (false, true) => EndpointStatus::RunningNoPidfile,
+/// Add roles and databases to `cluster`, and drop databases from it. Databases must be
+/// owned by one of its roles, or by cloud_admin, which compute_ctl runs as.
+fn merge_cluster_delta(
+    cluster: &mut Cluster,
+    add_roles: Vec<Role>,
+    add_databases: Vec<Database>,
+    drop_databases: &[PgIdent],
+) -> Result<()> {
+    for role in add_roles {
+        if cluster.roles.iter().any(|r| r.name == role.name) {
+            bail!("role {} already exists", role.name);
         }
+        cluster.roles.push(role);
     }
+    for name in drop_databases {
+        let before = cluster.databases.len();
+        cluster.databases.retain(|db| &db.name != name);
+        if cluster.databases.len() == before {
+            bail!("database {name} does not exist");
+        }
+    }
+    for database in add_databases {
+        if cluster.databases.iter().any(|db| db.name == database.name) {
+            bail!("database {} already exists", database.name);
+        }
+        if database.owner != "cloud_admin"
+            && !cluster.roles.iter().any(|r| r.name == database.owner)
+        {
+            bail!(
+                "owner {} of database {} is not a role of the endpoint",
+                database.owner,
+                database.name
+            );
+        }
+        cluster.databases.push(database);
+    }
+    Ok(())
+}
 
-    fn pg_ctl(&self, args: &[&str], auth_token: &Option<String>) -> Result<()> {
-        let pg_ctl_path = self.env.pg_bin_dir(self.pg_version)?.join("pg_ctl");
-        let mut cmd = Command::new(&pg_ctl_path);
-        cmd.args(
-            [
-                &[
-                    "-D",
-                    self.pgdata().to_str().unwrap(),
-                    "-w", //wait till pg_ctl actually does what was asked
-                ],
-                args,
-            ]
-            .concat(),
-        )
-        .env_clear()
-        .env(
-            "LD_LIBRARY_PATH",
-            self.env.pg_lib_dir(self.pg_version)?.to_str().unwrap(),
-        )
-        .env(
-            "DYLD_LIBRARY_PATH",
-            self.env.pg_lib_dir(self.pg_version)?.to_str().unwrap(),
-        );
+/// A warning if the storage auth token runs out before the compute would suspend: the
+/// compute can't reach the pageservers and safekeepers after that. `now` is in seconds
+/// since the epoch.
+fn storage_token_expiry_warning(
+    token: &str,
+    suspend_timeout: Option<Duration>,
+    now: u64,
+) -> Option<String> {
+    let lifetime = Duration::from_secs(auth::unverified_expiry(token)?.saturating_sub(now));
+    let suspends = match suspend_timeout {
+        Some(timeout) if lifetime >= timeout => return None,
+        Some(timeout) => format!("suspends after {}", humantime::format_duration(timeout)),
+        None => "never suspends".to_string(),
+    };
+    Some(format!(
+        "the storage auth token expires in {}, but the compute {suspends}. Rotate it with 'neon_local endpoint rotate-token'",
+        humantime::format_duration(lifetime)
+    ))
+}
 
-        // Pass authentication token used for the connections to pageserver and safekeepers
-        if let Some(token) = auth_token {
-            cmd.env("NEON_AUTH_TOKEN", token);
+/// Total size of the files under `path`.
+fn disk_usage(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            total += disk_usage(&entry.path())?;
+        } else if file_type.is_file() {
+            total += entry.metadata()?.len();
         }
+    }
+    Ok(total)
+}
 
-        let pg_ctl = cmd
-            .output()
-            .context(format!("{} failed", pg_ctl_path.display()))?;
-        if !pg_ctl.status.success() {
-            anyhow::bail!(
-                "pg_ctl failed, exit code: {}, stdout: {}, stderr: {}",
-                pg_ctl.status,
-                String::from_utf8_lossy(&pg_ctl.stdout),
-                String::from_utf8_lossy(&pg_ctl.stderr),
-            );
+/// The pageserver of one shard.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShardPageserver {
+    /// None if the URL matches none of the environment's pageservers
+    pub node_id: Option<NodeId>,
+    pub url: String,
+}
+
+impl std::fmt::Display for ShardPageserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.node_id {
+            Some(node_id) => write!(f, "ps {node_id}"),
+            None => f.write_str(&self.url),
         }
+    }
+}
 
-        Ok(())
+/// The pageservers of an endpoint's shards, in shard order, as given to the compute
+/// in the `pageserver_connstring` of its spec.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageserverConnInfo {
+    pub shards: Vec<ShardPageserver>,
+}
+
+impl PageserverConnInfo {
+    /// Parse the comma-separated URLs of a `pageserver_connstring`. The pageservers
+    /// are recognized by their libpq port, which is unique within an environment.
+    pub fn parse(connstring: &str, pageservers: &[PageServerConf]) -> Self {
+        let shards = connstring
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| {
+                let port = url::Url::parse(url).ok().and_then(|url| url.port());
+                let node_id = pageservers
+                    .iter()
+                    .find(|ps| port.is_some() && ps.pg_host_port().ok().map(|(_, p)| p) == port)
+                    .map(|ps| ps.id);
+                ShardPageserver {
+                    node_id,
+                    url: url.to_string(),
+                }
+            })
+            .collect();
+        PageserverConnInfo { shards }
     }
+}
 
-    fn wait_for_compute_ctl_to_exit(&self, send_sigterm: bool) -> Result<()> {
-        // TODO use background_process::stop_process instead: https://github.com/neondatabase/neon/pull/6482
-        let pidfile_path = self.endpoint_path().join("compute_ctl.pid");
-        let pid: u32 = std::fs::read_to_string(pidfile_path)?.parse()?;
-        let pid = nix::unistd::Pid::from_raw(pid as i32);
-        if send_sigterm {
-            kill(pid, Signal::SIGTERM).ok();
-        }
-        crate::background_process::wait_until_stopped("compute_ctl", pid)?;
-        Ok(())
+/// One line per shard, like `shard 0001: ps 2 postgresql://no_user@127.0.0.1:64001`.
+pub fn format_conninfo(conninfo: &PageserverConnInfo) -> String {
+    conninfo
+        .shards
+        .iter()
+        .enumerate()
+        .map(|(shard, ps)| match ps.node_id {
+            Some(node_id) => format!("shard {shard:04}: ps {node_id} {}", ps.url),
+            None => format!("shard {shard:04}: {}", ps.url),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A shard whose pageserver changed. `old` is None for a new shard, `new` for a
+/// shard that is gone.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShardChange {
+    pub shard: usize,
+    pub old: Option<ShardPageserver>,
+    pub new: Option<ShardPageserver>,
+}
+
+/// The difference between two [`PageserverConnInfo`], see [`diff_conninfo`].
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnInfoDiff {
+    pub old_shard_count: usize,
+    pub new_shard_count: usize,
+    pub changed: Vec<ShardChange>,
+}
+
+impl ConnInfoDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty()
     }
+}
 
-    fn read_postgresql_conf(&self) -> Result<String> {
-        // Slurp the endpoints/<endpoint id>/postgresql.conf file into
-        // memory. We will include it in the spec file that we pass to
-        // `compute_ctl`, and `compute_ctl` will write it to the postgresql.conf
-        // in the data directory.
-        let postgresql_conf_path = self.endpoint_path().join("postgresql.conf");
-        match std::fs::read(&postgresql_conf_path) {
-            Ok(content) => Ok(String::from_utf8(content)?),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok("".to_string()),
-            Err(e) => Err(anyhow::Error::new(e).context(format!(
-                "failed to read config file in {}",
-                postgresql_conf_path.to_str().unwrap()
-            ))),
+impl std::fmt::Display for ConnInfoDiff {
+    /// One line summary, like `shard count 1 -> 2, shard 0000: ps 1 -> ps 4, shard 0001: none -> ps 2`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("no changes");
+        }
+        let mut changes = Vec::new();
+        if self.old_shard_count != self.new_shard_count {
+            changes.push(format!(
+                "shard count {} -> {}",
+                self.old_shard_count, self.new_shard_count
+            ));
+        }
+        let label = |ps: &Option<ShardPageserver>| match ps {
+            Some(ps) => ps.to_string(),
+            None => "none".to_string(),
+        };
+        for change in &self.changed {
+            let (mut old, mut new) = (label(&change.old), label(&change.new));
+            if old == new {
+                // Same pageserver at another URL
+                old = change.old.as_ref().map_or(old, |ps| ps.url.clone());
+                new = change.new.as_ref().map_or(new, |ps| ps.url.clone());
+            }
+            changes.push(format!("shard {:04}: {old} -> {new}", change.shard));
         }
+        f.write_str(&changes.join(", "))
     }
+}
 
-    fn build_pageserver_connstr(pageservers: &[(Host, u16)]) -> String {
-        pageservers
-            .iter()
-            .map(|(host, port)| format!("postgresql://no_user@{host}:{port}"))
-            .collect::<Vec<_>>()
-            .join(",")
+/// The shards whose pageserver differs between `old` and `new`, including shards
+/// added or removed by a change of the shard count.
+pub fn diff_conninfo(old: &PageserverConnInfo, new: &PageserverConnInfo) -> ConnInfoDiff {
+    let shard_count = old.shards.len().max(new.shards.len());
+    let changed = (0..shard_count)
+        .filter_map(|shard| {
+            let old = old.shards.get(shard).cloned();
+            let new = new.shards.get(shard).cloned();
+            (old != new).then_some(ShardChange { shard, old, new })
+        })
+        .collect();
+    ConnInfoDiff {
+        old_shard_count: old.shards.len(),
+        new_shard_count: new.shards.len(),
+        changed,
     }
+}
 
-    /// Map safekeepers ids to the actual connection strings.
-    fn build_safekeepers_connstrs(&self, sk_ids: Vec<NodeId>) -> Result<Vec<String>> {
-        let mut safekeeper_connstrings = Vec::new();
-        if self.mode == ComputeMode::Primary {
-            for sk_id in sk_ids {
-                let sk = self
-                    .env
-                    .safekeepers
-                    .iter()
-                    .find(|node| node.id == sk_id)
-                    .ok_or_else(|| anyhow!("safekeeper {sk_id} does not exist"))?;
-                safekeeper_connstrings.push(format!("127.0.0.1:{}", sk.get_compute_port()));
-            }
+/// The last record LSN of a timeline, from the pageserver of its first shard.
+async fn branch_head(env: &LocalEnv, tenant_id: TenantId, timeline_id: TimelineId) -> Result<Lsn> {
+    let locate = StorageController::from_env(env)
+        .tenant_locate(tenant_id)
+        .await?;
+    let shard = locate
+        .shards
+        .first()
+        .with_context(|| format!("tenant {tenant_id} has no shards"))?;
+    let pageserver = PageServerNode::from_env(env, env.get_pageserver_conf(shard.node_id)?);
+    let timeline = pageserver
+        .timeline_list(&shard.shard_id)
+        .await?
+        .into_iter()
+        .find(|timeline| timeline.timeline_id == timeline_id)
+        .with_context(|| format!("timeline {timeline_id} not found"))?;
+    Ok(timeline.last_record_lsn)
+}
+
+fn check_lsn_within_branch(requested: Lsn, branch_head: Lsn) -> Result<()> {
+    if requested > branch_head {
+        bail!("requested LSN {requested} is beyond branch head {branch_head}, use --force to create the endpoint anyway");
+    }
+    Ok(())
+}
+
+/// Output format of [`Endpoint::dump`], see `pg_dump --format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    Plain,
+    Custom,
+    Directory,
+    Tar,
+}
+
+impl DumpFormat {
+    fn flag(self) -> &'static str {
+        match self {
+            DumpFormat::Plain => "plain",
+            DumpFormat::Custom => "custom",
+            DumpFormat::Directory => "directory",
+            DumpFormat::Tar => "tar",
         }
-        Ok(safekeeper_connstrings)
     }
 
-    pub async fn start(
-        &self,
-        auth_token: &Option<String>,
-        safekeepers: Vec<NodeId>,
-        pageservers: Vec<(Host, u16)>,
-        remote_ext_config: Option<&String>,
-        shard_stripe_size: usize,
-        create_test_user: bool,
-    ) -> Result<()> {
-        if self.status() == EndpointStatus::Running {
-            anyhow::bail!("The endpoint is already running");
+    fn extension(self) -> &'static str {
+        match self {
+            DumpFormat::Plain => ".sql",
+            DumpFormat::Custom => ".dump",
+            DumpFormat::Directory => "",
+            DumpFormat::Tar => ".tar",
         }
+    }
+}
 
-        let postgresql_conf = self.read_postgresql_conf()?;
+impl FromStr for DumpFormat {
+    type Err = anyhow::Error;
 
-        // We always start the compute node from scratch, so if the Postgres
-        // data dir exists from a previous launch, remove it first.
-        if self.pgdata().exists() {
-            std::fs::remove_dir_all(self.pgdata())?;
+    fn from_str(s: &str) -> Result<Self> {
+        [
+            DumpFormat::Plain,
+            DumpFormat::Custom,
+            DumpFormat::Directory,
+            DumpFormat::Tar,
+        ]
+        .into_iter()
+        .find(|format| format.flag() == s)
+        .ok_or_else(|| anyhow!("unknown dump format '{s}'"))
+    }
+}
+
+/// A dump made by [`Endpoint::dump`] or [`Endpoint::dump_all`].
+#[derive(Debug)]
+pub struct Dump {
+    /// The file, or directory for [`DumpFormat::Directory`]
+    pub path: PathBuf,
+    /// Hex SHA-256 of the contents, for comparing dumps
+    pub sha256: String,
+}
+
+/// Hash a dump file, or the names and contents of the files of a dump directory.
+fn hash_dump(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        let mut files = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.sort();
+        for file in files {
+            hasher.update(file.file_name().unwrap().as_encoded_bytes());
+            hasher.update(std::fs::read(&file)?);
         }
+    } else {
+        hasher.update(
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?,
+        );
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
 
-        let pageserver_connstring = Self::build_pageserver_connstr(&pageservers);
-        assert!(!pageserver_connstring.is_empty());
+/// How [`Endpoint::generate_wal`] writes its rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalWorkload {
+    /// Each batch of rows is a single INSERT ... SELECT, producing few large records
+    Bulk,
+    /// Each row is inserted in a transaction of its own, producing many small
+    /// records and commit records
+    SmallTransactions,
+}
 
-        let safekeeper_connstrings = self.build_safekeepers_connstrs(safekeepers)?;
+pub struct GenerateWalOptions {
+    pub workload: WalWorkload,
+    /// Rows written between checks of the WAL position
+    pub batch_rows: usize,
+}
 
-        // check for file remote_extensions_spec.json
-        // if it is present, read it and pass to compute_ctl
-        let remote_extensions_spec_path = self.endpoint_path().join("remote_extensions_spec.json");
-        let remote_extensions_spec = std::fs::File::open(remote_extensions_spec_path);
-        let remote_extensions: Option<RemoteExtSpec>;
+impl Default for GenerateWalOptions {
+    fn default() -> Self {
+        GenerateWalOptions {
+            workload: WalWorkload::Bulk,
+            batch_rows: 10_000,
+        }
+    }
+}
 
-        if let Ok(spec_file) = remote_extensions_spec {
-            remote_extensions = serde_json::from_reader(spec_file).ok();
-        } else {
-            remote_extensions = None;
-        };
+/// The WAL written by [`Endpoint::generate_wal`].
+#[derive(Debug)]
+pub struct GeneratedWal {
+    pub start_lsn: Lsn,
+    pub end_lsn: Lsn,
+    pub elapsed: Duration,
+}
 
-        // Create spec file
-        let spec = ComputeSpec {
-            skip_pg_catalog_updates: self.skip_pg_catalog_updates,
-            format_version: 1.0,
-            operation_uuid: None,
-            features: self.features.clone(),
-            swap_size_bytes: None,
-            cluster: Cluster {
-                cluster_id: None, // project ID: not used
-                name: None,       // project name: not used
-                state: None,
-                roles: if create_test_user {
-                    vec![Role {
-                        name: PgIdent::from_str("test").unwrap(),
-                        encrypted_password: None,
-                        options: None,
-                    }]
-                } else {
-                    Vec::new()
-                },
-                databases: if create_test_user {
-                    vec![Database {
-                        name: PgIdent::from_str("neondb").unwrap(),
-                        owner: PgIdent::from_str("test").unwrap(),
-                        options: None,
-                        restrict_conn: false,
-                        invalid: false,
-                    }]
-                } else {
-                    Vec::new()
-                },
-                settings: None,
-                postgresql_conf: Some(postgresql_conf),
-            },
-            delta_operations: None,
-            tenant_id: Some(self.tenant_id),
-            timeline_id: Some(self.timeline_id),
-            mode: self.mode,
-            pageserver_connstring: Some(pageserver_connstring),
-            safekeeper_connstrings,
-            storage_auth_token: auth_token.clone(),
-            remote_extensions,
-            pgbouncer_settings: None,
-            shard_stripe_size: Some(shard_stripe_size),
-        };
-        let spec_path = self.endpoint_path().join("spec.json");
-        std::fs::write(spec_path, serde_json::to_string_pretty(&spec)?)?;
+/// The SQL that writes one batch of rows of `workload` into `table`.
+fn wal_batch_sql(table: &str, workload: WalWorkload, rows: usize) -> String {
+    match workload {
+        WalWorkload::Bulk => format!(
+            "INSERT INTO {table} SELECT g, repeat('x', 100) FROM generate_series(1, {rows}) g"
+        ),
+        WalWorkload::SmallTransactions => {
+            // Statements of a multi-statement query run in one transaction unless
+            // they are delimited explicitly
+            format!("BEGIN; INSERT INTO {table} VALUES (1, repeat('x', 100)); COMMIT;").repeat(rows)
+        }
+    }
+}
 
-        // Open log file. We'll redirect the stdout and stderr of `compute_ctl` to it.
-        let logfile = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(self.endpoint_path().join("compute.log"))?;
+/// The safekeepers of an endpoint, see [`Endpoint::safekeeper_membership`].
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct SafekeeperMembership {
+    /// Connection strings in spec.json, as of the last start or reconfiguration
+    pub configured: Vec<String>,
+    /// Connection strings the compute uses, None if it isn't running
+    pub running: Option<Vec<String>>,
+    /// The compute doesn't use the configured safekeepers
+    pub diverged: bool,
+}
 
-        // Launch compute_ctl
-        let conn_str = self.connstr("cloud_admin", "postgres");
-        println!("Starting postgres node at '{}'", conn_str);
-        if create_test_user {
-            let conn_str = self.connstr("test", "neondb");
-            println!("Also at '{}'", conn_str);
-        }
-        let mut cmd = Command::new(self.env.neon_distrib_dir.join("compute_ctl"));
-        cmd.args(["--http-port", &self.http_address.port().to_string()])
-            .args(["--pgdata", self.pgdata().to_str().unwrap()])
-            .args(["--connstr", &conn_str])
-            .args([
-                "--spec-path",
-                self.endpoint_path().join("spec.json").to_str().unwrap(),
-            ])
-            .args([
-                "--pgbin",
-                self.env
-                    .pg_bin_dir(self.pg_version)?
-                    .join("postgres")
-                    .to_str()
-                    .unwrap(),
-            ])
-            .stdin(std::process::Stdio::null())
-            .stderr(logfile.try_clone()?)
-            .stdout(logfile);
-
-        if let Some(remote_ext_config) = remote_ext_config {
-            cmd.args(["--remote-ext-config", remote_ext_config]);
-        }
-
-        let child = cmd.spawn()?;
-        // set up a scopeguard to kill & wait for the child in case we panic or bail below
-        let child = scopeguard::guard(child, |mut child| {
-            println!("SIGKILL & wait the started process");
-            (|| {
-                // TODO: use another signal that can be caught by the child so it can clean up any children it spawned
-                child.kill().context("SIGKILL child")?;
-                child.wait().context("wait() for child process")?;
-                anyhow::Ok(())
-            })()
-            .with_context(|| format!("scopeguard kill&wait child {child:?}"))
-            .unwrap();
+impl SafekeeperMembership {
+    fn new(configured: Vec<String>, running: Option<Vec<String>>) -> Self {
+        let diverged = running.as_ref().is_some_and(|running| {
+            let mut running = running.clone();
+            running.sort();
+            let mut configured = configured.clone();
+            configured.sort();
+            running != configured
         });
+        SafekeeperMembership {
+            configured,
+            running,
+            diverged,
+        }
+    }
+}
 
-        // Write down the pid so we can wait for it when we want to stop
-        // TODO use background_process::start_process instead: https://github.com/neondatabase/neon/pull/6482
-        let pid = child.id();
-        let pidfile_path = self.endpoint_path().join("compute_ctl.pid");
-        std::fs::write(pidfile_path, pid.to_string())?;
+/// Returned by [`Endpoint::neon_stats`] for computes without the neon extension.
+#[derive(Debug, thiserror::Error)]
+#[error("the neon extension is not installed in endpoint {endpoint_id}")]
+pub struct NeonExtensionMissing {
+    pub endpoint_id: String,
+}
 
-        // Wait for it to start
-        let mut attempt = 0;
-        const ATTEMPT_INTERVAL: Duration = Duration::from_millis(100);
-        const MAX_ATTEMPTS: u32 = 10 * 90; // Wait up to 1.5 min
-        loop {
-            attempt += 1;
-            match self.get_status().await {
-                Ok(state) => {
-                    match state.status {
-                        ComputeStatus::Init => {
-                            if attempt == MAX_ATTEMPTS {
-                                bail!("compute startup timed out; still in Init state");
-                            }
-                            // keep retrying
-                        }
-                        ComputeStatus::Running => {
-                            // All good!
-                            break;
-                        }
-                        ComputeStatus::Failed => {
-                            bail!(
-                                "compute startup failed: {}",
-                                state
-                                    .error
-                                    .as_deref()
-                                    .unwrap_or("<no error from compute_ctl>")
-                            );
-                        }
-                        ComputeStatus::Empty
-                        | ComputeStatus::ConfigurationPending
-                        | ComputeStatus::Configuration
-                        | ComputeStatus::TerminationPending
-                        | ComputeStatus::Terminated => {
-                            bail!("unexpected compute status: {:?}", state.status)
-                        }
+/// Counters of the local file cache, from `neon_get_lfc_stats()`.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LfcStats {
+    pub hits: i64,
+    pub misses: i64,
+    /// Pages in the cache, not a counter
+    pub used: i64,
+    pub writes: i64,
+    /// Percentage of the reads served by the cache, None without any reads
+    pub hit_ratio: Option<f64>,
+}
+
+impl LfcStats {
+    fn new(hits: i64, misses: i64, used: i64, writes: i64) -> Self {
+        let reads = hits + misses;
+        LfcStats {
+            hits,
+            misses,
+            used,
+            writes,
+            hit_ratio: (reads > 0).then(|| hits as f64 * 100.0 / reads as f64),
+        }
+    }
+}
+
+/// Statistics of the neon extension of a running compute, see [`Endpoint::neon_stats`].
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NeonStats {
+    pub extension_version: String,
+    /// None if the extension is too old to report them, or the cache is disabled
+    pub lfc: Option<LfcStats>,
+    /// Time backends waited for the pageserver and safekeepers to catch up
+    pub backpressure_throttling_us: i64,
+}
+
+impl NeonStats {
+    /// Build the statistics from the query results: `lfc_rows` are the rows of
+    /// `neon_get_lfc_stats()`, None if the extension doesn't have it.
+    fn parse(
+        extension_version: String,
+        lfc_rows: Option<&[(String, Option<i64>)]>,
+        backpressure_throttling_us: i64,
+    ) -> Result<Self> {
+        let lfc = match lfc_rows {
+            Some(rows) => {
+                let get = |key: &str| -> Result<Option<i64>> {
+                    rows.iter()
+                        .find(|(name, _)| name == key)
+                        .map(|(_, value)| *value)
+                        .with_context(|| format!("neon_get_lfc_stats() did not report {key}"))
+                };
+                // The values are NULL when the cache is disabled
+                match (
+                    get("file_cache_hits")?,
+                    get("file_cache_misses")?,
+                    get("file_cache_used")?,
+                    get("file_cache_writes")?,
+                ) {
+                    (Some(hits), Some(misses), Some(used), Some(writes)) => {
+                        Some(LfcStats::new(hits, misses, used, writes))
                     }
+                    _ => None,
                 }
-                Err(e) => {
-                    if attempt == MAX_ATTEMPTS {
-                        return Err(e).context("timed out waiting to connect to compute_ctl HTTP");
-                    }
+            }
+            None => None,
+        };
+        Ok(NeonStats {
+            extension_version,
+            lfc,
+            backpressure_throttling_us,
+        })
+    }
+
+    /// What changed since `earlier`: the counters accumulated since, with the hit ratio
+    /// over that time.
+    pub fn since(&self, earlier: &NeonStats) -> NeonStats {
+        let lfc = match (self.lfc, earlier.lfc) {
+            (Some(now), Some(then)) => Some(LfcStats::new(
+                now.hits - then.hits,
+                now.misses - then.misses,
+                now.used,
+                now.writes - then.writes,
+            )),
+            (now, _) => now,
+        };
+        NeonStats {
+            extension_version: self.extension_version.clone(),
+            lfc,
+            backpressure_throttling_us: self.backpressure_throttling_us
+                - earlier.backpressure_throttling_us,
+        }
+    }
+}
+
+impl std::fmt::Display for NeonStats {
+    /// One line summary, like `lfc: 90 hits, 10 misses (90.00% hit ratio), 100 pages used, 5 writes; backpressure throttling: 0us`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.lfc {
+            Some(lfc) => {
+                write!(f, "lfc: {} hits, {} misses", lfc.hits, lfc.misses)?;
+                if let Some(hit_ratio) = lfc.hit_ratio {
+                    write!(f, " ({hit_ratio:.2}% hit ratio)")?;
                 }
+                write!(f, ", {} pages used, {} writes", lfc.used, lfc.writes)?;
             }
-            std::thread::sleep(ATTEMPT_INTERVAL);
+            None => f.write_str("lfc: unavailable")?,
         }
+        write!(
+            f,
+            "; backpressure throttling: {}us",
+            self.backpressure_throttling_us
+        )
+    }
+}
 
-        // disarm the scopeguard, let the child outlive this function (and neon_local invoction)
-        drop(scopeguard::ScopeGuard::into_inner(child));
+/// Whether `version` of the neon extension, like "1.3", is `at_least` (major, minor).
+fn neon_extension_version_at_least(version: &str, at_least: (u32, u32)) -> Result<bool> {
+    let parse = || -> Option<(u32, u32)> {
+        let (major, minor) = version.split_once('.')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    };
+    let parsed = parse().with_context(|| format!("invalid neon extension version '{version}'"))?;
+    Ok(parsed >= at_least)
+}
 
-        Ok(())
+/// A WAL position of a compute at some point in time. [`Endpoint::activity`] keeps the
+/// last one in [`WAL_SAMPLE`], to tell the WAL rate since.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalSample {
+    pub lsn: Lsn,
+    /// Milliseconds since the epoch
+    pub timestamp_ms: u64,
+}
+
+impl WalSample {
+    /// Bytes of WAL written per second since `earlier`. None if no time has passed, or
+    /// if the WAL position went back, e.g. because the endpoint was recreated.
+    pub fn rate_since(&self, earlier: &WalSample) -> Option<f64> {
+        let bytes = self.lsn.0.checked_sub(earlier.lsn.0)?;
+        let millis = self.timestamp_ms.checked_sub(earlier.timestamp_ms)?;
+        (millis > 0).then(|| bytes as f64 * 1000.0 / millis as f64)
     }
 
-    // Call the /status HTTP API
-    pub async fn get_status(&self) -> Result<ComputeState> {
-        let client = reqwest::Client::new();
+    /// The sample saved at `path`, None if there is none yet or it can't be read.
+    fn load(path: &Path) -> Option<WalSample> {
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+    }
 
-        let response = client
-            .request(
-                reqwest::Method::GET,
-                format!(
-                    "http://{}:{}/status",
-                    self.http_address.ip(),
-                    self.http_address.port()
-                ),
+    fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// What a compute is doing, see [`Endpoint::activity`].
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct EndpointActivity {
+    /// False for stopped endpoints, which report nothing else
+    pub running: bool,
+    /// Sessions of clients, leaving out those of compute_ctl and its monitors
+    pub client_backends: i64,
+    /// Of the transaction open the longest, in seconds
+    pub oldest_xact_age_secs: Option<f64>,
+    /// Since the previous call of [`Endpoint::activity`], None on the first one
+    pub wal_bytes_per_sec: Option<f64>,
+}
+
+/// Where the WAL of an endpoint ended when it was stopped, see [`Endpoint::terminate_lsns`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TerminateLsn {
+    /// Of the shutdown checkpoint
+    pub lsn: Lsn,
+    /// Milliseconds since the epoch
+    pub timestamp_ms: u64,
+    /// The pg_ctl stop mode: smart, fast or immediate
+    pub mode: String,
+}
+
+/// The location of the shutdown checkpoint in the output of pg_controldata, None if
+/// Postgres wasn't shut down cleanly, which is the case after an immediate stop.
+fn shutdown_checkpoint_lsn(controldata: &str) -> Result<Option<Lsn>> {
+    let field = |name: &str| controldata_field(controldata, name);
+    let state = field("Database cluster state").context("pg_controldata shows no cluster state")?;
+    // "shut down in recovery" for replicas
+    if !state.starts_with("shut down") {
+        return Ok(None);
+    }
+    let lsn = field("Latest checkpoint location")
+        .context("pg_controldata shows no checkpoint location")?;
+    Ok(Some(Lsn::from_str(lsn).map_err(|_| {
+        anyhow!("invalid checkpoint location {lsn}")
+    })?))
+}
+
+/// A port that [`ComputeControlPlane::restore_snapshot`] moved.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortMapping {
+    /// "pg" or "http"
+    pub name: &'static str,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// The ports `ep` listens on. Vanilla endpoints have no HTTP port.
+fn endpoint_ports(ep: &Endpoint) -> Vec<u16> {
+    let mut ports = vec![ep.pg_address.port()];
+    if !ep.vanilla {
+        ports.push(ep.http_address.port());
+    }
+    ports
+}
+
+/// Point the `port` and `listen_addresses` lines of `conf`, if it has them, at the
+/// address neon_local gives an endpoint with `pg_port`. Returns whether there were any.
+fn remap_pg_conf_port(conf: &mut PostgresConf, pg_port: u16) -> bool {
+    let mut changed = false;
+    if conf.get("port").is_some() {
+        conf.set("port", &pg_port.to_string());
+        changed = true;
+    }
+    if conf.get("listen_addresses").is_some() {
+        conf.set("listen_addresses", &Ipv4Addr::LOCALHOST.to_string());
+        changed = true;
+    }
+    changed
+}
+
+/// The timestamp a log line starts with, in the format of `compute_ctl` or of Postgres,
+/// also behind the "PG:" prefix of Postgres lines forwarded by `compute_ctl`. Both are
+/// taken to be in UTC.
+fn log_timestamp(line: &str) -> Option<SystemTime> {
+    static TIMESTAMP: Lazy<regex::Regex> = Lazy::new(|| {
+        regex::Regex::new(r"^(?:PG:)?(\d{4}-\d{2}-\d{2}[ T]\d{2}:\d{2}:\d{2}(?:\.\d+)?)")
+            .expect("regex is valid")
+    });
+    let timestamp = TIMESTAMP.captures(line)?.get(1)?.as_str();
+    humantime::parse_rfc3339_weak(timestamp).ok()
+}
+
+/// The last `lines` lines of `logs`, merged by timestamp. Lines without one, like the
+/// continuations of multi-line messages, stay after the line before them. Each log is
+/// taken to be in order, and on equal timestamps the earlier log goes first.
+fn merge_log_tails(logs: &[String], lines: usize) -> Vec<String> {
+    let mut tails: Vec<_> = logs
+        .iter()
+        .map(|log| {
+            let mut timestamp = None;
+            let mut timestamped: Vec<(Option<SystemTime>, &str)> = log
+                .lines()
+                .map(|line| {
+                    timestamp = log_timestamp(line).or(timestamp);
+                    (timestamp, line)
+                })
+                .collect();
+            // The merged tail can't have more lines of any one log
+            let skip = timestamped.len().saturating_sub(lines);
+            timestamped.split_off(skip).into_iter().peekable()
+        })
+        .collect();
+    let mut merged = Vec::new();
+    loop {
+        let next = tails
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, tail)| tail.peek().map(|(timestamp, _)| (*timestamp, i)))
+            .min();
+        let Some((_, i)) = next else {
+            break;
+        };
+        let (_, line) = tails[i].next().expect("peeked");
+        merged.push(line.to_string());
+    }
+    let skip = merged.len().saturating_sub(lines);
+    merged.split_off(skip)
+}
+
+/// The value of the field `name` in the output of pg_controldata.
+fn controldata_field<'a>(controldata: &'a str, name: &str) -> Option<&'a str> {
+    controldata.lines().find_map(|line| {
+        line.strip_prefix(name)?
+            .trim_start()
+            .strip_prefix(':')
+            .map(str::trim)
+    })
+}
+
+/// What the data directory of a stopped endpoint tells without starting Postgres, see
+/// [`Endpoint::inspect_pgdata`].
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PgDataInfo {
+    pub system_identifier: u64,
+    /// As pg_controldata shows it, e.g. "shut down" or "in production"
+    pub cluster_state: String,
+    pub checkpoint_lsn: Lsn,
+    pub redo_lsn: Lsn,
+    pub timeline: u32,
+    /// The signal file left by the basebackup, None if there is none
+    pub signal: Option<NeonSignal>,
+}
+
+/// The contents of the neon.signal file, or zenith.signal in older basebackups: the
+/// LSN of the record before the basebackup's.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct NeonSignal {
+    pub file: String,
+    pub prev_lsn: PrevLsn,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrevLsn {
+    /// The basebackup is at the start of the timeline
+    None,
+    /// The basebackup isn't at a record boundary the pageserver knows
+    Invalid,
+    Lsn(Lsn),
+}
+
+/// The files the basebackup can leave a [`NeonSignal`] in, newest name first.
+const SIGNAL_FILES: [&str; 2] = ["neon.signal", "zenith.signal"];
+
+/// Parse the output of pg_controldata, without the signal file.
+fn parse_controldata(controldata: &str) -> Result<PgDataInfo> {
+    let field = |name: &str| {
+        controldata_field(controldata, name)
+            .with_context(|| format!("pg_controldata shows no '{name}'"))
+    };
+    let lsn_field = |name: &str| -> Result<Lsn> {
+        let value = field(name)?;
+        Lsn::from_str(value).map_err(|_| anyhow!("invalid '{name}' {value}"))
+    };
+    Ok(PgDataInfo {
+        system_identifier: field("Database system identifier")?
+            .parse()
+            .context("invalid database system identifier")?,
+        cluster_state: field("Database cluster state")?.to_string(),
+        checkpoint_lsn: lsn_field("Latest checkpoint location")?,
+        redo_lsn: lsn_field("Latest checkpoint's REDO location")?,
+        timeline: field("Latest checkpoint's TimeLineID")?
+            .parse()
+            .context("invalid checkpoint timeline")?,
+        signal: None,
+    })
+}
+
+/// Parse a signal file, "PREV LSN: <lsn>", "PREV LSN: none" or "PREV LSN: invalid".
+fn parse_neon_signal(contents: &str) -> Result<PrevLsn> {
+    let value = contents
+        .trim()
+        .strip_prefix("PREV LSN:")
+        .with_context(|| format!("unexpected signal file contents '{}'", contents.trim()))?
+        .trim();
+    Ok(match value {
+        "none" => PrevLsn::None,
+        "invalid" => PrevLsn::Invalid,
+        lsn => {
+            PrevLsn::Lsn(Lsn::from_str(lsn).map_err(|_| anyhow!("invalid previous LSN '{lsn}'"))?)
+        }
+    })
+}
+
+/// One endpoint in [`ComputeControlPlane::sample`].
+#[derive(Debug)]
+pub struct EndpointSample {
+    pub endpoint_id: String,
+    /// As in [`ComputeControlPlane::statuses`]
+    pub status: Result<EndpointStatusInfo>,
+    /// None if it couldn't be told
+    pub activity: Option<EndpointActivity>,
+    /// See [`Endpoint::lfc_hit_ratio`], None for endpoints that don't run and if it can't
+    /// be told
+    pub lfc_hit_ratio: Option<f64>,
+}
+
+impl EndpointActivity {
+    /// Running without client sessions, and writing less than `wal_bytes_per_sec` of
+    /// WAL. A compute writes a bit of WAL even when nobody uses it.
+    pub fn is_idle(&self, wal_bytes_per_sec: f64) -> bool {
+        self.running
+            && self.client_backends == 0
+            && self
+                .wal_bytes_per_sec
+                .map_or(true, |rate| rate < wal_bytes_per_sec)
+    }
+}
+
+/// The spec format version to use with the `compute_ctl` binary at `compute_ctl`: the
+/// highest one that both it and neon_local support. The probe is cached for as long as
+/// the binary doesn't change.
+fn negotiate_spec_format_version(compute_ctl: &Path) -> Result<f32> {
+    static PROBES: Lazy<Mutex<HashMap<(PathBuf, SystemTime), SpecFormatVersions>>> =
+        Lazy::new(Default::default);
+
+    let modified = std::fs::metadata(compute_ctl)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("failed to stat {}", compute_ctl.display()))?;
+    let key = (compute_ctl.to_owned(), modified);
+    let cached = PROBES.lock().unwrap().get(&key).copied();
+    let supported = match cached {
+        Some(supported) => supported,
+        None => {
+            let supported = probe_spec_format_versions(compute_ctl)?;
+            PROBES.lock().unwrap().insert(key, supported);
+            supported
+        }
+    };
+    pick_spec_format_version(SPEC_FORMAT_VERSIONS, supported).with_context(|| {
+        format!(
+            "compute_ctl {} supports spec versions {}..{} but neon_local produces {}..{}",
+            compute_ctl.display(),
+            supported.min,
+            supported.max,
+            SPEC_FORMAT_VERSIONS.min,
+            SPEC_FORMAT_VERSIONS.max
+        )
+    })
+}
+
+fn probe_spec_format_versions(compute_ctl: &Path) -> Result<SpecFormatVersions> {
+    let output = Command::new(compute_ctl)
+        .arg("--supported-spec-versions")
+        .output()
+        .with_context(|| format!("failed to run {}", compute_ctl.display()))?;
+    if !output.status.success() {
+        // compute_ctl from before the flag existed, which only knows the first version
+        return Ok(SpecFormatVersions { min: 1.0, max: 1.0 });
+    }
+    serde_json::from_slice(&output.stdout).with_context(|| {
+        format!(
+            "failed to parse the spec versions reported by {}",
+            compute_ctl.display()
+        )
+    })
+}
+
+/// The names of the compute features the `compute_ctl` binary at `compute_ctl` knows,
+/// None if it predates `--supported-features`. Cached like the spec format versions.
+fn supported_compute_features(compute_ctl: &Path) -> Result<Option<Vec<String>>> {
+    static PROBES: Lazy<Mutex<HashMap<(PathBuf, SystemTime), Option<Vec<String>>>>> =
+        Lazy::new(Default::default);
+
+    let modified = std::fs::metadata(compute_ctl)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("failed to stat {}", compute_ctl.display()))?;
+    let key = (compute_ctl.to_owned(), modified);
+    if let Some(supported) = PROBES.lock().unwrap().get(&key) {
+        return Ok(supported.clone());
+    }
+    let output = Command::new(compute_ctl)
+        .arg("--supported-features")
+        .output()
+        .with_context(|| format!("failed to run {}", compute_ctl.display()))?;
+    let supported = if output.status.success() {
+        Some(serde_json::from_slice(&output.stdout).with_context(|| {
+            format!(
+                "failed to parse the features reported by {}",
+                compute_ctl.display()
             )
-            .send()
-            .await?;
+        })?)
+    } else {
+        None
+    };
+    PROBES.lock().unwrap().insert(key, supported.clone());
+    Ok(supported)
+}
 
-        // Interpret the response
-        let status = response.status();
-        if !(status.is_client_error() || status.is_server_error()) {
-            Ok(response.json().await?)
-        } else {
-            // reqwest does not export its error construction utility functions, so let's craft the message ourselves
-            let url = response.url().to_owned();
-            let msg = match response.text().await {
-                Ok(err_body) => format!("Error: {}", err_body),
-                Err(_) => format!("Http error ({}) at {}.", status.as_u16(), url),
-            };
-            Err(anyhow::anyhow!(msg))
+/// The name of `feature` in the spec.
+fn compute_feature_name(feature: &ComputeFeature) -> String {
+    match serde_json::to_value(feature) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{feature:?}"),
+    }
+}
+
+/// The `requested` features that are in `supported`. Unsupported ones are an error,
+/// unless `ignore_unsupported` is set, which leaves them out instead.
+fn select_compute_features(
+    requested: &[ComputeFeature],
+    supported: &[String],
+    ignore_unsupported: bool,
+) -> Result<Vec<ComputeFeature>> {
+    let (selected, unsupported): (Vec<_>, Vec<_>) = requested
+        .iter()
+        .partition(|feature| supported.contains(&compute_feature_name(feature)));
+    if !unsupported.is_empty() {
+        let names = unsupported
+            .iter()
+            .map(|feature| compute_feature_name(feature))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !ignore_unsupported {
+            bail!("compute_ctl doesn't support the features {names}, leave them out with --ignore-unsupported-features");
         }
+        eprintln!("Leaving out the features that compute_ctl doesn't support: {names}");
     }
+    Ok(selected.into_iter().copied().collect())
+}
 
-    pub async fn reconfigure(
-        &self,
-        mut pageservers: Vec<(Host, u16)>,
-        stripe_size: Option<ShardStripeSize>,
-        safekeepers: Option<Vec<NodeId>>,
-    ) -> Result<()> {
-        let mut spec: ComputeSpec = {
-            let spec_path = self.endpoint_path().join("spec.json");
-            let file = std::fs::File::open(spec_path)?;
-            serde_json::from_reader(file)?
+fn pick_spec_format_version(ours: SpecFormatVersions, theirs: SpecFormatVersions) -> Result<f32> {
+    let version = ours.max.min(theirs.max);
+    if version < ours.min.max(theirs.min) {
+        bail!("no spec version supported by both");
+    }
+    Ok(version)
+}
+
+/// Check a log filter for compute_ctl, in the syntax of `RUST_LOG`: a comma-separated list
+/// of `level` or `target=level` directives. Unlike `RUST_LOG`, a bare target without a
+/// level is not accepted, so that a misspelled level isn't taken for a target.
+pub fn validate_log_filter(filter: &str) -> Result<()> {
+    const LEVELS: [&str; 6] = ["trace", "debug", "info", "warn", "error", "off"];
+    if filter.is_empty() {
+        bail!("empty log filter");
+    }
+    for directive in filter.split(',') {
+        let level = match directive.split_once('=') {
+            Some((target, level)) => {
+                if target.is_empty()
+                    || !target
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ':'))
+                {
+                    bail!("invalid target '{target}' in log filter '{filter}'");
+                }
+                level
+            }
+            None => directive,
         };
+        if !LEVELS.contains(&level.to_ascii_lowercase().as_str()) {
+            bail!(
+                "invalid level '{level}' in log filter '{filter}', expected one of {}",
+                LEVELS.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The environment of compute_ctl beyond what it inherits: `RUST_LOG` if a log filter
+/// was given on start.
+fn compute_ctl_envs(log_level: Option<&str>) -> Result<Vec<(String, String)>> {
+    match log_level {
+        Some(filter) => {
+            validate_log_filter(filter)?;
+            Ok(vec![("RUST_LOG".to_string(), filter.to_string())])
+        }
+        None => Ok(Vec::new()),
+    }
+}
 
-        let postgresql_conf = self.read_postgresql_conf()?;
-        spec.cluster.postgresql_conf = Some(postgresql_conf);
+/// Check that a suspend timeout is long enough for the compute to do anything at all.
+fn validate_suspend_timeout(suspend_timeout: Option<Duration>) -> Result<()> {
+    match suspend_timeout {
+        Some(timeout) if timeout < MIN_SUSPEND_TIMEOUT => bail!(
+            "suspend timeout must be at least {}, got {}",
+            humantime::format_duration(MIN_SUSPEND_TIMEOUT),
+            humantime::format_duration(timeout)
+        ),
+        _ => Ok(()),
+    }
+}
 
-        // If we weren't given explicit pageservers, query the storage controller
-        if pageservers.is_empty() {
-            let storage_controller = StorageController::from_env(&self.env);
-            let locate_result = storage_controller.tenant_locate(self.tenant_id).await?;
-            pageservers = locate_result
-                .shards
-                .into_iter()
-                .map(|shard| {
-                    (
-                        Host::parse(&shard.listen_pg_addr)
-                            .expect("Storage controller reported bad hostname"),
-                        shard.listen_pg_port,
-                    )
-                })
-                .collect::<Vec<_>>();
+/// Whether [`Endpoint::start`] checks that the pageservers of all shards are reachable
+/// once the compute is running, and what happens if some are not.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectivityCheck {
+    #[default]
+    Skip,
+    Warn,
+    Fail,
+}
+
+impl FromStr for ConnectivityCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(ConnectivityCheck::Skip),
+            "warn" => Ok(ConnectivityCheck::Warn),
+            "fail" => Ok(ConnectivityCheck::Fail),
+            _ => bail!("unknown connectivity check mode {s:?}, expected skip, warn or fail"),
         }
+    }
+}
 
-        let pageserver_connstr = Self::build_pageserver_connstr(&pageservers);
-        assert!(!pageserver_connstr.is_empty());
-        spec.pageserver_connstring = Some(pageserver_connstr);
-        if stripe_size.is_some() {
-            spec.shard_stripe_size = stripe_size.map(|s| s.0 as usize);
+/// Try to connect to the pageserver of each shard in `connstr`, a comma separated list
+/// of URLs by shard index as built by `build_pageserver_connstr`. All shards are probed
+/// concurrently, each for at most `timeout`. The error lists the unreachable shards.
+async fn check_pageserver_connectivity(connstr: &str, timeout: Duration) -> Result<()> {
+    let probes = connstr
+        .split(',')
+        .enumerate()
+        .map(|(shard, url)| async move {
+            let result = async {
+                let url = url::Url::parse(url).with_context(|| format!("invalid URL {url:?}"))?;
+                let host = url.host_str().context("no host")?.to_string();
+                let port = url.port().context("no port")?;
+                tokio::time::timeout(
+                    timeout,
+                    tokio::net::TcpStream::connect((host.as_str(), port)),
+                )
+                .await
+                .with_context(|| format!("{host}:{port}: timed out after {timeout:?}"))?
+                .with_context(|| format!("{host}:{port}"))?;
+                anyhow::Ok(())
+            }
+            .await;
+            (shard, result)
+        });
+    let unreachable = futures::future::join_all(probes)
+        .await
+        .into_iter()
+        .filter_map(|(shard, result)| result.err().map(|e| format!("shard {shard} ({e:#})")))
+        .collect::<Vec<_>>();
+    if !unreachable.is_empty() {
+        bail!(
+            "pageservers of {} shard(s) are unreachable: {}",
+            unreachable.len(),
+            unreachable.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The suspend timeout as passed in the spec, where -1 means never.
+fn suspend_timeout_seconds(suspend_timeout: Option<Duration>) -> i64 {
+    suspend_timeout.map_or(-1, |timeout| {
+        i64::try_from(timeout.as_secs()).unwrap_or(i64::MAX)
+    })
+}
+
+/// Builds the [`ComputeSpec`] passed to compute_ctl. [`Endpoint::start`] begins with the
+/// settings of the endpoint, [`Endpoint::reconfigure`] with the spec the compute runs
+/// with, and both fill in the rest with the same methods.
+pub struct SpecBuilder {
+    spec: ComputeSpec,
+}
+
+impl SpecBuilder {
+    /// A spec for a fresh start of `endpoint`, in spec format `format_version`.
+    pub fn new(endpoint: &Endpoint, format_version: f32) -> Self {
+        SpecBuilder {
+            spec: ComputeSpec {
+                format_version,
+                skip_pg_catalog_updates: endpoint.skip_pg_catalog_updates,
+                features: endpoint.features.clone(),
+                tenant_id: Some(endpoint.tenant_id),
+                timeline_id: Some(endpoint.timeline_id),
+                mode: endpoint.mode,
+                suspend_timeout_seconds: suspend_timeout_seconds(endpoint.suspend_timeout),
+                ..Default::default()
+            },
         }
+    }
 
-        // If safekeepers are not specified, don't change them.
-        if let Some(safekeepers) = safekeepers {
-            let safekeeper_connstrings = self.build_safekeepers_connstrs(safekeepers)?;
-            spec.safekeeper_connstrings = safekeeper_connstrings;
+    /// Start from an existing spec, e.g. the one in spec.json. Whatever is not set again
+    /// is kept.
+    pub fn from_spec(spec: ComputeSpec) -> Self {
+        SpecBuilder { spec }
+    }
+
+    pub fn with_features(mut self, features: Vec<ComputeFeature>) -> Self {
+        self.spec.features = features;
+        self
+    }
+
+    pub fn with_postgresql_conf(mut self, postgresql_conf: String) -> Self {
+        self.spec.cluster.postgresql_conf = Some(postgresql_conf);
+        self
+    }
+
+    /// Set the pageservers, one per shard. The stripe size is left alone if `None`.
+    pub fn with_pageservers(
+        mut self,
+        pageservers: &[(Host, u16)],
+        shard_stripe_size: Option<usize>,
+    ) -> Self {
+        self.spec.pageserver_connstring = Some(Endpoint::build_pageserver_connstr(pageservers));
+        if shard_stripe_size.is_some() {
+            self.spec.shard_stripe_size = shard_stripe_size;
         }
+        self
+    }
 
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()
-            .unwrap();
-        let response = client
-            .post(format!(
-                "http://{}:{}/configure",
-                self.http_address.ip(),
-                self.http_address.port()
-            ))
-            .body(format!(
-                "{{\"spec\":{}}}",
-                serde_json::to_string_pretty(&spec)?
-            ))
-            .send()
-            .await?;
+    pub fn with_safekeepers(mut self, safekeeper_connstrings: Vec<String>) -> Self {
+        self.spec.safekeeper_connstrings = safekeeper_connstrings;
+        self
+    }
 
-        let status = response.status();
-        if !(status.is_client_error() || status.is_server_error()) {
-            Ok(())
+    /// Add the `test` role and its `neondb` database, or remove them.
+    pub fn with_test_user(mut self, create_test_user: bool) -> Self {
+        let cluster = &mut self.spec.cluster;
+        if create_test_user {
+            cluster.roles = vec![Role {
+                name: PgIdent::from_str("test").unwrap(),
+                encrypted_password: None,
+                options: None,
+            }];
+            cluster.databases = vec![Database {
+                name: PgIdent::from_str("neondb").unwrap(),
+                owner: PgIdent::from_str("test").unwrap(),
+                options: None,
+                restrict_conn: false,
+                invalid: false,
+            }];
         } else {
-            let url = response.url().to_owned();
-            let msg = match response.text().await {
-                Ok(err_body) => format!("Error: {}", err_body),
-                Err(_) => format!("Http error ({}) at {}.", status.as_u16(), url),
-            };
-            Err(anyhow::anyhow!(msg))
+            cluster.roles = Vec::new();
+            cluster.databases = Vec::new();
         }
+        self
     }
 
-    pub fn stop(&self, mode: &str, destroy: bool) -> Result<()> {
-        self.pg_ctl(&["-m", mode, "stop"], &None)?;
+    /// The token the compute presents to the pageservers and safekeepers.
+    pub fn with_storage_tokens(mut self, auth_token: Option<String>) -> Self {
+        self.spec.storage_auth_token = auth_token;
+        self
+    }
 
-        // Also wait for the compute_ctl process to die. It might have some
-        // cleanup work to do after postgres stops, like syncing safekeepers,
-        // etc.
-        //
-        // If destroying, send it SIGTERM before waiting. Sometimes we do *not*
-        // want this cleanup: tests intentionally do stop when majority of
-        // safekeepers is down, so sync-safekeepers would hang otherwise. This
-        // could be a separate flag though.
-        self.wait_for_compute_ctl_to_exit(destroy)?;
-        if destroy {
-            println!(
-                "Destroying postgres data directory '{}'",
-                self.pgdata().to_str().unwrap()
-            );
-            std::fs::remove_dir_all(self.endpoint_path())?;
+    pub fn with_remote_extensions(mut self, remote_extensions: Option<RemoteExtSpec>) -> Self {
+        self.spec.remote_extensions = remote_extensions;
+        self
+    }
+
+    pub fn with_suspend_timeout(mut self, suspend_timeout: Option<Duration>) -> Self {
+        self.spec.suspend_timeout_seconds = suspend_timeout_seconds(suspend_timeout);
+        self
+    }
+
+    /// The spec, if it is complete enough for compute_ctl to start a compute.
+    pub fn build(self) -> Result<ComputeSpec> {
+        match self.spec.pageserver_connstring.as_deref() {
+            None | Some("") => bail!("no pageservers in the compute spec"),
+            Some(_) => Ok(self.spec),
         }
-        Ok(())
     }
+}
 
-    pub fn connstr(&self, user: &str, db_name: &str) -> String {
-        format!(
-            "postgresql://{}@{}:{}/{}",
-            user,
-            self.pg_address.ip(),
-            self.pg_address.port(),
-            db_name
-        )
+/// Arguments of [`Endpoint::start`], as provided to [`ComputeControlPlane::start_all`].
+pub struct EndpointStartArgs {
+    pub auth_token: Option<String>,
+    pub safekeepers: Vec<NodeId>,
+    pub pageservers: Vec<(Host, u16)>,
+    pub remote_ext_config: Option<String>,
+    pub shard_stripe_size: usize,
+    pub create_test_user: bool,
+    /// Overrides the suspend timeout the endpoint was created with.
+    pub suspend_timeout: Option<Duration>,
+    pub skip_conf_validation: bool,
+    pub verify_pageserver_connectivity: ConnectivityCheck,
+    /// Leave out the features compute_ctl doesn't know, rather than failing.
+    pub ignore_unsupported_features: bool,
+    /// `RUST_LOG` for compute_ctl, see [`validate_log_filter`].
+    pub log_level: Option<String>,
+    /// Replaces `shard_stripe_size`, see [`Self::effective_shard_stripe_size`].
+    pub stripe_size_override: Option<ShardStripeSize>,
+}
+
+impl EndpointStartArgs {
+    /// The stripe size the compute is given: the tenant's, unless overridden. The override
+    /// is only allowed for unsharded tenants, to test a compute with the stripe size the
+    /// tenant will have once it is split. The stripe size used ends up in spec.json.
+    pub fn effective_shard_stripe_size(&self) -> Result<usize> {
+        let Some(stripe_size) = self.stripe_size_override else {
+            return Ok(self.shard_stripe_size);
+        };
+        match self.pageservers.len() {
+            0 => bail!("vanilla endpoints have no shards, there is no stripe size to override"),
+            1 => {}
+            shards => bail!(
+                "the tenant has {shards} shards, the compute must use the stripe size they were split with"
+            ),
+        }
+        eprintln!(
+            "WARNING: the compute is given a stripe size of {} pages instead of {}, as if the tenant was split with it",
+            stripe_size.0, self.shard_stripe_size
+        );
+        Ok(stripe_size.0 as usize)
+    }
+}
+
+/// Run `start` for the `endpoints`, primaries first, at most `parallelism` at a time.
+async fn start_in_phases<F, Fut>(
+    endpoints: Vec<Arc<Endpoint>>,
+    parallelism: usize,
+    start: F,
+) -> BTreeMap<String, Result<()>>
+where
+    F: Fn(Arc<Endpoint>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let (primaries, others): (Vec<_>, Vec<_>) = endpoints
+        .into_iter()
+        .partition(|ep| ep.mode == ComputeMode::Primary);
+    let mut results = BTreeMap::new();
+    for phase in [primaries, others] {
+        let phase_results: Vec<(String, Result<()>)> = futures::stream::iter(phase)
+            .map(|ep| {
+                let endpoint_id = ep.endpoint_id.clone();
+                let started = start(ep);
+                async move { (endpoint_id, started.await) }
+            })
+            .buffer_unordered(parallelism.max(1))
+            .collect()
+            .await;
+        results.extend(phase_results);
+    }
+    results
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct Endpoint {
+    /// used as the directory name
+    endpoint_id: String,
+    /// All zeroes for vanilla endpoints, which have no tenant or timeline.
+    pub tenant_id: TenantId,
+    pub timeline_id: TimelineId,
+    pub mode: ComputeMode,
+
+    // port and address of the Postgres server and `compute_ctl`'s HTTP API. The HTTP
+    // address is the one `compute_ctl` binds to, see `http_connect_address` for the
+    // one to reach it at.
+    pub pg_address: SocketAddr,
+    pub http_address: SocketAddr,
+
+    // postgres major version in the format: 14, 15, etc.
+    pg_version: u32,
+
+    // These are not part of the endpoint as such, but the environment
+    // the endpoint runs in.
+    pub env: LocalEnv,
+
+    // Optimizations
+    skip_pg_catalog_updates: bool,
+
+    // Feature flags
+    features: Vec<ComputeFeature>,
+
+    // Idle time before the compute suspends itself, never if None
+    suspend_timeout: Option<Duration>,
+
+    /// Runs vanilla Postgres on a local data directory instead of a neon compute, see
+    /// [`ComputeControlPlane::new_vanilla_endpoint`].
+    pub vanilla: bool,
+
+    /// The safekeepers the endpoint was last reconfigured with, None if it never was.
+    /// Later starts use them unless told otherwise.
+    pub last_safekeepers: Option<Vec<NodeId>>,
+
+    /// Profiles from the environment's profiles directory, applied in order on top of
+    /// the generated settings, see [`LocalEnv::profiles_path`].
+    pub profiles: Vec<String>,
+
+    /// The neon_local that created the endpoint, None if not recorded.
+    pub created_by: Option<BinaryVersion>,
+
+    /// For replicas, the primary endpoint to stream WAL from directly, None to stream
+    /// from the safekeepers.
+    pub stream_from: Option<String>,
+
+    /// Whether `compute_ctl` logs to [`COMPUTE_CTL_LOG`] and Postgres to [`POSTGRES_LOG`],
+    /// rather than both to [`COMPUTE_LOG`].
+    pub split_logs: bool,
+
+    // Hooks around the calls to compute_ctl, see the http_hooks module
+    http_hooks: Arc<dyn HttpHooks>,
+
+    // Told about starts and stops, see the endpoint_events module
+    events: EventSinks,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndpointStatus {
+    Running,
+    Stopped,
+    Crashed,
+    RunningNoPidfile,
+}
+
+impl EndpointStatus {
+    fn from_probes(has_pidfile: bool, can_connect: bool) -> Self {
+        match (has_pidfile, can_connect) {
+            (true, true) => EndpointStatus::Running,
+            (false, false) => EndpointStatus::Stopped,
+            (true, false) => EndpointStatus::Crashed,
+            (false, true) => EndpointStatus::RunningNoPidfile,
+        }
+    }
+}
+
+/// Result of [`ComputeControlPlane::statuses`] for one endpoint.
+#[derive(Debug)]
+pub struct EndpointStatusInfo {
+    pub status: EndpointStatus,
+    /// Reported by `compute_ctl`, None unless Postgres is running.
+    pub compute_status: Option<ComputeStatus>,
+}
+
+/// What [`Endpoint::wait_for_status`] makes of a status reported by `compute_ctl`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WaitDecision {
+    /// Poll again
+    Continue,
+    /// Stop waiting, the status is the one waited for
+    Done,
+    /// Stop waiting, with this error
+    Fail(String),
+}
+
+/// The decision of waiting for a starting compute to run.
+fn running_decision(state: &ComputeState) -> WaitDecision {
+    match state.status {
+        ComputeStatus::Init => WaitDecision::Continue,
+        ComputeStatus::Running => WaitDecision::Done,
+        ComputeStatus::Failed => WaitDecision::Fail(format!(
+            "compute startup failed: {}",
+            state
+                .error
+                .as_deref()
+                .unwrap_or("<no error from compute_ctl>")
+        )),
+        ComputeStatus::Empty
+        | ComputeStatus::ConfigurationPending
+        | ComputeStatus::Configuration
+        | ComputeStatus::TerminationPending
+        | ComputeStatus::Terminated => {
+            WaitDecision::Fail(format!("unexpected compute status: {:?}", state.status))
+        }
+    }
+}
+
+impl std::fmt::Display for EndpointStatus {
+    fn fmt(&self, writer: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Self::Running => "running",
+            Self::Stopped => "stopped",
+            Self::Crashed => "crashed",
+            Self::RunningNoPidfile => "running, no pidfile",
+        };
+        write!(writer, "{}", s)
+    }
+}
+
+impl Endpoint {
+    fn from_dir_entry(entry: std::fs::DirEntry, env: &LocalEnv) -> Result<Endpoint> {
+        if !entry.file_type()?.is_dir() {
+            anyhow::bail!(
+                "Endpoint::from_dir_entry failed: '{}' is not a directory",
+                entry.path().display()
+            );
+        }
+
+        // parse data directory name
+        let fname = entry.file_name();
+        let endpoint_id = fname.to_str().unwrap().to_string();
+
+        // Read the endpoint.json file
+        let conf_path = entry.path().join("endpoint.json");
+        let (conf, translated) = parse_endpoint_conf(&std::fs::read(&conf_path)?)
+            .with_context(|| format!("failed to parse {}", conf_path.display()))?;
+        if !translated.is_empty() {
+            let rewrite = env.endpoint_defaults.rewrite_legacy_endpoint_conf();
+            eprintln!(
+                "Endpoint {endpoint_id}: {} is in a legacy format: {}{}",
+                conf_path.display(),
+                translated.join(", "),
+                if rewrite { ", rewriting it" } else { "" }
+            );
+            if rewrite {
+                std::fs::write(&conf_path, serde_json::to_string_pretty(&conf)?)
+                    .with_context(|| format!("failed to write {}", conf_path.display()))?;
+            }
+        }
+
+        Endpoint::from_conf(endpoint_id, conf, env)
+    }
+
+    fn from_conf(endpoint_id: String, conf: EndpointConf, env: &LocalEnv) -> Result<Endpoint> {
+        let (tenant_id, timeline_id) = match (conf.tenant_id, conf.timeline_id) {
+            (Some(tenant_id), Some(timeline_id)) => (tenant_id, timeline_id),
+            _ if conf.vanilla => (
+                TenantId::from_array([0; 16]),
+                TimelineId::from_array([0; 16]),
+            ),
+            _ => bail!("endpoint {endpoint_id} has no tenant or timeline id"),
+        };
+        Ok(Endpoint {
+            pg_address: SocketAddr::new("127.0.0.1".parse().unwrap(), conf.pg_port),
+            http_address: SocketAddr::new(env.endpoint_defaults.http_bind_ip(), conf.http_port),
+            endpoint_id,
+            env: env.clone(),
+            timeline_id,
+            mode: conf.mode,
+            tenant_id,
+            pg_version: conf.pg_version,
+            skip_pg_catalog_updates: conf.skip_pg_catalog_updates,
+            features: conf.features,
+            suspend_timeout: conf.suspend_timeout,
+            vanilla: conf.vanilla,
+            last_safekeepers: conf.last_safekeepers,
+            profiles: conf.profiles,
+            created_by: conf.created_by,
+            stream_from: conf.stream_from,
+            split_logs: conf.split_logs,
+            http_hooks: http_hooks::from_env()?,
+            events: EventSinks::default(),
+        })
+    }
+
+    /// Replace the hooks around the calls to `compute_ctl`, e.g. to inject failures.
+    pub fn set_http_hooks(&mut self, hooks: Arc<dyn HttpHooks>) {
+        self.http_hooks = hooks;
+    }
+
+    /// Fail with a [`VanillaEndpointError`] if this is a vanilla endpoint.
+    pub fn check_not_vanilla(&self, operation: &'static str) -> Result<(), VanillaEndpointError> {
+        if self.vanilla {
+            return Err(VanillaEndpointError {
+                endpoint_id: self.endpoint_id.clone(),
+                operation,
+            });
+        }
+        Ok(())
+    }
+
+    fn create_endpoint_dir(&self) -> Result<()> {
+        std::fs::create_dir_all(self.endpoint_path()).with_context(|| {
+            format!(
+                "could not create endpoint directory {}",
+                self.endpoint_path().display()
+            )
+        })
+    }
+
+    /// Write the settings generated by neon_local, included by the endpoint's
+    /// postgresql.conf. Rewritten on every start, so that they follow changes
+    /// to the endpoint and environment.
+    fn write_managed_pg_conf(&self) -> Result<()> {
+        let path = self.endpoint_path().join(MANAGED_PG_CONF);
+        std::fs::write(&path, self.setup_pg_conf()?.to_string())
+            .with_context(|| format!("failed to write config file {}", path.display()))
+    }
+
+    // Generate postgresql.conf that includes the managed settings, followed by
+    // the user's settings
+    fn setup_user_pg_conf(overrides: &BTreeMap<String, String>) -> PostgresConf {
+        let mut conf = PostgresConf::new();
+        conf.append_line("# Settings generated by neon_local, rewritten on every start");
+        conf.append_include(MANAGED_PG_CONF);
+        conf.append_line("");
+        conf.append_line("# Settings below override the generated ones");
+        for (name, value) in overrides {
+            conf.set(name, value);
+        }
+        conf
+    }
+
+    // Generate neon_managed.conf: the default configuration, overridden by the
+    // endpoint's profiles in order. The user's settings in postgresql.conf come after
+    // it, and override both.
+    fn setup_pg_conf(&self) -> Result<PostgresConf> {
+        let mut conf = self.default_pg_conf()?;
+        for profile in &self.profiles {
+            let path = self.env.profile_path(profile);
+            let profile_conf = PostgresConf::load(&path).with_context(|| {
+                format!(
+                    "failed to load profile '{profile}' of endpoint {}",
+                    self.endpoint_id
+                )
+            })?;
+            conf.merge(profile_conf);
+        }
+        // Profiles can't move the endpoint to other ports
+        conf.merge(self.required_pg_conf());
+        Ok(conf)
+    }
+
+    fn default_pg_conf(&self) -> Result<PostgresConf> {
+        let mut conf = PostgresConf::new();
+        conf.append_int("max_wal_senders", 10);
+        conf.append_bool("wal_log_hints", false);
+        conf.append_int("max_replication_slots", 10);
+        conf.append_bool("hot_standby", true);
+        conf.append_bytes("shared_buffers", 1024 * 1024);
+        conf.append_bool("fsync", false);
+        conf.append_int("max_connections", 100);
+        conf.set("wal_level", "logical");
+        // wal_sender_timeout is the maximum time to wait for WAL replication.
+        // It also defines how often the walreciever will send a feedback message to the wal sender.
+        conf.append_duration("wal_sender_timeout", Duration::from_secs(5));
+        conf.merge(self.required_pg_conf());
+        conf.append_bytes("wal_keep_size", 0);
+        // walproposer panics when basebackup is invalid, it is pointless to restart in this case.
+        conf.append_bool("restart_after_crash", false);
+        if self.split_logs {
+            // Postgres writes its log itself, so that compute_ctl doesn't get it on stderr.
+            // The file name has no time escapes, so rotating would only append to it.
+            conf.append_bool("logging_collector", true);
+            conf.set("log_directory", &self.endpoint_path().to_string_lossy());
+            conf.set("log_filename", POSTGRES_LOG);
+            conf.append_int("log_rotation_age", 0);
+            conf.append_int("log_rotation_size", 0);
+        }
+
+        if self.vanilla {
+            return Ok(conf);
+        }
+
+        // Load the 'neon' extension
+        conf.set("shared_preload_libraries", "neon");
+
+        conf.append_line("");
+        // Replication-related configurations, such as WAL sending
+        match &self.mode {
+            ComputeMode::Primary => {
+                // Configure backpressure
+                // - Replication write lag depends on how fast the walreceiver can process incoming WAL.
+                //   This lag determines latency of get_page_at_lsn. Speed of applying WAL is about 10MB/sec,
+                //   so to avoid expiration of 1 minute timeout, this lag should not be larger than 600MB.
+                //   Actually latency should be much smaller (better if < 1sec). But we assume that recently
+                //   updates pages are not requested from pageserver.
+                // - Replication flush lag depends on speed of persisting data by checkpointer (creation of
+                //   delta/image layers) and advancing disk_consistent_lsn. Safekeepers are able to
+                //   remove/archive WAL only beyond disk_consistent_lsn. Too large a lag can cause long
+                //   recovery time (in case of pageserver crash) and disk space overflow at safekeepers.
+                // - Replication apply lag depends on speed of uploading changes to S3 by uploader thread.
+                //   To be able to restore database in case of pageserver node crash, safekeeper should not
+                //   remove WAL beyond this point. Too large lag can cause space exhaustion in safekeepers
+                //   (if they are not able to upload WAL to S3).
+                conf.append_bytes("max_replication_write_lag", 15 * 1024 * 1024);
+                conf.append_bytes("max_replication_flush_lag", 10 * 1024 * 1024 * 1024);
+
+                if !self.env.safekeepers.is_empty() {
+                    // Configure Postgres to connect to the safekeepers
+                    conf.set("synchronous_standby_names", "walproposer");
+
+                    let safekeepers = self
+                        .env
+                        .safekeepers
+                        .iter()
+                        .map(|sk| format!("localhost:{}", sk.get_compute_port()))
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    conf.set("neon.safekeepers", &safekeepers);
+                } else {
+                    // We only use setup without safekeepers for tests,
+                    // and don't care about data durability on pageserver,
+                    // so set more relaxed synchronous_commit.
+                    conf.set("synchronous_commit", "remote_write");
+
+                    // Configure the node to stream WAL directly to the pageserver
+                    // This isn't really a supported configuration, but can be useful for
+                    // testing.
+                    conf.set("synchronous_standby_names", "pageserver");
+                }
+            }
+            ComputeMode::Static(lsn) => {
+                conf.set("recovery_target_lsn", &lsn.to_string());
+            }
+            ComputeMode::Replica => {
+                if let Some(primary_id) = &self.stream_from {
+                    // Plain streaming replication from the primary's walsender. The sample
+                    // pg_hba.conf of the basebackup trusts local replication connections. There
+                    // is no replication slot, the replica falls behind for good if the primary
+                    // is restarted while it's stopped.
+                    let primary = stream_source_address(
+                        &self.env,
+                        primary_id,
+                        self.tenant_id,
+                        self.timeline_id,
+                    )?;
+                    let connstr = format!(
+                        "host={} port={} user=cloud_admin application_name=replica",
+                        primary.ip(),
+                        primary.port()
+                    );
+                    conf.set("primary_conninfo", connstr.as_str());
+                } else {
+                    if self.env.safekeepers.is_empty() {
+                        return Err(NoSafekeepersError {
+                            endpoint_id: self.endpoint_id.clone(),
+                        }
+                        .into());
+                    }
+
+                    // TODO: use future host field from safekeeper spec
+                    // Pass the list of safekeepers to the replica so that it can connect to any of them,
+                    // whichever is available.
+                    let sk_ports = self
+                        .env
+                        .safekeepers
+                        .iter()
+                        .map(|x| x.get_compute_port().to_string())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    let sk_hosts = vec!["localhost"; self.env.safekeepers.len()].join(",");
+
+                    let connstr = format!(
+                        "host={} port={} options='-c timeline_id={} tenant_id={}' application_name=replica replication=true",
+                        sk_hosts,
+                        sk_ports,
+                        &self.timeline_id.to_string(),
+                        &self.tenant_id.to_string(),
+                    );
+
+                    let slot_name = format!("repl_{}_", self.timeline_id);
+                    conf.set("primary_conninfo", connstr.as_str());
+                    conf.set("primary_slot_name", slot_name.as_str());
+                }
+                conf.append_bool("hot_standby", true);
+                // prefetching of blocks referenced in WAL doesn't make sense for us
+                // Neon hot standby ignores pages that are not in the shared_buffers
+                if self.pg_version >= 15 {
+                    conf.set("recovery_prefetch", "off");
+                }
+            }
+        }
+
+        Ok(conf)
+    }
+
+    /// Settings that neon_local relies on to connect to the endpoint. They take
+    /// precedence over the user's edits of postgresql.conf.
+    fn required_pg_conf(&self) -> PostgresConf {
+        let mut conf = PostgresConf::new();
+        conf.set("listen_addresses", &self.pg_address.ip().to_string());
+        conf.append_int("port", self.pg_address.port().into());
+        conf
+    }
+
+    pub fn endpoint_path(&self) -> PathBuf {
+        self.env.endpoints_path().join(&self.endpoint_id)
+    }
+
+    pub fn pgdata(&self) -> PathBuf {
+        self.endpoint_path().join("pgdata")
+    }
+
+    /// Archive the directory of this stopped endpoint into a tar file at `dest`, for
+    /// restoring with [`ComputeControlPlane::restore_snapshot`]. The data directory is
+    /// left out unless `include_pgdata` is set. The secrets in spec.json are redacted.
+    pub fn snapshot(&self, dest: &Path, include_pgdata: bool) -> Result<()> {
+        if self.status() != EndpointStatus::Stopped {
+            bail!(
+                "endpoint {} must be stopped to snapshot it",
+                self.endpoint_id
+            );
+        }
+        let file = std::fs::File::create(dest)
+            .with_context(|| format!("failed to create {}", dest.display()))?;
+        let mut builder = tar::Builder::new(file);
+        let mut entries = std::fs::read_dir(self.endpoint_path())?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+        for path in entries {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if path == self.pgdata() {
+                if include_pgdata {
+                    builder.append_dir_all(&name, &path)?;
+                }
+            } else if name == "spec.json" || name == PENDING_SPEC {
+                let mut spec: serde_json::Value = serde_json::from_slice(&std::fs::read(&path)?)?;
+                redact_spec(&mut spec);
+                let contents = serde_json::to_vec_pretty(&spec)?;
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                builder.append_data(&mut header, &name, contents.as_slice())?;
+            } else if path.is_file() && !name.ends_with(".pid") {
+                builder.append_path_with_name(&path, &name)?;
+            }
+        }
+        builder
+            .into_inner()
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("failed to write {}", dest.display()))
+    }
+
+    /// Gather the configuration, spec, status and last run of this endpoint, e.g. to
+    /// attach to a bug report. Parts that fail to load are described by their error.
+    pub async fn describe(&self) -> EndpointDescription {
+        let path = self.endpoint_path();
+        let read_json = |name: &str| -> Result<serde_json::Value> {
+            let file_path = path.join(name);
+            let contents = std::fs::read(&file_path)
+                .with_context(|| format!("failed to read {}", file_path.display()))?;
+            serde_json::from_slice(&contents)
+                .with_context(|| format!("failed to parse {}", file_path.display()))
+        };
+        let status = self.status();
+        let compute_state = match status {
+            EndpointStatus::Running | EndpointStatus::RunningNoPidfile if !self.vanilla => {
+                Some(self.get_status().await.into())
+            }
+            _ => None,
+        };
+        EndpointDescription {
+            endpoint_id: self.endpoint_id.clone(),
+            conf: read_json("endpoint.json")
+                .and_then(|conf| Ok(endpoint_conf_from_value(conf)?.0))
+                .into(),
+            spec: read_json("spec.json")
+                .map(|mut spec| {
+                    redact_spec(&mut spec);
+                    spec
+                })
+                .into(),
+            status: status.to_string(),
+            http_bind_address: self.http_address,
+            compute_state,
+            last_run: self.last_start_metrics().into(),
+            last_terminate: self
+                .terminate_lsns()
+                .map(|lsns| lsns.into_iter().next())
+                .into(),
+            disk_usage_bytes: disk_usage(&path).into(),
+        }
+    }
+
+    /// The pid of the Postgres process running in the data directory, according to its
+    /// postmaster.pid. The process must also have the data directory as its working
+    /// directory, so that a pid reused by another process is never taken for Postgres.
+    /// That is checked in /proc, without it, None is returned.
+    pub fn live_postmaster(&self) -> Option<Pid> {
+        let pgdata = self.pgdata().canonicalize().ok()?;
+        let pidfile = std::fs::read_to_string(pgdata.join("postmaster.pid")).ok()?;
+        // The first two lines are the pid and the data directory
+        let mut lines = pidfile.lines();
+        let pid = Pid::from_raw(lines.next()?.trim().parse().ok()?);
+        if Path::new(lines.next()?.trim()).canonicalize().ok()? != pgdata {
+            return None;
+        }
+        let cwd = std::fs::read_link(format!("/proc/{pid}/cwd")).ok()?;
+        (cwd == pgdata).then_some(pid)
+    }
+
+    pub fn status(&self) -> EndpointStatus {
+        let has_pidfile = self.pgdata().join("postmaster.pid").exists();
+        let can_connect = TcpStream::connect_timeout(&self.pg_address, PG_CONNECT_TIMEOUT).is_ok();
+        EndpointStatus::from_probes(has_pidfile, can_connect)
+    }
+
+    /// [`Self::status`] without blocking the runtime, and the status of `compute_ctl`
+    /// if Postgres runs.
+    async fn status_info(&self) -> Result<EndpointStatusInfo> {
+        let has_pidfile = self.pgdata().join("postmaster.pid").exists();
+        let can_connect = matches!(
+            tokio::time::timeout(
+                PG_CONNECT_TIMEOUT,
+                tokio::net::TcpStream::connect(self.pg_address)
+            )
+            .await,
+            Ok(Ok(_))
+        );
+        let status = EndpointStatus::from_probes(has_pidfile, can_connect);
+        let compute_status = if status == EndpointStatus::Running && !self.vanilla {
+            Some(self.get_status().await?.status)
+        } else {
+            None
+        };
+        Ok(EndpointStatusInfo {
+            status,
+            compute_status,
+        })
+    }
+
+    /// Command running the Postgres binary `program` of this endpoint's version, with
+    /// a clean environment apart from the library path.
+    fn pg_command(&self, program: &str) -> Result<(PathBuf, Command)> {
+        let path = self.env.pg_bin_dir(self.pg_version)?.join(program);
+        let mut cmd = Command::new(&path);
+        cmd.env_clear()
+            .env(
+                "LD_LIBRARY_PATH",
+                self.env.pg_lib_dir(self.pg_version)?.to_str().unwrap(),
+            )
+            .env(
+                "DYLD_LIBRARY_PATH",
+                self.env.pg_lib_dir(self.pg_version)?.to_str().unwrap(),
+            );
+        Ok((path, cmd))
+    }
+
+    fn pg_ctl(&self, args: &[&str], auth_token: &Option<String>) -> Result<()> {
+        let (pg_ctl_path, mut cmd) = self.pg_command("pg_ctl")?;
+        cmd.args(
+            [
+                &[
+                    "-D",
+                    self.pgdata().to_str().unwrap(),
+                    "-w", //wait till pg_ctl actually does what was asked
+                ],
+                args,
+            ]
+            .concat(),
+        );
+
+        // Pass authentication token used for the connections to pageserver and safekeepers
+        if let Some(token) = auth_token {
+            cmd.env("NEON_AUTH_TOKEN", token);
+        }
+
+        let pg_ctl = cmd
+            .output()
+            .context(format!("{} failed", pg_ctl_path.display()))?;
+        if !pg_ctl.status.success() {
+            anyhow::bail!(
+                "pg_ctl failed, exit code: {}, stdout: {}, stderr: {}",
+                pg_ctl.status,
+                String::from_utf8_lossy(&pg_ctl.stdout),
+                String::from_utf8_lossy(&pg_ctl.stderr),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn compute_ctl_pid_file(&self) -> Result<Utf8PathBuf> {
+        Ok(Utf8PathBuf::try_from(
+            self.endpoint_path().join("compute_ctl.pid"),
+        )?)
+    }
+
+    fn compute_ctl_running(&self) -> bool {
+        let Ok(pid_file) = self.compute_ctl_pid_file() else {
+            return false;
+        };
+        matches!(
+            pid_file::read(&pid_file),
+            Ok(PidFileRead::LockedByOtherProcess(_))
+        )
+    }
+
+    fn wait_for_compute_ctl_to_exit(&self, send_sigterm: bool) -> Result<()> {
+        let Some(pid) =
+            background_process::running_pid("compute_ctl", &self.compute_ctl_pid_file()?)?
+        else {
+            return Ok(());
+        };
+        if send_sigterm {
+            kill(pid, Signal::SIGTERM).ok();
+        }
+        let result = background_process::wait_until_stopped_timeout(
+            "compute_ctl",
+            pid,
+            COMPUTE_CTL_STOP_TIMEOUT,
+            COMPUTE_CTL_STOP_PROGRESS_INTERVAL,
+            |elapsed, state| {
+                let state = state.map_or("unknown".to_string(), String::from);
+                println!(
+                    "Waiting for compute_ctl with pid {pid} to exit for {}s, state {state}",
+                    elapsed.as_secs()
+                );
+            },
+        );
+        match result {
+            Ok(()) => Ok(()),
+            Err(background_process::WaitError::TimedOut { .. }) => {
+                println!(
+                    "compute_ctl with pid {pid} did not exit in {COMPUTE_CTL_STOP_TIMEOUT:?}, killing it"
+                );
+                kill(pid, Signal::SIGKILL).ok();
+                background_process::wait_until_stopped("compute_ctl", pid)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    //
+    // Syntax errors are always reported, with the offending line: the includes
+    // can't be resolved otherwise. `skip_validation` skips checking the values of
+    // known settings, for when that check is wrong.
+    fn read_postgresql_conf(&self, skip_validation: bool) -> Result<String> {
+        // Slurp the endpoints/<endpoint id>/postgresql.conf file into
+        // memory. We will include it in the spec file that we pass to
+        // `compute_ctl`, and `compute_ctl` will write it to the postgresql.conf
+        // in the data directory. Includes are resolved, because the files
+        // they refer to are not copied there.
+        let postgresql_conf_path = self.endpoint_path().join("postgresql.conf");
+        let mut conf = if postgresql_conf_path.exists() {
+            PostgresConf::load(&postgresql_conf_path)?
+        } else {
+            PostgresConf::new()
+        };
+
+        // Layer the required settings on top of the user's edits, so that each
+        // setting ends up in the file only once.
+        conf.merge(self.required_pg_conf());
+        if !skip_validation {
+            conf.validate_known_gucs().with_context(|| {
+                format!(
+                    "invalid config file in {}, use --skip-conf-validation to start anyway",
+                    postgresql_conf_path.to_str().unwrap()
+                )
+            })?;
+        }
+        Ok(conf.to_string())
+    }
+
+    /// The config passed to `compute_ctl` on the previous start or reconfiguration,
+    /// as stored in spec.json.
+    fn last_applied_pg_conf(&self) -> Option<String> {
+        let file = std::fs::File::open(self.endpoint_path().join("spec.json")).ok()?;
+        let spec: ComputeSpec = serde_json::from_reader(file).ok()?;
+        spec.cluster.postgresql_conf
+    }
+
+    /// Print a summary of the settings changed since the config was last applied.
+    fn print_pg_conf_changes(previous: Option<&str>, current: &str) {
+        let Some(previous) = previous else {
+            // first start
+            return;
+        };
+        let diff = match (PostgresConf::parse(previous), PostgresConf::parse(current)) {
+            (Ok(previous), Ok(current)) => previous.diff(&current),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("failed to compare postgresql.conf with the previous run: {e:#}");
+                return;
+            }
+        };
+        if !diff.is_empty() {
+            println!("postgresql.conf changed since the previous run: {diff}");
+        }
+    }
+
+    fn build_pageserver_connstr(pageservers: &[(Host, u16)]) -> String {
+        pageservers
+            .iter()
+            .map(|(host, port)| format!("postgresql://no_user@{host}:{port}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// The features of the endpoint to pass to `compute_ctl`, checked against the ones
+    /// it knows, see [`select_compute_features`].
+    fn compute_features(
+        &self,
+        compute_ctl: &Path,
+        ignore_unsupported: bool,
+    ) -> Result<Vec<ComputeFeature>> {
+        if self.features.is_empty() {
+            return Ok(Vec::new());
+        }
+        match supported_compute_features(compute_ctl)? {
+            Some(supported) => {
+                select_compute_features(&self.features, &supported, ignore_unsupported)
+            }
+            None => {
+                eprintln!(
+                    "compute_ctl {} can't tell which features it supports, passing all of them",
+                    compute_ctl.display()
+                );
+                Ok(self.features.clone())
+            }
+        }
+    }
+
+    /// Map safekeepers ids to the actual connection strings.
+    fn build_safekeepers_connstrs(&self, sk_ids: Vec<NodeId>) -> Result<Vec<String>> {
+        let mut safekeeper_connstrings = Vec::new();
+        if self.mode == ComputeMode::Primary {
+            for sk_id in sk_ids {
+                let sk = self
+                    .env
+                    .safekeepers
+                    .iter()
+                    .find(|node| node.id == sk_id)
+                    .ok_or_else(|| anyhow!("safekeeper {sk_id} does not exist"))?;
+                safekeeper_connstrings.push(format!("127.0.0.1:{}", sk.get_compute_port()));
+            }
+        }
+        Ok(safekeeper_connstrings)
+    }
+
+    pub async fn start(&self, args: &EndpointStartArgs) -> Result<()> {
+        if self.status() == EndpointStatus::Running {
+            anyhow::bail!("The endpoint is already running");
+        }
+        let shard_stripe_size = args.effective_shard_stripe_size()?;
+        let envs = compute_ctl_envs(args.log_level.as_deref())?;
+        // The data directory is about to be replaced
+        if let Some(pid) = self.live_postmaster() {
+            bail!(
+                "Postgres with pid {pid} from a previous run still runs in {}, stop it first, e.g. with 'neon_local endpoint kill-orphans --terminate'",
+                self.pgdata().display()
+            );
+        }
+        if let Some(warning) = version_skew_warning(
+            &self.endpoint_id,
+            self.created_by.as_ref(),
+            &BinaryVersion::current(),
+        ) {
+            eprintln!("Warning: {warning}");
+        }
+        let timer = Mutex::new(StartTimer::new(&self.endpoint_id));
+        // Preparing and writing the spec don't await, their spans can stay entered
+        let prepare_span = timer.lock().unwrap().span();
+        let prepare_entered = prepare_span.enter();
+        self.events
+            .emit(&self.endpoint_id, EndpointEventKind::Starting);
+        // Running once Postgres is up, even if a later step fails
+        let mut started = scopeguard::guard(false, |started| {
+            let kind = if started {
+                EndpointEventKind::Running
+            } else {
+                EndpointEventKind::Stopped
+            };
+            self.events.emit(&self.endpoint_id, kind);
+        });
+        self.check_pg_version()?;
+        if self.vanilla {
+            if args.log_level.is_some() {
+                bail!("vanilla endpoints don't run compute_ctl, a log level doesn't apply");
+            }
+            self.start_vanilla(args.skip_conf_validation)?;
+            *started = true;
+            return self.stamp_pg_version();
+        }
+        self.check_interrupted_reconfigure();
+        // The setting lives in the data directory, which is recreated below
+        self.clear_read_only_marker()?;
+        let suspend_timeout = args.suspend_timeout.or(self.suspend_timeout);
+        validate_suspend_timeout(suspend_timeout)?;
+        self.env.validate(self.pg_version)?;
+        let compute_ctl = self.env.neon_distrib_dir.join("compute_ctl");
+        let features = self.compute_features(&compute_ctl, args.ignore_unsupported_features)?;
+        if let Some(token) = &args.auth_token {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs();
+            if let Some(warning) = storage_token_expiry_warning(token, suspend_timeout, now) {
+                eprintln!("Warning: {warning}");
+            }
+        }
+
+        self.write_managed_pg_conf()?;
+        let postgresql_conf = self.read_postgresql_conf(args.skip_conf_validation)?;
+        Self::print_pg_conf_changes(self.last_applied_pg_conf().as_deref(), &postgresql_conf);
+
+        // We always start the compute node from scratch, so if the Postgres
+        // data dir exists from a previous launch, remove it first.
+        if self.pgdata().exists() {
+            std::fs::remove_dir_all(self.pgdata())?;
+        }
+
+        let safekeeper_connstrings = self.build_safekeepers_connstrs(args.safekeepers.clone())?;
+
+        // check for file remote_extensions_spec.json
+        // if it is present, read it and pass to compute_ctl
+        let remote_extensions_spec_path = self.endpoint_path().join("remote_extensions_spec.json");
+        let remote_extensions_spec = std::fs::File::open(remote_extensions_spec_path);
+        let remote_extensions: Option<RemoteExtSpec>;
+
+        if let Ok(spec_file) = remote_extensions_spec {
+            remote_extensions = serde_json::from_reader(spec_file).ok();
+        } else {
+            remote_extensions = None;
+        };
+
+        // Create spec file
+        drop(prepare_entered);
+        timer
+            .lock()
+            .unwrap()
+            .advance(&self.endpoint_id, StartPhase::Spec);
+        let spec_span = timer.lock().unwrap().span();
+        let spec_entered = spec_span.enter();
+        let format_version = negotiate_spec_format_version(&compute_ctl)?;
+        let spec = SpecBuilder::new(self, format_version)
+            .with_features(features)
+            .with_postgresql_conf(postgresql_conf)
+            .with_pageservers(&args.pageservers, Some(shard_stripe_size))
+            .with_safekeepers(safekeeper_connstrings)
+            .with_test_user(args.create_test_user)
+            .with_storage_tokens(args.auth_token.clone())
+            .with_remote_extensions(remote_extensions)
+            .with_suspend_timeout(suspend_timeout)
+            .build()?;
+        let pageserver_connstring = spec.pageserver_connstring.clone().unwrap_or_default();
+        let spec_path = self.endpoint_path().join("spec.json");
+        std::fs::write(spec_path, serde_json::to_string_pretty(&spec)?)?;
+
+        // Launch compute_ctl
+        let conn_str = self.connstr("cloud_admin", "postgres");
+        println!("Starting postgres node at '{}'", conn_str);
+        if args.create_test_user {
+            let conn_str = self.connstr("test", "neondb");
+            println!("Also at '{}'", conn_str);
+        }
+        let mut compute_ctl_args = vec![
+            "--http-port".to_string(),
+            self.http_address.port().to_string(),
+            "--http-addr".to_string(),
+            self.http_address.ip().to_string(),
+            "--pgdata".to_string(),
+            self.pgdata().to_str().unwrap().to_string(),
+            "--connstr".to_string(),
+            conn_str,
+            "--spec-path".to_string(),
+            self.endpoint_path()
+                .join("spec.json")
+                .to_str()
+                .unwrap()
+                .to_string(),
+            "--pgbin".to_string(),
+            self.env
+                .pg_bin_dir(self.pg_version)?
+                .join("postgres")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ];
+        if let Some(remote_ext_config) = &args.remote_ext_config {
+            compute_ctl_args.extend(["--remote-ext-config".to_string(), remote_ext_config.clone()]);
+        }
+
+        drop(spec_entered);
+        timer
+            .lock()
+            .unwrap()
+            .advance(&self.endpoint_id, StartPhase::Spawn);
+        let timer_ref = &timer;
+        let start_compute_ctl = background_process::start_process_with_options(
+            "compute_ctl",
+            &self.endpoint_path(),
+            &self.env.neon_distrib_dir.join("compute_ctl"),
+            &compute_ctl_args,
+            envs,
+            background_process::InitialPidFile::Create(self.compute_ctl_pid_file()?),
+            &COMPUTE_CTL_START_TIMEOUT,
+            // The loop is in start_process_with_options, which also watches the process
+            || async move {
+                // Only called once the process runs
+                timer_ref
+                    .lock()
+                    .unwrap()
+                    .advance(&self.endpoint_id, StartPhase::WaitForInit);
+                match self.get_status().await {
+                    Ok(state) => {
+                        timer_ref
+                            .lock()
+                            .unwrap()
+                            .advance(&self.endpoint_id, StartPhase::WaitForRunning);
+                        match running_decision(&state) {
+                            WaitDecision::Continue => Ok(false),
+                            WaitDecision::Done => Ok(true),
+                            WaitDecision::Fail(reason) => Err(anyhow!(reason)),
+                        }
+                    }
+                    // The HTTP server of compute_ctl is not up yet
+                    Err(_) => Ok(false),
+                }
+            },
+            background_process::ProcessOptions {
+                log_file_name: Some(
+                    if self.split_logs {
+                        COMPUTE_CTL_LOG
+                    } else {
+                        COMPUTE_LOG
+                    }
+                    .to_string(),
+                ),
+                inherit_env: true,
+            },
+        );
+        // The phase changes while compute_ctl starts, so enter its span on every poll
+        let started_compute_ctl = {
+            let mut start_compute_ctl = std::pin::pin!(start_compute_ctl);
+            std::future::poll_fn(|cx| {
+                let span = timer_ref.lock().unwrap().span();
+                let _entered = span.enter();
+                start_compute_ctl.as_mut().poll(cx)
+            })
+            .await
+        };
+        started_compute_ctl.inspect_err(|_| match self.log_tail(START_FAILURE_LOG_LINES) {
+            Ok(tail) if !tail.is_empty() => eprintln!(
+                "compute_ctl of endpoint {} failed to start, last lines of its logs:\n{}",
+                self.endpoint_id,
+                tail.join("\n")
+            ),
+            Ok(_) => {}
+            Err(e) => eprintln!(
+                "failed to read the logs of endpoint {}: {e:#}",
+                self.endpoint_id
+            ),
+        })?;
+        *started = true;
+        let timings = timer.into_inner().unwrap().finish();
+
+        // The metrics are informational: a compute that doesn't report them has still started
+        let mut metrics = self.get_start_metrics().await.unwrap_or_else(|e| {
+            eprintln!("failed to get startup metrics from compute_ctl: {e:#}");
+            StartMetrics::default()
+        });
+        if let ComputeMode::Static(lsn) = self.mode {
+            metrics.basebackup_lsn = Some(lsn);
+        }
+        metrics.started_by = Some(BinaryVersion::current());
+        println!(
+            "Endpoint {} started: {metrics}. Timings: {timings}",
+            self.endpoint_id
+        );
+        metrics.timings = Some(timings);
+        let last_run_path = self.endpoint_path().join("last_run.json");
+        std::fs::write(&last_run_path, serde_json::to_string_pretty(&metrics)?)
+            .with_context(|| format!("failed to write {}", last_run_path.display()))?;
+        self.stamp_pg_version()?;
+
+        // A wrong pageserver address only shows once a query touches that shard
+        if args.verify_pageserver_connectivity != ConnectivityCheck::Skip {
+            if let Err(e) =
+                check_pageserver_connectivity(&pageserver_connstring, PAGESERVER_PROBE_TIMEOUT)
+                    .await
+            {
+                let e = e.context(format!("endpoint {} is running, but", self.endpoint_id));
+                if args.verify_pageserver_connectivity == ConnectivityCheck::Fail {
+                    return Err(e);
+                }
+                eprintln!("Warning: {e:#}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start Postgres with pg_ctl, without compute_ctl. The data directory is created by
+    /// initdb on the first start, and kept across restarts: there is no storage to
+    /// restore it from.
+    fn start_vanilla(&self, skip_conf_validation: bool) -> Result<()> {
+        self.env.validate(self.pg_version)?;
+        self.write_managed_pg_conf()?;
+        let postgresql_conf = self.read_postgresql_conf(skip_conf_validation)?;
+
+        if !self.pgdata().exists() {
+            let (initdb_path, mut cmd) = self.pg_command("initdb")?;
+            let output = cmd
+                .args(["-D", self.pgdata().to_str().unwrap()])
+                .args(["-U", "cloud_admin", "--auth", "trust"])
+                .output()
+                .with_context(|| format!("{} failed", initdb_path.display()))?;
+            if !output.status.success() {
+                let _ = std::fs::remove_dir_all(self.pgdata());
+                bail!(
+                    "initdb failed, exit code: {}, stderr: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+        }
+        std::fs::write(self.pgdata().join("postgresql.conf"), postgresql_conf)?;
+
+        println!(
+            "Starting vanilla postgres node at '{}'",
+            self.connstr("cloud_admin", "postgres")
+        );
+        let log_file = self.endpoint_path().join(COMPUTE_LOG);
+        self.pg_ctl(&["-l", log_file.to_str().unwrap(), "start"], &None)
+    }
+
+    /// Refuse to start with an older Postgres than the endpoint ran before: its data was
+    /// written in a format the older version can't read. Upgrades are recorded with
+    /// [`ComputeControlPlane::upgrade_pg_version`].
+    fn check_pg_version(&self) -> Result<()> {
+        let path = self.endpoint_path().join(PG_VERSION_STAMP);
+        let stamped: u32 = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .trim()
+                .parse()
+                .with_context(|| format!("failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        if self.pg_version < stamped {
+            bail!(
+                "endpoint {} was started with Postgres {stamped} before, it can't be downgraded to Postgres {}",
+                self.endpoint_id,
+                self.pg_version
+            );
+        }
+        Ok(())
+    }
+
+    /// Record the Postgres version of a successful start, see [`Self::check_pg_version`].
+    fn stamp_pg_version(&self) -> Result<()> {
+        let path = self.endpoint_path().join(PG_VERSION_STAMP);
+        std::fs::write(&path, format!("{}\n", self.pg_version))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// The last `lines` lines of the logs of the endpoint: [`COMPUTE_LOG`], or with split
+    /// logs both [`COMPUTE_CTL_LOG`] and [`POSTGRES_LOG`], merged by their timestamps.
+    /// Missing files count as empty.
+    pub fn log_tail(&self, lines: usize) -> Result<Vec<String>> {
+        let names: &[&str] = if self.split_logs {
+            &[COMPUTE_CTL_LOG, POSTGRES_LOG]
+        } else {
+            &[COMPUTE_LOG]
+        };
+        let mut logs = Vec::new();
+        for name in names {
+            let path = self.endpoint_path().join(name);
+            match std::fs::read(&path) {
+                Ok(contents) => logs.push(String::from_utf8_lossy(&contents).into_owned()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to read {}", path.display()))
+                }
+            }
+        }
+        Ok(merge_log_tails(&logs, lines))
+    }
+
+    /// Startup metrics of the last successful start, None if the endpoint was never started.
+    pub fn last_start_metrics(&self) -> Result<Option<StartMetrics>> {
+        let path = self.endpoint_path().join("last_run.json");
+        match std::fs::read(&path) {
+            Ok(contents) => Ok(Some(
+                serde_json::from_slice(&contents)
+                    .with_context(|| format!("failed to parse {}", path.display()))?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// LSN of the basebackup of the last start: the pinned LSN of static endpoints, and
+    /// the one reported by `compute_ctl` otherwise. None if the endpoint was never
+    /// started, or for replicas.
+    pub fn basebackup_lsn(&self) -> Result<Option<Lsn>> {
+        if let ComputeMode::Static(lsn) = self.mode {
+            return Ok(Some(lsn));
+        }
+        Ok(self
+            .last_start_metrics()?
+            .and_then(|metrics| metrics.basebackup_lsn))
+    }
+
+    /// Where the WAL ended each time the endpoint was stopped cleanly, newest first, up
+    /// to the last [`TERMINATE_LSN_HISTORY`] stops. Immediate stops don't leave a shutdown
+    /// checkpoint, so they aren't recorded.
+    pub fn terminate_lsns(&self) -> Result<Vec<TerminateLsn>> {
+        let path = self.endpoint_path().join(TERMINATE_LSNS);
+        let mut lsns: Vec<TerminateLsn> = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        lsns.reverse();
+        Ok(lsns)
+    }
+
+    /// The control file and signal file of the data directory, read without starting
+    /// Postgres. Meant for stopped endpoints: for running ones, the control file lags
+    /// behind until the next checkpoint.
+    pub fn inspect_pgdata(&self) -> Result<PgDataInfo> {
+        let pgdata = self.pgdata();
+        if !pgdata.join("global").join("pg_control").exists() {
+            bail!(
+                "endpoint {} has no data directory at {}, it was never started",
+                self.endpoint_id,
+                pgdata.display()
+            );
+        }
+        let mut info = parse_controldata(&self.pg_controldata()?)?;
+        for file in SIGNAL_FILES {
+            let path = pgdata.join(file);
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => {
+                    let prev_lsn = parse_neon_signal(&contents)
+                        .with_context(|| format!("failed to parse {}", path.display()))?;
+                    info.signal = Some(NeonSignal {
+                        file: file.to_string(),
+                        prev_lsn,
+                    });
+                    break;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to read {}", path.display()))
+                }
+            }
+        }
+        Ok(info)
+    }
+
+    /// Output of pg_controldata for the data directory.
+    fn pg_controldata(&self) -> Result<String> {
+        let (pg_controldata_path, mut cmd) = self.pg_command("pg_controldata")?;
+        let output = cmd
+            .arg("-D")
+            .arg(self.pgdata())
+            .output()
+            .with_context(|| format!("{} failed", pg_controldata_path.display()))?;
+        if !output.status.success() {
+            bail!(
+                "pg_controldata failed, exit code: {}, stderr: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Add the shutdown checkpoint of a stop in `mode` to [`TERMINATE_LSNS`].
+    fn record_terminate_lsn(&self, mode: &str) -> Result<()> {
+        let Some(lsn) = shutdown_checkpoint_lsn(&self.pg_controldata()?)? else {
+            return Ok(());
+        };
+        let mut lsns = self.terminate_lsns()?;
+        lsns.reverse();
+        lsns.push(TerminateLsn {
+            lsn,
+            timestamp_ms: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis() as u64,
+            mode: mode.to_string(),
+        });
+        let excess = lsns.len().saturating_sub(TERMINATE_LSN_HISTORY);
+        lsns.drain(..excess);
+        let path = self.endpoint_path().join(TERMINATE_LSNS);
+        std::fs::write(&path, serde_json::to_string_pretty(&lsns)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Add how the endpoint was stopped to last_run.json.
+    fn record_stop(&self, skip_safekeeper_sync: bool) -> Result<()> {
+        let mut metrics = self.last_start_metrics()?.unwrap_or_default();
+        metrics.skip_safekeeper_sync = Some(skip_safekeeper_sync);
+        let path = self.endpoint_path().join("last_run.json");
+        std::fs::write(&path, serde_json::to_string_pretty(&metrics)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    // Call the /metrics.json HTTP API
+    async fn get_start_metrics(&self) -> Result<StartMetrics> {
+        let response = self
+            .http_client()
+            .get(self.http_url("metrics.json"))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    /// Client for the HTTP API of `compute_ctl`, see [`Self::http_url`].
+    pub fn http_client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .timeout(COMPUTE_CTL_HTTP_TIMEOUT)
+            .build()
+            .expect("failed to build http client")
+    }
+
+    /// Where to reach the HTTP API of `compute_ctl`: its bind address, or localhost if
+    /// it listens on all interfaces.
+    pub fn http_connect_address(&self) -> SocketAddr {
+        let ip = match self.http_address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        SocketAddr::new(ip, self.http_address.port())
+    }
+
+    /// URL of `path` in the HTTP API of `compute_ctl`.
+    pub fn http_url(&self, path: &str) -> String {
+        format!(
+            "http://{}/{}",
+            self.http_connect_address(),
+            path.trim_start_matches('/')
+        )
+    }
+
+    // Call the /status HTTP API
+    pub async fn get_status(&self) -> Result<ComputeState> {
+        http_hooks::inject(HttpCall::Status, self.http_hooks.before(HttpCall::Status)).await?;
+        let response = self
+            .http_client()
+            .get(self.http_url("status"))
+            .send()
+            .await?;
+        let body = self.read_response(HttpCall::Status, response).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Poll the status of `compute_ctl` every `interval` until `decide` is done with it,
+    /// and return that status. Failures to get the status are retried, as the HTTP server
+    /// of `compute_ctl` may not be up yet, until `timeout`.
+    pub async fn wait_for_status(
+        &self,
+        decide: impl Fn(&ComputeState) -> WaitDecision,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<ComputeState> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let last = match self.get_status().await {
+                Ok(state) => match decide(&state) {
+                    WaitDecision::Done => return Ok(state),
+                    WaitDecision::Fail(reason) => bail!(reason),
+                    WaitDecision::Continue => format!("{:?}", state.status),
+                },
+                Err(e) => format!("{e:#}"),
+            };
+            if tokio::time::Instant::now() + interval > deadline {
+                bail!(
+                    "compute_ctl of endpoint {} didn't get there within {}, last status: {last}",
+                    self.endpoint_id,
+                    humantime::format_duration(timeout)
+                );
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Wait for a starting compute to run, see [`Self::wait_for_status`].
+    pub async fn wait_until_running(&self, timeout: Duration) -> Result<ComputeState> {
+        self.wait_for_status(running_decision, timeout, COMPUTE_CTL_STATUS_POLL_INTERVAL)
+            .await
+    }
+
+    /// The body of a successful `response` to `call`, after the `after` hook.
+    async fn read_response(&self, call: HttpCall, response: reqwest::Response) -> Result<String> {
+        let status = response.status();
+        let url = response.url().to_owned();
+        let (status, body) = match response.text().await {
+            Ok(body) => self.http_hooks.after(call, status, body),
+            Err(e) if status.is_success() => return Err(e.into()),
+            Err(_) => bail!("Http error ({}) at {}.", status.as_u16(), url),
+        };
+        if status.is_client_error() || status.is_server_error() {
+            // reqwest does not export its error construction utility functions, so let's craft the message ourselves
+            bail!("Error: {body}");
+        }
+        Ok(body)
+    }
+
+    pub async fn reconfigure(
+        &self,
+        pageservers: Vec<(Host, u16)>,
+        stripe_size: Option<ShardStripeSize>,
+        safekeepers: Option<Vec<NodeId>>,
+        skip_conf_validation: bool,
+    ) -> Result<()> {
+        self.reconfigure_with_progress(
+            pageservers,
+            stripe_size,
+            safekeepers,
+            skip_conf_validation,
+            |_| {},
+        )
+        .await
+    }
+
+    /// [`Self::reconfigure`], calling `progress` with each new status of the compute
+    /// while it applies the spec.
+    pub async fn reconfigure_with_progress(
+        &self,
+        mut pageservers: Vec<(Host, u16)>,
+        stripe_size: Option<ShardStripeSize>,
+        safekeepers: Option<Vec<NodeId>>,
+        skip_conf_validation: bool,
+        progress: impl Fn(ComputeStatus),
+    ) -> Result<()> {
+        self.check_not_vanilla("reconfiguration")?;
+        self.check_interrupted_reconfigure();
+        let spec: ComputeSpec = {
+            let spec_path = self.endpoint_path().join("spec.json");
+            let file = std::fs::File::open(spec_path)?;
+            serde_json::from_reader(file)?
+        };
+
+        let postgresql_conf = self.read_postgresql_conf(skip_conf_validation)?;
+        Self::print_pg_conf_changes(spec.cluster.postgresql_conf.as_deref(), &postgresql_conf);
+
+        // If we weren't given explicit pageservers, query the storage controller
+        if pageservers.is_empty() {
+            let storage_controller = StorageController::from_env(&self.env);
+            let locate_result = storage_controller.tenant_locate(self.tenant_id).await?;
+            pageservers = locate_result
+                .shards
+                .into_iter()
+                .map(|shard| {
+                    (
+                        Host::parse(&shard.listen_pg_addr)
+                            .expect("Storage controller reported bad hostname"),
+                        shard.listen_pg_port,
+                    )
+                })
+                .collect::<Vec<_>>();
+        }
+
+        let pageserver_connstr = Self::build_pageserver_connstr(&pageservers);
+        let conninfo_diff = diff_conninfo(
+            &PageserverConnInfo::parse(
+                spec.pageserver_connstring.as_deref().unwrap_or_default(),
+                &self.env.pageservers,
+            ),
+            &PageserverConnInfo::parse(&pageserver_connstr, &self.env.pageservers),
+        );
+        if !conninfo_diff.is_empty() {
+            println!("Pageservers changed: {conninfo_diff}");
+        }
+        let mut builder = SpecBuilder::from_spec(spec)
+            .with_postgresql_conf(postgresql_conf)
+            .with_pageservers(&pageservers, stripe_size.map(|s| s.0 as usize));
+
+        // If safekeepers are not specified, don't change them.
+        if let Some(safekeepers) = &safekeepers {
+            let safekeeper_connstrings = self.build_safekeepers_connstrs(safekeepers.clone())?;
+            builder = builder.with_safekeepers(safekeeper_connstrings);
+        }
+        let spec = builder.build()?;
+
+        self.configure_and_persist(&serde_json::to_string_pretty(&spec)?, &progress)
+            .await?;
+        match safekeepers {
+            Some(safekeepers) => self.record_safekeepers(safekeepers),
+            None => Ok(()),
+        }
+    }
+
+    /// Give the compute a new storage auth token, e.g. before the current one expires.
+    /// Nothing else in spec.json changes, but compute_ctl only takes whole specs, so
+    /// the rest is sent along as it is.
+    pub async fn rotate_storage_auth_token(&self, new_token: Option<String>) -> Result<()> {
+        self.check_not_vanilla("token rotation")?;
+        self.check_interrupted_reconfigure();
+        let spec_path = self.endpoint_path().join("spec.json");
+        let spec: serde_json::Value = serde_json::from_slice(
+            &std::fs::read(&spec_path)
+                .with_context(|| format!("failed to read {}", spec_path.display()))?,
+        )
+        .with_context(|| format!("failed to parse {}", spec_path.display()))?;
+        let spec = patch_storage_auth_token(spec, new_token)?;
+        self.configure_and_persist(&serde_json::to_string_pretty(&spec)?, &|_| {})
+            .await
+    }
+
+    /// Have compute_ctl create roles and databases on a running primary, or drop
+    /// databases, without restarting it. They are recorded in spec.json once applied.
+    pub async fn apply_cluster_delta(
+        &self,
+        add_roles: Vec<Role>,
+        add_databases: Vec<Database>,
+        drop_databases: Vec<PgIdent>,
+    ) -> Result<()> {
+        self.check_not_vanilla("changing roles and databases")?;
+        if self.mode != ComputeMode::Primary {
+            bail!(
+                "endpoint {} is a {:?} endpoint, only primaries can change their roles and databases",
+                self.endpoint_id,
+                self.mode
+            );
+        }
+        self.check_interrupted_reconfigure();
+        let spec_path = self.endpoint_path().join("spec.json");
+        let mut spec: ComputeSpec = serde_json::from_slice(
+            &std::fs::read(&spec_path)
+                .with_context(|| format!("failed to read {}", spec_path.display()))?,
+        )
+        .with_context(|| format!("failed to parse {}", spec_path.display()))?;
+        merge_cluster_delta(&mut spec.cluster, add_roles, add_databases, &drop_databases)?;
+        // compute_ctl leaves databases that are missing from the spec alone, it only
+        // drops those it is told to
+        let drops = !drop_databases.is_empty();
+        spec.delta_operations = drops.then(|| {
+            drop_databases
+                .into_iter()
+                .map(|name| DeltaOp {
+                    action: "delete_db".to_string(),
+                    name,
+                    new_name: None,
+                })
+                .collect()
+        });
+        self.configure_and_persist(&serde_json::to_string_pretty(&spec)?, &|_| {})
+            .await?;
+        if drops {
+            // Done once, not again by the next reconfiguration
+            spec.delta_operations = None;
+            std::fs::write(&spec_path, serde_json::to_string_pretty(&spec)?)
+                .with_context(|| format!("failed to write {}", spec_path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Send `spec_json` to compute_ctl, and make it the new spec.json once applied.
+    async fn configure_and_persist(
+        &self,
+        spec_json: &str,
+        progress: &impl Fn(ComputeStatus),
+    ) -> Result<()> {
+        // spec.json is only replaced once compute_ctl has accepted the new spec, so
+        // that it keeps describing what the compute runs with. The pending file shows
+        // that a reconfiguration was interrupted, see check_interrupted_reconfigure.
+        let pending_path = self.endpoint_path().join(PENDING_SPEC);
+        std::fs::write(&pending_path, spec_json)
+            .with_context(|| format!("failed to write {}", pending_path.display()))?;
+        let result = self
+            .post_configure(spec_json, COMPUTE_CTL_CONFIGURE_TIMEOUT, progress)
+            .await;
+        match result {
+            Ok(()) => {
+                std::fs::rename(&pending_path, self.endpoint_path().join("spec.json"))
+                    .with_context(|| format!("failed to persist {}", pending_path.display()))?;
+                self.events
+                    .emit(&self.endpoint_id, EndpointEventKind::Reconfigured);
+                Ok(())
+            }
+            Err(e) => {
+                // The error of the reconfiguration is the one to report
+                if let Err(remove_err) = std::fs::remove_file(&pending_path) {
+                    eprintln!("failed to remove {}: {remove_err}", pending_path.display());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Save the safekeepers of a successful reconfiguration in endpoint.json, for the
+    /// next neon_local invocations.
+    fn record_safekeepers(&self, safekeepers: Vec<NodeId>) -> Result<()> {
+        let conf_path = self.endpoint_path().join("endpoint.json");
+        let (mut conf, _) = parse_endpoint_conf(&std::fs::read(&conf_path)?)
+            .with_context(|| format!("failed to parse {}", conf_path.display()))?;
+        conf.last_safekeepers = Some(safekeepers);
+        std::fs::write(&conf_path, serde_json::to_string_pretty(&conf)?)
+            .with_context(|| format!("failed to write {}", conf_path.display()))
+    }
+
+    // Call the /configure HTTP API. It only responds once the compute has applied the
+    // spec, so poll /status in the meantime to report on the progress.
+    async fn post_configure(
+        &self,
+        spec_json: &str,
+        timeout: Duration,
+        progress: &impl Fn(ComputeStatus),
+    ) -> Result<()> {
+        http_hooks::inject(
+            HttpCall::Configure,
+            self.http_hooks.before(HttpCall::Configure),
+        )
+        .await?;
+        let request = self
+            .http_client()
+            .post(self.http_url("configure"))
+            .timeout(timeout)
+            .body(format!("{{\"spec\":{spec_json}}}"))
+            .send();
+        tokio::pin!(request);
+        let mut observed: Vec<ComputeStatus> = Vec::new();
+        let mut poll = tokio::time::interval(COMPUTE_CTL_CONFIGURE_POLL_INTERVAL);
+        let response = loop {
+            tokio::select! {
+                response = &mut request => break response,
+                _ = poll.tick() => {
+                    if let Ok(state) = self.get_status().await {
+                        if observed.last() != Some(&state.status) {
+                            observed.push(state.status);
+                            progress(state.status);
+                        }
+                    }
+                }
+            }
+        };
+        let response =
+            response.with_context(|| format!("compute went through statuses {observed:?}"))?;
+        self.read_response(HttpCall::Configure, response).await?;
+        Ok(())
+    }
+
+    /// Report a reconfiguration that neon_local didn't see through, e.g. because it was
+    /// killed. compute_ctl may or may not have applied its spec, so spec.json might not
+    /// match what the compute runs with.
+    fn check_interrupted_reconfigure(&self) {
+        let pending_path = self.endpoint_path().join(PENDING_SPEC);
+        if pending_path.exists() {
+            eprintln!(
+                "A previous reconfiguration of endpoint {} was interrupted, the compute may be running with the spec in {}",
+                self.endpoint_id,
+                pending_path.display()
+            );
+            if let Err(e) = std::fs::remove_file(&pending_path) {
+                eprintln!("failed to remove {}: {e}", pending_path.display());
+            }
+        }
+    }
+
+    /// Make a running primary reject writes, or accept them again. When turning
+    /// read-only on, `terminate_writers` also terminates the sessions that are in
+    /// the middle of a write transaction; otherwise these can still commit.
+    ///
+    /// The setting is lost when the endpoint is restarted.
+    pub async fn set_read_only(&self, read_only: bool, terminate_writers: bool) -> Result<()> {
+        if self.mode != ComputeMode::Primary {
+            bail!(
+                "endpoint {} is a {:?} endpoint, only primaries can be made read-only",
+                self.endpoint_id,
+                self.mode
+            );
+        }
+        if self.status() != EndpointStatus::Running {
+            bail!("endpoint {} is not running", self.endpoint_id);
+        }
+
+        let client = self.admin_client().await?;
+        let statement = if read_only {
+            "ALTER SYSTEM SET default_transaction_read_only = on"
+        } else {
+            "ALTER SYSTEM RESET default_transaction_read_only"
+        };
+        client.batch_execute(statement).await?;
+        client.batch_execute("SELECT pg_reload_conf()").await?;
+        if read_only && terminate_writers {
+            // Sessions with a transaction id have written something
+            client
+                .batch_execute(
+                    "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+                     WHERE backend_type = 'client backend' AND backend_xid IS NOT NULL \
+                     AND pid <> pg_backend_pid()",
+                )
+                .await?;
+        }
+
+        let marker = self.endpoint_path().join(READ_ONLY_MARKER);
+        if read_only {
+            std::fs::write(&marker, "")
+                .with_context(|| format!("failed to write {}", marker.display()))?;
+            Ok(())
+        } else {
+            self.clear_read_only_marker()
+        }
+    }
+
+    /// Dump database `db` of this running endpoint with pg_dump into `dest_dir`, named
+    /// after the database. The hash is only comparable between plain format dumps,
+    /// the others include the time of the dump.
+    pub fn dump(&self, db: &str, dest_dir: &Path, format: DumpFormat) -> Result<Dump> {
+        let path = dest_dir.join(format!("{db}{}", format.extension()));
+        self.run_dump_tool(
+            "pg_dump",
+            &[
+                format!("--dbname={}", self.connstr("cloud_admin", db)),
+                format!("--format={}", format.flag()),
+            ],
+            path,
+        )
+    }
+
+    /// Dump the roles and tablespaces of this running endpoint with pg_dumpall, into
+    /// globals.sql in `dest_dir`.
+    pub fn dump_all(&self, dest_dir: &Path) -> Result<Dump> {
+        self.run_dump_tool(
+            "pg_dumpall",
+            &[
+                format!("--dbname={}", self.connstr("cloud_admin", "postgres")),
+                "--globals-only".to_string(),
+            ],
+            dest_dir.join("globals.sql"),
+        )
+    }
+
+    fn run_dump_tool(&self, program: &str, args: &[String], path: PathBuf) -> Result<Dump> {
+        if self.status() != EndpointStatus::Running {
+            bail!("endpoint {} is not running", self.endpoint_id);
+        }
+        let (program_path, mut cmd) = self.pg_command(program)?;
+        let output = cmd
+            .args(args)
+            .arg(format!("--file={}", path.display()))
+            .output()
+            .with_context(|| format!("failed to run {}", program_path.display()))?;
+        if !output.status.success() {
+            bail!(
+                "{program} failed, exit code: {}, stderr: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let sha256 = hash_dump(&path)?;
+        Ok(Dump { path, sha256 })
+    }
+
+    /// Write at least `bytes` of WAL on this running primary, into a scratch table that
+    /// is dropped afterwards. Stops early with an error if `cancel` is cancelled.
+    pub async fn generate_wal(
+        &self,
+        bytes: u64,
+        opts: &GenerateWalOptions,
+        cancel: &CancellationToken,
+    ) -> Result<GeneratedWal> {
+        if self.mode != ComputeMode::Primary {
+            bail!(
+                "endpoint {} is a {:?} endpoint, only primaries accept writes",
+                self.endpoint_id,
+                self.mode
+            );
+        }
+        if self.status() != EndpointStatus::Running {
+            bail!("endpoint {} is not running", self.endpoint_id);
+        }
+
+        let client = self.admin_client().await?;
+        let table = format!("neon_local_generate_wal_{}", std::process::id());
+        client
+            .batch_execute(&format!(
+                "DROP TABLE IF EXISTS {table}; CREATE TABLE {table} (id bigint, payload text)"
+            ))
+            .await?;
+
+        let started = std::time::Instant::now();
+        let client_ref = &client;
+        let flush_lsn = || async move {
+            let row = client_ref
+                .query_one("SELECT pg_current_wal_flush_lsn()::text", &[])
+                .await?;
+            let lsn: String = row.get(0);
+            Lsn::from_str(&lsn).map_err(|_| anyhow!("invalid lsn {lsn}"))
+        };
+        let batch = wal_batch_sql(&table, opts.workload, opts.batch_rows.max(1));
+        let result = async {
+            let start_lsn = flush_lsn().await?;
+            loop {
+                let end_lsn = flush_lsn().await?;
+                if end_lsn.0 - start_lsn.0 >= bytes {
+                    return Ok(GeneratedWal {
+                        start_lsn,
+                        end_lsn,
+                        elapsed: started.elapsed(),
+                    });
+                }
+                tokio::select! {
+                    _ = cancel.cancelled() => bail!("generating WAL was cancelled"),
+                    res = client.batch_execute(&batch) => res?,
+                }
+            }
+        }
+        .await;
+
+        // Clean up even if generating failed, unless the connection is gone
+        if let Err(e) = client
+            .batch_execute(&format!("DROP TABLE IF EXISTS {table}"))
+            .await
+        {
+            eprintln!("failed to drop table {table}: {e}");
+        }
+        result
+    }
+
+    /// Check that compute_ctl created the objects used by its availability checks: the
+    /// neon_superuser role and the health_check table. They are only created when
+    /// catalog updates are enabled, see `--update-catalog`.
+    pub async fn verify_availability_objects(&self) -> Result<()> {
+        if self.skip_pg_catalog_updates {
+            bail!(
+                "endpoint {} skips catalog updates, so compute_ctl doesn't create the availability check objects",
+                self.endpoint_id
+            );
+        }
+        if self.status() != EndpointStatus::Running {
+            bail!("endpoint {} is not running", self.endpoint_id);
+        }
+        let client = self.admin_client().await?;
+        let row = client
+            .query_one(
+                "SELECT \
+                    EXISTS (SELECT FROM pg_catalog.pg_roles WHERE rolname = 'neon_superuser'), \
+                    EXISTS (SELECT FROM pg_catalog.pg_tables WHERE tablename = 'health_check')",
+                &[],
+            )
+            .await?;
+        let mut missing = Vec::new();
+        if !row.get::<_, bool>(0) {
+            missing.push("role neon_superuser");
+        }
+        if !row.get::<_, bool>(1) {
+            missing.push("table health_check");
+        } else {
+            let rows = client
+                .query("SELECT FROM health_check WHERE id = 1", &[])
+                .await?;
+            if rows.is_empty() {
+                missing.push("health_check row");
+            }
+        }
+        if !missing.is_empty() {
+            bail!(
+                "endpoint {} is missing availability check objects: {}",
+                self.endpoint_id,
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// The safekeepers of this endpoint in spec.json, and those the compute uses if it is
+    /// running.
+    pub async fn safekeeper_membership(&self) -> Result<SafekeeperMembership> {
+        self.check_not_vanilla("safekeeper membership")?;
+        let spec_path = self.endpoint_path().join("spec.json");
+        let spec: ComputeSpec = serde_json::from_slice(
+            &std::fs::read(&spec_path)
+                .with_context(|| format!("failed to read {}", spec_path.display()))?,
+        )?;
+        let running = if self.status() == EndpointStatus::Running {
+            let client = self.admin_client().await?;
+            let row = client.query_one("SHOW neon.safekeepers", &[]).await?;
+            let safekeepers: String = row.get(0);
+            Some(
+                safekeepers
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|sk| !sk.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        Ok(SafekeeperMembership::new(
+            spec.safekeeper_connstrings,
+            running,
+        ))
+    }
+
+    /// Query the statistics of the neon extension of the running compute. Fails with
+    /// [`NeonExtensionMissing`] if the extension isn't installed.
+    pub async fn neon_stats(&self) -> Result<NeonStats> {
+        self.check_not_vanilla("neon statistics")?;
+        if self.status() != EndpointStatus::Running {
+            bail!("endpoint {} is not running", self.endpoint_id);
+        }
+        let client = self.admin_client().await?;
+        let Some(row) = client
+            .query_opt(
+                "SELECT extversion FROM pg_extension WHERE extname = 'neon'",
+                &[],
+            )
+            .await?
+        else {
+            return Err(NeonExtensionMissing {
+                endpoint_id: self.endpoint_id.clone(),
+            }
+            .into());
+        };
+        let extension_version: String = row.get(0);
+        // neon_get_lfc_stats() appeared in 1.1
+        let lfc_rows = if neon_extension_version_at_least(&extension_version, (1, 1))? {
+            let rows = client
+                .query(
+                    "SELECT stat_name, count FROM neon_get_lfc_stats() AS t(stat_name text, count bigint)",
+                    &[],
+                )
+                .await?;
+            Some(
+                rows.iter()
+                    .map(|row| (row.get(0), row.get(1)))
+                    .collect::<Vec<_>>(),
+            )
+        } else {
+            None
+        };
+        let backpressure_throttling_us: i64 = client
+            .query_one("SELECT backpressure_throttling_time()", &[])
+            .await?
+            .get(0);
+        NeonStats::parse(
+            extension_version,
+            lfc_rows.as_deref(),
+            backpressure_throttling_us,
+        )
+    }
+
+    /// What the compute is doing: its client sessions, and the WAL it wrote since the
+    /// previous call. A replica reports the WAL it replayed. Stopped endpoints report
+    /// as not running.
+    pub async fn activity(&self) -> Result<EndpointActivity> {
+        if self.status() != EndpointStatus::Running {
+            return Ok(EndpointActivity::default());
+        }
+        let client = self.admin_client().await?;
+        let row = client
+            .query_one(
+                "SELECT count(*), EXTRACT(EPOCH FROM max(now() - xact_start))::float8 \
+                 FROM pg_stat_activity \
+                 WHERE backend_type = 'client backend' AND pid <> pg_backend_pid() \
+                 AND application_name NOT IN ('compute_activity_monitor', 'vm-monitor')",
+                &[],
+            )
+            .await?;
+        let client_backends: i64 = row.get(0);
+        let oldest_xact_age_secs: Option<f64> = row.get(1);
+        let lsn: Option<String> = client
+            .query_one(
+                "SELECT (CASE WHEN pg_is_in_recovery() THEN pg_last_wal_replay_lsn() \
+                 ELSE pg_current_wal_insert_lsn() END)::text",
+                &[],
+            )
+            .await?
+            .get(0);
+
+        // Static endpoints replay no WAL
+        let wal_bytes_per_sec = match lsn {
+            Some(lsn) => {
+                let sample = WalSample {
+                    lsn: Lsn::from_str(&lsn)?,
+                    timestamp_ms: SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)?
+                        .as_millis() as u64,
+                };
+                let path = self.endpoint_path().join(WAL_SAMPLE);
+                let rate = WalSample::load(&path).and_then(|earlier| sample.rate_since(&earlier));
+                sample.save(&path)?;
+                rate
+            }
+            None => None,
+        };
+        Ok(EndpointActivity {
+            running: true,
+            client_backends,
+            oldest_xact_age_secs,
+            wal_bytes_per_sec,
+        })
+    }
+
+    /// Percentage of the page reads of the compute served by its local file cache, since
+    /// it started, None if it has served none. Fails if the neon extension is too old to
+    /// tell.
+    pub async fn lfc_hit_ratio(&self) -> Result<Option<f64>> {
+        let client = self.admin_client().await?;
+        let row = client
+            .query_opt(
+                "SELECT file_cache_hit_ratio::float8 FROM neon.neon_stat_file_cache",
+                &[],
+            )
+            .await?;
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    /// Connect to the running compute as the superuser.
+    async fn admin_client(&self) -> Result<tokio_postgres::Client> {
+        let (client, connection) = tokio_postgres::connect(
+            &self.connstr("cloud_admin", "postgres"),
+            tokio_postgres::NoTls,
+        )
+        .await
+        .with_context(|| format!("failed to connect to endpoint {}", self.endpoint_id))?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("connection error: {e}");
+            }
+        });
+        Ok(client)
+    }
+
+    /// Whether the endpoint was made read-only with [`Self::set_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.endpoint_path().join(READ_ONLY_MARKER).exists()
+    }
+
+    fn clear_read_only_marker(&self) -> Result<()> {
+        let marker = self.endpoint_path().join(READ_ONLY_MARKER);
+        match std::fs::remove_file(&marker) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed to remove {}", marker.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Stop Postgres with pg_ctl in shutdown `mode`, then wait for `compute_ctl` to exit.
+    ///
+    /// `compute_ctl` syncs the safekeepers after Postgres stops, which hangs if a
+    /// majority of them is down. With `skip_safekeeper_sync`, it is sent SIGTERM before
+    /// waiting, so it exits without syncing. neon_local always skips the sync when
+    /// destroying the endpoint, as there is nothing left to sync for.
+    pub fn stop(&self, mode: &str, destroy: bool, skip_safekeeper_sync: bool) -> Result<()> {
+        self.events
+            .emit(&self.endpoint_id, EndpointEventKind::Stopping);
+        self.pg_ctl(&["-m", mode, "stop"], &None)?;
+        self.wait_for_compute_ctl_to_exit(skip_safekeeper_sync)?;
+        self.clear_read_only_marker()?;
+        if !destroy {
+            self.record_stop(skip_safekeeper_sync)?;
+            // Informational, the endpoint has stopped either way
+            if let Err(e) = self.record_terminate_lsn(mode) {
+                eprintln!(
+                    "Failed to record where the WAL of endpoint {} ended: {e:#}",
+                    self.endpoint_id
+                );
+            }
+        }
+        if destroy {
+            println!(
+                "Destroying postgres data directory '{}'",
+                self.pgdata().to_str().unwrap()
+            );
+            let _lock = endpoints_lock::lock(&self.env.endpoints_path())?;
+            std::fs::remove_dir_all(self.endpoint_path())?;
+            port_registry(&self.env).release(&self.endpoint_id)?;
+        }
+        self.events
+            .emit(&self.endpoint_id, EndpointEventKind::Stopped);
+        Ok(())
+    }
+
+    pub fn connstr(&self, user: &str, db_name: &str) -> String {
+        format!(
+            "postgresql://{}@{}:{}/{}",
+            user,
+            self.pg_address.ip(),
+            self.pg_address.port(),
+            db_name
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use camino_tempfile::Utf8TempDir;
+
+    use crate::local_env::{
+        EndpointDefaults, NeonBroker, NeonStorageControllerConf, SafekeeperConf,
+    };
+
+    /// A [`LocalEnv`] in a temporary directory, which is removed with the returned guard.
+    fn test_env() -> (Utf8TempDir, LocalEnv) {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let env = test_env_at(dir.path().as_std_path().to_path_buf());
+        (dir, env)
+    }
+
+    fn test_env_at(base_data_dir: PathBuf) -> LocalEnv {
+        LocalEnv {
+            base_data_dir,
+            pg_distrib_dir: PathBuf::new(),
+            neon_distrib_dir: PathBuf::new(),
+            default_tenant_id: None,
+            private_key_path: PathBuf::new(),
+            broker: NeonBroker::default(),
+            storage_controller: NeonStorageControllerConf::default(),
+            pageservers: Vec::new(),
+            safekeepers: Vec::new(),
+            control_plane_api: None,
+            control_plane_compute_hook_api: None,
+            endpoint_defaults: EndpointDefaults::default(),
+            branch_name_mappings: Default::default(),
+            token_signer: Default::default(),
+        }
+    }
+
+    fn test_endpoint(
+        endpoint_id: &str,
+        timeline_id: TimelineId,
+        mode: ComputeMode,
+    ) -> Arc<Endpoint> {
+        let mut endpoint = test_endpoint_in(&test_env_at(PathBuf::from("/tmp/.neon")), endpoint_id);
+        endpoint.timeline_id = timeline_id;
+        endpoint.mode = mode;
+        Arc::new(endpoint)
+    }
+
+    /// A primary endpoint in `env`, not shared yet so that tests can adjust it.
+    fn test_endpoint_in(env: &LocalEnv, endpoint_id: &str) -> Endpoint {
+        Endpoint {
+            endpoint_id: endpoint_id.to_string(),
+            tenant_id: TenantId::from_array([1; 16]),
+            timeline_id: TimelineId::from_array([1; 16]),
+            mode: ComputeMode::Primary,
+            pg_address: "127.0.0.1:1".parse().unwrap(),
+            http_address: "127.0.0.1:2".parse().unwrap(),
+            pg_version: 15,
+            env: env.clone(),
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+            http_hooks: Arc::new(http_hooks::NoHooks),
+            events: EventSinks::default(),
+        }
+    }
+
+    /// Start arguments for an unsharded tenant on a single pageserver, without safekeepers.
+    fn test_start_args() -> EndpointStartArgs {
+        EndpointStartArgs {
+            auth_token: None,
+            safekeepers: Vec::new(),
+            pageservers: vec![(Host::parse("localhost").unwrap(), 1)],
+            remote_ext_config: None,
+            shard_stripe_size: 0,
+            create_test_user: false,
+            suspend_timeout: None,
+            skip_conf_validation: false,
+            verify_pageserver_connectivity: ConnectivityCheck::Skip,
+            ignore_unsupported_features: false,
+            log_level: None,
+            stripe_size_override: None,
+        }
+    }
+
+    #[test]
+    fn compute_ctl_http_url() {
+        let endpoint = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Primary);
+        assert_eq!(endpoint.http_url("status"), "http://127.0.0.1:2/status");
+        assert_eq!(
+            endpoint.http_url("/configure"),
+            "http://127.0.0.1:2/configure"
+        );
+    }
+
+    #[test]
+    fn stripe_size_override() {
+        let endpoint = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Primary);
+        let pageserver = (Host::parse("localhost").unwrap(), 6400);
+        let args = |pageservers: Vec<(Host, u16)>, stripe_size_override| EndpointStartArgs {
+            pageservers,
+            shard_stripe_size: 32768,
+            stripe_size_override,
+            ..test_start_args()
+        };
+        let spec = |args: &EndpointStartArgs| {
+            SpecBuilder::new(&endpoint, 1.0)
+                .with_pageservers(
+                    &args.pageservers,
+                    Some(args.effective_shard_stripe_size().unwrap()),
+                )
+                .build()
+                .unwrap()
+        };
+
+        let unsharded = args(vec![pageserver.clone()], None);
+        assert_eq!(spec(&unsharded).shard_stripe_size, Some(32768));
+        let overridden = args(vec![pageserver.clone()], Some(ShardStripeSize(8)));
+        assert_eq!(spec(&overridden).shard_stripe_size, Some(8));
+
+        // The shards of a split tenant are striped already
+        let sharded = args(
+            vec![pageserver.clone(), pageserver.clone()],
+            Some(ShardStripeSize(8)),
+        );
+        assert_eq!(
+            sharded
+                .effective_shard_stripe_size()
+                .unwrap_err()
+                .to_string(),
+            "the tenant has 2 shards, the compute must use the stripe size they were split with"
+        );
+        assert_eq!(
+            args(vec![pageserver.clone(), pageserver], None)
+                .effective_shard_stripe_size()
+                .unwrap(),
+            32768
+        );
+        args(Vec::new(), Some(ShardStripeSize(8)))
+            .effective_shard_stripe_size()
+            .unwrap_err();
+    }
+
+    #[test]
+    fn spec_builder() {
+        let endpoint = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Primary);
+        let pageservers = [
+            (Host::parse("localhost").unwrap(), 6400),
+            (Host::parse("127.0.0.1").unwrap(), 6401),
+        ];
+
+        // Without pageservers there is nothing to start
+        SpecBuilder::new(&endpoint, 1.0).build().unwrap_err();
+        SpecBuilder::new(&endpoint, 1.0)
+            .with_pageservers(&[], Some(8))
+            .build()
+            .unwrap_err();
+
+        let spec = SpecBuilder::new(&endpoint, 1.0)
+            .with_postgresql_conf("port=1\n".to_string())
+            .with_pageservers(&pageservers, Some(8))
+            .with_safekeepers(vec!["127.0.0.1:5454".to_string()])
+            .with_test_user(true)
+            .with_storage_tokens(Some("token".to_string()))
+            .build()
+            .unwrap();
+        assert_eq!(spec.format_version, 1.0);
+        assert_eq!(spec.tenant_id, Some(endpoint.tenant_id));
+        assert_eq!(spec.timeline_id, Some(endpoint.timeline_id));
+        assert!(spec.skip_pg_catalog_updates);
+        assert_eq!(spec.cluster.postgresql_conf.as_deref(), Some("port=1\n"));
+        assert_eq!(
+            spec.pageserver_connstring.as_deref(),
+            Some("postgresql://no_user@localhost:6400,postgresql://no_user@127.0.0.1:6401")
+        );
+        assert_eq!(spec.shard_stripe_size, Some(8));
+        assert_eq!(spec.safekeeper_connstrings, ["127.0.0.1:5454"]);
+        assert_eq!(spec.storage_auth_token.as_deref(), Some("token"));
+        assert_eq!(spec.cluster.roles[0].name, "test");
+        assert_eq!(spec.cluster.databases[0].name, "neondb");
+        assert_eq!(spec.suspend_timeout_seconds, -1);
+
+        // Reconfiguring keeps what isn't set again
+        let respec = SpecBuilder::from_spec(spec.clone())
+            .with_pageservers(&pageservers[..1], None)
+            .build()
+            .unwrap();
+        assert_eq!(
+            respec.pageserver_connstring.as_deref(),
+            Some("postgresql://no_user@localhost:6400")
+        );
+        assert_eq!(respec.shard_stripe_size, Some(8));
+        assert_eq!(respec.safekeeper_connstrings, spec.safekeeper_connstrings);
+        assert_eq!(respec.storage_auth_token, spec.storage_auth_token);
+        assert_eq!(respec.cluster.roles.len(), 1);
+
+        let respec = SpecBuilder::from_spec(spec)
+            .with_test_user(false)
+            .with_safekeepers(Vec::new())
+            .with_storage_tokens(None)
+            .with_suspend_timeout(Some(Duration::from_secs(300)))
+            .build()
+            .unwrap();
+        assert!(respec.cluster.roles.is_empty());
+        assert!(respec.cluster.databases.is_empty());
+        assert!(respec.safekeeper_connstrings.is_empty());
+        assert_eq!(respec.storage_auth_token, None);
+        assert_eq!(respec.suspend_timeout_seconds, 300);
+    }
+
+    #[test]
+    fn endpoint_id_validation() {
+        let longest = "a".repeat(63);
+        let too_long = "a".repeat(64);
+        for valid in ["ep-main", "primary", "ep_1", "0", longest.as_str()] {
+            validate_endpoint_id(valid).unwrap();
+        }
+        for (invalid, error) in [
+            ("", "must not be empty"),
+            (".", "is reserved"),
+            ("..", "is reserved"),
+            ("ep/1", "contains '/'"),
+            ("../ep", "contains '.'"),
+            ("ep 1", "contains ' '"),
+            ("Ep-1", "contains 'E'"),
+            ("ep-ä", "contains 'ä'"),
+            (too_long.as_str(), "longer than 63 characters"),
+        ] {
+            let err = validate_endpoint_id(invalid).unwrap_err().to_string();
+            assert!(err.contains(error), "{invalid}: {err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn orphaned_postgres() {
+        let (_dir, env) = test_env();
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        for (endpoint_id, port) in [("ep-1", 1), ("ep-2", 3)] {
+            let ep = cplane
+                .create_endpoint(EndpointConf {
+                    endpoint_id: endpoint_id.to_string(),
+                    tenant_id: Some(TenantId::from_array([1; 16])),
+                    timeline_id: Some(TimelineId::from_array([1; 16])),
+                    mode: ComputeMode::Primary,
+                    pg_port: port,
+                    http_port: port + 1,
+                    pg_version: 15,
+                    skip_pg_catalog_updates: true,
+                    features: Vec::new(),
+                    suspend_timeout: None,
+                    vanilla: false,
+                    last_safekeepers: None,
+                    profiles: Vec::new(),
+                    created_by: None,
+                    stream_from: None,
+                    split_logs: false,
+                })
+                .unwrap();
+            std::fs::create_dir_all(ep.pgdata()).unwrap();
+        }
+        let ep1 = Arc::clone(&cplane.endpoints["ep-1"]);
+        let ep2 = Arc::clone(&cplane.endpoints["ep-2"]);
+
+        // A stand-in for Postgres, running in the data directory of ep-1. ep-2 has a
+        // pid file with the same pid, which must not be mistaken for its Postgres.
+        let mut postgres = Command::new("sleep")
+            .arg("60")
+            .current_dir(ep1.pgdata())
+            .spawn()
+            .unwrap();
+        let pid = postgres.id() as i32;
+        for ep in [&ep1, &ep2] {
+            std::fs::write(
+                ep.pgdata().join("postmaster.pid"),
+                format!("{pid}\n{}\n1700000000\n", ep.pgdata().display()),
+            )
+            .unwrap();
+        }
+        let reaper = std::thread::spawn(move || postgres.wait().unwrap());
+
+        assert_eq!(ep1.live_postmaster(), Some(Pid::from_raw(pid)));
+        assert_eq!(ep2.live_postmaster(), None);
+        let orphans = cplane.find_orphans();
+        assert_eq!(
+            orphans,
+            [OrphanProcess {
+                endpoint_id: "ep-1".to_string(),
+                pid
+            }]
+        );
+
+        let err = ep1.start(&test_start_args()).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("Postgres with pid {pid}")),
+            "{err}"
+        );
+        assert!(ep1.pgdata().join("postmaster.pid").exists());
+
+        cplane
+            .kill_orphan(&OrphanProcess {
+                endpoint_id: "ep-2".to_string(),
+                pid,
+            })
+            .unwrap_err();
+        assert_eq!(ep1.live_postmaster(), Some(Pid::from_raw(pid)));
+
+        cplane.kill_orphan(&orphans[0]).unwrap();
+        assert!(!reaper.join().unwrap().success());
+        assert_eq!(ep1.live_postmaster(), None);
+        assert!(cplane.find_orphans().is_empty());
+    }
+
+    #[tokio::test]
+    async fn legacy_endpoint_conf() {
+        const V1: &str = include_str!("../test_data/endpoint_legacy_v1.json");
+        const V2: &str = include_str!("../test_data/endpoint_legacy_v2.json");
+        let (v1, translated) = parse_endpoint_conf(V1.as_bytes()).unwrap();
+        assert_eq!(
+            translated,
+            [
+                "renamed name to endpoint_id",
+                "renamed port to pg_port",
+                "set missing skip_pg_catalog_updates to true",
+                "set missing features to []",
+            ]
+        );
+        assert_eq!(v1.endpoint_id, "ep-legacy");
+        assert_eq!((v1.pg_port, v1.http_port), (55432, 55433));
+        assert!(v1.skip_pg_catalog_updates);
+        let (v2, translated) = parse_endpoint_conf(V2.as_bytes()).unwrap();
+        assert_eq!(
+            translated,
+            ["renamed port to pg_port", "set missing features to []"]
+        );
+        assert_eq!(v2.pg_port, 55432);
+        assert!(!v2.skip_pg_catalog_updates);
+
+        // The current format round trips without translations
+        let current = serde_json::to_vec(&v1).unwrap();
+        let (reparsed, translated) = parse_endpoint_conf(&current).unwrap();
+        assert!(translated.is_empty());
+        assert_eq!(reparsed, v1);
+
+        // Loading keeps the legacy file unless rewriting is enabled
+        let (_dir, mut env) = test_env();
+        let conf_path = env.endpoints_path().join("ep-legacy").join("endpoint.json");
+        std::fs::create_dir_all(conf_path.parent().unwrap()).unwrap();
+        std::fs::write(&conf_path, V1).unwrap();
+        let cplane = ComputeControlPlane::load(env.clone()).unwrap();
+        assert_eq!(cplane.endpoints["ep-legacy"].pg_address.port(), 55432);
+        assert_eq!(std::fs::read_to_string(&conf_path).unwrap(), V1);
+        match cplane.endpoints["ep-legacy"].describe().await.conf {
+            Described::Loaded(conf) => assert_eq!(conf, v1),
+            Described::Failed { error } => panic!("{error}"),
+        }
+
+        env.endpoint_defaults.rewrite_legacy_endpoint_conf = Some(true);
+        ComputeControlPlane::load(env).unwrap();
+        let rewritten = std::fs::read(&conf_path).unwrap();
+        let (conf, translated) = parse_endpoint_conf(&rewritten).unwrap();
+        assert!(translated.is_empty());
+        assert_eq!(conf, v1);
+    }
+
+    #[test]
+    fn skip_invalid_endpoint_dirs() {
+        let (_dir, env) = test_env();
+        std::fs::create_dir_all(env.endpoints_path().join("Old Endpoint")).unwrap();
+        let cplane = ComputeControlPlane::load(env).unwrap();
+        assert!(cplane.endpoints.is_empty());
+    }
+
+    #[test]
+    fn pageserver_conninfo_diff() {
+        let pageservers: Vec<PageServerConf> = (1..=4)
+            .map(|id| PageServerConf {
+                id: NodeId(id),
+                listen_pg_addr: format!("127.0.0.1:{}", 64000 + id),
+                ..Default::default()
+            })
+            .collect();
+        let conninfo = |ports: &[u16]| {
+            let connstring = ports
+                .iter()
+                .map(|port| format!("postgresql://no_user@127.0.0.1:{port}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            PageserverConnInfo::parse(&connstring, &pageservers)
+        };
+
+        let before = conninfo(&[64001, 64002, 64003, 64001]);
+        assert_eq!(
+            format_conninfo(&before),
+            "shard 0000: ps 1 postgresql://no_user@127.0.0.1:64001
+shard 0001: ps 2 postgresql://no_user@127.0.0.1:64002
+shard 0002: ps 3 postgresql://no_user@127.0.0.1:64003
+shard 0003: ps 1 postgresql://no_user@127.0.0.1:64001"
+        );
+        assert!(diff_conninfo(&before, &before).is_empty());
+
+        // A migration
+        let after = conninfo(&[64001, 64002, 64003, 64004]);
+        let diff = diff_conninfo(&before, &after);
+        assert_eq!(diff.to_string(), "shard 0003: ps 1 -> ps 4");
+        assert_eq!(
+            serde_json::to_value(&diff).unwrap(),
+            serde_json::json!({
+                "old_shard_count": 4,
+                "new_shard_count": 4,
+                "changed": [{
+                    "shard": 3,
+                    "old": {"node_id": 1, "url": "postgresql://no_user@127.0.0.1:64001"},
+                    "new": {"node_id": 4, "url": "postgresql://no_user@127.0.0.1:64004"},
+                }],
+            })
+        );
+
+        // Shard splits and merges, and pageservers unknown to the environment
+        let unsharded = conninfo(&[64001]);
+        let split = conninfo(&[64001, 64002]);
+        assert_eq!(
+            diff_conninfo(&unsharded, &split).to_string(),
+            "shard count 1 -> 2, shard 0001: none -> ps 2"
+        );
+        assert_eq!(
+            diff_conninfo(&split, &conninfo(&[9999])).to_string(),
+            "shard count 2 -> 1, shard 0000: ps 1 -> postgresql://no_user@127.0.0.1:9999, shard 0001: ps 2 -> none"
+        );
+        assert_eq!(
+            diff_conninfo(&PageserverConnInfo::default(), &unsharded).new_shard_count,
+            1
+        );
+    }
+
+    #[test]
+    fn compute_ctl_log_level() {
+        for valid in [
+            "debug",
+            "INFO",
+            "info,compute_ctl=debug",
+            "tokio_postgres=off,warn",
+        ] {
+            validate_log_filter(valid).unwrap();
+        }
+        for invalid in [
+            "",
+            "verbose",
+            "debug,",
+            "=debug",
+            "compute ctl=debug",
+            "info;warn",
+        ] {
+            assert!(validate_log_filter(invalid).is_err(), "{invalid}");
+        }
+        assert!(compute_ctl_envs(None).unwrap().is_empty());
+        compute_ctl_envs(Some("loud")).unwrap_err();
+
+        // A stand-in for compute_ctl that dumps the environment it was started with
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path();
+        let script = dir.join("compute_ctl");
+        std::fs::write(&script, "#!/bin/sh\nenv > \"$(dirname \"$0\")/env.txt\"\n").unwrap();
+        let status = Command::new("sh")
+            .arg(&script)
+            .env_remove("RUST_LOG")
+            .envs(compute_ctl_envs(Some("info,compute_ctl=debug")).unwrap())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let env = std::fs::read_to_string(dir.join("env.txt")).unwrap();
+        assert!(
+            env.lines()
+                .any(|line| line == "RUST_LOG=info,compute_ctl=debug"),
+            "{env}"
+        );
+    }
+
+    #[test]
+    fn suspend_timeout_in_spec() {
+        assert_eq!(suspend_timeout_seconds(None), -1);
+        assert_eq!(suspend_timeout_seconds(Some(Duration::from_secs(60))), 60);
+        validate_suspend_timeout(None).unwrap();
+        validate_suspend_timeout(Some(Duration::from_secs(5))).unwrap();
+        validate_suspend_timeout(Some(Duration::from_secs(4))).unwrap_err();
+
+        // Endpoints created before the setting existed never suspend
+        let conf: EndpointConf = serde_json::from_value(serde_json::json!({
+            "endpoint_id": "ep",
+            "tenant_id": TenantId::from_array([1; 16]),
+            "timeline_id": TimelineId::from_array([1; 16]),
+            "mode": "Primary",
+            "pg_port": 1,
+            "http_port": 2,
+            "pg_version": 15,
+            "skip_pg_catalog_updates": true,
+            "features": [],
+        }))
+        .unwrap();
+        assert_eq!(conf.suspend_timeout, None);
+    }
+
+    #[test]
+    fn start_metrics_from_compute_ctl() {
+        let reported = compute_api::responses::ComputeMetrics {
+            basebackup_bytes: 1024,
+            basebackup_ms: 20,
+            basebackup_lsn: Some(Lsn(0x1696628)),
+            total_startup_ms: 300,
+            ..Default::default()
+        };
+        let metrics: StartMetrics =
+            serde_json::from_str(&serde_json::to_string(&reported).unwrap()).unwrap();
+        assert_eq!(
+            metrics,
+            StartMetrics {
+                basebackup_bytes: Some(1024),
+                basebackup_ms: Some(20),
+                basebackup_lsn: Some(Lsn(0x1696628)),
+                sync_safekeepers_ms: Some(0),
+                total_startup_ms: Some(300),
+                skip_safekeeper_sync: None,
+                started_by: None,
+                timings: None,
+            }
+        );
+
+        // Fields an older compute_ctl doesn't report are left out
+        let metrics: StartMetrics = serde_json::from_str(r#"{"basebackup_ms": 20}"#).unwrap();
+        assert_eq!(metrics.basebackup_ms, Some(20));
+        assert_eq!(metrics.basebackup_bytes, None);
+        assert_eq!(
+            metrics.to_string(),
+            "basebackup ? bytes at LSN ? in 20ms, sync-safekeepers ?ms, total startup ?ms"
+        );
+    }
+
+    #[test]
+    fn basebackup_lsn() {
+        let (_dir, env) = test_env();
+        let conf = |endpoint_id: &str, mode| EndpointConf {
+            endpoint_id: endpoint_id.to_string(),
+            tenant_id: Some(TenantId::from_array([1; 16])),
+            timeline_id: Some(TimelineId::from_array([1; 16])),
+            mode,
+            pg_port: 1,
+            http_port: 2,
+            pg_version: 15,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        };
+
+        // Static endpoints report their pinned LSN, even before the first start
+        let pinned = Lsn(0x2000028);
+        let ep = Endpoint::from_conf(
+            "ep-static".to_string(),
+            conf("ep-static", ComputeMode::Static(pinned)),
+            &env,
+        )
+        .unwrap();
+        assert_eq!(ep.basebackup_lsn().unwrap(), Some(pinned));
+
+        // Primaries report what compute_ctl returned on the last start
+        let ep = Endpoint::from_conf(
+            "ep-primary".to_string(),
+            conf("ep-primary", ComputeMode::Primary),
+            &env,
+        )
+        .unwrap();
+        assert_eq!(ep.basebackup_lsn().unwrap(), None);
+        std::fs::create_dir_all(ep.endpoint_path()).unwrap();
+        std::fs::write(
+            ep.endpoint_path().join("last_run.json"),
+            r#"{"basebackup_ms": 20, "basebackup_lsn": "0/1696628"}"#,
+        )
+        .unwrap();
+        assert_eq!(ep.basebackup_lsn().unwrap(), Some(Lsn(0x1696628)));
+    }
+
+    #[tokio::test]
+    async fn batched_statuses() {
+        let (_dir, env) = test_env();
+        let mut endpoints: BTreeMap<String, Arc<Endpoint>> = (0..200)
+            .map(|i| {
+                let endpoint_id = format!("ep-stopped-{i}");
+                let endpoint = test_endpoint_in(&env, &endpoint_id);
+                (endpoint_id, Arc::new(endpoint))
+            })
+            .collect();
+
+        // Postgres accepts connections, but compute_ctl never answers
+        let pg = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let http = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut hanging = test_endpoint_in(&env, "ep-hanging");
+        hanging.pg_address = pg.local_addr().unwrap();
+        hanging.http_address = http.local_addr().unwrap();
+        std::fs::create_dir_all(hanging.pgdata()).unwrap();
+        std::fs::write(hanging.pgdata().join("postmaster.pid"), "1").unwrap();
+        endpoints.insert("ep-hanging".to_string(), Arc::new(hanging));
+
+        let cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints,
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let started = std::time::Instant::now();
+        let statuses = cplane.statuses(16, Duration::from_millis(500)).await;
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "took {:?}",
+            started.elapsed()
+        );
+
+        assert_eq!(statuses.len(), 201);
+        for i in 0..200 {
+            let info = statuses[&format!("ep-stopped-{i}")].as_ref().unwrap();
+            assert_eq!(info.status, EndpointStatus::Stopped);
+            assert!(info.compute_status.is_none());
+        }
+        let err = statuses["ep-hanging"].as_ref().unwrap_err();
+        assert_eq!(err.to_string(), "status check timed out after 500ms");
+    }
+
+    #[test]
+    fn concurrent_endpoint_creation() {
+        use std::collections::HashSet;
+
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        std::fs::create_dir_all(dir.join("pg_install").join("v15")).unwrap();
+        let env = LocalEnv {
+            pg_distrib_dir: dir.join("pg_install"),
+            safekeepers: vec![SafekeeperConf::default()],
+            ..env
+        };
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut source_cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let source = source_cplane
+            .new_endpoint(
+                "ep-source",
+                TenantId::from_array([1; 16]),
+                TimelineId::from_array([2; 16]),
+                None,
+                None,
+                15,
+                ComputeMode::Primary,
+                None,
+                None,
+                Vec::new(),
+                None,
+                false,
+            )
+            .unwrap();
+        let snapshot = dir.join("ep-source.tar");
+        source.snapshot(&snapshot, false).unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let env = env.clone();
+                let snapshot = snapshot.clone();
+                std::thread::spawn(move || {
+                    // Each thread acts as a separate neon_local invocation
+                    let mut cplane = ComputeControlPlane {
+                        ports: port_registry(&env),
+                        endpoints: BTreeMap::new(),
+                        env: env.clone(),
+                        events: EventSinks::default(),
+                    };
+                    let mut create = |endpoint_id: &str| {
+                        cplane.new_endpoint(
+                            endpoint_id,
+                            TenantId::from_array([1; 16]),
+                            TimelineId::from_array([1; 16]),
+                            None,
+                            None,
+                            15,
+                            ComputeMode::Primary,
+                            None,
+                            None,
+                            Vec::new(),
+                            None,
+                            false,
+                        )
+                    };
+                    let shared = create("ep-shared").ok();
+                    let mut created: Vec<Arc<Endpoint>> = (0..10)
+                        .map(|j| create(&format!("ep-{i}-{j}")).unwrap())
+                        .collect();
+                    created.extend(shared);
+                    // Restores race with the creations of the other threads
+                    let mut restore = |endpoint_id: &str| {
+                        cplane
+                            .restore_snapshot(&snapshot, Some(endpoint_id), false, false)
+                            .map(|(ep, _)| ep)
+                    };
+                    created.push(restore(&format!("ep-{i}-restored")).unwrap());
+                    created.extend(restore("ep-shared-restored").ok());
+                    created.extend(restore("ep-shared").ok());
+                    created
+                })
+            })
+            .collect();
+        let created: Vec<Arc<Endpoint>> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+
+        // Only one of the threads got each contended id, by creating or restoring it
+        assert_eq!(created.len(), 46);
+        let ids: HashSet<&str> = created.iter().map(|ep| ep.endpoint_id.as_str()).collect();
+        assert_eq!(ids.len(), 46);
+        let ports: HashSet<u16> = created
+            .iter()
+            .chain([&source])
+            .flat_map(|ep| [ep.pg_address.port(), ep.http_address.port()])
+            .collect();
+        assert_eq!(ports.len(), 94);
+        let loaded = ComputeControlPlane::load(env).unwrap();
+        assert_eq!(loaded.endpoints.len(), 47);
+    }
+
+    #[test]
+    fn pg_version_upgrade_and_downgrade() {
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        for version in ["v14", "v15"] {
+            std::fs::create_dir_all(dir.join("pg_install").join(version)).unwrap();
+        }
+        let env = LocalEnv {
+            pg_distrib_dir: dir.join("pg_install"),
+            safekeepers: vec![SafekeeperConf::default()],
+            ..env
+        };
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        // A replica, whose generated settings depend on the version
+        let conf = EndpointConf {
+            endpoint_id: "ep-1".to_string(),
+            tenant_id: Some(TenantId::from_array([1; 16])),
+            timeline_id: Some(TimelineId::from_array([1; 16])),
+            mode: ComputeMode::Replica,
+            pg_port: 1,
+            http_port: 2,
+            pg_version: 14,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        };
+        let ep = cplane.create_endpoint(conf.clone()).unwrap();
+        let managed_conf = |ep: &Endpoint| {
+            std::fs::read_to_string(ep.endpoint_path().join(MANAGED_PG_CONF)).unwrap()
+        };
+        assert!(!managed_conf(&ep).contains("recovery_prefetch"));
+        // As after a start
+        ep.stamp_pg_version().unwrap();
+
+        // Only to newer versions that are installed
+        cplane.upgrade_pg_version("ep-1", 14).unwrap_err();
+        cplane.upgrade_pg_version("ep-1", 16).unwrap_err();
+        let ep = cplane.upgrade_pg_version("ep-1", 15).unwrap();
+        assert_eq!(ep.pg_version, 15);
+        assert!(managed_conf(&ep).contains("recovery_prefetch"));
+        ep.check_pg_version().unwrap();
+        let reloaded = ComputeControlPlane::load(env.clone()).unwrap();
+        assert_eq!(reloaded.endpoints["ep-1"].pg_version, 15);
+
+        // endpoint.json edited back to the old version after a start with the new one
+        ep.stamp_pg_version().unwrap();
+        let downgraded = Endpoint::from_conf(
+            "ep-1".to_string(),
+            EndpointConf {
+                pg_version: 14,
+                ..conf
+            },
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            downgraded.check_pg_version().unwrap_err().to_string(),
+            "endpoint ep-1 was started with Postgres 15 before, it can't be downgraded to Postgres 14"
+        );
+    }
+
+    #[test]
+    fn pg_conf_profiles() {
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        std::fs::create_dir_all(dir.join("pg_install").join("v15")).unwrap();
+        let mut env = LocalEnv {
+            pg_distrib_dir: dir.join("pg_install"),
+            ..env
+        };
+        env.endpoint_defaults
+            .pg_conf
+            .insert("max_connections".to_string(), "20".to_string());
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        std::fs::create_dir_all(env.profiles_path()).unwrap();
+        std::fs::write(
+            env.profile_path("tiny"),
+            "shared_buffers = 128kB\nmax_connections = 10\nwork_mem = 64kB\n",
+        )
+        .unwrap();
+        std::fs::write(
+            env.profile_path("io-stress"),
+            "shared_buffers = 1GB\nport = 9999\n",
+        )
+        .unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+
+        let err = cplane
+            .new_vanilla_endpoint(
+                "ep-0",
+                None,
+                15,
+                vec!["tiny".to_string(), "huge".to_string()],
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains(r#"unknown profile 'huge'"#),
+            "{err}"
+        );
+        assert!(
+            err.to_string()
+                .ends_with(r#"available profiles: ["io-stress", "tiny"]"#),
+            "{err}"
+        );
+        assert!(!env.endpoints_path().join("ep-0").exists());
+
+        cplane
+            .new_endpoint(
+                "ep-1",
+                TenantId::from_array([1; 16]),
+                TimelineId::from_array([1; 16]),
+                Some(1),
+                Some(2),
+                15,
+                ComputeMode::Primary,
+                None,
+                None,
+                vec!["tiny".to_string(), "io-stress".to_string()],
+                None,
+                false,
+            )
+            .unwrap();
+
+        // Defaults < profiles in order < the endpoint's postgresql.conf, and the ports
+        // always win
+        let check = |ep: &Endpoint| {
+            assert_eq!(ep.profiles, ["tiny", "io-stress"]);
+            ep.write_managed_pg_conf().unwrap();
+            let conf = PostgresConf::load(&ep.endpoint_path().join("postgresql.conf")).unwrap();
+            assert_eq!(conf.get("shared_buffers"), Some("1GB"));
+            assert_eq!(conf.get("work_mem"), Some("64kB"));
+            assert_eq!(conf.get("max_connections"), Some("20"));
+            assert_eq!(conf.get("port"), Some("1"));
+            assert_eq!(conf.get("fsync"), Some("off"));
+        };
+        check(&cplane.endpoints["ep-1"]);
+
+        // Recorded in endpoint.json
+        let reloaded = ComputeControlPlane::load(env.clone()).unwrap();
+        check(&reloaded.endpoints["ep-1"]);
+    }
+
+    #[test]
+    fn replica_without_safekeepers() {
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        std::fs::create_dir_all(dir.join("pg_install").join("v15")).unwrap();
+        let env = LocalEnv {
+            pg_distrib_dir: dir.join("pg_install"),
+            ..env
+        };
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let (tenant_id, timeline_id) = (
+            TenantId::from_array([1; 16]),
+            TimelineId::from_array([1; 16]),
+        );
+        let mut create =
+            |endpoint_id: &str, pg_port: u16, mode: ComputeMode, stream_from: Option<&str>| {
+                cplane.new_endpoint(
+                    endpoint_id,
+                    tenant_id,
+                    timeline_id,
+                    Some(pg_port),
+                    Some(pg_port + 1),
+                    15,
+                    mode,
+                    None,
+                    None,
+                    Vec::new(),
+                    stream_from.map(str::to_string),
+                    false,
+                )
+            };
+
+        let err = create("ep-replica", 10, ComputeMode::Replica, None).unwrap_err();
+        let err = err.downcast::<NoSafekeepersError>().unwrap();
+        assert_eq!(err.endpoint_id, "ep-replica");
+        assert!(!env.endpoints_path().join("ep-replica").exists());
+
+        let err = create("ep-replica", 10, ComputeMode::Replica, Some("ep-primary")).unwrap_err();
+        assert_eq!(err.to_string(), "no endpoint ep-primary to stream WAL from");
+        create("ep-primary", 20, ComputeMode::Primary, None).unwrap();
+        let err = create("ep-primary-2", 30, ComputeMode::Primary, Some("ep-primary")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "only replicas can stream from another endpoint"
+        );
+
+        let replica = create("ep-replica", 10, ComputeMode::Replica, Some("ep-primary")).unwrap();
+        let conf = replica.setup_pg_conf().unwrap();
+        assert_eq!(
+            conf.get("primary_conninfo"),
+            Some("host=127.0.0.1 port=20 user=cloud_admin application_name=replica")
+        );
+        assert_eq!(conf.get("primary_slot_name"), None);
+        assert_eq!(conf.get("hot_standby"), Some("on"));
+
+        // Replicas of replicas aren't supported
+        let err = create("ep-replica-2", 40, ComputeMode::Replica, Some("ep-replica")).unwrap_err();
+        assert!(err.to_string().contains("is not a primary"), "{err}");
+
+        // Recorded in endpoint.json
+        let reloaded = ComputeControlPlane::load(env.clone()).unwrap();
+        assert_eq!(
+            reloaded.endpoints["ep-replica"].stream_from.as_deref(),
+            Some("ep-primary")
+        );
+
+        // An endpoint created with safekeepers, configured without them
+        let ep = test_endpoint("ep-orphan", timeline_id, ComputeMode::Replica);
+        let err = ep.setup_pg_conf().unwrap_err();
+        assert!(err.is::<NoSafekeepersError>(), "{err}");
+    }
+
+    #[test]
+    fn terminate_lsn_history() {
+        use std::os::unix::fs::PermissionsExt;
+        let controldata = |state: &str, lsn: &str| {
+            format!("Database cluster state:               {state}\nLatest checkpoint location:           {lsn}\n")
+        };
+        assert_eq!(
+            shutdown_checkpoint_lsn(&controldata("shut down", "0/16B3748")).unwrap(),
+            Some(Lsn(0x16B3748))
+        );
+        assert_eq!(
+            shutdown_checkpoint_lsn(&controldata("shut down in recovery", "1/0")).unwrap(),
+            Some(Lsn(0x1_0000_0000))
+        );
+        assert_eq!(
+            shutdown_checkpoint_lsn(&controldata("in production", "0/16B3748")).unwrap(),
+            None
+        );
+        shutdown_checkpoint_lsn("pg_controldata: error").unwrap_err();
+
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        let bin = dir.join("pg_install").join("v15").join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let controldata_path = dir.join("controldata");
+        let stub = |name: &str, script: &str| {
+            let path = bin.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        stub("pg_ctl", "exit 0");
+        stub(
+            "pg_controldata",
+            &format!("cat {}", controldata_path.display()),
+        );
+        let env = LocalEnv {
+            pg_distrib_dir: dir.join("pg_install"),
+            ..env
+        };
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let ep = cplane
+            .create_endpoint(EndpointConf {
+                endpoint_id: "ep-1".to_string(),
+                tenant_id: Some(TenantId::from_array([1; 16])),
+                timeline_id: Some(TimelineId::from_array([1; 16])),
+                mode: ComputeMode::Primary,
+                pg_port: 1,
+                http_port: 2,
+                pg_version: 15,
+                skip_pg_catalog_updates: true,
+                features: Vec::new(),
+                suspend_timeout: None,
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                created_by: None,
+                stream_from: None,
+                split_logs: false,
+            })
+            .unwrap();
+        std::fs::create_dir_all(ep.pgdata()).unwrap();
+        assert!(ep.terminate_lsns().unwrap().is_empty());
+
+        for (mode, state, lsn) in [
+            ("fast", "shut down", "0/1000028"),
+            // Leaves no shutdown checkpoint
+            ("immediate", "in production", "0/1000028"),
+            ("smart", "shut down", "0/2000028"),
+        ] {
+            std::fs::write(&controldata_path, controldata(state, lsn)).unwrap();
+            ep.stop(mode, false, false).unwrap();
+        }
+        let lsns = ep.terminate_lsns().unwrap();
+        assert_eq!(
+            lsns.iter()
+                .map(|terminate| (terminate.lsn, terminate.mode.as_str()))
+                .collect::<Vec<_>>(),
+            [(Lsn(0x2000028), "smart"), (Lsn(0x1000028), "fast")]
+        );
+        assert!(lsns[0].timestamp_ms >= lsns[1].timestamp_ms);
+    }
+
+    #[test]
+    fn split_log_tail() {
+        assert_eq!(
+            log_timestamp("2026-10-13T10:41:27.123456Z  INFO starting postgres"),
+            Some(humantime::parse_rfc3339("2026-10-13T10:41:27.123456Z").unwrap())
+        );
+        assert_eq!(
+            log_timestamp("PG:2026-10-13 10:41:27.200 UTC [42] LOG:  database system is ready"),
+            Some(humantime::parse_rfc3339("2026-10-13T10:41:27.200Z").unwrap())
+        );
+        assert_eq!(log_timestamp("\tat the second line"), None);
+
+        let compute_ctl_log = "\
+2026-10-13T10:41:27.100000Z  INFO starting compute
+2026-10-13T10:41:27.300000Z  ERROR could not start postgres
+caused by: exit status 1
+2026-10-13T10:41:27.500000Z  INFO shutting down
+";
+        let postgres_log = "\
+2026-10-13 10:41:27.000 UTC [42] LOG:  starting PostgreSQL
+2026-10-13 10:41:27.200 UTC [42] FATAL:  could not load library
+2026-10-13 10:41:27.400 UTC [42] LOG:  database system is shut down
+";
+        let logs = [compute_ctl_log.to_string(), postgres_log.to_string()];
+        assert_eq!(
+            merge_log_tails(&logs, 100),
+            [
+                "2026-10-13 10:41:27.000 UTC [42] LOG:  starting PostgreSQL",
+                "2026-10-13T10:41:27.100000Z  INFO starting compute",
+                "2026-10-13 10:41:27.200 UTC [42] FATAL:  could not load library",
+                "2026-10-13T10:41:27.300000Z  ERROR could not start postgres",
+                "caused by: exit status 1",
+                "2026-10-13 10:41:27.400 UTC [42] LOG:  database system is shut down",
+                "2026-10-13T10:41:27.500000Z  INFO shutting down",
+            ]
+        );
+        assert_eq!(
+            merge_log_tails(&logs, 2),
+            [
+                "2026-10-13 10:41:27.400 UTC [42] LOG:  database system is shut down",
+                "2026-10-13T10:41:27.500000Z  INFO shutting down",
+            ]
+        );
+
+        let (_dir, env) = test_env();
+        let conf = |split_logs| EndpointConf {
+            endpoint_id: "ep-1".to_string(),
+            tenant_id: Some(TenantId::from_array([1; 16])),
+            timeline_id: Some(TimelineId::from_array([1; 16])),
+            mode: ComputeMode::Primary,
+            pg_port: 1,
+            http_port: 2,
+            pg_version: 15,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs,
+        };
+        let combined = Endpoint::from_conf("ep-1".to_string(), conf(false), &env).unwrap();
+        let split = Endpoint::from_conf("ep-1".to_string(), conf(true), &env).unwrap();
+        std::fs::create_dir_all(split.endpoint_path()).unwrap();
+        assert!(split.log_tail(10).unwrap().is_empty());
+
+        std::fs::write(split.endpoint_path().join(COMPUTE_CTL_LOG), compute_ctl_log).unwrap();
+        std::fs::write(split.endpoint_path().join(POSTGRES_LOG), postgres_log).unwrap();
+        std::fs::write(
+            split.endpoint_path().join(COMPUTE_LOG),
+            "older combined log\n",
+        )
+        .unwrap();
+        assert_eq!(split.log_tail(100).unwrap(), merge_log_tails(&logs, 100));
+        assert_eq!(combined.log_tail(100).unwrap(), ["older combined log"]);
+
+        // Postgres itself writes its log lines to postgres.log
+        let managed = split.setup_pg_conf().unwrap();
+        assert_eq!(managed.get("logging_collector"), Some("on"));
+        let log_path = Path::new(managed.get("log_directory").unwrap())
+            .join(managed.get("log_filename").unwrap());
+        assert_eq!(log_path, split.endpoint_path().join(POSTGRES_LOG));
+        assert_eq!(managed.get("log_rotation_size"), Some("0"));
+        assert_eq!(
+            combined.setup_pg_conf().unwrap().get("logging_collector"),
+            None
+        );
+    }
+
+    #[test]
+    fn inspect_pgdata() {
+        use std::os::unix::fs::PermissionsExt;
+        const CONTROLDATA: &str = include_str!("../test_data/pg_controldata_v16.txt");
+        let expected = PgDataInfo {
+            system_identifier: 7409368227466035417,
+            cluster_state: "shut down".to_string(),
+            checkpoint_lsn: Lsn(0x1D8B2A8),
+            redo_lsn: Lsn(0x1D8B2A8),
+            timeline: 1,
+            signal: None,
+        };
+        assert_eq!(parse_controldata(CONTROLDATA).unwrap(), expected);
+        let err = parse_controldata("pg_controldata: error").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pg_controldata shows no 'Database system identifier'"
+        );
+
+        assert_eq!(
+            parse_neon_signal("PREV LSN: 0/16B3748\n").unwrap(),
+            PrevLsn::Lsn(Lsn(0x16B3748))
+        );
+        assert_eq!(parse_neon_signal("PREV LSN: none").unwrap(), PrevLsn::None);
+        assert_eq!(
+            parse_neon_signal("PREV LSN: invalid").unwrap(),
+            PrevLsn::Invalid
+        );
+        parse_neon_signal("PREV LSN: 0/XYZ").unwrap_err();
+        parse_neon_signal("").unwrap_err();
+
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        let bin = dir.join("pg_install").join("v16").join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let controldata_path = dir.join("controldata");
+        std::fs::write(&controldata_path, CONTROLDATA).unwrap();
+        let pg_controldata = bin.join("pg_controldata");
+        std::fs::write(
+            &pg_controldata,
+            format!(
+                "#!/bin/sh\n[ -n \"$LD_LIBRARY_PATH\" ] || exit 1\ncat {}\n",
+                controldata_path.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&pg_controldata, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let env = LocalEnv {
+            pg_distrib_dir: dir.join("pg_install"),
+            ..env
+        };
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let ep = cplane
+            .create_endpoint(EndpointConf {
+                endpoint_id: "ep-1".to_string(),
+                tenant_id: Some(TenantId::from_array([1; 16])),
+                timeline_id: Some(TimelineId::from_array([1; 16])),
+                mode: ComputeMode::Primary,
+                pg_port: 1,
+                http_port: 2,
+                pg_version: 16,
+                skip_pg_catalog_updates: true,
+                features: Vec::new(),
+                suspend_timeout: None,
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                created_by: None,
+                stream_from: None,
+                split_logs: false,
+            })
+            .unwrap();
+
+        let err = ep.inspect_pgdata().unwrap_err();
+        assert!(err.to_string().contains("it was never started"), "{err}");
+
+        std::fs::create_dir_all(ep.pgdata().join("global")).unwrap();
+        std::fs::write(ep.pgdata().join("global").join("pg_control"), "").unwrap();
+        assert_eq!(ep.inspect_pgdata().unwrap(), expected);
+
+        // neon.signal is preferred over the old name
+        std::fs::write(ep.pgdata().join("zenith.signal"), "PREV LSN: none").unwrap();
+        assert_eq!(
+            ep.inspect_pgdata().unwrap().signal,
+            Some(NeonSignal {
+                file: "zenith.signal".to_string(),
+                prev_lsn: PrevLsn::None,
+            })
+        );
+        std::fs::write(ep.pgdata().join("neon.signal"), "PREV LSN: 0/1D8B270\n").unwrap();
+        assert_eq!(
+            ep.inspect_pgdata().unwrap().signal,
+            Some(NeonSignal {
+                file: "neon.signal".to_string(),
+                prev_lsn: PrevLsn::Lsn(Lsn(0x1D8B270)),
+            })
+        );
+    }
+
+    #[test]
+    fn redact_spec_secrets() {
+        let mut spec = serde_json::json!({
+            "storage_auth_token": "secret",
+            "cluster": {
+                "roles": [
+                    {"name": "a", "encrypted_password": "SCRAM-SHA-256$x", "options": null},
+                    {"name": "b", "encrypted_password": null, "options": null},
+                    {"name": "c", "encrypted_password": "SCRAM-SHA-256$x", "options": null},
+                ],
+                "postgresql_conf": "port = 1",
+            },
+        });
+        redact_spec(&mut spec);
+        assert_eq!(spec["storage_auth_token"], "<redacted:2bb80d537b1da3e3>");
+        let roles = &spec["cluster"]["roles"];
+        assert!(roles[0]["encrypted_password"]
+            .as_str()
+            .unwrap()
+            .starts_with(REDACTED_PREFIX));
+        assert_eq!(roles[1]["encrypted_password"], serde_json::Value::Null);
+        // Equal secrets stay equal
+        assert_eq!(
+            roles[0]["encrypted_password"],
+            roles[2]["encrypted_password"]
+        );
+        assert_eq!(spec["cluster"]["postgresql_conf"], "port = 1");
+
+        clear_redacted_secrets(&mut spec);
+        assert_eq!(spec["storage_auth_token"], serde_json::Value::Null);
+        assert_eq!(
+            spec["cluster"]["roles"][0]["encrypted_password"],
+            serde_json::Value::Null
+        );
+    }
+
+    /// Fails when a field that looks like a secret is added to the spec types without
+    /// being classified in [`SPEC_SECRETS`] or [`SPEC_NOT_SECRETS`].
+    #[test]
+    fn spec_secrets_classified() {
+        const SPEC_RS: &str = include_str!("../../libs/compute_api/src/spec.rs");
+        let classified: Vec<&str> = SPEC_SECRETS
+            .iter()
+            .map(|path| path.rsplit('/').next().unwrap())
+            .chain(SPEC_NOT_SECRETS.iter().copied())
+            .collect();
+        let secret_like: Vec<&str> = SPEC_RS
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("pub ")?.split_once(':'))
+            .map(|(name, _)| name)
+            .filter(|name| name.contains("token") || name.contains("password"))
+            .collect();
+        assert!(!secret_like.is_empty());
+        for name in secret_like {
+            assert!(
+                classified.contains(&name),
+                "{name} in compute_api::spec looks like a secret, add it to SPEC_SECRETS or SPEC_NOT_SECRETS"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn describe_golden() {
+        const GOLDEN: &str = include_str!("../test_data/endpoint_describe.json");
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        let conf_json = r#"{"endpoint_id":"ep-1","tenant_id":"01010101010101010101010101010101","timeline_id":"01010101010101010101010101010101","mode":"Primary","pg_port":1,"http_port":1,"pg_version":15,"skip_pg_catalog_updates":true,"features":[],"suspend_timeout":null}"#;
+        let conf: EndpointConf = serde_json::from_str(conf_json).unwrap();
+        let ep = Endpoint::from_conf("ep-1".to_string(), conf, &env).unwrap();
+        std::fs::create_dir_all(ep.pgdata()).unwrap();
+        let path = ep.endpoint_path();
+        std::fs::write(path.join("endpoint.json"), conf_json).unwrap();
+        std::fs::write(
+            path.join("spec.json"),
+            r#"{"pageserver_connstring": "postgresql://no_user@localhost:1", "storage_auth_token": "secret"}"#,
+        )
+        .unwrap();
+        // A corrupt part doesn't fail the rest of the description
+        std::fs::write(path.join("last_run.json"), "{").unwrap();
+        std::fs::write(ep.pgdata().join("PG_VERSION"), "15\n").unwrap();
+
+        let description = serde_json::to_string_pretty(&ep.describe().await).unwrap();
+        assert_eq!(
+            description.replace(dir.to_str().unwrap(), "$DIR"),
+            GOLDEN.trim_end()
+        );
+    }
+
+    #[test]
+    fn http_bind_address() {
+        let conf: EndpointConf = serde_json::from_str(
+            r#"{"endpoint_id":"ep-1","tenant_id":"01010101010101010101010101010101","timeline_id":"01010101010101010101010101010101","mode":"Primary","pg_port":1,"http_port":2,"pg_version":15,"skip_pg_catalog_updates":true,"features":[],"suspend_timeout":null}"#,
+        )
+        .unwrap();
+
+        // Exposed by default, but still reached over localhost
+        let mut env = test_env_at(PathBuf::from("/tmp/.neon"));
+        let ep = Endpoint::from_conf("ep-1".to_string(), conf.clone(), &env).unwrap();
+        assert_eq!(ep.http_address, "0.0.0.0:2".parse().unwrap());
+        assert_eq!(ep.http_connect_address(), "127.0.0.1:2".parse().unwrap());
+        assert_eq!(ep.http_url("/status"), "http://127.0.0.1:2/status");
+
+        env.endpoint_defaults.expose_external_http = Some(false);
+        let ep = Endpoint::from_conf("ep-1".to_string(), conf, &env).unwrap();
+        assert_eq!(ep.http_address, "127.0.0.1:2".parse().unwrap());
+        assert_eq!(ep.http_connect_address(), ep.http_address);
+
+        let mut ep = test_endpoint_in(&env, "ep-2");
+        ep.http_address = "[::]:2".parse().unwrap();
+        assert_eq!(ep.http_url("status"), "http://[::1]:2/status");
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+
+        let conf = EndpointConf {
+            endpoint_id: "ep-1".to_string(),
+            tenant_id: Some(TenantId::from_array([1; 16])),
+            timeline_id: Some(TimelineId::from_array([1; 16])),
+            mode: ComputeMode::Primary,
+            pg_port: 1,
+            http_port: 2,
+            pg_version: 15,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        };
+        let ep = Endpoint::from_conf("ep-1".to_string(), conf.clone(), &env).unwrap();
+        std::fs::create_dir_all(ep.pgdata()).unwrap();
+        let path = ep.endpoint_path();
+        std::fs::write(
+            path.join("endpoint.json"),
+            serde_json::to_string(&conf).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            path.join("spec.json"),
+            r#"{"storage_auth_token": "secret"}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            path.join(PENDING_SPEC),
+            r#"{"storage_auth_token": "secret"}"#,
+        )
+        .unwrap();
+        std::fs::write(path.join("compute_ctl.pid"), "123").unwrap();
+        std::fs::write(ep.pgdata().join("PG_VERSION"), "15").unwrap();
+        cplane.endpoints.insert("ep-1".to_string(), Arc::new(ep));
+
+        let snapshot = dir.join("ep-1.tar");
+        cplane.endpoints["ep-1"].snapshot(&snapshot, true).unwrap();
+
+        // The endpoint still exists
+        cplane
+            .restore_snapshot(&snapshot, None, false, false)
+            .unwrap_err();
+
+        let (restored, _) = cplane
+            .restore_snapshot(&snapshot, Some("ep-2"), false, false)
+            .unwrap();
+        let path = restored.endpoint_path();
+        assert_eq!(path, env.endpoints_path().join("ep-2"));
+        assert_ne!(restored.pg_address.port(), 1);
+        assert_ne!(restored.http_address.port(), 2);
+        let spec = std::fs::read_to_string(path.join("spec.json")).unwrap();
+        assert!(!spec.contains("secret"));
+        assert!(!spec.contains(REDACTED_PREFIX));
+        let pending = std::fs::read_to_string(path.join(PENDING_SPEC)).unwrap();
+        assert!(!pending.contains("secret"));
+        assert!(!path.join("compute_ctl.pid").exists());
+        assert!(restored.pgdata().join("PG_VERSION").exists());
+        assert!(path.join(MANAGED_PG_CONF).exists());
+        let restored_conf: EndpointConf =
+            serde_json::from_slice(&std::fs::read(path.join("endpoint.json")).unwrap()).unwrap();
+        assert_eq!(restored_conf.endpoint_id, "ep-2");
+
+        // Replacing the original endpoint needs force
+        cplane
+            .restore_snapshot(&snapshot, None, true, false)
+            .unwrap();
+        assert_eq!(cplane.endpoints.len(), 2);
+        let leftovers = std::fs::read_dir(env.endpoints_path())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with(".restore")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn restore_remaps_ports() {
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+
+        // A snapshot from another machine, whose ports are taken by ep-1 here
+        let free_port = || {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let (old_pg_port, old_http_port) = (free_port(), free_port());
+        let pg_conf = format!(
+            "# edited\nport = {old_pg_port}\nlisten_addresses = '0.0.0.0'\nwork_mem = 4MB\n"
+        );
+        let ep = cplane
+            .create_endpoint(EndpointConf {
+                endpoint_id: "ep-1".to_string(),
+                tenant_id: Some(TenantId::from_array([1; 16])),
+                timeline_id: Some(TimelineId::from_array([1; 16])),
+                mode: ComputeMode::Primary,
+                pg_port: old_pg_port,
+                http_port: old_http_port,
+                pg_version: 15,
+                skip_pg_catalog_updates: true,
+                features: Vec::new(),
+                suspend_timeout: None,
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                created_by: None,
+                stream_from: None,
+                split_logs: false,
+            })
+            .unwrap();
+        let path = ep.endpoint_path();
+        std::fs::write(path.join("postgresql.conf"), &pg_conf).unwrap();
+        std::fs::create_dir_all(ep.pgdata()).unwrap();
+        std::fs::write(ep.pgdata().join("postgresql.conf"), &pg_conf).unwrap();
+        std::fs::write(
+            path.join("spec.json"),
+            serde_json::json!({"cluster": {"postgresql_conf": pg_conf}}).to_string(),
+        )
+        .unwrap();
+        let snapshot = dir.join("ep-1.tar");
+        ep.snapshot(&snapshot, true).unwrap();
+
+        let err = cplane
+            .restore_snapshot(&snapshot, Some("ep-2"), false, true)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("port {old_pg_port} is used by endpoint ep-1")
+        );
+        assert!(!env.endpoints_path().join("ep-2").exists());
+
+        let (restored, mapping) = cplane
+            .restore_snapshot(&snapshot, Some("ep-2"), false, false)
+            .unwrap();
+        let (pg_port, http_port) = (restored.pg_address.port(), restored.http_address.port());
+        assert_eq!(
+            mapping,
+            [
+                PortMapping {
+                    name: "pg",
+                    old: old_pg_port,
+                    new: pg_port
+                },
+                PortMapping {
+                    name: "http",
+                    old: old_http_port,
+                    new: http_port
+                },
+            ]
+        );
+        assert!(
+            ![old_pg_port, old_http_port].contains(&pg_port)
+                && ![old_pg_port, old_http_port].contains(&http_port)
+        );
+
+        // Every copy of the settings agrees on the new port
+        let path = restored.endpoint_path();
+        let check = |text: &str| {
+            let conf = PostgresConf::parse(text).unwrap();
+            assert_eq!(conf.get("port"), Some(pg_port.to_string().as_str()));
+            assert_eq!(conf.get("listen_addresses"), Some("127.0.0.1"));
+            assert_eq!(conf.get("work_mem"), Some("4MB"));
+            assert!(text.starts_with("# edited\n"), "{text}");
+        };
+        check(&std::fs::read_to_string(path.join("postgresql.conf")).unwrap());
+        check(&std::fs::read_to_string(restored.pgdata().join("postgresql.conf")).unwrap());
+        let spec: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.join("spec.json")).unwrap()).unwrap();
+        check(spec["cluster"]["postgresql_conf"].as_str().unwrap());
+        let managed = PostgresConf::load(&path.join(MANAGED_PG_CONF)).unwrap();
+        assert_eq!(managed.get("port"), Some(pg_port.to_string().as_str()));
+        let restored_conf: EndpointConf =
+            serde_json::from_slice(&std::fs::read(path.join("endpoint.json")).unwrap()).unwrap();
+        assert_eq!(
+            (restored_conf.pg_port, restored_conf.http_port),
+            (pg_port, http_port)
+        );
+
+        // Once the ports are free, they can be kept
+        std::fs::remove_dir_all(env.endpoints_path().join("ep-1")).unwrap();
+        cplane.endpoints.remove("ep-1");
+        let (kept, mapping) = cplane
+            .restore_snapshot(&snapshot, Some("ep-3"), false, true)
+            .unwrap();
+        assert!(mapping.is_empty());
+        assert_eq!(kept.pg_address.port(), old_pg_port);
+        let allocations = cplane.ports.allocations().unwrap();
+        assert_eq!(allocations[&old_pg_port].endpoint_id, "ep-3");
+        assert_eq!(allocations[&old_http_port].endpoint_id, "ep-3");
+        let text = std::fs::read_to_string(kept.endpoint_path().join("postgresql.conf")).unwrap();
+        assert_eq!(text, pg_conf);
+    }
+
+    #[test]
+    fn forced_restore_failure_keeps_endpoint() {
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let free_port = || {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let ep = cplane
+            .create_endpoint(EndpointConf {
+                endpoint_id: "ep-1".to_string(),
+                tenant_id: Some(TenantId::from_array([1; 16])),
+                timeline_id: Some(TimelineId::from_array([1; 16])),
+                mode: ComputeMode::Primary,
+                pg_port: free_port(),
+                http_port: free_port(),
+                pg_version: 15,
+                skip_pg_catalog_updates: true,
+                features: Vec::new(),
+                suspend_timeout: None,
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                created_by: None,
+                stream_from: None,
+                split_logs: false,
+            })
+            .unwrap();
+        let snapshot = dir.join("ep-1.tar");
+        ep.snapshot(&snapshot, false).unwrap();
+        let path = ep.endpoint_path();
+        std::fs::write(path.join("notes"), "not in the snapshot").unwrap();
+        let old_port = ep.pg_address.port();
+        let leftovers = || {
+            std::fs::read_dir(env.endpoints_path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with(".re"))
+                .collect::<Vec<_>>()
+        };
+
+        // The ports can't be kept, after the point where the endpoint used to be deleted
+        let listener = std::net::TcpListener::bind(("127.0.0.1", old_port)).unwrap();
+        let err = cplane
+            .restore_snapshot(&snapshot, None, true, true)
+            .unwrap_err();
+        assert_eq!(err.to_string(), format!("port {old_port} is not free"));
+        assert!(path.join("notes").exists());
+        assert!(cplane.endpoints.contains_key("ep-1"));
+        let allocation = &cplane.ports.allocations().unwrap()[&old_port];
+        assert_eq!(
+            (allocation.endpoint_id.as_str(), allocation.released_at),
+            ("ep-1", None)
+        );
+        assert!(leftovers().is_empty(), "{:?}", leftovers());
+        drop(listener);
+
+        let (restored, _) = cplane
+            .restore_snapshot(&snapshot, None, true, false)
+            .unwrap();
+        assert_eq!(restored.endpoint_path(), path);
+        assert!(!path.join("notes").exists());
+        assert_ne!(restored.pg_address.port(), old_port);
+        let allocations = cplane.ports.allocations().unwrap();
+        assert!(allocations[&old_port].released_at.is_some());
+        assert_eq!(allocations[&restored.pg_address.port()].released_at, None);
+        assert!(leftovers().is_empty(), "{:?}", leftovers());
+    }
+
+    /// Serve HTTP requests on a local port, answering with the status line and body
+    /// that `handler` returns for the request path.
+    fn serve<F>(handler: F) -> u16
+    where
+        F: Fn(&str) -> (&'static str, String) + Send + Sync + 'static,
+    {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handler = Arc::new(handler);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let handler = Arc::clone(&handler);
+                std::thread::spawn(move || {
+                    // Read the whole request, so that the client doesn't see a reset connection
+                    let mut request = Vec::new();
+                    let mut buf = [0; 4096];
+                    loop {
+                        let n = stream.read(&mut buf).unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request).to_lowercase();
+                        if let Some(headers_end) = text.find("\r\n\r\n") {
+                            let content_length = text[..headers_end]
+                                .lines()
+                                .find_map(|line| line.strip_prefix("content-length:"))
+                                .map_or(0, |len| len.trim().parse::<usize>().unwrap());
+                            if request.len() >= headers_end + 4 + content_length {
+                                break;
+                            }
+                        }
+                        if n == 0 {
+                            break;
+                        }
+                    }
+                    let text = String::from_utf8_lossy(&request);
+                    let path = text.split_whitespace().nth(1).unwrap_or_default();
+                    let (status_line, body) = handler(path);
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status_line}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                });
+            }
+        });
+        port
+    }
+
+    /// A compute_ctl that answers /configure with `status_line`, and has no /status.
+    fn serve_configure(status_line: &'static str) -> u16 {
+        serve(move |path| match path {
+            "/configure" => (status_line, "failed".to_string()),
+            _ => ("404 Not Found", String::new()),
+        })
+    }
+
+    fn compute_status_json(status: &str) -> String {
+        format!(r#"{{"status": "{status}", "last_active": null, "error": null}}"#)
+    }
+
+    fn endpoint_with_http_port(http_port: u16) -> Endpoint {
+        let mut endpoint = test_endpoint_in(&test_env_at(PathBuf::from("/tmp/.neon")), "ep");
+        endpoint.http_address = SocketAddr::new("127.0.0.1".parse().unwrap(), http_port);
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn wait_for_status() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Walks through the statuses of a start, one /status call at a time
+        let calls = Arc::new(AtomicUsize::new(0));
+        let port = serve({
+            let calls = Arc::clone(&calls);
+            move |_| {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                let status = ["empty", "init", "init"].get(call).unwrap_or(&"running");
+                ("200 OK", compute_status_json(status))
+            }
+        });
+        let ep = endpoint_with_http_port(port);
+        // Until it runs, with a predicate of the caller's
+        let state = ep
+            .wait_for_status(
+                |state| match state.status {
+                    ComputeStatus::Running => WaitDecision::Done,
+                    _ => WaitDecision::Continue,
+                },
+                Duration::from_secs(10),
+                Duration::from_millis(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(state.status, ComputeStatus::Running);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        // The start's predicate doesn't expect empty
+        calls.store(0, Ordering::SeqCst);
+        let err = ep
+            .wait_until_running(Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unexpected compute status: Empty");
+
+        // Flaps between errors and init, and never gets further
+        let calls = AtomicUsize::new(0);
+        let port = serve(move |_| {
+            if calls.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                ("500 Internal Server Error", "not ready".to_string())
+            } else {
+                ("200 OK", compute_status_json("init"))
+            }
+        });
+        let ep = endpoint_with_http_port(port);
+        let err = ep
+            .wait_for_status(
+                running_decision,
+                Duration::from_millis(300),
+                Duration::from_millis(10),
+            )
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with(
+                "compute_ctl of endpoint ep didn't get there within 300ms, last status: "
+            ),
+            "{err}"
+        );
+
+        let port = serve(|_| {
+            (
+                "200 OK",
+                r#"{"status": "failed", "last_active": null, "error": "no space left"}"#
+                    .to_string(),
+            )
+        });
+        let err = endpoint_with_http_port(port)
+            .wait_until_running(Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "compute startup failed: no space left");
+    }
+
+    #[tokio::test]
+    async fn configure_progress() {
+        // The compute goes through the statuses of a reconfiguration one /status call
+        // at a time, and responds to /configure after a while
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let port = serve(move |path| match path {
+            "/status" => {
+                let call = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let status = ["configuration_pending", "configuration"]
+                    .get(call)
+                    .unwrap_or(&"running");
+                ("200 OK", compute_status_json(status))
+            }
+            _ => {
+                std::thread::sleep(Duration::from_millis(600));
+                ("200 OK", String::new())
+            }
+        });
+        let ep = endpoint_with_http_port(port);
+        let observed = Mutex::new(Vec::new());
+        ep.post_configure("{}", Duration::from_secs(10), &|status| {
+            observed.lock().unwrap().push(status)
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            observed.into_inner().unwrap(),
+            [
+                ComputeStatus::ConfigurationPending,
+                ComputeStatus::Configuration,
+                ComputeStatus::Running
+            ]
+        );
+
+        // A compute that never picks up the spec
+        let port = serve(|path| match path {
+            "/status" => ("200 OK", compute_status_json("configuration_pending")),
+            _ => {
+                std::thread::sleep(Duration::from_secs(3));
+                ("200 OK", String::new())
+            }
+        });
+        let ep = endpoint_with_http_port(port);
+        let err = ep
+            .post_configure("{}", Duration::from_millis(500), &|_| {})
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "compute went through statuses [ConfigurationPending]"
+        );
+    }
+
+    #[test]
+    fn storage_token_expiry() {
+        // {"exp":1600}, unsigned
+        let token = "e30.eyJleHAiOjE2MDB9.c2ln";
+        let ten_minutes = Some(Duration::from_secs(600));
+        assert_eq!(storage_token_expiry_warning(token, ten_minutes, 1000), None);
+        assert_eq!(
+            storage_token_expiry_warning(token, ten_minutes, 1100).unwrap(),
+            "the storage auth token expires in 8m 20s, but the compute suspends after 10m. Rotate it with 'neon_local endpoint rotate-token'"
+        );
+        assert!(storage_token_expiry_warning(token, None, 1000)
+            .unwrap()
+            .contains("never suspends"));
+        // Tokens that don't expire, or that we can't read, are fine
+        assert_eq!(
+            storage_token_expiry_warning("e30.e30.c2ln", None, 1000),
+            None
+        );
+        assert_eq!(storage_token_expiry_warning("opaque", None, 1000), None);
+    }
+
+    #[test]
+    fn version_skew() {
+        let version = |version: &str| BinaryVersion {
+            version: version.to_string(),
+            git_version: "git:0123abc".to_string(),
+        };
+        let current = version("1.4.2");
+        assert_eq!(version_skew_warning("ep-1", None, &current), None);
+        for compatible in ["1.4.2", "1.0.0", "1.9.1-rc1"] {
+            assert_eq!(
+                version_skew_warning("ep-1", Some(&version(compatible)), &current),
+                None,
+                "{compatible}"
+            );
+        }
+        assert_eq!(
+            version_skew_warning("ep-1", Some(&version("2.0.0")), &current).unwrap(),
+            "endpoint ep-1 was created by neon_local 2.0.0 (git:0123abc), this is neon_local 1.4.2 (git:0123abc)"
+        );
+        // Before 1.0, minor versions are incompatible
+        let current = version("0.1.0");
+        assert!(version_skew_warning("ep-1", Some(&version("0.1.7")), &current).is_none());
+        assert!(version_skew_warning("ep-1", Some(&version("0.2.0")), &current).is_some());
+        assert!(version_skew_warning("ep-1", Some(&version("1.0.0")), &current).is_some());
+        // Nothing to compare with
+        assert!(version_skew_warning("ep-1", Some(&version("dev")), &current).is_none());
+
+        // Files written before the versions were recorded
+        let conf: EndpointConf = serde_json::from_str(
+            r#"{"endpoint_id": "ep-1", "mode": "Primary", "pg_port": 1, "http_port": 2,
+                "pg_version": 15, "skip_pg_catalog_updates": true, "features": []}"#,
+        )
+        .unwrap();
+        assert_eq!(conf.created_by, None);
+        let metrics: StartMetrics = serde_json::from_str("{}").unwrap();
+        assert_eq!(metrics.started_by, None);
+    }
+
+    #[tokio::test]
+    async fn rotate_storage_auth_token() {
+        let (_dir, env) = test_env();
+        let conf = EndpointConf {
+            endpoint_id: "ep-1".to_string(),
+            tenant_id: Some(TenantId::from_array([1; 16])),
+            timeline_id: Some(TimelineId::from_array([1; 16])),
+            mode: ComputeMode::Primary,
+            pg_port: 1,
+            http_port: serve_configure("500 Internal Server Error"),
+            pg_version: 15,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        };
+        let ep = Endpoint::from_conf("ep-1".to_string(), conf.clone(), &env).unwrap();
+        ep.create_endpoint_dir().unwrap();
+        let spec_path = ep.endpoint_path().join("spec.json");
+        let original = r#"{"pageserver_connstring": "postgresql://no_user@localhost:1", "storage_auth_token": "old", "added_later": [1, 2]}"#;
+        std::fs::write(&spec_path, original).unwrap();
+
+        // Only the token changes, even in fields neon_local doesn't know
+        let patched =
+            patch_storage_auth_token(serde_json::from_str(original).unwrap(), Some("new".into()))
+                .unwrap();
+        let mut expected: serde_json::Value = serde_json::from_str(original).unwrap();
+        expected["storage_auth_token"] = "new".into();
+        assert_eq!(patched, expected);
+        let cleared = patch_storage_auth_token(patched, None).unwrap();
+        assert_eq!(cleared["storage_auth_token"], serde_json::Value::Null);
+        patch_storage_auth_token(serde_json::json!([]), None).unwrap_err();
+
+        ep.rotate_storage_auth_token(Some("new".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(std::fs::read_to_string(&spec_path).unwrap(), original);
+        assert!(!ep.endpoint_path().join(PENDING_SPEC).exists());
+
+        let ep = Endpoint::from_conf(
+            "ep-1".to_string(),
+            EndpointConf {
+                http_port: serve_configure("200 OK"),
+                ..conf
+            },
+            &env,
+        )
+        .unwrap();
+        ep.rotate_storage_auth_token(Some("new".to_string()))
+            .await
+            .unwrap();
+        let spec: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&spec_path).unwrap()).unwrap();
+        assert_eq!(spec, expected);
+
+        // Never shown
+        let mut redacted = spec;
+        redact_spec(&mut redacted);
+        assert_eq!(
+            redacted["storage_auth_token"],
+            "<redacted:11507a0e2f5e69d5>"
+        );
+    }
+
+    #[tokio::test]
+    async fn start_timings() {
+        use std::os::unix::fs::PermissionsExt;
+        let (tmp, env) = test_env();
+        let dir = tmp.path().as_std_path();
+        let pg_install = dir.join("pg_install").join("v15");
+        std::fs::create_dir_all(pg_install.join("bin")).unwrap();
+        std::fs::create_dir_all(pg_install.join("lib")).unwrap();
+        for binary in ["postgres", "pg_ctl"] {
+            std::fs::write(pg_install.join("bin").join(binary), "").unwrap();
+        }
+        // A stand-in for compute_ctl, too old to tell what it supports, that keeps running
+        let neon_distrib_dir = dir.join("bin");
+        std::fs::create_dir_all(&neon_distrib_dir).unwrap();
+        let compute_ctl = neon_distrib_dir.join("compute_ctl");
+        std::fs::write(
+            &compute_ctl,
+            "#!/bin/sh\ncase \"$1\" in --supported-*) exit 1;; esac\nexec sleep 60\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&compute_ctl, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let env = LocalEnv {
+            pg_distrib_dir: dir.join("pg_install"),
+            neon_distrib_dir,
+            ..env
+        };
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+
+        // The HTTP API answers after 500ms, and Postgres runs after another 500ms
+        let begin = std::time::Instant::now();
+        let http_port = serve(move |path| match path {
+            "/status" if begin.elapsed() < Duration::from_millis(500) => {
+                ("503 Service Unavailable", String::new())
+            }
+            "/status" if begin.elapsed() < Duration::from_millis(1000) => {
+                ("200 OK", compute_status_json("init"))
+            }
+            "/status" => ("200 OK", compute_status_json("running")),
+            "/metrics.json" => ("200 OK", "{}".to_string()),
+            _ => ("404 Not Found", String::new()),
+        });
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let ep = cplane
+            .create_endpoint(EndpointConf {
+                endpoint_id: "ep-1".to_string(),
+                tenant_id: Some(TenantId::from_array([1; 16])),
+                timeline_id: Some(TimelineId::from_array([1; 16])),
+                mode: ComputeMode::Primary,
+                pg_port: 1,
+                http_port,
+                pg_version: 15,
+                skip_pg_catalog_updates: true,
+                features: Vec::new(),
+                suspend_timeout: None,
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                created_by: None,
+                stream_from: None,
+                split_logs: false,
+            })
+            .unwrap();
+        ep.start(&test_start_args()).await.unwrap();
+        let elapsed = begin.elapsed().as_millis() as u64;
+        let pid: i32 = std::fs::read_to_string(ep.endpoint_path().join("compute_ctl.pid"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        kill(Pid::from_raw(pid), Signal::SIGKILL).unwrap();
+
+        let timings = ep.last_start_metrics().unwrap().unwrap().timings.unwrap();
+        // The status is polled every 100ms
+        assert!(
+            timings.prepare_ms + timings.spec_ms + timings.spawn_ms + timings.wait_for_init_ms
+                >= 450,
+            "{timings}"
+        );
+        assert!(timings.wait_for_running_ms >= 400, "{timings}");
+        let phases = timings.prepare_ms
+            + timings.spec_ms
+            + timings.spawn_ms
+            + timings.wait_for_init_ms
+            + timings.wait_for_running_ms;
+        assert!(phases <= timings.total_ms, "{timings}");
+        assert!(
+            timings.total_ms >= 950 && timings.total_ms <= elapsed,
+            "{timings}"
+        );
+    }
+
+    #[tokio::test]
+    async fn lifecycle_events() {
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<(String, EndpointEventKind)>>);
+
+        impl Recorder {
+            fn record(&self, endpoint_id: &str, kind: EndpointEventKind) {
+                self.0.lock().unwrap().push((endpoint_id.to_string(), kind));
+            }
+            fn take(&self) -> Vec<(String, EndpointEventKind)> {
+                std::mem::take(&mut *self.0.lock().unwrap())
+            }
+        }
+
+        impl EndpointEventSink for Recorder {
+            fn on_starting(&self, endpoint_id: &str) {
+                self.record(endpoint_id, EndpointEventKind::Starting)
+            }
+            fn on_reconfigured(&self, endpoint_id: &str) {
+                self.record(endpoint_id, EndpointEventKind::Reconfigured)
+            }
+            fn on_stopped(&self, endpoint_id: &str) {
+                self.record(endpoint_id, EndpointEventKind::Stopped)
+            }
+            fn on_crashed(&self, endpoint_id: &str) {
+                self.record(endpoint_id, EndpointEventKind::Crashed)
+            }
+        }
+
+        let (_dir, env) = test_env();
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let recorder = Arc::new(Recorder::default());
+        cplane.add_event_sink(recorder.clone());
+        let ep = cplane
+            .create_endpoint(EndpointConf {
+                endpoint_id: "ep-1".to_string(),
+                tenant_id: Some(TenantId::from_array([1; 16])),
+                timeline_id: Some(TimelineId::from_array([1; 16])),
+                mode: ComputeMode::Primary,
+                pg_port: 1,
+                http_port: serve_configure("200 OK"),
+                pg_version: 15,
+                skip_pg_catalog_updates: true,
+                features: Vec::new(),
+                suspend_timeout: None,
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                created_by: None,
+                stream_from: None,
+                split_logs: false,
+            })
+            .unwrap();
+        let ep_id = || "ep-1".to_string();
+
+        // A start that fails early still ends in Stopped
+        std::fs::write(ep.endpoint_path().join(PG_VERSION_STAMP), "99").unwrap();
+        ep.start(&test_start_args()).await.unwrap_err();
+        assert_eq!(
+            recorder.take(),
+            [
+                (ep_id(), EndpointEventKind::Starting),
+                (ep_id(), EndpointEventKind::Stopped)
+            ]
+        );
+
+        std::fs::write(ep.endpoint_path().join("spec.json"), "{}").unwrap();
+        ep.rotate_storage_auth_token(Some("new".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            recorder.take(),
+            [(ep_id(), EndpointEventKind::Reconfigured)]
+        );
+
+        // Postgres left its pid file behind, but nothing listens
+        std::fs::create_dir_all(ep.pgdata()).unwrap();
+        std::fs::write(ep.pgdata().join("postmaster.pid"), "1").unwrap();
+        cplane.statuses(1, Duration::from_secs(5)).await;
+        assert_eq!(recorder.take(), [(ep_id(), EndpointEventKind::Crashed)]);
+    }
+
+    #[tokio::test]
+    async fn status_stream() {
+        let (_dir, env) = test_env();
+        let stopped = test_endpoint_in(&env, "ep-stopped");
+        // Postgres left its pid file behind, but nothing listens
+        let crashed = test_endpoint_in(&env, "ep-crashed");
+        std::fs::create_dir_all(crashed.pgdata()).unwrap();
+        std::fs::write(crashed.pgdata().join("postmaster.pid"), "1").unwrap();
+        let cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: [stopped, crashed]
+                .into_iter()
+                .map(|ep| (ep.endpoint_id.clone(), Arc::new(ep)))
+                .collect(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+
+        let started = std::time::Instant::now();
+        let ticks: Vec<Vec<EndpointSample>> = cplane
+            .status_stream(Duration::from_millis(200))
+            .take(2)
+            .collect()
+            .await;
+        // Only the second tick waits for the interval
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(ticks.len(), 2);
+        for samples in ticks {
+            let summary: Vec<_> = samples
+                .iter()
+                .map(|sample| {
+                    (
+                        sample.endpoint_id.as_str(),
+                        sample.status.as_ref().unwrap().status,
+                        sample.activity.clone(),
+                        sample.lfc_hit_ratio,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                summary,
+                [
+                    (
+                        "ep-crashed",
+                        EndpointStatus::Crashed,
+                        Some(EndpointActivity::default()),
+                        None
+                    ),
+                    (
+                        "ep-stopped",
+                        EndpointStatus::Stopped,
+                        Some(EndpointActivity::default()),
+                        None
+                    ),
+                ]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn endpoint_activity() {
+        let sample = |lsn: u64, timestamp_ms: u64| WalSample {
+            lsn: Lsn(lsn),
+            timestamp_ms,
+        };
+        assert_eq!(
+            sample(3000, 2000).rate_since(&sample(1000, 1000)),
+            Some(2000.0)
+        );
+        assert_eq!(
+            sample(1000, 1500).rate_since(&sample(1000, 1000)),
+            Some(0.0)
+        );
+        // Recreated endpoint, clock going back, or no time at all
+        assert_eq!(sample(1000, 2000).rate_since(&sample(3000, 1000)), None);
+        assert_eq!(sample(3000, 1000).rate_since(&sample(1000, 2000)), None);
+        assert_eq!(sample(3000, 1000).rate_since(&sample(1000, 1000)), None);
+
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path();
+        let path = dir.join(WAL_SAMPLE);
+        assert_eq!(WalSample::load(&path), None);
+        sample(0x16B3748, 1000).save(&path).unwrap();
+        assert_eq!(WalSample::load(&path), Some(sample(0x16B3748, 1000)));
+        std::fs::write(&path, "{ not json").unwrap();
+        assert_eq!(WalSample::load(&path), None);
+
+        let busy = EndpointActivity {
+            running: true,
+            client_backends: 1,
+            oldest_xact_age_secs: Some(5.0),
+            wal_bytes_per_sec: Some(0.0),
+        };
+        assert!(!busy.is_idle(1024.0));
+        let quiet = EndpointActivity {
+            client_backends: 0,
+            oldest_xact_age_secs: None,
+            ..busy.clone()
+        };
+        assert!(quiet.is_idle(1024.0));
+        assert!(!EndpointActivity {
+            wal_bytes_per_sec: Some(4096.0),
+            ..quiet.clone()
+        }
+        .is_idle(1024.0));
+        // Nothing to compare with on the first sample
+        assert!(EndpointActivity {
+            wal_bytes_per_sec: None,
+            ..quiet
+        }
+        .is_idle(1024.0));
+
+        // Stopped endpoints are inert rather than idle
+        let stopped = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Primary);
+        let activity = stopped.activity().await.unwrap();
+        assert_eq!(activity, EndpointActivity::default());
+        assert!(!activity.is_idle(1024.0));
+    }
+
+    #[tokio::test]
+    async fn cluster_delta() {
+        let role = |name: &str| Role {
+            name: name.to_string(),
+            encrypted_password: None,
+            options: None,
+        };
+        let database = |name: &str, owner: &str| Database {
+            name: name.to_string(),
+            owner: owner.to_string(),
+            options: None,
+            restrict_conn: false,
+            invalid: false,
+        };
+        let names = |databases: &[Database]| -> Vec<String> {
+            databases.iter().map(|db| db.name.clone()).collect()
+        };
+
+        let mut cluster = Cluster::default();
+        merge_cluster_delta(
+            &mut cluster,
+            vec![role("alice")],
+            vec![database("a", "alice"), database("b", "cloud_admin")],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(names(&cluster.databases), ["a", "b"]);
+        merge_cluster_delta(&mut cluster, Vec::new(), Vec::new(), &["a".to_string()]).unwrap();
+        assert_eq!(names(&cluster.databases), ["b"]);
+        for (roles, databases, drops) in [
+            (vec![role("alice")], Vec::new(), Vec::new()),
+            (Vec::new(), vec![database("b", "alice")], Vec::new()),
+            (Vec::new(), vec![database("c", "bob")], Vec::new()),
+            (Vec::new(), Vec::new(), vec!["a".to_string()]),
+        ] {
+            merge_cluster_delta(&mut cluster.clone(), roles, databases, &drops).unwrap_err();
+        }
+
+        let replica = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Replica);
+        let err = replica
+            .apply_cluster_delta(Vec::new(), vec![database("c", "cloud_admin")], Vec::new())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("only primaries"), "{err}");
+
+        let (_dir, env) = test_env();
+        let conf = EndpointConf {
+            endpoint_id: "ep-1".to_string(),
+            tenant_id: Some(TenantId::from_array([1; 16])),
+            timeline_id: Some(TimelineId::from_array([1; 16])),
+            mode: ComputeMode::Primary,
+            pg_port: 1,
+            http_port: serve_configure("200 OK"),
+            pg_version: 15,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        };
+        let ep = Endpoint::from_conf("ep-1".to_string(), conf, &env).unwrap();
+        ep.create_endpoint_dir().unwrap();
+        let spec_path = ep.endpoint_path().join("spec.json");
+        let spec = ComputeSpec {
+            cluster,
+            ..Default::default()
+        };
+        std::fs::write(&spec_path, serde_json::to_string_pretty(&spec).unwrap()).unwrap();
+
+        ep.apply_cluster_delta(
+            vec![role("bob")],
+            vec![database("c", "bob")],
+            vec!["b".to_string()],
+        )
+        .await
+        .unwrap();
+        let spec: ComputeSpec =
+            serde_json::from_slice(&std::fs::read(&spec_path).unwrap()).unwrap();
+        assert_eq!(names(&spec.cluster.databases), ["c"]);
+        assert_eq!(spec.cluster.roles.len(), 2);
+        // The drop is not repeated by the next reconfiguration
+        assert!(spec.delta_operations.is_none());
+    }
+
+    #[tokio::test]
+    async fn reconfigure_persists_spec_on_success_only() {
+        let (_dir, env) = test_env();
+        let conf = EndpointConf {
+            endpoint_id: "ep-1".to_string(),
+            tenant_id: Some(TenantId::from_array([1; 16])),
+            timeline_id: Some(TimelineId::from_array([1; 16])),
+            mode: ComputeMode::Primary,
+            pg_port: 1,
+            http_port: serve_configure("500 Internal Server Error"),
+            pg_version: 15,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: false,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        };
+        let ep = Endpoint::from_conf("ep-1".to_string(), conf.clone(), &env).unwrap();
+        ep.create_endpoint_dir().unwrap();
+        let spec_path = ep.endpoint_path().join("spec.json");
+        let spec = ComputeSpec {
+            pageserver_connstring: Some("postgresql://no_user@localhost:1".to_string()),
+            ..Default::default()
+        };
+        std::fs::write(&spec_path, serde_json::to_string_pretty(&spec).unwrap()).unwrap();
+        let original = std::fs::read(&spec_path).unwrap();
+        let pageservers = vec![(Host::parse("localhost").unwrap(), 2)];
+
+        let err = ep
+            .reconfigure(pageservers.clone(), None, None, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Error: failed");
+        assert_eq!(std::fs::read(&spec_path).unwrap(), original);
+        assert!(!ep.endpoint_path().join(PENDING_SPEC).exists());
+
+        let ep = Endpoint::from_conf(
+            "ep-1".to_string(),
+            EndpointConf {
+                http_port: serve_configure("200 OK"),
+                ..conf
+            },
+            &env,
+        )
+        .unwrap();
+        ep.reconfigure(pageservers, None, None, false)
+            .await
+            .unwrap();
+        let spec: ComputeSpec =
+            serde_json::from_slice(&std::fs::read(&spec_path).unwrap()).unwrap();
+        assert_eq!(
+            spec.pageserver_connstring.as_deref(),
+            Some("postgresql://no_user@localhost:2")
+        );
+        assert!(!ep.endpoint_path().join(PENDING_SPEC).exists());
+    }
+
+    #[tokio::test]
+    async fn reconfigure_records_safekeepers() {
+        let (_dir, env) = test_env();
+        let env = LocalEnv {
+            safekeepers: vec![SafekeeperConf {
+                id: NodeId(1),
+                ..Default::default()
+            }],
+            ..env
+        };
+        std::fs::create_dir_all(env.endpoints_path()).unwrap();
+        let mut cplane = ComputeControlPlane {
+            ports: port_registry(&env),
+            endpoints: BTreeMap::new(),
+            env: env.clone(),
+            events: EventSinks::default(),
+        };
+        let ep = cplane
+            .create_endpoint(EndpointConf {
+                endpoint_id: "ep-1".to_string(),
+                tenant_id: Some(TenantId::from_array([1; 16])),
+                timeline_id: Some(TimelineId::from_array([1; 16])),
+                mode: ComputeMode::Primary,
+                pg_port: 1,
+                http_port: serve_configure("200 OK"),
+                pg_version: 15,
+                skip_pg_catalog_updates: true,
+                features: Vec::new(),
+                suspend_timeout: None,
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                created_by: None,
+                stream_from: None,
+                split_logs: false,
+            })
+            .unwrap();
+        std::fs::write(
+            ep.endpoint_path().join("spec.json"),
+            serde_json::to_string_pretty(&ComputeSpec::default()).unwrap(),
+        )
+        .unwrap();
+        let pageservers = vec![(Host::parse("localhost").unwrap(), 2)];
+
+        // Keeping the safekeepers doesn't record anything
+        ep.reconfigure(pageservers.clone(), None, None, false)
+            .await
+            .unwrap();
+        let reloaded = ComputeControlPlane::load(env.clone()).unwrap();
+        assert_eq!(reloaded.endpoints["ep-1"].last_safekeepers, None);
+
+        ep.reconfigure(pageservers, None, Some(vec![NodeId(1)]), false)
+            .await
+            .unwrap();
+        let reloaded = ComputeControlPlane::load(env.clone()).unwrap();
+        assert_eq!(
+            reloaded.endpoints["ep-1"].last_safekeepers,
+            Some(vec![NodeId(1)])
+        );
+    }
+
+    /// Fails /configure without sending it, and replaces the status in /status.
+    #[derive(Debug, Default)]
+    struct FlakyCompute {
+        status: Option<&'static str>,
+        calls: Mutex<Vec<HttpCall>>,
+    }
+
+    impl HttpHooks for FlakyCompute {
+        fn before(&self, call: HttpCall) -> http_hooks::Injection {
+            self.calls.lock().unwrap().push(call);
+            http_hooks::Injection {
+                delay: Duration::from_millis(100),
+                fail: (call == HttpCall::Configure)
+                    .then_some(reqwest::StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+
+        fn after(
+            &self,
+            _call: HttpCall,
+            status: reqwest::StatusCode,
+            body: String,
+        ) -> (reqwest::StatusCode, String) {
+            match self.status {
+                Some(compute_status) => (status, compute_status_json(compute_status)),
+                None => (status, body),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn injected_http_failures() {
+        let (_dir, env) = test_env();
+        let configure_calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let port = {
+            let configure_calls = Arc::clone(&configure_calls);
+            serve(move |path| match path {
+                "/status" => ("200 OK", compute_status_json("running")),
+                _ => {
+                    configure_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    ("200 OK", String::new())
+                }
+            })
+        };
+        let mut ep = test_endpoint_in(&env, "ep-1");
+        ep.http_address = SocketAddr::new("127.0.0.1".parse().unwrap(), port);
+        let hooks = Arc::new(FlakyCompute {
+            status: Some("failed"),
+            ..Default::default()
+        });
+        ep.set_http_hooks(hooks.clone());
+        ep.create_endpoint_dir().unwrap();
+        let spec_path = ep.endpoint_path().join("spec.json");
+        let spec = serde_json::to_string_pretty(&ComputeSpec::default()).unwrap();
+        std::fs::write(&spec_path, &spec).unwrap();
+
+        // Responses pass through the after hook, calls are delayed
+        let started = std::time::Instant::now();
+        assert_eq!(ep.get_status().await.unwrap().status, ComputeStatus::Failed);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // The injected 500 fails the reconfiguration as if compute_ctl had returned it,
+        // without the request reaching compute_ctl
+        let pageservers = vec![(Host::parse("localhost").unwrap(), 2)];
+        let err = ep
+            .reconfigure(pageservers, None, None, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "injected 500 Internal Server Error response to POST /configure"
+        );
+        assert!(err.downcast_ref::<http_hooks::InjectedError>().is_some());
+        assert_eq!(configure_calls.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(std::fs::read_to_string(&spec_path).unwrap(), spec);
+        assert!(!ep.endpoint_path().join(PENDING_SPEC).exists());
+        assert_eq!(
+            *hooks.calls.lock().unwrap(),
+            [HttpCall::Status, HttpCall::Configure]
+        );
+    }
+
+    #[test]
+    fn compute_features_from_stub_compute_ctl() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path();
+        let stub = |name: &str, script: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+
+        // Probed once per binary
+        let calls = dir.join("calls");
+        let current = stub(
+            "current",
+            &format!(r#"echo >> {}; echo '["anon_extension"]'"#, calls.display()),
+        );
+        let supported = supported_compute_features(&current).unwrap().unwrap();
+        assert_eq!(supported, ["anon_extension"]);
+        supported_compute_features(&current).unwrap();
+        assert_eq!(std::fs::read_to_string(&calls).unwrap().lines().count(), 1);
+
+        // Predates --supported-features
+        let unaware = stub("unaware", "exit 2");
+        assert_eq!(supported_compute_features(&unaware).unwrap(), None);
+
+        let requested = [
+            ComputeFeature::AnonExtension,
+            ComputeFeature::ActivityMonitorExperimental,
+        ];
+        let err = select_compute_features(&requested, &supported, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "compute_ctl doesn't support the features activity_monitor_experimental, leave them out with --ignore-unsupported-features"
+        );
+        assert_eq!(
+            select_compute_features(&requested, &supported, true).unwrap(),
+            [ComputeFeature::AnonExtension]
+        );
+        assert_eq!(
+            select_compute_features(&requested[..1], &supported, false).unwrap(),
+            [ComputeFeature::AnonExtension]
+        );
+
+        // Every feature neon_local knows is reported by the compute_ctl of the same build
+        let ours: Vec<String> = compute_api::spec::KNOWN_COMPUTE_FEATURES
+            .iter()
+            .map(compute_feature_name)
+            .collect();
+        select_compute_features(compute_api::spec::KNOWN_COMPUTE_FEATURES, &ours, false).unwrap();
+    }
+
+    #[test]
+    fn spec_format_version_from_stub_compute_ctl() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path();
+        let stub = |name: &str, script: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+
+        let current = stub("current", r#"echo '{"min": 1.0, "max": 1.0}'"#);
+        assert_eq!(negotiate_spec_format_version(&current).unwrap(), 1.0);
+
+        // Predates --supported-spec-versions
+        let unaware = stub("unaware", "exit 2");
+        assert_eq!(negotiate_spec_format_version(&unaware).unwrap(), 1.0);
+
+        let old = stub("old", r#"echo '{"min": 0.5, "max": 0.9}'"#);
+        let err = negotiate_spec_format_version(&old).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "compute_ctl {} supports spec versions 0.5..0.9 but neon_local produces 1..1",
+                old.display()
+            )
+        );
+    }
+
+    #[test]
+    fn postgresql_conf_errors() {
+        let (_dir, env) = test_env();
+        let ep = test_endpoint_in(&env, "ep");
+        ep.create_endpoint_dir().unwrap();
+        let conf_path = ep.endpoint_path().join("postgresql.conf");
+
+        std::fs::write(&conf_path, "fsync = off\nwork_mem = '4MB\n").unwrap();
+        for skip_validation in [false, true] {
+            let err = ep.read_postgresql_conf(skip_validation).unwrap_err();
+            assert_eq!(
+                format!("{err:#}"),
+                format!(
+                    "failed to parse config file {}: invalid line 2: work_mem = '4MB: unterminated quoted string",
+                    conf_path.display()
+                )
+            );
+        }
+
+        std::fs::write(&conf_path, "shared_buffers = 15Q\n").unwrap();
+        ep.read_postgresql_conf(false).unwrap_err();
+        let conf = ep.read_postgresql_conf(true).unwrap();
+        assert!(conf.contains("shared_buffers = 15Q"), "{conf}");
+    }
+
+    #[test]
+    fn static_lsn_within_branch() {
+        check_lsn_within_branch(Lsn(0x100), Lsn(0x100)).unwrap();
+        check_lsn_within_branch(Lsn(0x10), Lsn(0x100)).unwrap();
+        let err = check_lsn_within_branch(Lsn(0x1_0000_0200), Lsn(0x100)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "requested LSN 1/200 is beyond branch head 0/100, use --force to create the endpoint anyway"
+        );
+    }
+
+    #[test]
+    fn dumps() {
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path();
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        for name in ["a", "b"] {
+            std::fs::write(dir.join(name).join("toc.dat"), "toc").unwrap();
+            std::fs::write(dir.join(name).join("3001.dat"), "rows").unwrap();
+        }
+        assert_eq!(
+            hash_dump(&dir.join("a")).unwrap(),
+            hash_dump(&dir.join("b")).unwrap()
+        );
+        std::fs::write(dir.join("b").join("3001.dat"), "other rows").unwrap();
+        assert_ne!(
+            hash_dump(&dir.join("a")).unwrap(),
+            hash_dump(&dir.join("b")).unwrap()
+        );
+        assert_eq!(
+            DumpFormat::from_str("directory").unwrap(),
+            DumpFormat::Directory
+        );
+
+        let ep = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Primary);
+        let err = ep.dump("postgres", dir, DumpFormat::Plain).unwrap_err();
+        assert_eq!(err.to_string(), "endpoint ep is not running");
+    }
+
+    #[test]
+    fn wal_batches() {
+        assert_eq!(
+            wal_batch_sql("t", WalWorkload::Bulk, 10),
+            "INSERT INTO t SELECT g, repeat('x', 100) FROM generate_series(1, 10) g"
+        );
+        let sql = wal_batch_sql("t", WalWorkload::SmallTransactions, 3);
+        assert_eq!(sql.matches("BEGIN;").count(), 3);
+        assert_eq!(sql.matches("COMMIT;").count(), 3);
+    }
+
+    #[test]
+    fn safekeeper_membership_divergence() {
+        let sks = |list: &[&str]| list.iter().map(|sk| sk.to_string()).collect::<Vec<_>>();
+        let configured = sks(&["127.0.0.1:5454", "127.0.0.1:5455"]);
+
+        // Not running: nothing to compare with
+        assert!(!SafekeeperMembership::new(configured.clone(), None).diverged);
+        // Order doesn't matter
+        let running = sks(&["127.0.0.1:5455", "127.0.0.1:5454"]);
+        assert!(!SafekeeperMembership::new(configured.clone(), Some(running)).diverged);
+        // spec.json edited without reconfiguring
+        let running = sks(&["127.0.0.1:5454"]);
+        let membership = SafekeeperMembership::new(configured, Some(running));
+        assert!(membership.diverged);
+        assert_eq!(
+            serde_json::to_value(&membership).unwrap(),
+            serde_json::json!({
+                "configured": ["127.0.0.1:5454", "127.0.0.1:5455"],
+                "running": ["127.0.0.1:5454"],
+                "diverged": true,
+            })
+        );
+    }
+
+    #[test]
+    fn neon_stats_parsing() {
+        // As returned by neon_get_lfc_stats() of extension version 1.3
+        let rows = |values: [Option<i64>; 4]| {
+            [
+                "file_cache_misses",
+                "file_cache_hits",
+                "file_cache_used",
+                "file_cache_writes",
+            ]
+            .into_iter()
+            .zip(values)
+            .map(|(key, value)| (key.to_string(), value))
+            .collect::<Vec<_>>()
+        };
+        let earlier = NeonStats::parse(
+            "1.3".to_string(),
+            Some(&rows([Some(10), Some(30), Some(100), Some(40)])),
+            1000,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&earlier).unwrap(),
+            serde_json::json!({
+                "extension_version": "1.3",
+                "lfc": {"hits": 30, "misses": 10, "used": 100, "writes": 40, "hit_ratio": 75.0},
+                "backpressure_throttling_us": 1000,
+            })
+        );
+
+        let later = NeonStats::parse(
+            "1.3".to_string(),
+            Some(&rows([Some(20), Some(120), Some(120), Some(45)])),
+            1500,
+        )
+        .unwrap();
+        assert_eq!(
+            later.since(&earlier).to_string(),
+            "lfc: 90 hits, 10 misses (90.00% hit ratio), 120 pages used, 5 writes; backpressure throttling: 500us"
+        );
+        assert_eq!(
+            earlier.since(&earlier).to_string(),
+            "lfc: 0 hits, 0 misses, 100 pages used, 0 writes; backpressure throttling: 0us"
+        );
+
+        // Cache disabled, extension too old, or an unexpected result
+        let disabled = NeonStats::parse("1.3".to_string(), Some(&rows([None; 4])), 0).unwrap();
+        assert_eq!(disabled.lfc, None);
+        assert_eq!(
+            NeonStats::parse("1.0".to_string(), None, 0)
+                .unwrap()
+                .to_string(),
+            "lfc: unavailable; backpressure throttling: 0us"
+        );
+        NeonStats::parse("1.3".to_string(), Some(&rows([Some(1); 4])[1..]), 0).unwrap_err();
+
+        assert!(!neon_extension_version_at_least("1.0", (1, 1)).unwrap());
+        assert!(neon_extension_version_at_least("1.1", (1, 1)).unwrap());
+        assert!(neon_extension_version_at_least("1.10", (1, 2)).unwrap());
+        neon_extension_version_at_least("latest", (1, 1)).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn availability_objects_need_catalog_updates() {
+        let ep = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Primary);
+        let err = ep.verify_availability_objects().await.unwrap_err();
+        assert!(err.to_string().contains("skips catalog updates"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn read_only_needs_primary() {
+        let ep = test_endpoint("ep", TimelineId::from_array([1; 16]), ComputeMode::Replica);
+        let err = ep.set_read_only(true, false).await.unwrap_err();
+        assert!(err.to_string().contains("only primaries"), "{err}");
+        assert!(!ep.is_read_only());
+    }
+
+    #[tokio::test]
+    async fn vanilla_endpoints() {
+        let env = test_env_at(PathBuf::from("/tmp/.neon"));
+        let conf = EndpointConf {
+            endpoint_id: "ep-vanilla".to_string(),
+            tenant_id: None,
+            timeline_id: None,
+            mode: ComputeMode::Primary,
+            pg_port: 1,
+            http_port: 0,
+            pg_version: 15,
+            skip_pg_catalog_updates: true,
+            features: Vec::new(),
+            suspend_timeout: None,
+            vanilla: true,
+            last_safekeepers: None,
+            profiles: Vec::new(),
+            created_by: None,
+            stream_from: None,
+            split_logs: false,
+        };
+        let json = serde_json::to_value(&conf).unwrap();
+        assert!(json.get("tenant_id").is_none(), "{json}");
+        assert_eq!(serde_json::from_value::<EndpointConf>(json).unwrap(), conf);
+
+        let ep = Endpoint::from_conf("ep-vanilla".to_string(), conf.clone(), &env).unwrap();
+        let pg_conf = ep.setup_pg_conf().unwrap().to_string();
+        for neon_setting in [
+            "shared_preload_libraries",
+            "neon.",
+            "synchronous_standby_names",
+        ] {
+            assert!(!pg_conf.contains(neon_setting), "{pg_conf}");
+        }
+        assert!(pg_conf.contains("listen_addresses"), "{pg_conf}");
+
+        let err = ep
+            .reconfigure(Vec::new(), None, None, false)
+            .await
+            .unwrap_err();
+        let err = err.downcast::<VanillaEndpointError>().unwrap();
+        assert_eq!(err.operation, "reconfiguration");
+        let err = ep.safekeeper_membership().await.unwrap_err();
+        assert!(err.is::<VanillaEndpointError>(), "{err}");
+
+        // Only vanilla endpoints go without a tenant and timeline
+        let err = Endpoint::from_conf(
+            "ep".to_string(),
+            EndpointConf {
+                vanilla: false,
+                last_safekeepers: None,
+                profiles: Vec::new(),
+                ..conf
+            },
+            &env,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "endpoint ep has no tenant or timeline id");
+    }
+
+    #[tokio::test]
+    async fn pageserver_connectivity() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap().port();
+        // A port nobody listens on
+        let bogus = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+
+        let connstr = |ports: &[u16]| {
+            let pageservers = ports
+                .iter()
+                .map(|port| (Host::parse("127.0.0.1").unwrap(), *port))
+                .collect::<Vec<_>>();
+            Endpoint::build_pageserver_connstr(&pageservers)
+        };
+        check_pageserver_connectivity(&connstr(&[reachable, reachable]), Duration::from_secs(2))
+            .await
+            .unwrap();
+
+        let err = check_pageserver_connectivity(
+            &connstr(&[reachable, bogus, reachable]),
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("1 shard(s)"), "{err}");
+        assert!(
+            err.contains(&format!("shard 1 (127.0.0.1:{bogus}")),
+            "{err}"
+        );
+        assert!(
+            !err.contains("shard 0") && !err.contains("shard 2"),
+            "{err}"
+        );
+
+        assert_eq!(
+            ConnectivityCheck::from_str("warn").unwrap(),
+            ConnectivityCheck::Warn
+        );
+        ConnectivityCheck::from_str("maybe").unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn start_primaries_first() {
+        let timeline_1 = TimelineId::from_array([1; 16]);
+        let timeline_2 = TimelineId::from_array([2; 16]);
+        let endpoints = vec![
+            test_endpoint("a-replica", timeline_1, ComputeMode::Replica),
+            test_endpoint("b-static", timeline_2, ComputeMode::Static(Lsn(0x10))),
+            test_endpoint("c-primary", timeline_1, ComputeMode::Primary),
+            test_endpoint("d-primary", timeline_2, ComputeMode::Primary),
+        ];
+
+        let started = Mutex::new(Vec::new());
+        let results = start_in_phases(endpoints, 2, |ep| {
+            let started = &started;
+            async move {
+                // Let the second primary finish first
+                if ep.endpoint_id == "c-primary" {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                started.lock().unwrap().push(ep.endpoint_id.clone());
+                if ep.endpoint_id == "b-static" {
+                    bail!("no such lsn");
+                }
+                Ok(())
+            }
+        })
+        .await;
+
+        let started = started.into_inner().unwrap();
+        assert_eq!(started[..2], ["d-primary", "c-primary"]);
+        assert_eq!(started.len(), 4);
+
+        // One failure doesn't stop the others
+        assert_eq!(results.len(), 4);
+        for (endpoint_id, result) in &results {
+            if endpoint_id == "b-static" {
+                assert_eq!(result.as_ref().unwrap_err().to_string(), "no such lsn");
+            } else {
+                result.as_ref().unwrap();
+            }
+        }
     }
 }
@@ -0,0 +1,170 @@
+//! Callbacks on the lifecycle of endpoints, for tools built on the control plane that
+//! want to react to an endpoint starting or stopping without polling.
+//!
+//! Sinks are added with [`crate::endpoint::ComputeControlPlane::add_event_sink`], and
+//! see the transitions of all endpoints of that control plane. [`BroadcastSink`]
+//! forwards them to a tokio broadcast channel. There is no watchdog: a crash is only
+//! noticed when the status of the endpoint is checked, see
+//! [`crate::endpoint::ComputeControlPlane::statuses`].
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndpointEventKind {
+    Starting,
+    /// Started and passed the status checks
+    Running,
+    /// compute_ctl applied a new spec
+    Reconfigured,
+    Stopping,
+    /// Stopped, or failed to start
+    Stopped,
+    /// Postgres went away without being stopped
+    Crashed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndpointEvent {
+    pub endpoint_id: String,
+    pub kind: EndpointEventKind,
+}
+
+/// Receives the transitions of endpoints. The calls are made from the code doing the
+/// transition, so they should return quickly.
+pub trait EndpointEventSink: Send + Sync {
+    fn on_starting(&self, _endpoint_id: &str) {}
+    fn on_running(&self, _endpoint_id: &str) {}
+    fn on_reconfigured(&self, _endpoint_id: &str) {}
+    fn on_stopping(&self, _endpoint_id: &str) {}
+    fn on_stopped(&self, _endpoint_id: &str) {}
+    fn on_crashed(&self, _endpoint_id: &str) {}
+}
+
+/// Sends every event to a broadcast channel, for async consumers. Events are dropped
+/// while nobody subscribes.
+pub struct BroadcastSink {
+    sender: broadcast::Sender<EndpointEvent>,
+}
+
+impl BroadcastSink {
+    /// A sink keeping up to `capacity` events for lagging receivers.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        BroadcastSink { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EndpointEvent> {
+        self.sender.subscribe()
+    }
+
+    fn send(&self, endpoint_id: &str, kind: EndpointEventKind) {
+        // Fails only without receivers
+        let _ = self.sender.send(EndpointEvent {
+            endpoint_id: endpoint_id.to_string(),
+            kind,
+        });
+    }
+}
+
+impl EndpointEventSink for BroadcastSink {
+    fn on_starting(&self, endpoint_id: &str) {
+        self.send(endpoint_id, EndpointEventKind::Starting)
+    }
+    fn on_running(&self, endpoint_id: &str) {
+        self.send(endpoint_id, EndpointEventKind::Running)
+    }
+    fn on_reconfigured(&self, endpoint_id: &str) {
+        self.send(endpoint_id, EndpointEventKind::Reconfigured)
+    }
+    fn on_stopping(&self, endpoint_id: &str) {
+        self.send(endpoint_id, EndpointEventKind::Stopping)
+    }
+    fn on_stopped(&self, endpoint_id: &str) {
+        self.send(endpoint_id, EndpointEventKind::Stopped)
+    }
+    fn on_crashed(&self, endpoint_id: &str) {
+        self.send(endpoint_id, EndpointEventKind::Crashed)
+    }
+}
+
+/// The sinks of a control plane, shared with its endpoints.
+#[derive(Clone, Default)]
+pub struct EventSinks(Arc<Mutex<Vec<Arc<dyn EndpointEventSink>>>>);
+
+impl fmt::Debug for EventSinks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EventSinks({})", self.0.lock().unwrap().len())
+    }
+}
+
+impl EventSinks {
+    pub fn add(&self, sink: Arc<dyn EndpointEventSink>) {
+        self.0.lock().unwrap().push(sink);
+    }
+
+    /// Tell every sink about the transition. A sink that panics is reported, and
+    /// doesn't keep the others from being called.
+    pub(crate) fn emit(&self, endpoint_id: &str, kind: EndpointEventKind) {
+        let sinks = self.0.lock().unwrap().clone();
+        for sink in sinks {
+            let call = AssertUnwindSafe(|| match kind {
+                EndpointEventKind::Starting => sink.on_starting(endpoint_id),
+                EndpointEventKind::Running => sink.on_running(endpoint_id),
+                EndpointEventKind::Reconfigured => sink.on_reconfigured(endpoint_id),
+                EndpointEventKind::Stopping => sink.on_stopping(endpoint_id),
+                EndpointEventKind::Stopped => sink.on_stopped(endpoint_id),
+                EndpointEventKind::Crashed => sink.on_crashed(endpoint_id),
+            });
+            if panic::catch_unwind(call).is_err() {
+                eprintln!("endpoint event sink panicked on {kind:?} of endpoint {endpoint_id}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Panicking;
+
+    impl EndpointEventSink for Panicking {
+        fn on_running(&self, _endpoint_id: &str) {
+            panic!("buggy sink");
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcast_despite_panicking_sink() {
+        let sinks = EventSinks::default();
+        let broadcast = Arc::new(BroadcastSink::new(16));
+        // Nobody listens yet
+        sinks.add(broadcast.clone());
+        sinks.emit("ep-1", EndpointEventKind::Starting);
+
+        let mut receiver = broadcast.subscribe();
+        sinks.add(Arc::new(Panicking));
+        sinks.add(broadcast.clone());
+        sinks.emit("ep-1", EndpointEventKind::Running);
+        sinks.emit("ep-1", EndpointEventKind::Stopping);
+
+        let mut received = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            received.push(event.kind);
+        }
+        // Once for each registration, the panic in between notwithstanding
+        assert_eq!(
+            received,
+            [
+                EndpointEventKind::Running,
+                EndpointEventKind::Running,
+                EndpointEventKind::Stopping,
+                EndpointEventKind::Stopping
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Advisory lock on the endpoints directory, `endpoints/.lock`, for the changes that
+//! several neon_local invocations could make at once: creating and destroying endpoints,
+//! and updating the port registry. Operations that only read the directory don't take it.
+//!
+//! The lock is reentrant within a thread, so that creating an endpoint can allocate its
+//! ports while holding it. Other threads and processes wait for it, up to a timeout. The
+//! holder's pid is written to the lock file, to tell who to wait for.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+
+const LOCK_FILE: &str = ".lock";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+thread_local! {
+    // The lock files held by this thread, with the number of guards sharing each
+    static HELD: RefCell<HashMap<PathBuf, (fs::File, usize)>> = RefCell::new(HashMap::new());
+}
+
+/// The lock on an endpoints directory, released when the last guard of the thread is
+/// dropped.
+#[must_use]
+pub(crate) struct EndpointsLock {
+    path: PathBuf,
+}
+
+/// Lock the endpoints directory `dir`, waiting for other neon_local invocations to
+/// release it.
+pub(crate) fn lock(dir: &Path) -> Result<EndpointsLock> {
+    lock_with_timeout(dir, LOCK_TIMEOUT)
+}
+
+fn lock_with_timeout(dir: &Path, timeout: Duration) -> Result<EndpointsLock> {
+    let path = dir.join(LOCK_FILE);
+    let reentered = HELD.with(|held| match held.borrow_mut().get_mut(&path) {
+        Some((_, guards)) => {
+            *guards += 1;
+            true
+        }
+        None => false,
+    });
+    if reentered {
+        return Ok(EndpointsLock { path });
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => break,
+            Err(Errno::EAGAIN) if Instant::now() < deadline => {
+                std::thread::sleep(LOCK_POLL_INTERVAL)
+            }
+            Err(Errno::EAGAIN) => {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                let holder = match holder.trim() {
+                    "" => "unknown",
+                    pid => pid,
+                };
+                bail!(
+                    "another neon_local is modifying endpoints (pid {holder}), gave up waiting after {}",
+                    humantime::format_duration(timeout)
+                );
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to lock {}", path.display())),
+        }
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", std::process::id())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    HELD.with(|held| held.borrow_mut().insert(path.clone(), (file, 1)));
+    Ok(EndpointsLock { path })
+}
+
+impl Drop for EndpointsLock {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some((_, guards)) = held.get_mut(&self.path) {
+                *guards -= 1;
+                if *guards == 0 {
+                    // Closing the file releases the lock
+                    held.remove(&self.path);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reentrant_but_exclusive() {
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path().to_owned();
+
+        let outer = lock(&dir).unwrap();
+        let inner = lock(&dir).unwrap();
+        drop(inner);
+        // Still held by the outer guard
+        let other_thread = {
+            let dir = dir.clone();
+            std::thread::spawn(move || lock_with_timeout(&dir, Duration::from_millis(100)).err())
+        };
+        let err = other_thread.join().unwrap().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "another neon_local is modifying endpoints (pid {}), gave up waiting after 100ms",
+                std::process::id()
+            )
+        );
+
+        drop(outer);
+        let other_thread =
+            std::thread::spawn(move || lock_with_timeout(&dir, Duration::from_millis(100)).is_ok());
+        assert!(other_thread.join().unwrap());
+    }
+}
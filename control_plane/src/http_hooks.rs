@@ -0,0 +1,172 @@
+//! Hooks around the calls to the HTTP API of `compute_ctl`, to test how neon_local and
+//! its callers cope with a slow or flaky compute.
+//!
+//! Tests install their own [`HttpHooks`] with [`crate::endpoint::Endpoint::set_http_hooks`].
+//! For manual use, `NEON_LOCAL_CHAOS=latency:500ms,error_rate:0.1` makes every endpoint
+//! delay its calls and fail some of them, see [`ChaosHooks`].
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use reqwest::StatusCode;
+
+pub const CHAOS_ENV_VAR: &str = "NEON_LOCAL_CHAOS";
+
+/// The `compute_ctl` calls that go through the hooks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpCall {
+    /// GET /status
+    Status,
+    /// POST /configure, made by reconfigure
+    Configure,
+}
+
+impl fmt::Display for HttpCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpCall::Status => write!(f, "GET /status"),
+            HttpCall::Configure => write!(f, "POST /configure"),
+        }
+    }
+}
+
+/// What to do with a call before it is sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Injection {
+    /// Wait this long before sending the call.
+    pub delay: Duration,
+    /// Don't send the call, fail it with an [`InjectedError`] with this status instead.
+    pub fail: Option<StatusCode>,
+}
+
+/// A failure made up by the hooks rather than returned by `compute_ctl`.
+#[derive(Debug, thiserror::Error)]
+#[error("injected {status} response to {call}")]
+pub struct InjectedError {
+    pub call: HttpCall,
+    pub status: StatusCode,
+}
+
+pub trait HttpHooks: Send + Sync + fmt::Debug {
+    /// Called before `call` is sent.
+    fn before(&self, _call: HttpCall) -> Injection {
+        Injection::default()
+    }
+
+    /// Called with the response to `call`, returns the response the caller sees.
+    fn after(&self, _call: HttpCall, status: StatusCode, body: String) -> (StatusCode, String) {
+        (status, body)
+    }
+}
+
+/// The default hooks, which leave the calls alone.
+#[derive(Debug)]
+pub struct NoHooks;
+
+impl HttpHooks for NoHooks {}
+
+/// Delays every call by `latency`, and fails a share of them with a 500.
+#[derive(Debug, Default, PartialEq)]
+pub struct ChaosHooks {
+    pub latency: Duration,
+    /// Between 0 and 1.
+    pub error_rate: f64,
+}
+
+impl HttpHooks for ChaosHooks {
+    fn before(&self, _call: HttpCall) -> Injection {
+        let fail = rand::thread_rng().gen_bool(self.error_rate);
+        Injection {
+            delay: self.latency,
+            fail: fail.then_some(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+}
+
+impl std::str::FromStr for ChaosHooks {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut hooks = ChaosHooks::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = setting
+                .split_once(':')
+                .with_context(|| format!("expected key:value, got '{setting}'"))?;
+            match key {
+                "latency" => {
+                    hooks.latency = humantime::parse_duration(value)
+                        .with_context(|| format!("invalid latency '{value}'"))?
+                }
+                "error_rate" => {
+                    hooks.error_rate = value
+                        .parse()
+                        .with_context(|| format!("invalid error rate '{value}'"))?;
+                    if !(0.0..=1.0).contains(&hooks.error_rate) {
+                        bail!("error rate {value} is not between 0 and 1");
+                    }
+                }
+                _ => bail!("unknown setting '{key}', expected latency or error_rate"),
+            }
+        }
+        Ok(hooks)
+    }
+}
+
+/// The hooks for new endpoints: [`ChaosHooks`] if [`CHAOS_ENV_VAR`] is set, no hooks
+/// otherwise.
+pub fn from_env() -> Result<Arc<dyn HttpHooks>> {
+    match std::env::var(CHAOS_ENV_VAR) {
+        Ok(settings) => {
+            let hooks: ChaosHooks = settings
+                .parse()
+                .with_context(|| format!("invalid {CHAOS_ENV_VAR}"))?;
+            Ok(Arc::new(hooks))
+        }
+        Err(_) => Ok(Arc::new(NoHooks)),
+    }
+}
+
+/// Apply `injection` to `call`: wait, then fail if asked to.
+pub(crate) async fn inject(call: HttpCall, injection: Injection) -> Result<(), InjectedError> {
+    if !injection.delay.is_zero() {
+        tokio::time::sleep(injection.delay).await;
+    }
+    match injection.fail {
+        Some(status) => Err(InjectedError { call, status }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_chaos_settings() {
+        assert_eq!(
+            "latency:500ms,error_rate:0.1"
+                .parse::<ChaosHooks>()
+                .unwrap(),
+            ChaosHooks {
+                latency: Duration::from_millis(500),
+                error_rate: 0.1
+            }
+        );
+        assert_eq!("".parse::<ChaosHooks>().unwrap(), ChaosHooks::default());
+        for invalid in ["latency", "error_rate:2", "jitter:1s", "latency:soon"] {
+            assert!(invalid.parse::<ChaosHooks>().is_err(), "{invalid}");
+        }
+
+        let always_fail = ChaosHooks {
+            latency: Duration::ZERO,
+            error_rate: 1.0,
+        };
+        assert_eq!(
+            always_fail.before(HttpCall::Status).fail,
+            Some(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+    }
+}
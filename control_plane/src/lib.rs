@@ -9,8 +9,12 @@
 mod background_process;
 pub mod broker;
 pub mod endpoint;
+pub mod endpoint_events;
+mod endpoints_lock;
+pub mod http_hooks;
 pub mod local_env;
 pub mod pageserver;
+mod port_registry;
 pub mod postgresql_conf;
 pub mod safekeeper;
 pub mod storage_controller;
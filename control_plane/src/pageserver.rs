@@ -23,13 +23,15 @@ use pageserver_api::shard::TenantShardId;
 use pageserver_client::mgmt_api;
 use postgres_backend::AuthType;
 use postgres_connection::{parse_host_port, PgConnectionConfig};
-use utils::auth::{Claims, Scope};
+use utils::auth::Scope;
 use utils::{
     id::{TenantId, TimelineId},
     lsn::Lsn,
 };
 
-use crate::local_env::{NeonLocalInitPageserverConf, PageServerConf};
+use crate::local_env::{
+    AuthComponent, NeonLocalInitPageserverConf, PageServerConf, AUTH_PUBLIC_KEY_FILE,
+};
 use crate::{background_process, local_env::LocalEnv};
 
 /// Directory within .neon which will be used by default for LocalFs remote storage.
@@ -63,7 +65,7 @@ impl PageServerNode {
                     match conf.http_auth_type {
                         AuthType::Trust => None,
                         AuthType::NeonJWT => Some(
-                            env.generate_auth_token(&Claims::new(None, Scope::PageServerApi))
+                            env.generate_scoped_token(Scope::PageServerApi, None, None)
                                 .unwrap(),
                         ),
                     }
@@ -102,7 +104,7 @@ impl PageServerNode {
             if matches!(conf.http_auth_type, AuthType::NeonJWT) {
                 let jwt_token = self
                     .env
-                    .generate_auth_token(&Claims::new(None, Scope::GenerationsApi))
+                    .generate_scoped_token(Scope::GenerationsApi, None, None)
                     .unwrap();
                 overrides.push(format!("control_plane_api_token='{}'", jwt_token));
             }
@@ -115,9 +117,12 @@ impl PageServerNode {
         }
 
         if conf.http_auth_type != AuthType::Trust || conf.pg_auth_type != AuthType::Trust {
+            self.env.jwt_auth_for(AuthComponent::Pageserver)?;
             // Keys are generated in the toplevel repo dir, pageservers' workdirs
             // are one level below that, so refer to keys with ../
-            overrides.push("auth_validation_public_key_path='../auth_public_key.pem'".to_owned());
+            overrides.push(format!(
+                "auth_validation_public_key_path='../{AUTH_PUBLIC_KEY_FILE}'"
+            ));
         }
 
         // Apply the user-provided overrides
@@ -262,7 +267,7 @@ impl PageServerNode {
             // Generate a token to connect from the pageserver to a safekeeper
             let token = self
                 .env
-                .generate_auth_token(&Claims::new(None, Scope::SafekeeperData))?;
+                .generate_scoped_token(Scope::SafekeeperData, None, None)?;
             vec![("NEON_AUTH_TOKEN".to_owned(), token)]
         } else {
             Vec::new()
@@ -291,7 +296,7 @@ impl PageServerNode {
         if self.conf.pg_auth_type == AuthType::NeonJWT {
             let token = self
                 .env
-                .generate_auth_token(&Claims::new(None, Scope::PageServerApi))?;
+                .generate_scoped_token(Scope::PageServerApi, None, None)?;
             config = config.set_password(Some(token));
         }
         Ok(config.connect_no_tls().await?)
@@ -0,0 +1,258 @@
+//! Registry of the ports allocated to compute endpoints, stored in `endpoints/ports.json`.
+//!
+//! Ports of destroyed endpoints are not handed out again right away: the old compute
+//! may still be shutting down and holding on to them. Instead, the registry remembers
+//! when a port was released, and only reuses it after a cooldown.
+//!
+//! Several neon_local invocations can allocate at the same time, so every update
+//! happens under the lock of the endpoints directory, see the endpoints_lock module, and
+//! the file is replaced atomically. A missing or unreadable registry is rebuilt from the live endpoints.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+use utils::crashsafe;
+
+use crate::endpoints_lock;
+
+const REGISTRY_FILE: &str = "ports.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PortAllocation {
+    pub endpoint_id: String,
+    /// Seconds since the epoch.
+    pub allocated_at: u64,
+    /// Seconds since the epoch, None while the endpoint exists.
+    pub released_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct RegistryFile {
+    ports: BTreeMap<u16, PortAllocation>,
+}
+
+pub struct PortRegistry {
+    /// The endpoints directory. Each endpoint has a subdirectory named after its id.
+    dir: PathBuf,
+    /// Ports are allocated above this one.
+    base_port: u16,
+    /// How long a released port is not reused.
+    cooldown: Duration,
+}
+
+impl PortRegistry {
+    pub fn new(dir: &Path, base_port: u16, cooldown: Duration) -> Self {
+        PortRegistry {
+            dir: dir.to_owned(),
+            base_port,
+            cooldown,
+        }
+    }
+
+    /// Allocate the lowest available port for `endpoint_id`. `live` are the ports of the
+    /// endpoints the caller knows about, which are never handed out even if the registry
+    /// has lost track of them.
+    pub fn allocate(&self, endpoint_id: &str, live: &[u16]) -> Result<u16> {
+        self.update(|registry, now| {
+            let port = (self.base_port.saturating_add(1)..=u16::MAX)
+                .find(|port| {
+                    !live.contains(port)
+                        && registry
+                            .ports
+                            .get(port)
+                            .map_or(true, |allocation| self.is_free(allocation, now))
+                })
+                .context("no free ports left")?;
+            registry.ports.insert(
+                port,
+                PortAllocation {
+                    endpoint_id: endpoint_id.to_string(),
+                    allocated_at: now,
+                    released_at: None,
+                },
+            );
+            Ok(port)
+        })
+    }
+
+    /// Record a port that was chosen by the user rather than allocated.
+    pub fn reserve(&self, endpoint_id: &str, port: u16) -> Result<()> {
+        self.update(|registry, now| {
+            registry.ports.insert(
+                port,
+                PortAllocation {
+                    endpoint_id: endpoint_id.to_string(),
+                    allocated_at: now,
+                    released_at: None,
+                },
+            );
+            Ok(())
+        })
+    }
+
+    /// Mark the ports of `endpoint_id` as released. They become available again once
+    /// the cooldown has passed.
+    pub fn release(&self, endpoint_id: &str) -> Result<()> {
+        self.update(|registry, now| {
+            for allocation in registry.ports.values_mut() {
+                if allocation.endpoint_id == endpoint_id && allocation.released_at.is_none() {
+                    allocation.released_at = Some(now);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Mark only `ports` of `endpoint_id` as released, e.g. those an endpoint doesn't use
+    /// anymore after it was replaced.
+    pub fn release_ports(&self, endpoint_id: &str, ports: &[u16]) -> Result<()> {
+        if ports.is_empty() {
+            return Ok(());
+        }
+        self.update(|registry, now| {
+            for port in ports {
+                if let Some(allocation) = registry.ports.get_mut(port) {
+                    if allocation.endpoint_id == endpoint_id && allocation.released_at.is_none() {
+                        allocation.released_at = Some(now);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// All entries of the registry, for inspection.
+    pub fn allocations(&self) -> Result<BTreeMap<u16, PortAllocation>> {
+        let _lock = endpoints_lock::lock(&self.dir)?;
+        Ok(self.read().ports)
+    }
+
+    fn is_free(&self, allocation: &PortAllocation, now: u64) -> bool {
+        let cooldown = self.cooldown.as_secs();
+        match allocation.released_at {
+            Some(released_at) => now >= released_at.saturating_add(cooldown),
+            // The endpoint may have been removed without going through `release`, e.g.
+            // by deleting its directory. Its allocation still counts for the cooldown,
+            // as the endpoint directory might just not have been created yet.
+            None => {
+                !self.dir.join(&allocation.endpoint_id).exists()
+                    && now >= allocation.allocated_at.saturating_add(cooldown)
+            }
+        }
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut RegistryFile, u64) -> Result<T>) -> Result<T> {
+        let _lock = endpoints_lock::lock(&self.dir)?;
+        let mut registry = self.read();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("system time before the epoch")?
+            .as_secs();
+        let result = f(&mut registry, now)?;
+        self.write(&registry)?;
+        Ok(result)
+    }
+
+    /// Read the registry. A missing or corrupt file yields an empty registry, which is
+    /// filled again from the live endpoints passed to [`Self::allocate`].
+    fn read(&self) -> RegistryFile {
+        let path = self.dir.join(REGISTRY_FILE);
+        match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                eprintln!(
+                    "Ignoring corrupt port registry {}, rebuilding it: {e}",
+                    path.display()
+                );
+                RegistryFile::default()
+            }),
+            Err(_) => RegistryFile::default(),
+        }
+    }
+
+    fn write(&self, registry: &RegistryFile) -> Result<()> {
+        let path = Utf8PathBuf::try_from(self.dir.join(REGISTRY_FILE))?;
+        let tmp_path = crashsafe::path_with_suffix_extension(&path, "tmp");
+        crashsafe::overwrite(
+            &path,
+            &tmp_path,
+            serde_json::to_string_pretty(registry)?.as_bytes(),
+        )
+        .with_context(|| format!("failed to write {path}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    #[test]
+    fn released_ports_cool_down() {
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path();
+        let registry = PortRegistry::new(dir, 1000, Duration::from_secs(3600));
+        fs::create_dir(dir.join("ep-1")).unwrap();
+        assert_eq!(registry.allocate("ep-1", &[]).unwrap(), 1001);
+        assert_eq!(registry.allocate("ep-1", &[]).unwrap(), 1002);
+
+        // Destroyed endpoint: its ports are not reused within the cooldown
+        fs::remove_dir(dir.join("ep-1")).unwrap();
+        registry.release("ep-1").unwrap();
+        assert_eq!(registry.allocate("ep-2", &[]).unwrap(), 1003);
+        let allocations = registry.allocations().unwrap();
+        assert!(allocations[&1001].released_at.is_some());
+        assert!(allocations[&1003].released_at.is_none());
+
+        // But they are once it has passed
+        let registry = PortRegistry::new(dir, 1000, Duration::ZERO);
+        assert_eq!(registry.allocate("ep-3", &[]).unwrap(), 1001);
+    }
+
+    #[test]
+    fn rebuild_from_live_endpoints() {
+        let tmp = camino_tempfile::tempdir().unwrap();
+        let dir = tmp.path().as_std_path();
+        fs::write(dir.join(REGISTRY_FILE), "{ not json").unwrap();
+        let registry = PortRegistry::new(dir, 1000, Duration::from_secs(3600));
+        assert_eq!(registry.allocate("ep-3", &[1001, 1002]).unwrap(), 1003);
+
+        fs::remove_file(dir.join(REGISTRY_FILE)).unwrap();
+        assert_eq!(registry.allocate("ep-4", &[1001, 1002]).unwrap(), 1003);
+        assert!(!dir.join("ports.json.tmp").exists());
+    }
+
+    #[test]
+    fn concurrent_allocations() {
+        let dir = Arc::new(camino_tempfile::tempdir().unwrap());
+        let threads: Vec<_> = (0..2)
+            .map(|i| {
+                let dir = Arc::clone(&dir);
+                std::thread::spawn(move || {
+                    // Each thread acts as a separate control plane instance
+                    let registry = PortRegistry::new(
+                        dir.path().as_std_path(),
+                        1000,
+                        Duration::from_secs(3600),
+                    );
+                    (0..50)
+                        .map(|j| registry.allocate(&format!("ep-{i}-{j}"), &[]).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ports: Vec<u16> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        let unique: HashSet<u16> = ports.iter().copied().collect();
+        assert_eq!(unique.len(), 100);
+        let registry = PortRegistry::new(dir.path().as_std_path(), 1000, Duration::from_secs(3600));
+        assert_eq!(registry.allocations().unwrap().len(), 100);
+    }
+}
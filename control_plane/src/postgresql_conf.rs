@@ -1,68 +1,239 @@
 ///
-/// Module for parsing postgresql.conf file.
+/// Module for parsing and editing postgresql.conf file.
 ///
 /// NOTE: This doesn't implement the full, correct postgresql.conf syntax. Just
 /// enough to extract a few settings we need in Neon, assuming you don't do
-/// funny stuff like include-directives or funny escaping.
+/// funny stuff like include_dir directives or funny escaping.
 use anyhow::{bail, Context, Result};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
-use std::io::BufRead;
+use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
 /// In-memory representation of a postgresql.conf file
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct PostgresConf {
-    lines: Vec<String>,
-    hash: HashMap<String, String>,
+    lines: Vec<ConfLine>,
 }
 
-static CONF_LINE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^((?:\w|\.)+)\s*=\s*(\S+)$").unwrap());
+#[derive(Debug, Clone, PartialEq)]
+enum ConfLine {
+    /// A `name = value` line. `raw` is the original text of a line read from a
+    /// file, written back as is unless the setting is changed.
+    Setting {
+        name: String,
+        value: String,
+        raw: Option<String>,
+    },
+    /// An `include` or `include_if_exists` directive. The path is relative to
+    /// the directory of the file containing it.
+    Include {
+        path: String,
+        if_exists: bool,
+        raw: Option<String>,
+    },
+    /// Comment, blank line, or anything else that is written back verbatim.
+    Other(String),
+}
+
+/// Settings that differ between two configs, see [`PostgresConf::diff`]. Names
+/// are lowercase, since setting names are case-insensitive.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfDiff {
+    /// Settings only in the new config, with their values.
+    pub added: BTreeMap<String, String>,
+    /// Settings only in the old config, with their values.
+    pub removed: BTreeMap<String, String>,
+    pub changed: BTreeMap<String, ChangedSetting>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedSetting {
+    pub old: String,
+    pub new: String,
+}
+
+impl ConfDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for ConfDiff {
+    /// One line summary, like `+work_mem=64MB, -fsync, port: 5432 -> 5433`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("no changes");
+        }
+        let added = self
+            .added
+            .iter()
+            .map(|(name, value)| format!("+{}={}", name, escape_str(value)));
+        let removed = self.removed.keys().map(|name| format!("-{}", name));
+        let changed = self.changed.iter().map(|(name, change)| {
+            format!(
+                "{}: {} -> {}",
+                name,
+                escape_str(&change.old),
+                escape_str(&change.new)
+            )
+        });
+        let changes: Vec<_> = added.chain(removed).chain(changed).collect();
+        f.write_str(&changes.join(", "))
+    }
+}
+
+/// Maximum nesting depth of include directives, same as in PostgreSQL. Also
+/// stops include cycles.
+const MAX_INCLUDE_DEPTH: usize = 10;
 
 impl PostgresConf {
     pub fn new() -> PostgresConf {
         PostgresConf::default()
     }
 
-    /// Read file into memory
-    pub fn read(read: impl std::io::Read) -> Result<PostgresConf> {
+    /// Read file into memory, see [`PostgresConf::parse`].
+    pub fn read(mut read: impl std::io::Read) -> Result<PostgresConf> {
+        let mut text = String::new();
+        read.read_to_string(&mut text)?;
+        Self::parse(&text)
+    }
+
+    /// Parse the contents of a postgresql.conf file. Comments and blank lines are
+    /// preserved. If a setting appears more than once, the last value wins, like
+    /// in PostgreSQL, and the setting is kept once at its first position.
+    ///
+    /// FIXME: This doesn't match exactly the flex/bison grammar in PostgreSQL.
+    /// But it's close enough for our usage.
+    pub fn parse(text: &str) -> Result<PostgresConf> {
         let mut result = Self::new();
+        for (lineno, line) in text.lines().enumerate() {
+            let parsed = parse_line(line)
+                .with_context(|| format!("invalid line {}: {}", lineno + 1, line))?;
+            let raw = Some(line.to_string());
+            match parsed {
+                Some((name, path)) if name.eq_ignore_ascii_case("include") => {
+                    result.lines.push(ConfLine::Include {
+                        path,
+                        if_exists: false,
+                        raw,
+                    })
+                }
+                Some((name, path)) if name.eq_ignore_ascii_case("include_if_exists") => {
+                    result.lines.push(ConfLine::Include {
+                        path,
+                        if_exists: true,
+                        raw,
+                    })
+                }
+                Some((name, value)) => result.set_line(name, value, raw),
+                None => result.lines.push(ConfLine::Other(line.to_string())),
+            }
+        }
+        Ok(result)
+    }
 
-        for line in std::io::BufReader::new(read).lines() {
-            let line = line?;
+    /// Read a postgresql.conf file, replacing include directives with the
+    /// contents of the included files. Paths are resolved relative to the
+    /// directory of the including file, and missing `include_if_exists` targets
+    /// are skipped. The result has the effective value of each setting, and can
+    /// be used as a single file without includes.
+    pub fn load(path: &Path) -> Result<PostgresConf> {
+        let mut result = Self::new();
+        result.include_file(path, 0)?;
+        Ok(result)
+    }
 
-            // Store each line in a vector, in original format
-            result.lines.push(line.clone());
+    fn include_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            bail!(
+                "could not open config file {}: maximum nesting depth exceeded",
+                path.display()
+            );
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let conf = Self::parse(&content)
+            .with_context(|| format!("failed to parse config file {}", path.display()))?;
 
-            // Also parse each line and insert key=value lines into a hash map.
-            //
-            // FIXME: This doesn't match exactly the flex/bison grammar in PostgreSQL.
-            // But it's close enough for our usage.
-            let line = line.trim();
-            if line.starts_with('#') {
-                // comment, ignore
-                continue;
-            } else if let Some(caps) = CONF_LINE_RE.captures(line) {
-                let name = caps.get(1).unwrap().as_str();
-                let raw_val = caps.get(2).unwrap().as_str();
-
-                if let Ok(val) = deescape_str(raw_val) {
-                    // Note: if there's already an entry in the hash map for
-                    // this key, this will replace it. That's the behavior what
-                    // we want; when PostgreSQL reads the file, each line
-                    // overrides any previous value for the same setting.
-                    result.hash.insert(name.to_string(), val.to_string());
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for line in conf.lines {
+            match line {
+                ConfLine::Setting { name, value, raw } => self.set_line(name, value, raw),
+                ConfLine::Include {
+                    path: included,
+                    if_exists,
+                    ..
+                } => {
+                    // absolute paths replace the directory
+                    let included = dir.join(included);
+                    if if_exists && !included.exists() {
+                        continue;
+                    }
+                    self.include_file(&included, depth + 1)?;
                 }
+                other @ ConfLine::Other(_) => self.lines.push(other),
             }
         }
-        Ok(result)
+        Ok(())
     }
 
     /// Return the current value of 'option'
     pub fn get(&self, option: &str) -> Option<&str> {
-        self.hash.get(option).map(|x| x.as_ref())
+        // with append(), the same setting can be present multiple times, and
+        // the last one wins
+        self.lines.iter().rev().find_map(|line| match line {
+            ConfLine::Setting { name, value, .. } if name.eq_ignore_ascii_case(option) => {
+                Some(value.as_str())
+            }
+            _ => None,
+        })
+    }
+
+    /// Compare the effective settings with `other`, which is treated as the newer
+    /// config. Comments, formatting and the order of settings are ignored.
+    pub fn diff(&self, other: &PostgresConf) -> ConfDiff {
+        let old = self.settings();
+        let new = other.settings();
+
+        let mut diff = ConfDiff::default();
+        for (name, old_value) in old.iter() {
+            match new.get(name) {
+                None => {
+                    diff.removed.insert(name.clone(), old_value.to_string());
+                }
+                Some(new_value) if new_value != old_value => {
+                    let change = ChangedSetting {
+                        old: old_value.to_string(),
+                        new: new_value.to_string(),
+                    };
+                    diff.changed.insert(name.clone(), change);
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, new_value) in new {
+            if !old.contains_key(&name) {
+                diff.added.insert(name, new_value.to_string());
+            }
+        }
+        diff
+    }
+
+    /// Effective value of each setting, by lowercase name.
+    fn settings(&self) -> BTreeMap<String, &str> {
+        let mut settings = BTreeMap::new();
+        for line in self.lines.iter() {
+            if let ConfLine::Setting { name, value, .. } = line {
+                // with append(), the last one wins
+                settings.insert(name.to_ascii_lowercase(), value.as_str());
+            }
+        }
+        settings
     }
 
     /// Return the current value of a field, parsed to the right datatype.
@@ -97,20 +268,139 @@ impl PostgresConf {
         }
     }
 
+    /// Set 'option' to 'value', replacing the existing line for the option if
+    /// there is one, so that each option is in the file only once.
+    pub fn set(&mut self, option: &str, value: &str) {
+        self.set_line(option.to_string(), value.to_string(), None);
+    }
+
+    /// Set all settings of 'other' on top of this config, see [`PostgresConf::set`].
+    /// Comments and other non-setting lines of 'other' are not copied.
+    pub fn merge(&mut self, other: PostgresConf) {
+        for line in other.lines {
+            if let ConfLine::Setting { name, value, raw } = line {
+                self.set_line(name, value, raw);
+            }
+        }
+    }
+
     ///
     /// Note: if you call this multiple times for the same option, the config
-    /// file will a line for each call. It would be nice to have a function
-    /// to change an existing line, but that's a TODO.
+    /// file will a line for each call. Use [`PostgresConf::set`] to change an
+    /// existing line instead.
     ///
     pub fn append(&mut self, option: &str, value: &str) {
-        self.lines
-            .push(format!("{}={}\n", option, escape_str(value)));
-        self.hash.insert(option.to_string(), value.to_string());
+        self.lines.push(ConfLine::Setting {
+            name: option.to_string(),
+            value: value.to_string(),
+            raw: None,
+        });
+    }
+
+    /// Set a memory setting, like "shared_buffers", to a size in bytes. Like
+    /// [`PostgresConf::set`], replaces an existing line.
+    pub fn append_bytes(&mut self, option: &str, bytes: u64) {
+        self.set(option, &format_bytes(bytes));
+    }
+
+    /// Set a time setting, like "statement_timeout". Like [`PostgresConf::set`],
+    /// replaces an existing line. Precision below a microsecond is dropped.
+    pub fn append_duration(&mut self, option: &str, duration: Duration) {
+        self.set(option, &format_duration(duration));
+    }
+
+    /// Set a boolean setting. Like [`PostgresConf::set`], replaces an existing line.
+    pub fn append_bool(&mut self, option: &str, value: bool) {
+        self.set(option, if value { "on" } else { "off" });
+    }
+
+    /// Set an integer setting. Like [`PostgresConf::set`], replaces an existing line.
+    pub fn append_int(&mut self, option: &str, value: i64) {
+        self.set(option, &value.to_string());
+    }
+
+    /// Check the values of well-known settings, so that a typo like "15M" fails
+    /// before PostgreSQL is started. Unknown settings are not an error, since
+    /// extensions add their own: a warning is printed for each, and their names
+    /// are returned.
+    pub fn validate_known_gucs(&self) -> Result<Vec<String>> {
+        let mut unknown = Vec::new();
+        for line in self.lines.iter() {
+            let ConfLine::Setting { name, value, .. } = line else {
+                continue;
+            };
+            let known = KNOWN_GUCS
+                .iter()
+                .find(|(known_name, _)| known_name.eq_ignore_ascii_case(name));
+            match known {
+                Some((_, guc_type)) => {
+                    if !guc_type.is_valid(value) {
+                        bail!(
+                            "invalid value for {:?} setting '{}': '{}'",
+                            guc_type,
+                            name,
+                            value
+                        );
+                    }
+                }
+                None => {
+                    eprintln!("WARNING: unknown setting '{}' in postgresql.conf", name);
+                    unknown.push(name.clone());
+                }
+            }
+        }
+        Ok(unknown)
+    }
+
+    /// Append an `include` directive. PostgreSQL fails to start if the file
+    /// doesn't exist.
+    pub fn append_include(&mut self, path: &str) {
+        self.lines.push(ConfLine::Include {
+            path: path.to_string(),
+            if_exists: false,
+            raw: None,
+        });
+    }
+
+    /// Append an `include_if_exists` directive.
+    pub fn append_include_if_exists(&mut self, path: &str) {
+        self.lines.push(ConfLine::Include {
+            path: path.to_string(),
+            if_exists: true,
+            raw: None,
+        });
     }
 
     /// Append an arbitrary non-setting line to the config file
     pub fn append_line(&mut self, line: &str) {
-        self.lines.push(line.to_string());
+        self.lines.push(ConfLine::Other(line.to_string()));
+    }
+
+    fn set_line(&mut self, option: String, new_value: String, new_raw: Option<String>) {
+        let mut found = false;
+        self.lines.retain_mut(|line| {
+            let ConfLine::Setting { name, value, raw } = line else {
+                return true;
+            };
+            if !name.eq_ignore_ascii_case(&option) {
+                return true;
+            }
+            if found {
+                // duplicate added with append()
+                return false;
+            }
+            found = true;
+            value.clone_from(&new_value);
+            raw.clone_from(&new_raw);
+            true
+        });
+        if !found {
+            self.lines.push(ConfLine::Setting {
+                name: option,
+                value: new_value,
+                raw: new_raw,
+            });
+        }
     }
 }
 
@@ -118,40 +408,249 @@ impl fmt::Display for PostgresConf {
     /// Return the whole configuration file as a string
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for line in self.lines.iter() {
-            f.write_str(line)?;
+            match line {
+                ConfLine::Setting { raw: Some(raw), .. } => writeln!(f, "{}", raw)?,
+                ConfLine::Setting { name, value, .. } => {
+                    writeln!(f, "{}={}", name, escape_str(value))?
+                }
+                ConfLine::Include { raw: Some(raw), .. } => writeln!(f, "{}", raw)?,
+                ConfLine::Include {
+                    path, if_exists, ..
+                } => {
+                    let directive = if *if_exists {
+                        "include_if_exists"
+                    } else {
+                        "include"
+                    };
+                    // quoted even if not required, like in PostgreSQL docs
+                    writeln!(f, "{} '{}'", directive, path.replace('\'', "''"))?
+                }
+                ConfLine::Other(line) => writeln!(f, "{}", line)?,
+            }
         }
         Ok(())
     }
 }
 
-/// Escape a value for putting in postgresql.conf.
-fn escape_str(s: &str) -> String {
-    // If the string doesn't contain anything that needs quoting or escaping, return it
-    // as it is.
-    //
+/// Type of a setting value, for [`PostgresConf::validate_known_gucs`].
+#[derive(Debug, Clone, Copy)]
+enum GucType {
+    Bool,
+    Int,
+    /// Integer with an optional memory unit.
+    Bytes,
+    /// Integer with an optional time unit.
+    Duration,
+    /// Strings and enums, not checked.
+    String,
+}
+
+impl GucType {
+    fn is_valid(self, value: &str) -> bool {
+        static INT_RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^[-+]?([0-9]+(\.[0-9]+)?|0[xX][0-9a-fA-F]+)$").unwrap());
+        static BYTES_RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^[-+]?[0-9]+(\.[0-9]+)?\s*(B|kB|MB|GB|TB)?$").unwrap());
+        static DURATION_RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^[-+]?[0-9]+(\.[0-9]+)?\s*(us|ms|s|min|h|d)?$").unwrap());
+
+        match self {
+            GucType::Bool => is_valid_bool(value),
+            GucType::Int => INT_RE.is_match(value),
+            GucType::Bytes => BYTES_RE.is_match(value),
+            GucType::Duration => DURATION_RE.is_match(value),
+            GucType::String => true,
+        }
+    }
+}
+
+/// Common settings, including the ones set by neon_local.
+const KNOWN_GUCS: &[(&str, GucType)] = &[
+    ("autovacuum", GucType::Bool),
+    ("checkpoint_timeout", GucType::Duration),
+    ("effective_cache_size", GucType::Bytes),
+    ("fsync", GucType::Bool),
+    ("hot_standby", GucType::Bool),
+    ("listen_addresses", GucType::String),
+    ("log_min_messages", GucType::String),
+    ("log_statement", GucType::String),
+    ("maintenance_work_mem", GucType::Bytes),
+    ("max_connections", GucType::Int),
+    ("max_replication_flush_lag", GucType::Bytes),
+    ("max_replication_slots", GucType::Int),
+    ("max_replication_write_lag", GucType::Bytes),
+    ("max_wal_senders", GucType::Int),
+    ("max_wal_size", GucType::Bytes),
+    ("max_worker_processes", GucType::Int),
+    ("min_wal_size", GucType::Bytes),
+    ("neon.pageserver_connstring", GucType::String),
+    ("neon.safekeepers", GucType::String),
+    ("neon.tenant_id", GucType::String),
+    ("neon.timeline_id", GucType::String),
+    ("port", GucType::Int),
+    ("primary_conninfo", GucType::String),
+    ("primary_slot_name", GucType::String),
+    ("recovery_prefetch", GucType::String),
+    ("recovery_target_lsn", GucType::String),
+    ("restart_after_crash", GucType::Bool),
+    ("shared_buffers", GucType::Bytes),
+    ("shared_preload_libraries", GucType::String),
+    ("statement_timeout", GucType::Duration),
+    ("synchronous_commit", GucType::String),
+    ("synchronous_standby_names", GucType::String),
+    ("wal_keep_size", GucType::Bytes),
+    ("wal_level", GucType::String),
+    ("wal_log_hints", GucType::Bool),
+    ("wal_sender_timeout", GucType::Duration),
+    ("wal_writer_delay", GucType::Duration),
+    ("work_mem", GucType::Bytes),
+];
+
+/// Accepts the same values as `parse_bool` in PostgreSQL: on/off, and unique
+/// prefixes of true/false/yes/no, case-insensitive.
+fn is_valid_bool(value: &str) -> bool {
+    let value = value.to_ascii_lowercase();
+    if value.is_empty() {
+        return false;
+    }
+    matches!(value.as_str(), "on" | "off" | "of" | "1" | "0")
+        || ["true", "false", "yes", "no"]
+            .iter()
+            .any(|word| word.starts_with(&value))
+}
+
+/// Format a size with the largest memory unit that represents it exactly.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [(&str, u64); 4] = [
+        ("TB", 1 << 40),
+        ("GB", 1 << 30),
+        ("MB", 1 << 20),
+        ("kB", 1 << 10),
+    ];
+    if bytes == 0 {
+        return "0".to_string();
+    }
+    for (unit, size) in UNITS {
+        if bytes % size == 0 {
+            return format!("{}{}", bytes / size, unit);
+        }
+    }
+    format!("{}B", bytes)
+}
+
+/// Format a duration with the largest time unit that represents it exactly.
+fn format_duration(duration: Duration) -> String {
+    const UNITS: [(&str, u128); 5] = [
+        ("d", 86_400_000_000),
+        ("h", 3_600_000_000),
+        ("min", 60_000_000),
+        ("s", 1_000_000),
+        ("ms", 1_000),
+    ];
+    let micros = duration.as_micros();
+    if micros == 0 {
+        return "0".to_string();
+    }
+    for (unit, size) in UNITS {
+        if micros % size == 0 {
+            return format!("{}{}", micros / size, unit);
+        }
+    }
+    format!("{}us", micros)
+}
+
+/// Parse a line into a setting name and de-escaped value. Returns None for
+/// comments and blank lines.
+fn parse_line(line: &str) -> Result<Option<(String, String)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let name_end = line
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(line.len());
+    let (name, rest) = line.split_at(name_end);
+    if name.is_empty() {
+        bail!("expected a setting name");
+    }
+
+    // the equals sign is optional
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+
+    let value_end = if rest.starts_with('\'') {
+        quoted_len(rest)?
+    } else {
+        rest.find(|c: char| c.is_whitespace() || c == '#')
+            .unwrap_or(rest.len())
+    };
+    let (raw_value, rest) = rest.split_at(value_end);
+    if raw_value.is_empty() {
+        bail!("expected a value for '{}'", name);
+    }
+
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        bail!("unexpected text after the value of '{}'", name);
+    }
+
+    Ok(Some((name.to_string(), deescape_str(raw_value)?)))
+}
+
+/// Length of the quoted string at the beginning of 's', including the quotes.
+fn quoted_len(s: &str) -> Result<usize> {
+    let mut iter = s.char_indices().skip(1).peekable();
+    while let Some((i, c)) = iter.next() {
+        match c {
+            '\\' => {
+                iter.next();
+            }
+            '\'' if iter.peek().map(|(_, c)| *c) == Some('\'') => {
+                // doubled quote
+                iter.next();
+            }
+            '\'' => return Ok(i + 1),
+            _ => {}
+        }
+    }
+    bail!("unterminated quoted string")
+}
+
+/// Returns true if the value has to be quoted in postgresql.conf: if it contains
+/// whitespace, '#', quotes, or anything else that doesn't make a single token.
+fn needs_quoting(s: &str) -> bool {
     // The first part of the regex, before the '|', matches the INTEGER rule in the
     // PostgreSQL flex grammar (guc-file.l). It matches plain integers like "123" and
     // "-123", and also accepts units like "10MB". The second part of the regex matches
-    // the UNQUOTED_STRING rule, and accepts strings that contain a single word, beginning
-    // with a letter. That covers words like "off" or "posix". Everything else is quoted.
+    // the UNQUOTED_STRING rule, and accepts identifiers, beginning with a letter or an
+    // underscore. That covers words like "off", "posix" or "remote_write". Everything
+    // else is quoted.
     //
     // This regex is a bit more conservative than the rules in guc-file.l, so we quote some
     // strings that PostgreSQL would accept without quoting, but that's OK.
-
     static UNQUOTED_RE: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r"(^[-+]?[0-9]+[a-zA-Z]*$)|(^[a-zA-Z][a-zA-Z0-9]*$)").unwrap());
+        Lazy::new(|| Regex::new(r"(^[-+]?[0-9]+[a-zA-Z]*$)|(^[a-zA-Z_][a-zA-Z0-9_]*$)").unwrap());
 
-    if UNQUOTED_RE.is_match(s) {
-        s.to_string()
-    } else {
-        // Otherwise escape and quote it
-        let s = s
-            .replace('\\', "\\\\")
-            .replace('\n', "\\n")
-            .replace('\'', "''");
+    !UNQUOTED_RE.is_match(s)
+}
 
-        "\'".to_owned() + &s + "\'"
+/// Escape a value for putting in postgresql.conf.
+fn escape_str(s: &str) -> String {
+    // If the string doesn't contain anything that needs quoting or escaping, return it
+    // as it is.
+    if !needs_quoting(s) {
+        return s.to_string();
     }
+
+    // Otherwise escape and quote it. Single quotes are doubled, like in SQL.
+    let s = s
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\'', "''");
+
+    "\'".to_owned() + &s + "\'"
 }
 
 /// De-escape a possibly-quoted value.
@@ -224,3 +723,355 @@ fn test_postgresql_conf_escapes() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_postgresql_conf_round_trip() -> Result<()> {
+    let text = "\
+# comment
+shared_buffers = 128MB   # trailing comment
+
+search_path='it''s, \\'quoted\\''
+Log_Line_Prefix = '%m [%p] '
+neon.safekeepers 'localhost:5454,localhost:5455'
+";
+    let conf = PostgresConf::parse(text)?;
+    assert_eq!(conf.to_string(), text);
+    assert_eq!(conf.get("shared_buffers"), Some("128MB"));
+    assert_eq!(conf.get("search_path"), Some("it's, 'quoted'"));
+    // setting names are case-insensitive
+    assert_eq!(conf.get("log_line_prefix"), Some("%m [%p] "));
+    assert_eq!(
+        conf.get("neon.safekeepers"),
+        Some("localhost:5454,localhost:5455")
+    );
+
+    // values written by set() are parsed back the same
+    let mut conf = PostgresConf::new();
+    conf.set("application_name", "it's a \\ 'test'");
+    conf.set("port", "5432");
+    let parsed = PostgresConf::parse(&conf.to_string())?;
+    assert_eq!(parsed.get("application_name"), Some("it's a \\ 'test'"));
+    assert_eq!(parsed.get("port"), Some("5432"));
+    assert_eq!(parsed.to_string(), conf.to_string());
+
+    assert!(PostgresConf::parse("foo = 'unterminated").is_err());
+    assert!(PostgresConf::parse("foo = bar baz").is_err());
+    assert!(PostgresConf::parse("foo =").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_merge() -> Result<()> {
+    let mut conf = PostgresConf::parse(
+        "\
+port = 1234
+shared_buffers = 1MB
+fsync = off
+shared_buffers = 128MB
+",
+    )?;
+    // the last value wins, and the setting is kept once
+    assert_eq!(conf.get("shared_buffers"), Some("128MB"));
+    assert_eq!(
+        conf.to_string(),
+        "port = 1234\nshared_buffers = 128MB\nfsync = off\n"
+    );
+
+    let mut overrides = PostgresConf::new();
+    overrides.set("PORT", "5432");
+    overrides.set("listen_addresses", "127.0.0.1");
+    conf.merge(overrides);
+    assert_eq!(conf.get("port"), Some("5432"));
+    assert_eq!(
+        conf.to_string(),
+        "port=5432\nshared_buffers = 128MB\nfsync = off\nlisten_addresses='127.0.0.1'\n"
+    );
+
+    // set() replaces duplicates added with append()
+    conf.append("fsync", "on");
+    assert_eq!(conf.get("fsync"), Some("on"));
+    conf.set("fsync", "off");
+    assert_eq!(conf.to_string().matches("fsync").count(), 1);
+    assert_eq!(conf.get("fsync"), Some("off"));
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_typed_setters() -> Result<()> {
+    assert_eq!(format_bytes(0), "0");
+    assert_eq!(format_bytes(1000), "1000B");
+    assert_eq!(format_bytes(1024), "1kB");
+    assert_eq!(format_bytes(1536), "1536B");
+    assert_eq!(format_bytes(15 * 1024 * 1024), "15MB");
+    assert_eq!(format_bytes(1536 * 1024 * 1024), "1536MB");
+    assert_eq!(format_bytes(10 << 30), "10GB");
+    assert_eq!(format_bytes(1 << 40), "1TB");
+
+    assert_eq!(format_duration(Duration::ZERO), "0");
+    assert_eq!(format_duration(Duration::from_nanos(1500)), "1us");
+    assert_eq!(format_duration(Duration::from_micros(1500)), "1500us");
+    assert_eq!(format_duration(Duration::from_millis(200)), "200ms");
+    assert_eq!(format_duration(Duration::from_secs(5)), "5s");
+    assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+    assert_eq!(format_duration(Duration::from_secs(120)), "2min");
+    assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+    assert_eq!(format_duration(Duration::from_secs(86400)), "1d");
+
+    let mut conf = PostgresConf::new();
+    conf.append_bytes("shared_buffers", 128 * 1024 * 1024);
+    conf.append_duration("wal_sender_timeout", Duration::from_secs(5));
+    conf.append_bool("fsync", false);
+    conf.append_bool("hot_standby", true);
+    conf.append_int("max_connections", 100);
+    conf.append_int("port", 55432);
+    assert_eq!(
+        conf.to_string(),
+        "shared_buffers=128MB\nwal_sender_timeout=5s\nfsync=off\nhot_standby=on\nmax_connections=100\nport=55432\n"
+    );
+    assert!(conf.validate_known_gucs()?.is_empty());
+
+    // typed setters replace existing lines too
+    conf.append_bool("fsync", true);
+    assert_eq!(conf.get("fsync"), Some("on"));
+    assert_eq!(conf.to_string().matches("fsync").count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_validate() -> Result<()> {
+    let conf = PostgresConf::parse(
+        "\
+shared_buffers = 1GB
+work_mem = '64 kB'
+statement_timeout = 0
+checkpoint_timeout = 5min
+fsync = false
+autovacuum = T
+max_connections = 0x10
+neon.my_extension_setting = 'whatever'
+Custom_Setting = 1
+",
+    )?;
+    assert_eq!(
+        conf.validate_known_gucs()?,
+        vec!["neon.my_extension_setting", "Custom_Setting"]
+    );
+
+    for bad in [
+        "shared_buffers = 15M",
+        "wal_sender_timeout = 5sec",
+        "fsync = maybe",
+        "hot_standby = o",
+        "max_connections = 'lots'",
+        "port = 5432kB",
+    ] {
+        let conf = PostgresConf::parse(bad)?;
+        assert!(conf.validate_known_gucs().is_err(), "{}", bad);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_quoting() -> Result<()> {
+    // single tokens are left unquoted for readability
+    for plain in [
+        "on",
+        "posix",
+        "remote_write",
+        "_private",
+        "10MB",
+        "-1",
+        "+5s",
+    ] {
+        assert!(!needs_quoting(plain), "{}", plain);
+    }
+    for tricky in [
+        "",
+        " ",
+        "two words",
+        "tab\there",
+        "new\nline",
+        "has#hash",
+        "it's",
+        "'quoted'",
+        "\"double\"",
+        "back\\slash",
+        "127.0.0.1",
+        "localhost:5454,localhost:5455",
+        "1.5GB",
+        "option=value",
+    ] {
+        assert!(needs_quoting(tricky), "{:?}", tricky);
+
+        let mut conf = PostgresConf::new();
+        conf.set("application_name", tricky);
+        let parsed = PostgresConf::parse(&conf.to_string())?;
+        assert_eq!(parsed.get("application_name"), Some(tricky), "{:?}", tricky);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_conninfo_round_trip() -> Result<()> {
+    // like primary_conninfo of a replica, with libpq quoting inside
+    let connstr = "host=localhost port=5454 options='-c timeline_id=de200bd42b49cc1814412c7e592dd6e9 tenant_id=9ef87a5bf0d92544f6fafeeb3239695c' application_name='o\\'brien''s replica' replication=true";
+
+    let mut conf = PostgresConf::new();
+    conf.set("primary_conninfo", connstr);
+    conf.set(
+        "primary_slot_name",
+        "repl_de200bd42b49cc1814412c7e592dd6e9_",
+    );
+    let text = conf.to_string();
+
+    let parsed = PostgresConf::parse(&text)?;
+    assert_eq!(parsed.get("primary_conninfo"), Some(connstr));
+    assert_eq!(parsed.to_string(), text);
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_includes() -> Result<()> {
+    let tmp = camino_tempfile::tempdir()?;
+    let dir = tmp.path().as_std_path();
+    std::fs::create_dir(dir.join("conf.d"))?;
+
+    let mut managed = PostgresConf::new();
+    managed.append_bytes("shared_buffers", 1024 * 1024);
+    managed.append_int("port", 55432);
+    managed.append_include_if_exists("conf.d/extra.conf");
+    std::fs::write(dir.join("conf.d/managed.conf"), managed.to_string())?;
+
+    let mut conf = PostgresConf::new();
+    conf.append_line("# managed settings");
+    conf.append_include("conf.d/managed.conf");
+    conf.append_include_if_exists("missing.conf");
+    conf.append_bytes("shared_buffers", 128 * 1024 * 1024);
+    let text = conf.to_string();
+    assert_eq!(
+        text,
+        "# managed settings\ninclude 'conf.d/managed.conf'\ninclude_if_exists 'missing.conf'\nshared_buffers=128MB\n"
+    );
+    std::fs::write(dir.join("postgresql.conf"), &text)?;
+
+    // includes are kept as is when parsing a single file
+    let parsed = PostgresConf::parse(&text)?;
+    assert_eq!(parsed.to_string(), text);
+    assert_eq!(parsed.get("port"), None);
+    assert_eq!(parsed.get("include"), None);
+
+    // extra.conf is relative to the file including it, and doesn't exist yet
+    let loaded = PostgresConf::load(&dir.join("postgresql.conf"))?;
+    assert_eq!(loaded.get("port"), Some("55432"));
+    assert_eq!(loaded.get("shared_buffers"), Some("128MB"));
+    assert_eq!(
+        loaded.to_string(),
+        "# managed settings\nshared_buffers=128MB\nport=55432\n"
+    );
+
+    std::fs::write(dir.join("conf.d/extra.conf"), "port = 6000\n")?;
+    let loaded = PostgresConf::load(&dir.join("postgresql.conf"))?;
+    assert_eq!(loaded.get("port"), Some("6000"));
+
+    // unlike include_if_exists, a missing include fails
+    std::fs::remove_file(dir.join("conf.d/managed.conf"))?;
+    assert!(PostgresConf::load(&dir.join("postgresql.conf")).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_include_cycle() -> Result<()> {
+    let tmp = camino_tempfile::tempdir()?;
+    let dir = tmp.path().as_std_path();
+    std::fs::write(dir.join("a.conf"), "port = 1\ninclude 'b.conf'\n")?;
+    std::fs::write(dir.join("b.conf"), "include_if_exists 'a.conf'\n")?;
+
+    let err = PostgresConf::load(&dir.join("a.conf")).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("maximum nesting depth exceeded"),
+        "{:#}",
+        err
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_postgresql_conf_diff() -> Result<()> {
+    let before = PostgresConf::parse(
+        "\
+# generated
+shared_buffers = 1MB
+port = 5432   # the port
+fsync = off
+search_path = 'public'
+",
+    )?;
+
+    // comments, formatting, quoting, order and case of names don't matter
+    let same = PostgresConf::parse(
+        "\
+FSYNC=off
+search_path=public
+
+port='5432'
+shared_buffers=1MB
+",
+    )?;
+    let diff = before.diff(&same);
+    assert!(diff.is_empty(), "{}", diff);
+    assert_eq!(diff.to_string(), "no changes");
+
+    let after = PostgresConf::parse(
+        "\
+shared_buffers = 128MB
+port = 5432
+search_path = 'public'
+work_mem = 64MB
+application_name = 'my app'
+",
+    )?;
+    let diff = before.diff(&after);
+    assert_eq!(
+        diff.added,
+        BTreeMap::from([
+            ("application_name".to_string(), "my app".to_string()),
+            ("work_mem".to_string(), "64MB".to_string()),
+        ])
+    );
+    assert_eq!(
+        diff.removed,
+        BTreeMap::from([("fsync".to_string(), "off".to_string())])
+    );
+    assert_eq!(
+        diff.changed,
+        BTreeMap::from([(
+            "shared_buffers".to_string(),
+            ChangedSetting {
+                old: "1MB".to_string(),
+                new: "128MB".to_string()
+            }
+        )])
+    );
+    assert_eq!(
+        diff.to_string(),
+        "+application_name='my app', +work_mem=64MB, -fsync, shared_buffers: 1MB -> 128MB"
+    );
+
+    // the reverse diff swaps added and removed
+    let reverse = after.diff(&before);
+    assert_eq!(reverse.added, diff.removed);
+    assert_eq!(reverse.removed, diff.added);
+
+    let json = serde_json::to_string(&diff)?;
+    assert_eq!(serde_json::from_str::<ConfDiff>(&json)?, diff);
+
+    Ok(())
+}
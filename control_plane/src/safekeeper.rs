@@ -15,12 +15,12 @@ use camino::Utf8PathBuf;
 use postgres_connection::PgConnectionConfig;
 use reqwest::{IntoUrl, Method};
 use thiserror::Error;
-use utils::auth::{Claims, Scope};
+use utils::auth::Scope;
 use utils::{http::error::HttpErrorBody, id::NodeId};
 
 use crate::{
     background_process,
-    local_env::{LocalEnv, SafekeeperConf},
+    local_env::{AuthComponent, LocalEnv, SafekeeperConf},
 };
 
 #[derive(Error, Debug)]
@@ -175,8 +175,9 @@ impl SafekeeperNode {
             args.extend(["--remote-storage".to_owned(), remote_storage.clone()]);
         }
 
-        let key_path = self.env.base_data_dir.join("auth_public_key.pem");
+        let key_path = self.env.public_key_path();
         if self.conf.auth_enabled {
+            self.env.jwt_auth_for(AuthComponent::Safekeeper)?;
             let key_path_string = key_path
                 .to_str()
                 .with_context(|| {
@@ -223,7 +224,7 @@ impl SafekeeperNode {
         if self.conf.auth_enabled {
             let token = self
                 .env
-                .generate_auth_token(&Claims::new(None, Scope::SafekeeperData))?;
+                .generate_scoped_token(Scope::SafekeeperData, None, None)?;
             Ok(vec![("SAFEKEEPER_AUTH_TOKEN".to_owned(), token)])
         } else {
             Ok(Vec::new())
@@ -1,6 +1,6 @@
 use crate::{
     background_process,
-    local_env::{LocalEnv, NeonStorageControllerConf},
+    local_env::{AuthComponent, LocalEnv, NeonStorageControllerConf},
 };
 use camino::{Utf8Path, Utf8PathBuf};
 use pageserver_api::{
@@ -100,28 +100,15 @@ impl StorageController {
 
                 // If pageserver auth is enabled, this implicitly enables auth for this service,
                 // using the same credentials.
-                let public_key_path =
-                    camino::Utf8PathBuf::try_from(env.base_data_dir.join("auth_public_key.pem"))
-                        .unwrap();
-
-                // This service takes keys as a string rather than as a path to a file/dir: read the key into memory.
-                let public_key = if std::fs::metadata(&public_key_path)
-                    .expect("Can't stat public key")
-                    .is_dir()
-                {
-                    // Our config may specify a directory: this is for the pageserver's ability to handle multiple
-                    // keys.  We only use one key at a time, so, arbitrarily load the first one in the directory.
-                    let mut dir =
-                        std::fs::read_dir(&public_key_path).expect("Can't readdir public key path");
-                    let dent = dir
-                        .next()
-                        .expect("Empty key dir")
-                        .expect("Error reading key dir");
-
-                    std::fs::read_to_string(dent.path()).expect("Can't read public key")
-                } else {
-                    std::fs::read_to_string(&public_key_path).expect("Can't read public key")
-                };
+                let public_key = env
+                    .storage_controller_public_key()
+                    .expect("Can't read public key");
+                let auth = env
+                    .jwt_auth_for(AuthComponent::StorageController)
+                    .expect("Can't load public key");
+                if let Err(e) = utils::auth::self_test(&private_key, &auth) {
+                    panic!("{e}");
+                }
                 (Some(private_key), Some(public_key))
             }
         };
@@ -310,10 +297,11 @@ impl StorageController {
         .into_iter()
         .map(|s| s.to_string())
         .collect::<Vec<_>>();
-        if let Some(private_key) = &self.private_key {
-            let claims = Claims::new(None, Scope::PageServerApi);
-            let jwt_token =
-                encode_from_key_file(&claims, private_key).expect("failed to generate jwt token");
+        if self.private_key.is_some() {
+            let jwt_token = self
+                .env
+                .generate_scoped_token(Scope::PageServerApi, None, None)
+                .expect("failed to generate jwt token");
             args.push(format!("--jwt-token={jwt_token}"));
         }
 
@@ -2,6 +2,7 @@
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize, Serializer};
+use utils::lsn::Lsn;
 
 use crate::spec::{ComputeSpec, Database, Role};
 
@@ -94,6 +95,10 @@ pub struct ComputeMetrics {
     /// Compressed size of basebackup received.
     pub basebackup_bytes: u64,
 
+    /// LSN the basebackup was requested at. None for replicas, which get the
+    /// basebackup at the latest LSN known to the pageserver.
+    pub basebackup_lsn: Option<Lsn>,
+
     /// Time spent starting potgres. This includes initialization of shared
     /// buffers, preloading extensions, and other pg operations.
     pub start_postgres_ms: u64,
@@ -12,6 +12,17 @@ use utils::lsn::Lsn;
 use regex::Regex;
 use remote_storage::RemotePath;
 
+/// The range of spec format versions a `compute_ctl` understands, as printed by
+/// `compute_ctl --supported-spec-versions`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SpecFormatVersions {
+    pub min: f32,
+    pub max: f32,
+}
+
+/// The spec format versions this version of the crate reads and writes.
+pub const SPEC_FORMAT_VERSIONS: SpecFormatVersions = SpecFormatVersions { min: 1.0, max: 1.0 };
+
 /// String type alias representing Postgres identifier and
 /// intended to be used for DB / role names.
 pub type PgIdent = String;
@@ -96,6 +107,15 @@ pub struct ComputeSpec {
     // Stripe size for pageserver sharding, in pages
     #[serde(default)]
     pub shard_stripe_size: Option<usize>,
+
+    /// How long the compute can stay idle before it is suspended, in seconds.
+    /// -1 means never.
+    #[serde(default = "default_suspend_timeout_seconds")]
+    pub suspend_timeout_seconds: i64,
+}
+
+fn default_suspend_timeout_seconds() -> i64 {
+    -1
 }
 
 /// Feature flag to signal `compute_ctl` to enable certain experimental functionality.
@@ -117,6 +137,13 @@ pub enum ComputeFeature {
     UnknownFeature,
 }
 
+/// The features this version of the crate knows, as printed by
+/// `compute_ctl --supported-features`.
+pub const KNOWN_COMPUTE_FEATURES: &[ComputeFeature] = &[
+    ComputeFeature::ActivityMonitorExperimental,
+    ComputeFeature::AnonExtension,
+];
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct RemoteExtSpec {
     pub public_extensions: Option<Vec<String>>,
@@ -280,6 +307,9 @@ mod tests {
 
         // Features list defaults to empty vector.
         assert!(spec.features.is_empty());
+
+        // Never suspend unless asked to.
+        assert_eq!(spec.suspend_timeout_seconds, -1);
     }
 
     #[test]
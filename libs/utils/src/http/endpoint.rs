@@ -443,12 +443,13 @@ pub fn auth_middleware<B: hyper::body::HttpBody + Send + Sync + 'static>(
                     })?;
                     let token = parse_token(header_value)?;
 
-                    let data = auth.decode(token).map_err(|err| {
+                    let validated = auth.decode_described(token).map_err(|err| {
                         warn!("Authentication error: {err}");
                         // Rely on From<AuthError> for ApiError impl
                         err
                     })?;
-                    req.set_context(data.claims);
+                    debug!("token validated by {}", validated.key);
+                    req.set_context(validated.data.claims);
                 }
                 None => {
                     return Err(ApiError::Unauthorized(
@@ -343,7 +343,8 @@ fn start_pageserver(
         let key_path = conf.auth_validation_public_key_path.as_ref().unwrap();
         info!("Loading public key(s) for verifying JWT tokens from {key_path:?}");
 
-        let jwt_auth = JwtAuth::from_key_path(key_path)?;
+        let jwt_auth = JwtAuth::from_key_path(key_path)?
+            .with_metrics_sink(Arc::new(pageserver::metrics::AuthValidationMetrics));
         let auth: Arc<SwappableJwtAuth> = Arc::new(SwappableJwtAuth::new(jwt_auth));
 
         http_auth = match &conf.http_auth_type {
@@ -1903,6 +1903,26 @@ pub(crate) static WAL_REDO_TIME: Lazy<Histogram> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+static AUTH_VALIDATION_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_auth_validation_total",
+        "Number of JWT validations, by outcome and fingerprint of the validating key",
+        &["outcome", "key_fingerprint"]
+    )
+    .expect("failed to define a metric")
+});
+
+/// Exports JWT validation outcomes of the pageserver's [`utils::auth::JwtAuth`] as prometheus metrics.
+pub struct AuthValidationMetrics;
+
+impl utils::auth::AuthMetricsSink for AuthValidationMetrics {
+    fn record(&self, outcome: utils::auth::ValidationOutcome, key_fingerprint: Option<&str>) {
+        AUTH_VALIDATION_TOTAL
+            .with_label_values(&[outcome.as_str(), key_fingerprint.unwrap_or("")])
+            .inc();
+    }
+}
+
 pub(crate) static WAL_REDO_RECORDS_HISTOGRAM: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "pageserver_wal_redo_records_histogram",
@@ -2967,11 +2987,14 @@ pub fn preinitialize_metrics() {
     }
 
     // countervecs
-    [&BACKGROUND_LOOP_PERIOD_OVERRUN_COUNT]
-        .into_iter()
-        .for_each(|c| {
-            Lazy::force(c);
-        });
+    [
+        &BACKGROUND_LOOP_PERIOD_OVERRUN_COUNT,
+        &AUTH_VALIDATION_TOTAL,
+    ]
+    .into_iter()
+    .for_each(|c| {
+        Lazy::force(c);
+    });
 
     // gauges
     WALRECEIVER_ACTIVE_MANAGERS.get();
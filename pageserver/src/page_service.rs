@@ -43,7 +43,7 @@ use tracing::*;
 use utils::id::ConnectionId;
 use utils::sync::gate::GateGuard;
 use utils::{
-    auth::{Claims, Scope, SwappableJwtAuth},
+    auth::{Claims, Scope, SwappableJwtAuth, ValidatedToken},
     id::{TenantId, TimelineId},
     lsn::Lsn,
     simple_rcu::RcuReadGuard,
@@ -1403,7 +1403,8 @@ impl PageServerHandler {
             .claims
             .as_ref()
             .expect("claims presence already checked");
-        check_permission(claims, tenant_id).map_err(|e| QueryError::Unauthorized(e.0))
+        check_permission(claims, tenant_id)
+            .map_err(|e| QueryError::Unauthorized(e.to_string().into()))
     }
 
     /// Shorthand for getting a reference to a Timeline of an Active tenant.
@@ -1490,21 +1491,21 @@ where
     ) -> Result<(), QueryError> {
         // this unwrap is never triggered, because check_auth_jwt only called when auth_type is NeonJWT
         // which requires auth to be present
-        let data = self
+        let ValidatedToken { data, key } = self
             .auth
             .as_ref()
             .unwrap()
-            .decode(str::from_utf8(jwt_response).context("jwt response is not UTF-8")?)
-            .map_err(|e| QueryError::Unauthorized(e.0))?;
+            .decode_described(str::from_utf8(jwt_response).context("jwt response is not UTF-8")?)
+            .map_err(|e| QueryError::Unauthorized(e.to_string().into()))?;
 
-        if matches!(data.claims.scope, Scope::Tenant) && data.claims.tenant_id.is_none() {
+        if matches!(data.claims.scope, Scope::Tenant) && data.claims.tenants().next().is_none() {
             return Err(QueryError::Unauthorized(
                 "jwt token scope is Tenant, but tenant id is missing".into(),
             ));
         }
 
         debug!(
-            "jwt scope check succeeded for scope: {:#?} by tenant id: {:?}",
+            "jwt scope check succeeded for scope: {:#?} by tenant id: {:?}, validated by {key}",
             data.claims.scope, data.claims.tenant_id,
         );
 
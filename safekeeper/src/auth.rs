@@ -1,18 +1,19 @@
-use utils::auth::{AuthError, Claims, Scope};
+use utils::auth::{check_tenant_permission, AuthError, Claims, Scope};
 use utils::id::TenantId;
 
 pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<(), AuthError> {
     match (&claims.scope, tenant_id) {
-        (Scope::Tenant, None) => Err(AuthError(
+        (Scope::Tenant, None) => Err(AuthError::Denied(
             "Attempt to access management api with tenant scope. Permission denied".into(),
         )),
-        (Scope::Tenant, Some(tenant_id)) => {
-            if claims.tenant_id.unwrap() != tenant_id {
-                return Err(AuthError("Tenant id mismatch. Permission denied".into()));
-            }
-            Ok(())
-        }
-        (Scope::Admin | Scope::PageServerApi | Scope::GenerationsApi, _) => Err(AuthError(
+        (Scope::Tenant, Some(tenant_id)) => check_tenant_permission(claims, tenant_id),
+        (Scope::Unknown(scope), _) => Err(AuthError::Denied(
+            format!("Unknown JWT scope '{scope}'. Permission denied").into(),
+        )),
+        (
+            Scope::Admin | Scope::PageServerApi | Scope::GenerationsApi | Scope::TenantEndpoint,
+            _,
+        ) => Err(AuthError::Denied(
             format!(
                 "JWT scope '{:?}' is ineligible for Safekeeper auth",
                 claims.scope
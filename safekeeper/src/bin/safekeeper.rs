@@ -28,8 +28,9 @@ use utils::pid_file;
 
 use metrics::set_build_info_metric;
 use safekeeper::defaults::{
-    DEFAULT_CONTROL_FILE_SAVE_INTERVAL, DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_HTTP_LISTEN_ADDR,
-    DEFAULT_MAX_OFFLOADER_LAG_BYTES, DEFAULT_PARTIAL_BACKUP_CONCURRENCY,
+    DEFAULT_CONTROL_FILE_SAVE_INTERVAL, DEFAULT_GUARD_HOLD_WARN_THRESHOLD,
+    DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_HTTP_LISTEN_ADDR, DEFAULT_MAX_OFFLOADER_LAG_BYTES,
+    DEFAULT_MAX_RESIDENCE_GUARDS, DEFAULT_PARTIAL_BACKUP_CONCURRENCY,
     DEFAULT_PARTIAL_BACKUP_TIMEOUT, DEFAULT_PG_LISTEN_ADDR,
 };
 use safekeeper::http;
@@ -195,6 +196,14 @@ struct Args {
     /// Number of allowed concurrent uploads of partial segments to remote storage.
     #[arg(long, default_value = DEFAULT_PARTIAL_BACKUP_CONCURRENCY)]
     partial_backup_concurrency: usize,
+    /// Log a warning about residence guards held for longer than this, as they keep
+    /// the timeline's WAL on disk.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = DEFAULT_GUARD_HOLD_WARN_THRESHOLD)]
+    guard_hold_warn_threshold: Duration,
+    /// Maximum number of residence guards on a single timeline. Requests for
+    /// more guards fail until some are dropped.
+    #[arg(long, default_value = DEFAULT_MAX_RESIDENCE_GUARDS)]
+    max_residence_guards: usize,
 }
 
 // Like PathBufValueParser, but allows empty string.
@@ -349,6 +358,8 @@ async fn main() -> anyhow::Result<()> {
         delete_offloaded_wal: args.delete_offloaded_wal,
         control_file_save_interval: args.control_file_save_interval,
         partial_backup_concurrency: args.partial_backup_concurrency,
+        guard_hold_warn_threshold: args.guard_hold_warn_threshold,
+        max_residence_guards: args.max_residence_guards,
     };
 
     // initialize sentry if SENTRY_DSN is provided
@@ -46,7 +46,7 @@ pub async fn handle_request(request: Request) -> Result<()> {
         }
     }
 
-    let source_tli = request.source.wal_residence_guard().await?;
+    let source_tli = request.source.wal_residence_guard("copy_timeline").await?;
 
     let conf = &GlobalTimelines::get_global_config();
     let ttid = request.destination_ttid;
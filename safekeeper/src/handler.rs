@@ -166,7 +166,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin + Send> postgres_backend::Handler<IO>
             .expect("auth_type is configured but .auth of handler is missing");
         let data = auth
             .decode(str::from_utf8(jwt_response).context("jwt response is not UTF-8")?)
-            .map_err(|e| QueryError::Unauthorized(e.0))?;
+            .map_err(|e| QueryError::Unauthorized(e.to_string().into()))?;
 
         // The handler might be configured to allow only tenant scope tokens.
         if matches!(allowed_auth_scope, Scope::Tenant)
@@ -177,7 +177,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin + Send> postgres_backend::Handler<IO>
             ));
         }
 
-        if matches!(data.claims.scope, Scope::Tenant) && data.claims.tenant_id.is_none() {
+        if matches!(data.claims.scope, Scope::Tenant) && data.claims.tenants().next().is_none() {
             return Err(QueryError::Unauthorized(
                 "jwt token scope is Tenant, but tenant id is missing".into(),
             ));
@@ -272,7 +272,8 @@ impl SafekeeperPostgresHandler {
             .claims
             .as_ref()
             .expect("claims presence already checked");
-        check_permission(claims, tenant_id).map_err(|e| QueryError::Unauthorized(e.0))
+        check_permission(claims, tenant_id)
+            .map_err(|e| QueryError::Unauthorized(e.to_string().into()))
     }
 
     async fn handle_timeline_status<IO: AsyncRead + AsyncWrite + Unpin>(
@@ -6,6 +6,7 @@ use std::fmt;
 use std::io::Write as _;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use storage_broker::proto::SafekeeperTimelineInfo;
 use storage_broker::proto::TenantTimelineId as ProtoTenantTimelineId;
 use tokio::sync::mpsc;
@@ -39,6 +40,7 @@ use crate::safekeeper::Term;
 use crate::safekeeper::{ServerInfo, TermLsn};
 use crate::send_wal::WalSenderState;
 use crate::timeline::PeerInfo;
+use crate::timeline_guard::{GuardError, GuardInfo, GuardsSummary, TimelineDeleted};
 use crate::timelines_global_map::TimelineDeleteForceResult;
 use crate::GlobalTimelines;
 use crate::SafeKeeperConf;
@@ -106,6 +108,12 @@ pub struct TimelineStatus {
     pub peers: Vec<PeerInfo>,
     pub walsenders: Vec<WalSenderState>,
     pub walreceivers: Vec<WalReceiverState>,
+    /// Guards keeping WAL of the timeline on disk. None if the timeline manager
+    /// didn't reply in time.
+    pub residence_guards: Option<Vec<GuardInfo>>,
+    /// Guards preventing eviction of the timeline. None if the timeline manager
+    /// didn't reply in time.
+    pub eviction_blocked_by: Option<GuardsSummary>,
 }
 
 fn check_permission(request: &Request<Body>, tenant_id: Option<TenantId>) -> Result<(), ApiError> {
@@ -160,6 +168,8 @@ async fn timeline_status_handler(request: Request<Body>) -> Result<Response<Body
         peers: tli.get_peers(conf).await,
         walsenders: tli.get_walsenders().get_all(),
         walreceivers: tli.get_walreceivers().get_all(),
+        residence_guards: tli.residence_guards(Duration::from_secs(1)).await,
+        eviction_blocked_by: tli.guards_summary(Duration::from_secs(1)).await,
     };
     json_response(StatusCode::OK, status)
 }
@@ -217,7 +227,7 @@ async fn timeline_snapshot_handler(request: Request<Body>) -> Result<Response<Bo
     // and stream control file, or return WalResidentTimeline if timeline is not
     // evicted.
     let tli = tli
-        .wal_residence_guard()
+        .wal_residence_guard("snapshot")
         .await
         .map_err(ApiError::InternalServerError)?;
 
@@ -262,6 +272,22 @@ async fn timeline_copy_handler(mut request: Request<Body>) -> Result<Response<Bo
     json_response(StatusCode::OK, ())
 }
 
+/// Convert an error from acquiring a residence guard, so that hitting the guard
+/// limit is reported as retryable and a timeline being deleted as not found.
+fn guard_error_to_api(e: anyhow::Error) -> ApiError {
+    match e.downcast_ref::<GuardError>() {
+        Some(GuardError::LimitExceeded(limit)) => {
+            ApiError::ResourceUnavailable(limit.to_string().into())
+        }
+        Some(GuardError::TimelineDeleted(_)) => ApiError::NotFound(e.into()),
+        None if e.is::<TimelineDeleted>() => ApiError::NotFound(e.into()),
+        None => ApiError::InternalServerError(e),
+    }
+}
+
+/// How long the digest request waits for an offloaded timeline to be unevicted.
+const DIGEST_GUARD_TIMEOUT: Duration = Duration::from_secs(30);
+
 async fn timeline_digest_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
     let ttid = TenantTimelineId::new(
         parse_request_param(&request, "tenant_id")?,
@@ -283,9 +309,9 @@ async fn timeline_digest_handler(request: Request<Body>) -> Result<Response<Body
 
     let tli = GlobalTimelines::get(ttid).map_err(ApiError::from)?;
     let tli = tli
-        .wal_residence_guard()
+        .acquire_residence_guard("digest", DIGEST_GUARD_TIMEOUT)
         .await
-        .map_err(ApiError::InternalServerError)?;
+        .map_err(guard_error_to_api)?;
 
     let response = debug_dump::calculate_digest(&tli, request)
         .await
@@ -115,7 +115,7 @@ async fn prepare_safekeeper(
     )
     .await?;
 
-    tli.wal_residence_guard().await
+    tli.wal_residence_guard("json_ctrl").await
 }
 
 async fn send_proposer_elected(
@@ -53,6 +53,8 @@ pub mod defaults {
     pub const DEFAULT_PARTIAL_BACKUP_TIMEOUT: &str = "15m";
     pub const DEFAULT_CONTROL_FILE_SAVE_INTERVAL: &str = "300s";
     pub const DEFAULT_PARTIAL_BACKUP_CONCURRENCY: &str = "5";
+    pub const DEFAULT_GUARD_HOLD_WARN_THRESHOLD: &str = "10m";
+    pub const DEFAULT_MAX_RESIDENCE_GUARDS: &str = "1024";
 }
 
 #[derive(Debug, Clone)]
@@ -93,6 +95,10 @@ pub struct SafeKeeperConf {
     pub delete_offloaded_wal: bool,
     pub control_file_save_interval: Duration,
     pub partial_backup_concurrency: usize,
+    /// Residence guards held for longer than this are logged with a warning.
+    pub guard_hold_warn_threshold: Duration,
+    /// Maximum number of residence guards issued on a single timeline.
+    pub max_residence_guards: usize,
 }
 
 impl SafeKeeperConf {
@@ -136,6 +142,8 @@ impl SafeKeeperConf {
             delete_offloaded_wal: false,
             control_file_save_interval: Duration::from_secs(1),
             partial_backup_concurrency: 1,
+            guard_hold_warn_threshold: Duration::from_secs(600),
+            max_residence_guards: 1024,
         }
     }
 }
@@ -12,8 +12,8 @@ use metrics::{
     core::{AtomicU64, Collector, Desc, GenericCounter, GenericGaugeVec, Opts},
     proto::MetricFamily,
     register_histogram_vec, register_int_counter, register_int_counter_pair,
-    register_int_counter_pair_vec, register_int_counter_vec, Gauge, HistogramVec, IntCounter,
-    IntCounterPair, IntCounterPairVec, IntCounterVec, IntGaugeVec,
+    register_int_counter_pair_vec, register_int_counter_vec, register_int_gauge_vec, Gauge,
+    HistogramVec, IntCounter, IntCounterPair, IntCounterPairVec, IntCounterVec, IntGaugeVec,
 };
 use once_cell::sync::Lazy;
 
@@ -195,6 +195,64 @@ pub static MANAGER_ACTIVE_CHANGES: Lazy<IntCounter> = Lazy::new(|| {
     )
     .expect("Failed to register safekeeper_manager_active_changes_total counter")
 });
+pub static MANAGER_UNEXPECTED_GUARD_DROPS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_manager_unexpected_guard_drops_total",
+        "Number of dropped residence guards the timeline manager didn't know about"
+    )
+    .expect("Failed to register safekeeper_manager_unexpected_guard_drops_total counter")
+});
+pub static RESIDENCE_GUARDS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "safekeeper_residence_guards",
+        "Number of currently held residence guards",
+        &["purpose"]
+    )
+    .expect("Failed to register safekeeper_residence_guards gauge")
+});
+pub static RESIDENCE_GUARDS_CREATED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "safekeeper_residence_guard_created_total",
+        "Number of residence guards issued by timeline managers",
+        &["purpose"]
+    )
+    .expect("Failed to register safekeeper_residence_guard_created_total counter")
+});
+pub static RESIDENCE_GUARDS_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "safekeeper_residence_guard_dropped_total",
+        "Number of residence guards dropped",
+        &["purpose"]
+    )
+    .expect("Failed to register safekeeper_residence_guard_dropped_total counter")
+});
+pub static RESIDENCE_GUARDS_LEAKED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_residence_guards_leaked_total",
+        "Number of residence guards still held when the timeline manager finished"
+    )
+    .expect("Failed to register safekeeper_residence_guards_leaked_total counter")
+});
+pub const GUARD_HOLD_SECONDS_BUCKETS: &[f64] = &[
+    0.01,
+    0.1,
+    1.0,
+    10.0,
+    60.0,
+    600.0,
+    3600.0,
+    6.0 * 3600.0,
+    24.0 * 3600.0,
+];
+pub static RESIDENCE_GUARD_HOLD_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "safekeeper_residence_guard_hold_seconds",
+        "Seconds a residence guard was held for, observed when it is dropped",
+        &["purpose"],
+        GUARD_HOLD_SECONDS_BUCKETS.to_vec()
+    )
+    .expect("Failed to register safekeeper_residence_guard_hold_seconds histogram vec")
+});
 pub static WAL_BACKUP_TASKS: Lazy<IntCounterPair> = Lazy::new(|| {
     register_int_counter_pair!(
         "safekeeper_wal_backup_tasks_started_total",
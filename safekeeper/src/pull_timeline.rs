@@ -210,7 +210,7 @@ impl WalResidentTimeline {
         // Drop shared_state to release the lock, before calling wal_residence_guard().
         drop(shared_state);
 
-        let tli_copy = self.wal_residence_guard().await?;
+        let tli_copy = self.wal_residence_guard("snapshot").await?;
         let bctx = SnapshotContext {
             from_segno,
             upto_segno,
@@ -269,7 +269,7 @@ impl SafekeeperPostgresHandler {
                     .get_walreceivers()
                     .pageserver_feedback_tx
                     .subscribe();
-            *tli = Some(timeline.wal_residence_guard().await?);
+            *tli = Some(timeline.wal_residence_guard("walreceiver").await?);
 
             tokio::select! {
                 // todo: add read|write .context to these errors
@@ -340,7 +340,7 @@ impl<'a, IO: AsyncRead + AsyncWrite + Unpin> NetworkReader<'a, IO> {
                 let tli =
                     GlobalTimelines::create(self.ttid, server_info, Lsn::INVALID, Lsn::INVALID)
                         .await?;
-                tli.wal_residence_guard().await?
+                tli.wal_residence_guard("walreceiver").await?
             }
             _ => {
                 return Err(CopyStreamHandlerEnd::Other(anyhow::anyhow!(
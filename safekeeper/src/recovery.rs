@@ -205,7 +205,7 @@ async fn recovery_main_loop(tli: WalResidentTimeline, conf: SafeKeeperConf) {
                     "starting recovery from donor {}: {}",
                     donor.sk_id, recovery_needed_info
                 );
-                let res = tli.wal_residence_guard().await;
+                let res = tli.wal_residence_guard("recovery").await;
                 if let Err(e) = res {
                     warn!("failed to obtain guard: {}", e);
                     continue;
@@ -369,7 +369,12 @@ async fn recovery_stream(
     // As in normal walreceiver, do networking and writing to disk in parallel.
     let (msg_tx, msg_rx) = channel(MSG_QUEUE_SIZE);
     let (reply_tx, reply_rx) = channel(REPLY_QUEUE_SIZE);
-    let wa = WalAcceptor::spawn(tli.wal_residence_guard().await?, msg_rx, reply_tx, None);
+    let wa = WalAcceptor::spawn(
+        tli.wal_residence_guard("recovery").await?,
+        msg_rx,
+        reply_tx,
+        None,
+    );
 
     let res = tokio::select! {
         r = network_io(physical_stream, msg_tx, donor.clone(), tli, conf.clone()) => r,
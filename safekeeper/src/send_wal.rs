@@ -387,7 +387,7 @@ impl SafekeeperPostgresHandler {
         term: Option<Term>,
     ) -> Result<(), QueryError> {
         let tli = GlobalTimelines::get(self.ttid).map_err(|e| QueryError::Other(e.into()))?;
-        let residence_guard = tli.wal_residence_guard().await?;
+        let residence_guard = tli.wal_residence_guard("walsender").await?;
 
         if let Err(end) = self
             .handle_start_replication_guts(pgb, start_pos, term, residence_guard)
@@ -459,7 +459,7 @@ impl SafekeeperPostgresHandler {
         let mut sender = WalSender {
             pgb,
             // should succeed since we're already holding another guard
-            tli: tli.wal_residence_guard().await?,
+            tli: tli.wal_residence_guard("walsender").await?,
             appname,
             start_pos,
             end_pos,
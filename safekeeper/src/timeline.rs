@@ -32,7 +32,9 @@ use crate::safekeeper::{
 };
 use crate::send_wal::WalSenders;
 use crate::state::{EvictionState, TimelineMemState, TimelinePersistentState, TimelineState};
-use crate::timeline_guard::ResidenceGuard;
+use crate::timeline_guard::{
+    GuardInfo, GuardIssuer, GuardsSummary, ResidenceGuard, WeakResidenceGuard,
+};
 use crate::timeline_manager::{AtomicStatus, ManagerCtl};
 use crate::timelines_set::TimelinesSet;
 use crate::wal_backup::{self};
@@ -44,6 +46,11 @@ use crate::wal_storage::{Storage as wal_storage_iface, WalReader};
 use crate::{debug_dump, timeline_manager, wal_storage};
 use crate::{GlobalTimelines, SafeKeeperConf};
 
+/// How long deletion waits for the manager to drain residence guards. The manager
+/// gives up on guards itself after a shorter deadline, this only covers a stuck
+/// manager.
+const DELETE_GUARDS_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Things safekeeper should know about timeline state on peers.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerInfo {
@@ -531,7 +538,7 @@ impl Timeline {
             walreceivers,
             cancel: CancellationToken::default(),
             timeline_dir: get_timeline_dir(conf, &ttid),
-            manager_ctl: ManagerCtl::new(),
+            manager_ctl: ManagerCtl::new(&ttid),
             broker_active: AtomicBool::new(false),
             wal_backup_active: AtomicBool::new(false),
             last_removed_segno: AtomicU64::new(0),
@@ -569,7 +576,7 @@ impl Timeline {
             walreceivers,
             cancel: CancellationToken::default(),
             timeline_dir: get_timeline_dir(conf, &ttid),
-            manager_ctl: ManagerCtl::new(),
+            manager_ctl: ManagerCtl::new(&ttid),
             broker_active: AtomicBool::new(false),
             wal_backup_active: AtomicBool::new(false),
             last_removed_segno: AtomicU64::new(0),
@@ -629,7 +636,7 @@ impl Timeline {
         broker_active_set: Arc<TimelinesSet>,
         partial_backup_rate_limiter: RateLimiter,
     ) {
-        let (tx, rx) = self.manager_ctl.bootstrap_manager();
+        let (guard_issuer, rx) = self.manager_ctl.bootstrap_manager();
 
         // Start manager task which will monitor timeline state and update
         // background tasks.
@@ -637,7 +644,7 @@ impl Timeline {
             ManagerTimeline { tli: self.clone() },
             conf.clone(),
             broker_active_set,
-            tx,
+            guard_issuer,
             rx,
             partial_backup_rate_limiter,
         ));
@@ -648,12 +655,32 @@ impl Timeline {
     ///
     /// Also deletes WAL in s3. Might fail if e.g. s3 is unavailable, but
     /// deletion API endpoint is retriable.
-    pub async fn delete(
-        &self,
-        shared_state: &mut WriteGuardSharedState<'_>,
-        only_local: bool,
-    ) -> Result<bool> {
-        self.cancel(shared_state);
+    pub async fn delete(self: &Arc<Self>, only_local: bool) -> Result<bool> {
+        self.cancel(&mut self.write_shared_state().await);
+
+        // Background tasks drop out after cancellation. Wait for them to release
+        // their residence guards, so that WAL isn't removed under an active reader.
+        // The lock is not held meanwhile, guard holders may need it to finish.
+        let res =
+            tokio::time::timeout(DELETE_GUARDS_TIMEOUT, self.manager_ctl.block_new_guards()).await;
+        match res {
+            Ok(Ok(report)) if !report.leaked.is_empty() => warn!(
+                "deleting timeline {} with {} residence guards still held: {:?}",
+                self.ttid,
+                report.leaked.len(),
+                report.leaked
+            ),
+            Ok(Ok(_)) => {}
+            // the manager drains guards itself before exiting
+            Ok(Err(e)) => debug!("manager exited before blocking new guards: {e}"),
+            Err(_) => warn!(
+                "timeout while waiting for residence guards, manager status {:?}",
+                self.mgr_status.get()
+            ),
+        }
+
+        // Take a lock and finish the deletion holding this mutex.
+        let _shared_state = self.write_shared_state().await;
 
         // TODO: It's better to wait for s3 offloader termination before
         // removing data from s3. Though since s3 doesn't have transactions it
@@ -846,6 +873,72 @@ impl Timeline {
         Ok(res)
     }
 
+    /// Get a weak guard, which allows reading WAL files if they are on disk but
+    /// doesn't prevent eviction. The holder must stop reading once
+    /// [`WeakResidenceGuard::is_evicting`] is set. Fails if the timeline is
+    /// offloaded.
+    ///
+    /// NB: don't use this function from timeline_manager, it will deadlock.
+    pub async fn weak_residence_guard(
+        &self,
+        purpose: &'static str,
+        timeout: Duration,
+    ) -> Result<WeakResidenceGuard> {
+        match tokio::time::timeout(timeout, self.manager_ctl.weak_residence_guard(purpose)).await {
+            Ok(res) => res,
+            Err(_) => bail!("timeout while acquiring weak residence guard"),
+        }
+    }
+
+    /// Describe the guards preventing eviction of this timeline, or None if the
+    /// manager didn't reply within `timeout`.
+    ///
+    /// NB: don't use this function from timeline_manager, it will deadlock.
+    pub async fn guards_summary(&self, timeout: Duration) -> Option<GuardsSummary> {
+        match tokio::time::timeout(timeout, self.manager_ctl.blocking_summary()).await {
+            Ok(Ok(summary)) => Some(summary),
+            Ok(Err(e)) => {
+                warn!("failed to get residence guards summary: {:?}", e);
+                None
+            }
+            Err(_) => {
+                warn!(
+                    "timeout while getting residence guards summary, manager status {:?}",
+                    self.mgr_status.get()
+                );
+                None
+            }
+        }
+    }
+
+    /// Handle to take residence guards from synchronous code. The guards are only
+    /// issued while WAL is on disk, the timeline is never unevicted for them.
+    pub fn guard_issuer(&self) -> GuardIssuer {
+        self.manager_ctl.guard_issuer()
+    }
+
+    /// List the residence guards currently issued for this timeline. Returns None
+    /// if the manager didn't reply within `timeout`, so that status requests don't
+    /// hang on a stuck manager.
+    ///
+    /// NB: don't use this function from timeline_manager, it will deadlock.
+    pub async fn residence_guards(&self, timeout: Duration) -> Option<Vec<GuardInfo>> {
+        match tokio::time::timeout(timeout, self.manager_ctl.list_guards()).await {
+            Ok(Ok(guards)) => Some(guards),
+            Ok(Err(e)) => {
+                warn!("failed to list residence guards: {:?}", e);
+                None
+            }
+            Err(_) => {
+                warn!(
+                    "timeout while listing residence guards, manager status {:?}",
+                    self.mgr_status.get()
+                );
+                None
+            }
+        }
+    }
+
     /// Get the timeline guard for reading/writing WAL files.
     /// If WAL files are not present on disk (evicted), they will be automatically
     /// downloaded from remote storage. This is done in the manager task, which is
@@ -853,12 +946,16 @@ impl Timeline {
     ///
     /// NB: don't use this function from timeline_manager, it will deadlock.
     /// NB: don't use this function while holding shared_state lock.
-    pub async fn wal_residence_guard(self: &Arc<Self>) -> Result<WalResidentTimeline> {
+    /// `purpose` names the caller in logs and guard listings, e.g. "walsender".
+    pub async fn wal_residence_guard(
+        self: &Arc<Self>,
+        purpose: &'static str,
+    ) -> Result<WalResidentTimeline> {
         if self.is_cancelled() {
             bail!(TimelineError::Cancelled(self.ttid));
         }
 
-        debug!("requesting WalResidentTimeline guard");
+        debug!("requesting WalResidentTimeline guard for {purpose}");
         let started_at = Instant::now();
         let status_before = self.mgr_status.get();
 
@@ -867,7 +964,7 @@ impl Timeline {
         // is stuck.
         let res = tokio::time::timeout_at(
             started_at + Duration::from_secs(30),
-            self.manager_ctl.wal_residence_guard(),
+            self.manager_ctl.wal_residence_guard(purpose),
         )
         .await;
 
@@ -901,6 +998,49 @@ impl Timeline {
 
         Ok(WalResidentTimeline::new(self.clone(), guard))
     }
+
+    /// Get the timeline guard for reading/writing WAL files, waiting up to
+    /// `timeout` for the timeline to be unevicted if WAL is offloaded.
+    ///
+    /// Unlike [`Self::wal_residence_guard`], uneviction doesn't block the manager
+    /// and concurrent callers share a single uneviction.
+    ///
+    /// NB: don't use this function from timeline_manager, it will deadlock.
+    /// NB: don't use this function while holding shared_state lock.
+    pub async fn acquire_residence_guard(
+        self: &Arc<Self>,
+        purpose: &'static str,
+        timeout: Duration,
+    ) -> Result<WalResidentTimeline> {
+        if self.is_cancelled() {
+            bail!(TimelineError::Cancelled(self.ttid));
+        }
+
+        debug!("acquiring WalResidentTimeline guard for {purpose}");
+        let started_at = Instant::now();
+        let res = tokio::time::timeout_at(
+            started_at + timeout,
+            self.manager_ctl.acquire_residence_guard(purpose),
+        )
+        .await;
+
+        match res {
+            Ok(Ok(guard)) => {
+                MISC_OPERATION_SECONDS
+                    .with_label_values(&["acquire_residence_guard"])
+                    .observe(started_at.elapsed().as_secs_f64());
+                Ok(WalResidentTimeline::new(self.clone(), guard))
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                warn!(
+                    "timeout while acquiring WalResidentTimeline guard for {purpose}, manager status {:?}",
+                    self.mgr_status.get()
+                );
+                bail!("timeout while acquiring WalResidentTimeline guard after {timeout:?}");
+            }
+        }
+    }
 }
 
 /// This is a guard that allows to read/write disk timeline state.
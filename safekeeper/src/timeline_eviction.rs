@@ -62,10 +62,21 @@ impl Manager {
             }
         };
 
-        info!("starting eviction, using {:?}", partial_backup_uploaded);
+        if !self.access_service.begin_eviction() {
+            debug!("guards were issued meanwhile, skipping eviction");
+            return;
+        }
+
+        info!(
+            "starting eviction, using {:?}, {} weak guards outstanding",
+            partial_backup_uploaded,
+            self.access_service.num_weak_guards()
+        );
+        self.access_service.set_evicting(true);
 
         if let Err(e) = do_eviction(self, &partial_backup_uploaded).await {
             warn!("failed to evict timeline: {:?}", e);
+            self.access_service.eviction_failed(self.is_offloaded);
             return;
         }
 
@@ -90,6 +101,7 @@ impl Manager {
             warn!("failed to unevict timeline: {:?}", e);
             return;
         }
+        self.access_service.unevicted();
 
         info!("successfully restored evicted timeline");
     }
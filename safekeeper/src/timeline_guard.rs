@@ -1,71 +1,1458 @@
 //! Timeline residence guard is needed to ensure that WAL segments are present on disk,
 //! as long as the code is holding the guard. This file implements guard logic, to issue
 //! and drop guards, and to notify the manager when the guard is dropped.
+//!
+//! Dropped guards are collected in a queue shared with [`AccessService`], and the
+//! manager is woken up only when the queue becomes non-empty, so that churn of
+//! short-lived guards doesn't wake it up on every drop.
+//!
+//! Synchronous code can take guards through [`GuardIssuer`], which doesn't wait for
+//! the manager and fails if the timeline is not resident.
+//!
+//! Weak guards are for observers which want to read WAL only if it happens to be on
+//! disk. They don't prevent eviction, but get notified when it is about to happen.
 
-use std::collections::HashSet;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use tracing::{debug, warn};
+use crossbeam_deque::{Injector, Steal};
 
-use crate::timeline_manager::ManagerCtlMessage;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use tracing::debug;
+use utils::id::TenantTimelineId;
 
-#[derive(Debug, Clone, Copy)]
-pub struct GuardId(u64);
+use crate::{
+    metrics::{
+        RESIDENCE_GUARDS, RESIDENCE_GUARDS_CREATED, RESIDENCE_GUARDS_DROPPED,
+        RESIDENCE_GUARD_HOLD_SECONDS,
+    },
+    timeline_manager::ManagerCtlMessage,
+};
 
+/// Id of a residence guard, unique within the process. Formatted as
+/// `<timeline>-<epoch>-<seq>`, where `timeline` is a short hash of the timeline
+/// id, so that guards of different timelines can be told apart in logs, and
+/// `epoch` distinguishes [`AccessService`]s of the same timeline, e.g. after the
+/// timeline is deleted and recreated.
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    serde_with::SerializeDisplay,
+    serde_with::DeserializeFromStr,
+)]
+pub struct GuardId {
+    timeline: u32,
+    epoch: u32,
+    seq: u64,
+}
+
+/// Epoch of the next [`AccessService`] created in this process.
+static NEXT_EPOCH: AtomicU32 = AtomicU32::new(0);
+
+impl GuardId {
+    /// Short hash of the timeline id used in guard ids.
+    fn timeline_hash(ttid: &TenantTimelineId) -> u32 {
+        let hash = crc32c::crc32c(&ttid.tenant_id.as_arr());
+        crc32c::crc32c_append(hash, &ttid.timeline_id.as_arr())
+    }
+}
+
+impl fmt::Display for GuardId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}-{}-{}", self.timeline, self.epoch, self.seq)
+    }
+}
+
+impl fmt::Debug for GuardId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GuardId({self})")
+    }
+}
+
+impl FromStr for GuardId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('-');
+        let (Some(timeline), Some(epoch), Some(seq), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("guard id must be <timeline>-<epoch>-<seq>, got {s:?}");
+        };
+        Ok(GuardId {
+            timeline: u32::from_str_radix(timeline, 16)?,
+            epoch: epoch.parse()?,
+            seq: seq.parse()?,
+        })
+    }
+}
+
+/// State shared between [`AccessService`], [`GuardIssuer`]s and issued guards.
+struct SharedGuardState {
+    timeline_hash: u32,
+    epoch: u32,
+    next_seq: AtomicU64,
+    /// Set by the manager while WAL is on disk, see [`AccessService::begin_eviction`].
+    resident: AtomicBool,
+    /// Guards issued by [`GuardIssuer`] not yet registered by the manager.
+    pending_registrations: AtomicUsize,
+    /// Set when the timeline is being deleted, no new guards are issued.
+    blocked: AtomicBool,
+    /// Ids of dropped guards not yet processed by the manager.
+    dropped: Injector<GuardId>,
+    /// Set when [`ManagerCtlMessage::GuardDropBatch`] is sent, reset by the manager
+    /// before it drains the queue.
+    notified: AtomicBool,
+    /// Set when the manager doesn't process messages anymore, so that guards
+    /// dropped later don't try to notify it.
+    manager_gone: AtomicBool,
+}
+
+impl SharedGuardState {
+    fn new(ttid: &TenantTimelineId) -> Self {
+        SharedGuardState {
+            timeline_hash: GuardId::timeline_hash(ttid),
+            epoch: NEXT_EPOCH.fetch_add(1, Ordering::Relaxed),
+            next_seq: AtomicU64::new(0),
+            resident: AtomicBool::new(false),
+            pending_registrations: AtomicUsize::new(0),
+            blocked: AtomicBool::new(false),
+            dropped: Injector::new(),
+            notified: AtomicBool::new(false),
+            manager_gone: AtomicBool::new(false),
+        }
+    }
+
+    fn next_id(&self) -> GuardId {
+        GuardId {
+            timeline: self.timeline_hash,
+            epoch: self.epoch,
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+    /// Send a message to the manager, unless it is gone. Guards outliving the
+    /// manager are expected on shutdown, so this is logged only once.
+    fn notify_manager(
+        &self,
+        manager_tx: &tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+        msg: ManagerCtlMessage,
+    ) {
+        if self.manager_gone.load(Ordering::Acquire) {
+            return;
+        }
+        if let Err(e) = manager_tx.send(msg) {
+            if !self.manager_gone.swap(true, Ordering::AcqRel) {
+                debug!("manager is gone, failed to send {:?}", e.0);
+            }
+        }
+    }
+}
+
+/// Keeps WAL of the timeline on disk while held.
+///
+/// The guard is `Send`, so work holding it can be handed to another task by
+/// moving the guard there, see [`ResidenceGuard::transfer`]. Dropping the guard
+/// and taking a new one in the other task instead leaves a window in which the
+/// timeline can be evicted. If both tasks need WAL, use
+/// [`ResidenceGuard::clone_handle`].
 pub struct ResidenceGuard {
     manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+    shared: Arc<SharedGuardState>,
     guard_id: GuardId,
+    /// Number of live handles of this guard. The guard is released when the last
+    /// one is dropped. Counted here rather than in the manager, so that a clone
+    /// can't race with the drop of the original.
+    handles: Arc<AtomicUsize>,
+    /// Issued by [`GuardIssuer`]. Such guards are registered through the manager
+    /// channel, so their drop goes through it as well to stay ordered after the
+    /// registration.
+    unregister_on_drop: bool,
+}
+
+// Guards are moved between tasks, make sure it stays possible.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<ResidenceGuard>();
+};
+
+impl ResidenceGuard {
+    /// Hand the guard over to another task, without releasing it in between:
+    ///
+    /// ```ignore
+    /// let guard = guard.transfer();
+    /// tokio::spawn(async move {
+    ///     // WAL stays on disk until the spawned task drops the guard
+    ///     do_work().await;
+    ///     drop(guard);
+    /// });
+    /// ```
+    pub fn transfer(self) -> ResidenceGuard {
+        self
+    }
+
+    /// Get another handle of the same guard. The guard is released only when all
+    /// of its handles are dropped. The handle shares the id and purpose of the
+    /// original guard.
+    pub fn clone_handle(&self) -> ResidenceGuard {
+        self.handles.fetch_add(1, Ordering::Relaxed);
+        ResidenceGuard {
+            manager_tx: self.manager_tx.clone(),
+            shared: self.shared.clone(),
+            guard_id: self.guard_id,
+            handles: self.handles.clone(),
+            unregister_on_drop: self.unregister_on_drop,
+        }
+    }
 }
 
 impl Drop for ResidenceGuard {
+    fn drop(&mut self) {
+        // same as Arc: release ordering on decrement, acquire for the last one
+        if self.handles.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        std::sync::atomic::fence(Ordering::Acquire);
+
+        if self.unregister_on_drop {
+            self.shared.notify_manager(
+                &self.manager_tx,
+                ManagerCtlMessage::UnregisterGuard(self.guard_id),
+            );
+            return;
+        }
+
+        self.shared.dropped.push(self.guard_id);
+        // notify the manager only if it hasn't been notified about the queue yet
+        if self.shared.notified.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.shared
+            .notify_manager(&self.manager_tx, ManagerCtlMessage::GuardDropBatch);
+    }
+}
+
+/// Returned by [`GuardIssuer::try_create_guard`] if WAL is not on disk.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("timeline is not resident")]
+pub struct NotResident;
+
+/// Reason why [`GuardIssuer::try_create_guard`] didn't issue a guard.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum TryGuardError {
+    #[error(transparent)]
+    NotResident(#[from] NotResident),
+    #[error(transparent)]
+    TimelineDeleted(#[from] TimelineDeleted),
+}
+
+/// Cheap handle to take residence guards without waiting for the manager, for
+/// synchronous code. Fails instead of unevicting the timeline.
+#[derive(Clone)]
+pub struct GuardIssuer {
+    manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+    shared: Arc<SharedGuardState>,
+}
+
+impl GuardIssuer {
+    pub(crate) fn new(
+        ttid: &TenantTimelineId,
+        manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+    ) -> Self {
+        GuardIssuer {
+            manager_tx,
+            shared: Arc::new(SharedGuardState::new(ttid)),
+        }
+    }
+
+    /// Take a guard if WAL is on disk, registering it with the manager in the
+    /// background. Doesn't block. Fails with [`TimelineDeleted`] once the deletion of
+    /// the timeline has started, a timeline that may become resident again fails
+    /// with [`NotResident`].
+    pub fn try_create_guard(&self, purpose: &'static str) -> Result<ResidenceGuard, TryGuardError> {
+        // Announce the registration before checking the flag. The manager clears
+        // the flag before checking for pending registrations, so either we see
+        // the flag cleared or it sees our registration and doesn't evict.
+        let shared = &self.shared;
+        shared.pending_registrations.fetch_add(1, Ordering::SeqCst);
+        if shared.blocked.load(Ordering::SeqCst) {
+            shared.pending_registrations.fetch_sub(1, Ordering::SeqCst);
+            return Err(TimelineDeleted.into());
+        }
+        if !shared.resident.load(Ordering::SeqCst) || shared.manager_gone.load(Ordering::Acquire) {
+            shared.pending_registrations.fetch_sub(1, Ordering::SeqCst);
+            return Err(NotResident.into());
+        }
+
+        let guard_id = shared.next_id();
+        let msg = ManagerCtlMessage::RegisterGuard(guard_id, purpose);
+        if self.manager_tx.send(msg).is_err() {
+            shared.pending_registrations.fetch_sub(1, Ordering::SeqCst);
+            return Err(NotResident.into());
+        }
+
+        Ok(ResidenceGuard {
+            manager_tx: self.manager_tx.clone(),
+            shared: shared.clone(),
+            guard_id,
+            handles: Arc::new(AtomicUsize::new(1)),
+            unregister_on_drop: true,
+        })
+    }
+}
+
+/// Guard which doesn't prevent eviction of the timeline. The holder should check
+/// [`WeakResidenceGuard::is_evicting`] or wait on [`WeakResidenceGuard::evicting`]
+/// and abort reading WAL once eviction starts.
+pub struct WeakResidenceGuard {
+    manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+    shared: Arc<SharedGuardState>,
+    guard_id: GuardId,
+    evicting_rx: tokio::sync::watch::Receiver<bool>,
+}
+
+impl WeakResidenceGuard {
+    /// Returns true if the timeline is being evicted, and WAL can disappear.
+    pub fn is_evicting(&self) -> bool {
+        *self.evicting_rx.borrow()
+    }
+
+    /// Wait until eviction of the timeline starts. Returns immediately if it
+    /// has already started.
+    pub async fn evicting(&mut self) {
+        while !*self.evicting_rx.borrow_and_update() {
+            if self.evicting_rx.changed().await.is_err() {
+                // manager is gone, nothing to wait for
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for WeakResidenceGuard {
     fn drop(&mut self) {
         // notify the manager that the guard is dropped
-        let res = self
-            .manager_tx
-            .send(ManagerCtlMessage::GuardDrop(self.guard_id));
-        if let Err(e) = res {
-            warn!("failed to send GuardDrop message: {:?}", e);
+        self.shared.notify_manager(
+            &self.manager_tx,
+            ManagerCtlMessage::WeakGuardDrop(self.guard_id),
+        );
+    }
+}
+
+/// Description of an issued guard, see [`AccessService::list`].
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardInfo {
+    pub id: GuardId,
+    /// Which subsystem holds the guard, e.g. "walsender".
+    pub purpose: Cow<'static, str>,
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    #[serde(rename = "held_for_secs")]
+    pub held_for: Duration,
+}
+
+/// Summary of the guards preventing eviction of the timeline, see
+/// [`AccessService::blocking_summary`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardsSummary {
+    /// Number of guards held. Weak guards are not counted, as they don't prevent eviction.
+    pub count: usize,
+    /// The guard held for the longest time.
+    pub oldest: Option<GuardInfo>,
+}
+
+impl fmt::Display for GuardsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.oldest {
+            None => write!(f, "no guards"),
+            Some(oldest) => write!(
+                f,
+                "{} guards, oldest is {:?} for '{}' held for {:?}",
+                self.count, oldest.id, oldest.purpose, oldest.held_for
+            ),
         }
     }
 }
 
+/// Returned when a timeline already has the maximum number of guards issued, see
+/// `max_residence_guards` in [`crate::SafeKeeperConf`]. The request can be
+/// retried once some guards are dropped.
+#[derive(Debug, Clone)]
+pub struct GuardLimitExceeded {
+    pub limit: usize,
+    pub count: usize,
+    /// Purposes holding the most guards, with the number of guards for each.
+    pub top_purposes: Vec<(&'static str, usize)>,
+}
+
+impl fmt::Display for GuardLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "too many residence guards on the timeline: {} issued, limit is {}, top purposes: ",
+            self.count, self.limit
+        )?;
+        for (i, (purpose, count)) in self.top_purposes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{purpose}={count}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GuardLimitExceeded {}
+
+/// Returned to would-be guard holders once the timeline deletion has started,
+/// see [`AccessService::block_new_guards`].
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("timeline is being deleted")]
+pub struct TimelineDeleted;
+
+/// Reason why a residence guard was not issued.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum GuardError {
+    #[error(transparent)]
+    LimitExceeded(#[from] GuardLimitExceeded),
+    #[error(transparent)]
+    TimelineDeleted(#[from] TimelineDeleted),
+}
+
+/// Result of [`AccessService::drain`].
+#[derive(Debug, Clone)]
+pub(crate) struct DrainReport {
+    /// Number of guards dropped while draining.
+    pub(crate) drained: usize,
+    /// Guards still held after the deadline.
+    pub(crate) leaked: Vec<GuardInfo>,
+}
+
+/// How often [`AccessService::drain`] rechecks registrations that may have been
+/// abandoned by a [`GuardIssuer`].
+const PENDING_RECHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Number of purposes reported in [`GuardLimitExceeded`].
+const TOP_PURPOSES: usize = 3;
+
+struct IssuedGuard {
+    purpose: &'static str,
+    created_at: Instant,
+}
+
+impl IssuedGuard {
+    fn observe_drop(&self) {
+        RESIDENCE_GUARDS.with_label_values(&[self.purpose]).dec();
+        RESIDENCE_GUARDS_DROPPED
+            .with_label_values(&[self.purpose])
+            .inc();
+        RESIDENCE_GUARD_HOLD_SECONDS
+            .with_label_values(&[self.purpose])
+            .observe(self.created_at.elapsed().as_secs_f64());
+    }
+}
+
 /// AccessService is responsible for issuing and dropping residence guards.
-/// All guards are stored in the `guards` set.
-/// TODO: it's possible to add `String` name to each guard, for better observability.
+/// All guards are stored in the `guards` map, along with their purpose and
+/// creation time. Weak guards are tracked separately in `weak_guards`.
 pub(crate) struct AccessService {
-    next_guard_id: u64,
-    guards: HashSet<u64>,
+    guards: HashMap<GuardId, IssuedGuard>,
+    weak_guards: HashMap<GuardId, &'static str>,
+    shared: Arc<SharedGuardState>,
+    /// Limit on the number of guards, strong and weak combined.
+    max_guards: usize,
+    evicting_tx: tokio::sync::watch::Sender<bool>,
     manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
 }
 
 impl AccessService {
-    pub(crate) fn new(manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>) -> Self {
+    pub(crate) fn new(issuer: GuardIssuer, max_guards: usize) -> Self {
         Self {
-            next_guard_id: 0,
-            guards: HashSet::new(),
-            manager_tx,
+            guards: HashMap::new(),
+            weak_guards: HashMap::new(),
+            shared: issuer.shared,
+            max_guards,
+            evicting_tx: tokio::sync::watch::channel(false).0,
+            manager_tx: issuer.manager_tx,
         }
     }
 
+    /// Allow [`GuardIssuer`] to issue guards, WAL is on disk.
+    pub(crate) fn set_resident(&self) {
+        self.shared.resident.store(true, Ordering::SeqCst);
+    }
+
+    /// Stop [`GuardIssuer`] from issuing guards before evicting the timeline.
+    /// Returns false, leaving the timeline resident, if some guards were issued
+    /// meanwhile and eviction must not proceed.
+    pub(crate) fn begin_eviction(&self) -> bool {
+        self.shared.resident.store(false, Ordering::SeqCst);
+        if self.shared.pending_registrations.load(Ordering::SeqCst) > 0 || !self.is_empty() {
+            self.set_resident();
+            return false;
+        }
+        true
+    }
+
+    /// Undo [`Self::begin_eviction`] after eviction failed. If the timeline was
+    /// switched to offloaded before the failure, it stays non-resident as after a
+    /// successful eviction, until [`Self::unevicted`].
+    pub(crate) fn eviction_failed(&self, offloaded: bool) {
+        if !offloaded {
+            self.set_evicting(false);
+            self.set_resident();
+        }
+    }
+
+    /// WAL of the evicted timeline is on disk again.
+    pub(crate) fn unevicted(&self) {
+        self.set_evicting(false);
+        self.set_resident();
+    }
+
+    /// Refuse all new guards, including ones from [`GuardIssuer`], because the
+    /// timeline is being deleted. Guards already issued are waited for with
+    /// [`Self::drain`].
+    pub(crate) fn block_new_guards(&self) {
+        self.shared.blocked.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_blocked(&self) -> bool {
+        self.shared.blocked.load(Ordering::SeqCst)
+    }
+
+    /// Register a guard issued by [`GuardIssuer`].
+    pub(crate) fn register_guard(&mut self, guard_id: GuardId, purpose: &'static str) {
+        self.shared
+            .pending_registrations
+            .fetch_sub(1, Ordering::SeqCst);
+        self.insert_guard(guard_id, purpose, Instant::now());
+    }
+
+    /// Returns true if there are no guards preventing eviction. Weak guards
+    /// are not taken into account.
     pub(crate) fn is_empty(&self) -> bool {
         self.guards.is_empty()
     }
 
-    pub(crate) fn create_guard(&mut self) -> ResidenceGuard {
-        let guard_id = self.next_guard_id;
-        self.next_guard_id += 1;
-        self.guards.insert(guard_id);
+    pub(crate) fn num_weak_guards(&self) -> usize {
+        self.weak_guards.len()
+    }
+
+    /// Notify weak guard holders that eviction is starting (or, with false,
+    /// that it has failed or the timeline was unevicted).
+    pub(crate) fn set_evicting(&self, evicting: bool) {
+        self.evicting_tx.send_replace(evicting);
+    }
+
+    /// Issued guards, ordered by id.
+    pub(crate) fn list(&self) -> Vec<GuardInfo> {
+        self.list_at(Instant::now())
+    }
+
+    fn list_at(&self, now: Instant) -> Vec<GuardInfo> {
+        let mut guards: Vec<_> = self
+            .guards
+            .iter()
+            .map(|(id, guard)| GuardInfo {
+                id: *id,
+                purpose: Cow::Borrowed(guard.purpose),
+                held_for: now.saturating_duration_since(guard.created_at),
+            })
+            .collect();
+        guards.sort_by_key(|g| g.id);
+        guards
+    }
+
+    /// Describe the guards preventing eviction, for logs and the status endpoint.
+    pub(crate) fn blocking_summary(&self) -> GuardsSummary {
+        self.blocking_summary_at(Instant::now())
+    }
+
+    fn blocking_summary_at(&self, now: Instant) -> GuardsSummary {
+        let guards = self.list_at(now);
+        GuardsSummary {
+            count: guards.len(),
+            oldest: guards.into_iter().max_by_key(|g| g.held_for),
+        }
+    }
+
+    /// Guards held for at least `threshold`. A guard that is never dropped, e.g. by a
+    /// stuck task, keeps the WAL on disk forever.
+    pub(crate) fn long_held(&self, threshold: Duration) -> Vec<GuardInfo> {
+        self.long_held_at(threshold, Instant::now())
+    }
+
+    fn long_held_at(&self, threshold: Duration, now: Instant) -> Vec<GuardInfo> {
+        let mut guards = self.list_at(now);
+        guards.retain(|g| g.held_for >= threshold);
+        guards
+    }
+
+    /// Fails if the timeline already has `max_guards` guards or is being deleted.
+    pub(crate) fn create_guard(
+        &mut self,
+        purpose: &'static str,
+    ) -> Result<ResidenceGuard, GuardError> {
+        self.check_blocked()?;
+        self.check_limit()?;
+        Ok(self.create_guard_at(purpose, Instant::now()))
+    }
+
+    /// Create a guard ignoring the limit. Only for the manager's own background
+    /// tasks, whose number is bounded.
+    pub(crate) fn create_internal_guard(&mut self, purpose: &'static str) -> ResidenceGuard {
+        self.create_guard_at(purpose, Instant::now())
+    }
+
+    fn check_blocked(&self) -> Result<(), TimelineDeleted> {
+        if self.is_blocked() {
+            return Err(TimelineDeleted);
+        }
+        Ok(())
+    }
+
+    fn check_limit(&self) -> Result<(), GuardLimitExceeded> {
+        let count = self.guards.len() + self.weak_guards.len();
+        if count < self.max_guards {
+            return Ok(());
+        }
+
+        let mut by_purpose: HashMap<&'static str, usize> = HashMap::new();
+        let purposes = self.guards.values().map(|g| g.purpose);
+        for purpose in purposes.chain(self.weak_guards.values().copied()) {
+            *by_purpose.entry(purpose).or_default() += 1;
+        }
+        let mut top_purposes: Vec<_> = by_purpose.into_iter().collect();
+        top_purposes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top_purposes.truncate(TOP_PURPOSES);
+
+        Err(GuardLimitExceeded {
+            limit: self.max_guards,
+            count,
+            top_purposes,
+        })
+    }
 
-        let guard_id = GuardId(guard_id);
-        debug!("issued a new guard {:?}", guard_id);
+    fn create_guard_at(&mut self, purpose: &'static str, created_at: Instant) -> ResidenceGuard {
+        let guard_id = self.shared.next_id();
+        self.insert_guard(guard_id, purpose, created_at);
 
         ResidenceGuard {
             manager_tx: self.manager_tx.clone(),
+            shared: self.shared.clone(),
+            guard_id,
+            handles: Arc::new(AtomicUsize::new(1)),
+            unregister_on_drop: false,
+        }
+    }
+
+    fn insert_guard(&mut self, guard_id: GuardId, purpose: &'static str, created_at: Instant) {
+        self.guards.insert(
             guard_id,
+            IssuedGuard {
+                purpose,
+                created_at,
+            },
+        );
+
+        debug!("issued a new guard {:?} for {}", guard_id, purpose);
+        // purpose is always a static string, so labels have low cardinality
+        RESIDENCE_GUARDS.with_label_values(&[purpose]).inc();
+        RESIDENCE_GUARDS_CREATED.with_label_values(&[purpose]).inc();
+    }
+
+    /// Fails if the timeline already has `max_guards` guards or is being deleted.
+    pub(crate) fn create_weak_guard(
+        &mut self,
+        purpose: &'static str,
+    ) -> Result<WeakResidenceGuard, GuardError> {
+        self.check_blocked()?;
+        self.check_limit()?;
+        let guard_id = self.shared.next_id();
+        self.weak_guards.insert(guard_id, purpose);
+
+        debug!("issued a new weak guard {:?} for {}", guard_id, purpose);
+
+        Ok(WeakResidenceGuard {
+            manager_tx: self.manager_tx.clone(),
+            shared: self.shared.clone(),
+            guard_id,
+            evicting_rx: self.evicting_tx.subscribe(),
+        })
+    }
+
+    /// Wait until all guards are dropped or the deadline passes, processing drop
+    /// notifications from `manager_rx`. Used on shutdown and deletion, when guards
+    /// can't be issued anymore: requests for new guards are refused. Weak guards
+    /// don't keep WAL on disk, so they are not waited for.
+    pub(crate) async fn drain(
+        &mut self,
+        manager_rx: &mut tokio::sync::mpsc::UnboundedReceiver<ManagerCtlMessage>,
+        deadline: tokio::time::Instant,
+    ) -> DrainReport {
+        let mut drained = 0;
+        // other deletions waiting for the same drain
+        let mut waiters = Vec::new();
+        loop {
+            // A registration from GuardIssuer may be in flight, unless the issuer
+            // sees the timeline blocked and abandons it.
+            let pending = self.shared.pending_registrations.load(Ordering::SeqCst) > 0;
+            if self.guards.is_empty() && !pending {
+                break;
+            }
+            let msg = tokio::select! {
+                msg = manager_rx.recv() => msg,
+                _ = tokio::time::sleep_until(deadline) => break,
+                _ = tokio::time::sleep(PENDING_RECHECK_INTERVAL), if pending => continue,
+            };
+            // we hold manager_tx, so the channel can't be closed
+            let Some(msg) = msg else { break };
+            match msg {
+                ManagerCtlMessage::GuardDropBatch => {
+                    for guard_id in self.take_dropped() {
+                        if self.drop_guard(guard_id).is_some() {
+                            drained += 1;
+                        }
+                    }
+                }
+                ManagerCtlMessage::RegisterGuard(guard_id, purpose) => {
+                    self.register_guard(guard_id, purpose);
+                }
+                ManagerCtlMessage::UnregisterGuard(guard_id) => {
+                    if self.drop_guard(guard_id).is_some() {
+                        drained += 1;
+                    }
+                }
+                ManagerCtlMessage::WeakGuardDrop(guard_id) => {
+                    self.drop_weak_guard(guard_id);
+                }
+                ManagerCtlMessage::ListGuards(tx) => {
+                    let _ = tx.send(self.list());
+                }
+                ManagerCtlMessage::BlockingSummary(tx) => {
+                    let _ = tx.send(self.blocking_summary());
+                }
+                ManagerCtlMessage::BlockNewGuards(reply) => {
+                    self.block_new_guards();
+                    waiters.push(reply);
+                }
+                ManagerCtlMessage::GuardRequest(_, reply)
+                | ManagerCtlMessage::TryGuardAsync { reply, .. }
+                    if self.is_blocked() =>
+                {
+                    let _ = reply.send(Err(TimelineDeleted.into()));
+                }
+                ManagerCtlMessage::WeakGuardRequest(_, reply) if self.is_blocked() => {
+                    let _ = reply.send(Err(TimelineDeleted.into()));
+                }
+                msg => {
+                    // dropping the reply sender fails the request
+                    debug!("refusing {:?}, timeline is shutting down", msg);
+                }
+            }
+        }
+
+        let report = DrainReport {
+            drained,
+            leaked: self.list(),
+        };
+        for reply in waiters {
+            let _ = reply.send(report.clone());
+        }
+        report
+    }
+
+    /// Tell guards that the manager doesn't process messages anymore, so that
+    /// dropping them later doesn't notify it. Called before the manager exits.
+    pub(crate) fn close(&self) {
+        self.shared.manager_gone.store(true, Ordering::Release);
+    }
+
+    /// Queue `guard_id` as dropped, like its guard does, to replay a drop.
+    #[cfg(test)]
+    pub(crate) fn report_dropped(&self, guard_id: GuardId) {
+        self.shared.dropped.push(guard_id);
+    }
+
+    /// Take ids of the guards dropped since the last call. Must be called on each
+    /// [`ManagerCtlMessage::GuardDropBatch`], ids are then passed to
+    /// [`Self::drop_guard`].
+    pub(crate) fn take_dropped(&self) -> Vec<GuardId> {
+        // reset before draining: a guard dropped after this point either gets
+        // drained below, or sends a new notification
+        self.shared.notified.store(false, Ordering::Release);
+        let mut ids = Vec::new();
+        loop {
+            match self.shared.dropped.steal() {
+                Steal::Success(id) => ids.push(id),
+                Steal::Empty => break,
+                Steal::Retry => continue,
+            }
+        }
+        ids
+    }
+
+    /// Returns the purpose the weak guard was issued for, or None if there is no
+    /// such weak guard.
+    pub(crate) fn drop_weak_guard(&mut self, guard_id: GuardId) -> Option<&'static str> {
+        let purpose = self.weak_guards.remove(&guard_id)?;
+        debug!("dropping weak guard {:?} of {}", guard_id, purpose);
+        Some(purpose)
+    }
+
+    /// Returns the purpose the guard was issued for, or None if there is no such
+    /// guard, e.g. because the drop was reported twice.
+    pub(crate) fn drop_guard(&mut self, guard_id: GuardId) -> Option<&'static str> {
+        let guard = self.guards.remove(&guard_id)?;
+        debug!("dropping guard {:?} of {}", guard_id, guard.purpose);
+        guard.observe_drop();
+        Some(guard.purpose)
+    }
+}
+
+impl Drop for AccessService {
+    fn drop(&mut self) {
+        self.close();
+        // Manager is gone, outstanding guards can't be reported anymore. Account
+        // them as dropped to keep the gauge accurate.
+        for (_, guard) in self.guards.drain() {
+            guard.observe_drop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Receive the drop notification of a single guard, as the manager does.
+    fn recv_dropped(
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<ManagerCtlMessage>,
+        access_service: &AccessService,
+    ) -> GuardId {
+        let Ok(ManagerCtlMessage::GuardDropBatch) = rx.try_recv() else {
+            panic!("expected GuardDropBatch");
+        };
+        let dropped = access_service.take_dropped();
+        assert_eq!(dropped.len(), 1);
+        dropped[0]
+    }
+
+    #[test]
+    fn test_guard_purpose() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+
+        let backup = access_service.create_guard("wal_backup").unwrap();
+        let walsender = access_service.create_guard("walsender").unwrap();
+        let guards = access_service.list();
+        let purposes: Vec<_> = guards.iter().map(|g| g.purpose.as_ref()).collect();
+        assert_eq!(purposes, vec!["wal_backup", "walsender"]);
+
+        drop(walsender);
+        let id = recv_dropped(&mut rx, &access_service);
+        assert_eq!(access_service.drop_guard(id), Some("walsender"));
+        let guards = access_service.list();
+        let purposes: Vec<_> = guards.iter().map(|g| g.purpose.as_ref()).collect();
+        assert_eq!(purposes, vec!["wal_backup"]);
+        drop(backup);
+    }
+
+    #[test]
+    fn test_duplicate_drop() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+
+        let _other = access_service.create_guard("walsender").unwrap();
+        drop(access_service.create_guard("wal_backup").unwrap());
+        let id = recv_dropped(&mut rx, &access_service);
+        assert_eq!(access_service.drop_guard(id), Some("wal_backup"));
+        // a replayed message is reported, and doesn't affect the other guards
+        assert_eq!(access_service.drop_guard(id), None);
+        assert_eq!(access_service.list().len(), 1);
+    }
+
+    #[test]
+    fn test_guard_metrics() {
+        // unique purpose, metrics are global and tests run in parallel
+        const PURPOSE: &str = "test_guard_metrics";
+        let gauge = RESIDENCE_GUARDS.with_label_values(&[PURPOSE]);
+        let dropped = RESIDENCE_GUARDS_DROPPED.with_label_values(&[PURPOSE]);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+
+        let first = access_service.create_guard(PURPOSE).unwrap();
+        let second = access_service.create_guard(PURPOSE).unwrap();
+        assert_eq!(gauge.get(), 2);
+        assert_eq!(
+            RESIDENCE_GUARDS_CREATED.with_label_values(&[PURPOSE]).get(),
+            2
+        );
+
+        drop(first);
+        let id = recv_dropped(&mut rx, &access_service);
+        access_service.drop_guard(id);
+        assert_eq!(gauge.get(), 1);
+        assert_eq!(dropped.get(), 1);
+        // unknown drops don't skew the gauge
+        access_service.drop_guard(id);
+        assert_eq!(gauge.get(), 1);
+
+        // guards outstanding when the manager exits are accounted as dropped
+        drop(access_service);
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(dropped.get(), 2);
+        assert_eq!(
+            RESIDENCE_GUARD_HOLD_SECONDS
+                .with_label_values(&[PURPOSE])
+                .get_sample_count(),
+            2
+        );
+        drop(second);
+    }
+
+    #[tokio::test]
+    async fn test_weak_guard() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+
+        let strong = access_service.create_guard("walsender").unwrap();
+        let weak = access_service.create_weak_guard("debug_dump").unwrap();
+        assert!(!access_service.is_empty());
+        assert!(!weak.is_evicting());
+
+        drop(strong);
+        let id = recv_dropped(&mut rx, &access_service);
+        access_service.drop_guard(id);
+        // only the weak guard is outstanding, timeline can be evicted
+        assert!(access_service.is_empty());
+        assert_eq!(access_service.num_weak_guards(), 1);
+
+        access_service.set_evicting(true);
+        assert!(weak.is_evicting());
+        tokio::time::timeout(Duration::from_secs(1), weak.evicting())
+            .await
+            .expect("eviction notification");
+
+        drop(weak);
+        let Ok(ManagerCtlMessage::WeakGuardDrop(id)) = rx.try_recv() else {
+            panic!("expected WeakGuardDrop");
+        };
+        // weak guard ids don't collide with strong ones
+        assert_eq!(access_service.drop_guard(id), None);
+        assert_eq!(access_service.drop_weak_guard(id), Some("debug_dump"));
+        assert_eq!(access_service.num_weak_guards(), 0);
+    }
+
+    #[test]
+    fn test_drop_batching() {
+        const GUARDS: usize = 100_000;
+        const THREADS: usize = 4;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), GUARDS);
+
+        // drop guards from several threads while the "manager" processes batches
+        let mut per_thread: Vec<Vec<ResidenceGuard>> = (0..THREADS).map(|_| Vec::new()).collect();
+        for i in 0..GUARDS {
+            per_thread[i % THREADS].push(access_service.create_guard("walsender").unwrap());
+        }
+        let handles: Vec<_> = per_thread
+            .into_iter()
+            .map(|guards| {
+                std::thread::spawn(move || {
+                    for guard in guards {
+                        drop(guard);
+                    }
+                })
+            })
+            .collect();
+
+        let mut messages = 0;
+        let mut dropped = 0;
+        while dropped < GUARDS {
+            match rx.try_recv() {
+                Ok(ManagerCtlMessage::GuardDropBatch) => {
+                    messages += 1;
+                    // manager wakeup isn't instant, more drops pile up meanwhile
+                    std::thread::sleep(Duration::from_micros(100));
+                    for id in access_service.take_dropped() {
+                        assert!(access_service.drop_guard(id).is_some());
+                        dropped += 1;
+                    }
+                }
+                Ok(msg) => panic!("unexpected message {:?}", msg),
+                Err(_) => std::thread::yield_now(),
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(access_service.is_empty());
+        assert!(access_service.take_dropped().is_empty());
+        assert!(
+            messages < GUARDS / 10,
+            "{messages} messages for {GUARDS} dropped guards"
+        );
+    }
+
+    /// Counts events logged at warn level or above.
+    struct CountWarnings(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CountWarnings {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if *event.metadata().level() <= tracing::Level::WARN {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_drop_after_manager_gone() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let warnings = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry().with(CountWarnings(warnings.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            // receiver dropped without close(), e.g. the manager task panicked
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut access_service =
+                AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+            let guards: Vec<_> = (0..100)
+                .map(|_| access_service.create_guard("walsender").unwrap())
+                .collect();
+            let weak = access_service.create_weak_guard("debug_dump").unwrap();
+            drop(rx);
+            for guard in guards {
+                drop(guard);
+                // let every drop try to notify the manager
+                access_service.take_dropped();
+            }
+            drop(weak);
+            assert!(access_service.shared.manager_gone.load(Ordering::Relaxed));
+
+            // after close(), guards don't notify the manager at all
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let mut access_service =
+                AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+            let guard = access_service.create_guard("walsender").unwrap();
+            access_service.close();
+            drop(guard);
+            assert!(rx.try_recv().is_err());
+        });
+        assert_eq!(warnings.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_guard_limit() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 3);
+
+        let _walsender1 = access_service.create_guard("walsender").unwrap();
+        let walsender2 = access_service.create_guard("walsender").unwrap();
+        let _weak = access_service.create_weak_guard("debug_dump").unwrap();
+
+        let Err(GuardError::LimitExceeded(err)) = access_service.create_guard("walreceiver") else {
+            panic!("expected GuardLimitExceeded");
+        };
+        assert_eq!(err.limit, 3);
+        assert_eq!(err.count, 3);
+        assert_eq!(err.top_purposes, vec![("walsender", 2), ("debug_dump", 1)]);
+        assert!(access_service.create_weak_guard("debug_dump").is_err());
+        // manager's own guards are not limited
+        let backup = access_service.create_internal_guard("wal_backup");
+
+        drop(walsender2);
+        let id = recv_dropped(&mut rx, &access_service);
+        access_service.drop_guard(id);
+        // still at the limit because of the internal guard
+        assert!(access_service.create_guard("walreceiver").is_err());
+        drop(backup);
+        let id = recv_dropped(&mut rx, &access_service);
+        access_service.drop_guard(id);
+        let _walreceiver = access_service.create_guard("walreceiver").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+
+        let _leaked = access_service.create_guard("walsender").unwrap();
+        let dropped = access_service.create_guard("walreceiver").unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(dropped);
+        });
+
+        let started_at = tokio::time::Instant::now();
+        let deadline = started_at + Duration::from_millis(500);
+        let report = access_service.drain(&mut rx, deadline).await;
+        assert!(tokio::time::Instant::now() >= deadline);
+        assert_eq!(report.drained, 1);
+        assert_eq!(report.leaked.len(), 1);
+        assert_eq!(report.leaked[0].purpose, "walsender");
+        assert!(report.leaked[0].held_for >= Duration::from_millis(500));
+
+        // returns right away when there is nothing to wait for
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+        let report = access_service
+            .drain(
+                &mut rx,
+                tokio::time::Instant::now() + Duration::from_secs(60),
+            )
+            .await;
+        assert_eq!(report.drained, 0);
+        assert!(report.leaked.is_empty());
+    }
+
+    #[test]
+    fn test_blocking_summary() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+        let now = Instant::now() + Duration::from_secs(3600);
+        let minute = Duration::from_secs(60);
+
+        let summary = access_service.blocking_summary_at(now);
+        assert_eq!(summary.count, 0);
+        assert!(summary.oldest.is_none());
+        assert_eq!(summary.to_string(), "no guards");
+
+        let _walsender = access_service.create_guard_at("walsender", now - minute);
+        let summary = access_service.blocking_summary_at(now);
+        assert_eq!(summary.count, 1);
+        let oldest = summary.oldest.unwrap();
+        assert_eq!(
+            (oldest.purpose.as_ref(), oldest.held_for),
+            ("walsender", minute)
+        );
+
+        let _backup = access_service.create_guard_at("wal_backup", now - 5 * minute);
+        let _recovery = access_service.create_guard_at("recovery", now - 2 * minute);
+        // weak guards don't block eviction
+        let _weak = access_service.create_weak_guard("debug_dump").unwrap();
+        let summary = access_service.blocking_summary_at(now);
+        assert_eq!(summary.count, 3);
+        let oldest = summary.oldest.as_ref().unwrap();
+        assert_eq!(
+            (oldest.purpose.as_ref(), oldest.held_for),
+            ("wal_backup", 5 * minute)
+        );
+        assert!(summary.to_string().starts_with("3 guards, oldest is"));
+    }
+
+    #[test]
+    fn test_clone_handle() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+
+        let guard = access_service.create_guard("walsender").unwrap();
+        let handle = std::thread::spawn({
+            let handle = guard.clone_handle().transfer();
+            move || handle
+        })
+        .join()
+        .unwrap();
+
+        drop(guard);
+        // the other handle still keeps the guard
+        assert!(rx.try_recv().is_err());
+        assert!(access_service.take_dropped().is_empty());
+        assert!(!access_service.is_empty());
+        let guards = access_service.list();
+        assert_eq!(guards.len(), 1);
+        assert_eq!(guards[0].purpose, "walsender");
+
+        drop(handle);
+        let id = recv_dropped(&mut rx, &access_service);
+        assert_eq!(access_service.drop_guard(id), Some("walsender"));
+        assert!(access_service.is_empty());
+    }
+
+    #[test]
+    fn test_guard_id_format() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let ttid = TenantTimelineId::generate();
+        let mut first = AccessService::new(GuardIssuer::new(&ttid, tx.clone()), 1024);
+        let mut other_timeline = AccessService::new(
+            GuardIssuer::new(&TenantTimelineId::generate(), tx.clone()),
+            1024,
+        );
+        // same timeline recreated in the same process
+        let mut recreated = AccessService::new(GuardIssuer::new(&ttid, tx), 1024);
+
+        let ids: Vec<_> = [&mut first, &mut other_timeline, &mut recreated]
+            .into_iter()
+            .map(|access_service| access_service.create_guard("walsender").unwrap().guard_id)
+            .collect();
+        let logged: Vec<_> = ids.iter().map(|id| format!("{:?}", id)).collect();
+        assert_ne!(logged[0], logged[1]);
+        assert_ne!(logged[0], logged[2]);
+        // same timeline has the same prefix, in the same process and across restarts
+        let prefix = format!("GuardId({:08x}-", GuardId::timeline_hash(&ttid));
+        assert!(logged[0].starts_with(&prefix), "{}", logged[0]);
+        assert!(logged[2].starts_with(&prefix), "{}", logged[2]);
+        assert!(!logged[1].starts_with(&prefix), "{}", logged[1]);
+
+        for id in ids {
+            assert_eq!(id.to_string().parse::<GuardId>().unwrap(), id);
+            let json = serde_json::to_string(&id).unwrap();
+            assert_eq!(serde_json::from_str::<GuardId>(&json).unwrap(), id);
         }
     }
 
-    pub(crate) fn drop_guard(&mut self, guard_id: GuardId) {
-        debug!("dropping guard {:?}", guard_id);
-        assert!(self.guards.remove(&guard_id.0));
+    #[test]
+    fn test_long_held() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut access_service =
+            AccessService::new(GuardIssuer::new(&TenantTimelineId::empty(), tx), 1024);
+        let now = Instant::now() + Duration::from_secs(3600);
+        let minute = Duration::from_secs(60);
+
+        let _stuck = access_service.create_guard_at("wal_backup", now - 15 * minute);
+        let _recent = access_service.create_guard_at("walsender", now - minute);
+        let _exact = access_service.create_guard_at("recovery", now - 10 * minute);
+
+        let long_held = access_service.long_held_at(10 * minute, now);
+        let held: Vec<_> = long_held
+            .iter()
+            .map(|g| (g.purpose.as_ref(), g.held_for))
+            .collect();
+        assert_eq!(
+            held,
+            vec![("wal_backup", 15 * minute), ("recovery", 10 * minute)]
+        );
+
+        assert!(access_service.long_held_at(20 * minute, now).is_empty());
+        assert_eq!(access_service.long_held_at(Duration::ZERO, now).len(), 3);
+    }
+
+    /// Handle a message from [`GuardIssuer`], as the manager does.
+    fn handle_issuer_message(msg: ManagerCtlMessage, access_service: &mut AccessService) {
+        match msg {
+            ManagerCtlMessage::RegisterGuard(guard_id, purpose) => {
+                access_service.register_guard(guard_id, purpose)
+            }
+            ManagerCtlMessage::UnregisterGuard(guard_id) => {
+                assert!(access_service.drop_guard(guard_id).is_some());
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_issuer_resident() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let issuer = GuardIssuer::new(&TenantTimelineId::empty(), tx);
+        let mut access_service = AccessService::new(issuer.clone(), 1024);
+        access_service.set_resident();
+
+        let guard = issuer.try_create_guard("wal_reader").unwrap();
+        handle_issuer_message(rx.try_recv().unwrap(), &mut access_service);
+        let list = access_service.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, guard.guard_id);
+        assert_eq!(list[0].purpose, "wal_reader");
+        assert!(!access_service.begin_eviction());
+
+        drop(guard);
+        let Ok(ManagerCtlMessage::UnregisterGuard(guard_id)) = rx.try_recv() else {
+            panic!("expected UnregisterGuard");
+        };
+        assert_eq!(guard_id, list[0].id);
+        access_service.drop_guard(guard_id);
+        assert!(access_service.begin_eviction());
+    }
+
+    #[test]
+    fn test_issuer_not_resident() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let issuer = GuardIssuer::new(&TenantTimelineId::empty(), tx);
+        let access_service = AccessService::new(issuer.clone(), 1024);
+
+        // offloaded timeline is never marked resident
+        assert!(matches!(
+            issuer.try_create_guard("wal_reader"),
+            Err(TryGuardError::NotResident(_))
+        ));
+
+        access_service.set_resident();
+        assert!(access_service.begin_eviction());
+        assert!(matches!(
+            issuer.try_create_guard("wal_reader"),
+            Err(TryGuardError::NotResident(_))
+        ));
+        assert!(rx.try_recv().is_err());
+
+        // guards are not issued once the manager is gone
+        access_service.set_resident();
+        access_service.close();
+        assert!(matches!(
+            issuer.try_create_guard("wal_reader"),
+            Err(TryGuardError::NotResident(_))
+        ));
+    }
+
+    #[test]
+    fn test_issuer_races_eviction() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let issuer = GuardIssuer::new(&TenantTimelineId::empty(), tx);
+        let mut access_service = AccessService::new(issuer.clone(), 1024);
+        access_service.set_resident();
+
+        let live = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::spawn({
+            let live = live.clone();
+            let stop = stop.clone();
+            move || {
+                let mut issued = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    if let Ok(guard) = issuer.try_create_guard("wal_reader") {
+                        live.fetch_add(1, Ordering::SeqCst);
+                        issued += 1;
+                        std::hint::spin_loop();
+                        live.fetch_sub(1, Ordering::SeqCst);
+                        drop(guard);
+                    }
+                }
+                issued
+            }
+        });
+
+        let mut evictions = 0;
+        for i in 0..100_000 {
+            while let Ok(msg) = rx.try_recv() {
+                handle_issuer_message(msg, &mut access_service);
+            }
+            if i % 10 == 0 && access_service.begin_eviction() {
+                evictions += 1;
+                for _ in 0..10 {
+                    assert_eq!(live.load(Ordering::SeqCst), 0);
+                }
+                access_service.set_resident();
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        let issued = thread.join().unwrap();
+        while let Ok(msg) = rx.try_recv() {
+            handle_issuer_message(msg, &mut access_service);
+        }
+
+        assert!(issued > 0);
+        assert!(evictions > 0);
+        assert!(access_service.is_empty());
+    }
+
+    #[test]
+    fn test_evict_unevict() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let issuer = GuardIssuer::new(&TenantTimelineId::empty(), tx);
+        let mut access_service = AccessService::new(issuer.clone(), 1024);
+        access_service.set_resident();
+
+        // eviction failing before the switch to offloaded leaves the timeline resident
+        assert!(access_service.begin_eviction());
+        access_service.set_evicting(true);
+        access_service.eviction_failed(false);
+        drop(issuer.try_create_guard("wal_reader").unwrap());
+        while let Ok(msg) = rx.try_recv() {
+            handle_issuer_message(msg, &mut access_service);
+        }
+
+        // after it, the timeline is offloaded even if the eviction failed
+        assert!(access_service.begin_eviction());
+        access_service.set_evicting(true);
+        access_service.eviction_failed(true);
+        assert!(issuer.try_create_guard("wal_reader").is_err());
+        let weak = access_service.create_weak_guard("debug_dump").unwrap();
+        assert!(weak.is_evicting());
+
+        access_service.unevicted();
+        assert!(!weak.is_evicting());
+        let guard = issuer.try_create_guard("wal_reader").unwrap();
+        handle_issuer_message(rx.try_recv().unwrap(), &mut access_service);
+        assert_eq!(access_service.list()[0].id, guard.guard_id);
+        assert!(!access_service.begin_eviction());
+    }
+
+    #[tokio::test]
+    async fn test_block_new_guards() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let issuer = GuardIssuer::new(&TenantTimelineId::empty(), tx.clone());
+        let mut access_service = AccessService::new(issuer.clone(), 1024);
+        access_service.set_resident();
+
+        let held = access_service.create_guard("walsender").unwrap();
+        access_service.block_new_guards();
+        assert!(matches!(
+            access_service.create_guard("walsender"),
+            Err(GuardError::TimelineDeleted(_))
+        ));
+        assert!(matches!(
+            access_service.create_weak_guard("debug_dump"),
+            Err(GuardError::TimelineDeleted(_))
+        ));
+        assert!(matches!(
+            issuer.try_create_guard("wal_reader"),
+            Err(TryGuardError::TimelineDeleted(_))
+        ));
+
+        // requests arriving while draining fail right away, the drain finishes
+        // only after the held guard is dropped
+        let requester = tokio::spawn(async move {
+            let (reply, reply_rx) = tokio::sync::oneshot::channel();
+            tx.send(ManagerCtlMessage::TryGuardAsync {
+                purpose: "digest",
+                reply,
+            })
+            .unwrap();
+            let err = reply_rx.await.unwrap().err().unwrap();
+            assert!(err.is::<TimelineDeleted>());
+            let refused_at = tokio::time::Instant::now();
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(held);
+            refused_at
+        });
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let report = access_service.drain(&mut rx, deadline).await;
+        let drained_at = tokio::time::Instant::now();
+        let refused_at = requester.await.unwrap();
+        assert!(drained_at >= refused_at + Duration::from_millis(50));
+        assert!(drained_at < deadline);
+        assert_eq!(report.drained, 1);
+        assert!(report.leaked.is_empty());
     }
 }
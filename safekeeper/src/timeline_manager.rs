@@ -7,6 +7,7 @@
 //! Also, if it will stuck in some branch, it will prevent any further progress in the timeline.
 
 use std::{
+    collections::HashSet,
     sync::{atomic::AtomicUsize, Arc},
     time::Duration,
 };
@@ -17,19 +18,25 @@ use tokio::{
     task::{JoinError, JoinHandle},
     time::Instant,
 };
-use tracing::{debug, info, info_span, instrument, warn, Instrument};
-use utils::lsn::Lsn;
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
+use utils::{id::TenantTimelineId, lsn::Lsn};
 
 use crate::{
     control_file::{FileStorage, Storage},
-    metrics::{MANAGER_ACTIVE_CHANGES, MANAGER_ITERATIONS_TOTAL, MISC_OPERATION_SECONDS},
+    metrics::{
+        MANAGER_ACTIVE_CHANGES, MANAGER_ITERATIONS_TOTAL, MANAGER_UNEXPECTED_GUARD_DROPS,
+        MISC_OPERATION_SECONDS, RESIDENCE_GUARDS_LEAKED,
+    },
     recovery::recovery_main,
     remove_wal::calc_horizon_lsn,
     safekeeper::Term,
     send_wal::WalSenders,
     state::TimelineState,
     timeline::{ManagerTimeline, PeerInfo, ReadGuardSharedState, StateSK, WalResidentTimeline},
-    timeline_guard::{AccessService, GuardId, ResidenceGuard},
+    timeline_guard::{
+        AccessService, DrainReport, GuardId, GuardInfo, GuardIssuer, GuardsSummary, ResidenceGuard,
+        TimelineDeleted, WeakResidenceGuard,
+    },
     timelines_set::{TimelineSetGuard, TimelinesSet},
     wal_backup::{self, WalBackupTaskHandle},
     wal_backup_partial::{self, PartialRemoteSegment, RateLimiter},
@@ -86,55 +93,108 @@ impl StateSnapshot {
     }
 }
 
+/// How long the manager waits on shutdown for residence guards to be dropped.
+const GUARD_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Control how often the manager task should wake up to check updates.
 /// There is no need to check for updates more often than this.
 const REFRESH_INTERVAL: Duration = Duration::from_millis(300);
 
 pub enum ManagerCtlMessage {
     /// Request to get a guard for WalResidentTimeline, with WAL files available locally.
-    GuardRequest(tokio::sync::oneshot::Sender<anyhow::Result<ResidenceGuard>>),
-    /// Request to drop the guard.
-    GuardDrop(GuardId),
+    /// The label names the subsystem that will hold the guard.
+    GuardRequest(
+        &'static str,
+        tokio::sync::oneshot::Sender<anyhow::Result<ResidenceGuard>>,
+    ),
+    /// Some guards were dropped, their ids are in `AccessService::take_dropped`.
+    /// Sent once per batch of drops, when the queue becomes non-empty.
+    GuardDropBatch,
+    /// Request to get a weak guard, which doesn't prevent eviction. Fails if the
+    /// timeline is offloaded.
+    WeakGuardRequest(
+        &'static str,
+        tokio::sync::oneshot::Sender<anyhow::Result<WeakResidenceGuard>>,
+    ),
+    /// Request to drop the weak guard.
+    WeakGuardDrop(GuardId),
+    /// Register a guard issued by `GuardIssuer` without waiting for the manager.
+    RegisterGuard(GuardId, &'static str),
+    /// Drop a guard registered with `RegisterGuard`.
+    UnregisterGuard(GuardId),
+    /// Request to list the currently issued guards.
+    ListGuards(tokio::sync::oneshot::Sender<Vec<GuardInfo>>),
+    /// Request to describe the guards preventing eviction.
+    BlockingSummary(tokio::sync::oneshot::Sender<GuardsSummary>),
+    /// Request to get a guard without blocking the manager. If the timeline is
+    /// offloaded, the reply is sent once the WAL is back on disk. All requests
+    /// waiting at the same time are served by a single uneviction.
+    TryGuardAsync {
+        purpose: &'static str,
+        reply: tokio::sync::oneshot::Sender<anyhow::Result<ResidenceGuard>>,
+    },
+    /// Timeline is being deleted: refuse new guards, fail pending requests and
+    /// reply once the issued guards are dropped or the drain deadline passes.
+    BlockNewGuards(tokio::sync::oneshot::Sender<DrainReport>),
 }
 
 impl std::fmt::Debug for ManagerCtlMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ManagerCtlMessage::GuardRequest(_) => write!(f, "GuardRequest"),
-            ManagerCtlMessage::GuardDrop(id) => write!(f, "GuardDrop({:?})", id),
+            ManagerCtlMessage::GuardRequest(purpose, _) => write!(f, "GuardRequest({purpose})"),
+            ManagerCtlMessage::GuardDropBatch => write!(f, "GuardDropBatch"),
+            ManagerCtlMessage::RegisterGuard(id, purpose) => {
+                write!(f, "RegisterGuard({:?}, {purpose})", id)
+            }
+            ManagerCtlMessage::UnregisterGuard(id) => write!(f, "UnregisterGuard({:?})", id),
+            ManagerCtlMessage::WeakGuardRequest(purpose, _) => {
+                write!(f, "WeakGuardRequest({purpose})")
+            }
+            ManagerCtlMessage::WeakGuardDrop(id) => write!(f, "WeakGuardDrop({:?})", id),
+            ManagerCtlMessage::ListGuards(_) => write!(f, "ListGuards"),
+            ManagerCtlMessage::BlockingSummary(_) => write!(f, "BlockingSummary"),
+            ManagerCtlMessage::TryGuardAsync { purpose, .. } => {
+                write!(f, "TryGuardAsync({purpose})")
+            }
+            ManagerCtlMessage::BlockNewGuards(_) => write!(f, "BlockNewGuards"),
         }
     }
 }
 
 pub struct ManagerCtl {
     manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+    guard_issuer: GuardIssuer,
 
     // this is used to initialize manager, it will be moved out in bootstrap().
     init_manager_rx:
         std::sync::Mutex<Option<tokio::sync::mpsc::UnboundedReceiver<ManagerCtlMessage>>>,
 }
 
-impl Default for ManagerCtl {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl ManagerCtl {
-    pub fn new() -> Self {
+    pub fn new(ttid: &TenantTimelineId) -> Self {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
         Self {
+            guard_issuer: GuardIssuer::new(ttid, tx.clone()),
             manager_tx: tx,
             init_manager_rx: std::sync::Mutex::new(Some(rx)),
         }
     }
 
+    /// Handle to take guards from synchronous code, see [`GuardIssuer`].
+    pub fn guard_issuer(&self) -> GuardIssuer {
+        self.guard_issuer.clone()
+    }
+
     /// Issue a new guard and wait for manager to prepare the timeline.
     /// Sends a message to the manager and waits for the response.
     /// Can be blocked indefinitely if the manager is stuck.
-    pub async fn wal_residence_guard(&self) -> anyhow::Result<ResidenceGuard> {
+    pub async fn wal_residence_guard(
+        &self,
+        purpose: &'static str,
+    ) -> anyhow::Result<ResidenceGuard> {
         let (tx, rx) = tokio::sync::oneshot::channel();
-        self.manager_tx.send(ManagerCtlMessage::GuardRequest(tx))?;
+        self.manager_tx
+            .send(ManagerCtlMessage::GuardRequest(purpose, tx))?;
 
         // wait for the manager to respond with the guard
         rx.await
@@ -142,11 +202,71 @@ impl ManagerCtl {
             .and_then(std::convert::identity)
     }
 
+    /// Issue a new guard, waiting for the timeline to become resident if it is
+    /// offloaded. Can be blocked indefinitely if the manager is stuck.
+    pub async fn acquire_residence_guard(
+        &self,
+        purpose: &'static str,
+    ) -> anyhow::Result<ResidenceGuard> {
+        let (reply, rx) = tokio::sync::oneshot::channel();
+        self.manager_tx
+            .send(ManagerCtlMessage::TryGuardAsync { purpose, reply })?;
+
+        rx.await
+            .map_err(|e| anyhow::anyhow!("response read fail: {:?}", e))
+            .and_then(std::convert::identity)
+    }
+
+    /// Issue a new weak guard, which doesn't prevent eviction. Doesn't unevict
+    /// the timeline. Can be blocked indefinitely if the manager is stuck.
+    pub async fn weak_residence_guard(
+        &self,
+        purpose: &'static str,
+    ) -> anyhow::Result<WeakResidenceGuard> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.manager_tx
+            .send(ManagerCtlMessage::WeakGuardRequest(purpose, tx))?;
+
+        rx.await
+            .map_err(|e| anyhow::anyhow!("response read fail: {:?}", e))
+            .and_then(std::convert::identity)
+    }
+
+    /// List the guards currently issued by the manager.
+    /// Can be blocked indefinitely if the manager is stuck.
+    pub async fn list_guards(&self) -> anyhow::Result<Vec<GuardInfo>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.manager_tx.send(ManagerCtlMessage::ListGuards(tx))?;
+        rx.await
+            .map_err(|e| anyhow::anyhow!("response read fail: {:?}", e))
+    }
+
+    /// Describe the guards preventing eviction of the timeline.
+    /// Can be blocked indefinitely if the manager is stuck.
+    pub async fn blocking_summary(&self) -> anyhow::Result<GuardsSummary> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.manager_tx
+            .send(ManagerCtlMessage::BlockingSummary(tx))?;
+        rx.await
+            .map_err(|e| anyhow::anyhow!("response read fail: {:?}", e))
+    }
+
+    /// Stop issuing guards before deleting the timeline, and wait until the issued
+    /// ones are dropped. Returns guards still held after the drain deadline.
+    /// Fails if the manager has already exited.
+    pub async fn block_new_guards(&self) -> anyhow::Result<DrainReport> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.manager_tx
+            .send(ManagerCtlMessage::BlockNewGuards(tx))?;
+        rx.await
+            .map_err(|e| anyhow::anyhow!("response read fail: {:?}", e))
+    }
+
     /// Must be called exactly once to bootstrap the manager.
     pub fn bootstrap_manager(
         &self,
     ) -> (
-        tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+        GuardIssuer,
         tokio::sync::mpsc::UnboundedReceiver<ManagerCtlMessage>,
     ) {
         let rx = self
@@ -156,7 +276,7 @@ impl ManagerCtl {
             .take()
             .expect("manager already bootstrapped");
 
-        (self.manager_tx.clone(), rx)
+        (self.guard_issuer.clone(), rx)
     }
 }
 
@@ -186,6 +306,17 @@ pub(crate) struct Manager {
     // misc
     pub(crate) access_service: AccessService,
     pub(crate) partial_backup_rate_limiter: RateLimiter,
+    /// Long held guards we already warned about, to warn only once per guard.
+    pub(crate) long_held_guards_warned: HashSet<GuardId>,
+    /// Guard requests waiting for the timeline to be unevicted.
+    pub(crate) pending_guards: Vec<PendingGuard>,
+}
+
+/// Guard request from [`ManagerCtlMessage::TryGuardAsync`] which can't be served
+/// until the timeline is unevicted.
+pub(crate) struct PendingGuard {
+    purpose: &'static str,
+    reply: tokio::sync::oneshot::Sender<anyhow::Result<ResidenceGuard>>,
 }
 
 /// This task gets spawned alongside each timeline and is responsible for managing the timeline's
@@ -196,7 +327,7 @@ pub async fn main_task(
     tli: ManagerTimeline,
     conf: SafeKeeperConf,
     broker_active_set: Arc<TimelinesSet>,
-    manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+    guard_issuer: GuardIssuer,
     mut manager_rx: tokio::sync::mpsc::UnboundedReceiver<ManagerCtlMessage>,
     partial_backup_rate_limiter: RateLimiter,
 ) {
@@ -215,14 +346,18 @@ pub async fn main_task(
         tli,
         conf,
         broker_active_set,
-        manager_tx,
+        guard_issuer,
         partial_backup_rate_limiter,
     )
     .await;
 
+    if !mgr.is_offloaded {
+        mgr.access_service.set_resident();
+    }
+
     // Start recovery task which always runs on the timeline.
     if !mgr.is_offloaded && mgr.conf.peer_recovery_enabled {
-        let tli = mgr.wal_resident_timeline();
+        let tli = mgr.wal_resident_timeline("recovery");
         mgr.recovery_task = Some(tokio::spawn(recovery_main(tli, mgr.conf.clone())));
     }
 
@@ -232,7 +367,13 @@ pub async fn main_task(
         mgr.set_status(Status::StateSnapshot);
         let state_snapshot = mgr.state_snapshot().await;
 
+        if mgr.is_offloaded && !mgr.pending_guards.is_empty() {
+            mgr.set_status(Status::UnevictTimeline);
+            mgr.serve_pending_guards().await;
+        }
+
         let mut next_event: Option<Instant> = None;
+        mgr.warn_long_held_guards(&mut next_event);
         if !mgr.is_offloaded {
             let num_computes = *mgr.num_computes_rx.borrow();
 
@@ -250,9 +391,16 @@ pub async fn main_task(
             mgr.set_status(Status::UpdatePartialBackup);
             mgr.update_partial_backup(&state_snapshot).await;
 
-            if mgr.conf.enable_offload && mgr.ready_for_eviction(&next_event, &state_snapshot) {
-                mgr.set_status(Status::EvictTimeline);
-                mgr.evict_timeline().await;
+            if mgr.conf.enable_offload {
+                if mgr.ready_for_eviction(&next_event, &state_snapshot) {
+                    mgr.set_status(Status::EvictTimeline);
+                    mgr.evict_timeline().await;
+                } else if !mgr.access_service.is_empty() {
+                    debug!(
+                        "not evicting, blocked by {}",
+                        mgr.access_service.blocking_summary()
+                    );
+                }
             }
         }
 
@@ -290,7 +438,13 @@ pub async fn main_task(
 
             msg = manager_rx.recv() => {
                 mgr.set_status(Status::HandleMessage);
-                mgr.handle_message(msg).await;
+                match msg {
+                    Some(ManagerCtlMessage::BlockNewGuards(reply)) => {
+                        mgr.set_status(Status::DrainGuards);
+                        mgr.block_new_guards(&mut manager_rx, reply).await;
+                    }
+                    msg => mgr.handle_message(msg).await,
+                }
             }
         }
     };
@@ -321,6 +475,24 @@ pub async fn main_task(
         mgr.update_wal_removal_end(res);
     }
 
+    // all tasks holding guards should be cancelled by now, guards left are leaked
+    mgr.set_status(Status::DrainGuards);
+    let report = mgr
+        .access_service
+        .drain(&mut manager_rx, Instant::now() + GUARD_DRAIN_TIMEOUT)
+        .await;
+    if !report.leaked.is_empty() {
+        error!(
+            "{} residence guards not dropped on shutdown ({} dropped while waiting): {:?}",
+            report.leaked.len(),
+            report.drained,
+            report.leaked
+        );
+        RESIDENCE_GUARDS_LEAKED.inc_by(report.leaked.len() as u64);
+    }
+    // guards dropped from now on shouldn't try to notify the manager
+    mgr.access_service.close();
+
     mgr.set_status(Status::Finished);
 }
 
@@ -329,10 +501,11 @@ impl Manager {
         tli: ManagerTimeline,
         conf: SafeKeeperConf,
         broker_active_set: Arc<TimelinesSet>,
-        manager_tx: tokio::sync::mpsc::UnboundedSender<ManagerCtlMessage>,
+        guard_issuer: GuardIssuer,
         partial_backup_rate_limiter: RateLimiter,
     ) -> Manager {
         let (is_offloaded, partial_backup_uploaded) = tli.bootstrap_mgr().await;
+        let max_residence_guards = conf.max_residence_guards;
         Manager {
             conf,
             wal_seg_size: tli.get_wal_seg_size().await,
@@ -347,9 +520,11 @@ impl Manager {
             wal_removal_task: None,
             partial_backup_task: None,
             partial_backup_uploaded,
-            access_service: AccessService::new(manager_tx),
+            access_service: AccessService::new(guard_issuer, max_residence_guards),
             tli,
             partial_backup_rate_limiter,
+            long_held_guards_warned: HashSet::new(),
+            pending_guards: Vec::new(),
         }
     }
 
@@ -360,12 +535,98 @@ impl Manager {
     /// Get a WalResidentTimeline.
     /// Manager code must use this function instead of one from `Timeline`
     /// directly, because it will deadlock.
-    pub(crate) fn wal_resident_timeline(&mut self) -> WalResidentTimeline {
+    pub(crate) fn wal_resident_timeline(&mut self, purpose: &'static str) -> WalResidentTimeline {
         assert!(!self.is_offloaded);
-        let guard = self.access_service.create_guard();
+        let guard = self.access_service.create_internal_guard(purpose);
         WalResidentTimeline::new(self.tli.clone(), guard)
     }
 
+    /// Unevict the timeline once for all pending guard requests, and reply to
+    /// them. Requests whose caller has already given up are dropped before
+    /// starting the uneviction.
+    async fn serve_pending_guards(&mut self) {
+        self.pending_guards.retain(|p| !p.reply.is_closed());
+        if self.pending_guards.is_empty() {
+            return;
+        }
+
+        if self.is_offloaded {
+            self.unevict_timeline().await;
+        }
+
+        for pending in std::mem::take(&mut self.pending_guards) {
+            let guard = if self.is_offloaded {
+                Err(anyhow::anyhow!(
+                    "failed to unevict timeline, can't get a guard"
+                ))
+            } else {
+                self.access_service
+                    .create_guard(pending.purpose)
+                    .map_err(anyhow::Error::from)
+            };
+            if pending.reply.send(guard).is_err() {
+                warn!("failed to reply with a guard, receiver dropped");
+            }
+        }
+    }
+
+    /// Refuse new guards because the timeline is being deleted, then wait for the
+    /// issued ones to be dropped and reply with the result. Requests waiting for
+    /// uneviction fail right away.
+    async fn block_new_guards(
+        &mut self,
+        manager_rx: &mut tokio::sync::mpsc::UnboundedReceiver<ManagerCtlMessage>,
+        reply: tokio::sync::oneshot::Sender<DrainReport>,
+    ) {
+        info!(
+            "blocking new guards for deletion, waiting for {}",
+            self.access_service.blocking_summary()
+        );
+        self.access_service.block_new_guards();
+        for pending in std::mem::take(&mut self.pending_guards) {
+            let _ = pending.reply.send(Err(TimelineDeleted.into()));
+        }
+
+        let report = self
+            .access_service
+            .drain(manager_rx, Instant::now() + GUARD_DRAIN_TIMEOUT)
+            .await;
+        if reply.send(report).is_err() {
+            warn!("failed to reply with drain report, receiver dropped");
+        }
+    }
+
+    /// Warn once about each guard held for longer than `guard_hold_warn_threshold`,
+    /// and schedule a wakeup for when the next one crosses the threshold.
+    ///
+    /// Guards only exist while the timeline can't be evicted, so the extra wakeup
+    /// doesn't delay eviction.
+    fn warn_long_held_guards(&mut self, next_event: &mut Option<Instant>) {
+        let threshold = self.conf.guard_hold_warn_threshold;
+        let long_held = self.access_service.long_held(threshold);
+        self.long_held_guards_warned
+            .retain(|id| long_held.iter().any(|g| g.id == *id));
+        for guard in long_held {
+            if self.long_held_guards_warned.insert(guard.id) {
+                warn!(
+                    "residence guard {:?} for '{}' held for {:?}, WAL can't be evicted",
+                    guard.id, guard.purpose, guard.held_for
+                );
+            }
+        }
+
+        let oldest_below_threshold = self
+            .access_service
+            .list()
+            .into_iter()
+            .map(|g| g.held_for)
+            .filter(|held_for| *held_for < threshold)
+            .max();
+        if let Some(held_for) = oldest_below_threshold {
+            update_next_event(next_event, Instant::now() + (threshold - held_for));
+        }
+    }
+
     /// Get a snapshot of the timeline state.
     async fn state_snapshot(&self) -> StateSnapshot {
         let _timer = MISC_OPERATION_SECONDS
@@ -410,8 +671,11 @@ impl Manager {
         if self.tli_broker_active.set(is_active) {
             // write log if state has changed
             info!(
-                "timeline active={} now, remote_consistent_lsn={}, commit_lsn={}",
-                is_active, state.remote_consistent_lsn, state.commit_lsn,
+                "timeline active={} now, remote_consistent_lsn={}, commit_lsn={}, {}",
+                is_active,
+                state.remote_consistent_lsn,
+                state.commit_lsn,
+                self.access_service.blocking_summary(),
             );
 
             MANAGER_ACTIVE_CHANGES.inc();
@@ -534,7 +798,7 @@ impl Manager {
 
         // Get WalResidentTimeline and start partial backup task.
         self.partial_backup_task = Some(tokio::spawn(wal_backup_partial::main_task(
-            self.wal_resident_timeline(),
+            self.wal_resident_timeline("partial_backup"),
             self.conf.clone(),
             self.partial_backup_rate_limiter.clone(),
         )));
@@ -556,24 +820,106 @@ impl Manager {
     async fn handle_message(&mut self, msg: Option<ManagerCtlMessage>) {
         debug!("received manager message: {:?}", msg);
         match msg {
-            Some(ManagerCtlMessage::GuardRequest(tx)) => {
-                if self.is_offloaded {
+            Some(ManagerCtlMessage::GuardRequest(purpose, tx)) => {
+                if self.is_offloaded && !self.access_service.is_blocked() {
                     // trying to unevict timeline, but without gurarantee that it will be successful
                     self.unevict_timeline().await;
                 }
 
-                let guard = if self.is_offloaded {
+                // a blocked timeline is reported as deleted by create_guard
+                let guard = if self.is_offloaded && !self.access_service.is_blocked() {
                     Err(anyhow::anyhow!("timeline is offloaded, can't get a guard"))
                 } else {
-                    Ok(self.access_service.create_guard())
+                    self.access_service
+                        .create_guard(purpose)
+                        .map_err(anyhow::Error::from)
                 };
 
                 if tx.send(guard).is_err() {
                     warn!("failed to reply with a guard, receiver dropped");
                 }
             }
-            Some(ManagerCtlMessage::GuardDrop(guard_id)) => {
-                self.access_service.drop_guard(guard_id);
+            Some(ManagerCtlMessage::GuardDropBatch) => {
+                let mut purposes = Vec::new();
+                for guard_id in self.access_service.take_dropped() {
+                    match self.access_service.drop_guard(guard_id) {
+                        Some(purpose) => purposes.push(purpose),
+                        None => {
+                            warn!("unexpected drop of unknown guard {:?}", guard_id);
+                            MANAGER_UNEXPECTED_GUARD_DROPS.inc();
+                        }
+                    }
+                }
+                if purposes.is_empty() {
+                    return;
+                }
+                if self.access_service.is_empty() {
+                    debug!("last guards {:?} dropped", purposes);
+                } else {
+                    debug!(
+                        "guards {:?} dropped, still held: {:?}",
+                        purposes,
+                        self.access_service.list()
+                    );
+                }
+            }
+            Some(ManagerCtlMessage::TryGuardAsync { purpose, reply }) => {
+                if self.is_offloaded && !self.access_service.is_blocked() {
+                    // uneviction is done in the main loop, so that requests
+                    // arriving meanwhile are served by the same uneviction
+                    self.pending_guards.push(PendingGuard { purpose, reply });
+                    return;
+                }
+                let guard = self
+                    .access_service
+                    .create_guard(purpose)
+                    .map_err(anyhow::Error::from);
+                if reply.send(guard).is_err() {
+                    warn!("failed to reply with a guard, receiver dropped");
+                }
+            }
+            Some(ManagerCtlMessage::WeakGuardRequest(purpose, tx)) => {
+                let guard = if self.is_offloaded && !self.access_service.is_blocked() {
+                    Err(anyhow::anyhow!(
+                        "timeline is offloaded, can't get a weak guard"
+                    ))
+                } else {
+                    self.access_service
+                        .create_weak_guard(purpose)
+                        .map_err(anyhow::Error::from)
+                };
+                if tx.send(guard).is_err() {
+                    warn!("failed to reply with a weak guard, receiver dropped");
+                }
+            }
+            Some(ManagerCtlMessage::WeakGuardDrop(guard_id)) => {
+                if self.access_service.drop_weak_guard(guard_id).is_none() {
+                    warn!("unexpected drop of unknown weak guard {:?}", guard_id);
+                    MANAGER_UNEXPECTED_GUARD_DROPS.inc();
+                }
+            }
+            Some(ManagerCtlMessage::RegisterGuard(guard_id, purpose)) => {
+                self.access_service.register_guard(guard_id, purpose);
+            }
+            Some(ManagerCtlMessage::UnregisterGuard(guard_id)) => {
+                if self.access_service.drop_guard(guard_id).is_none() {
+                    warn!("unexpected drop of unknown guard {:?}", guard_id);
+                    MANAGER_UNEXPECTED_GUARD_DROPS.inc();
+                }
+            }
+            Some(ManagerCtlMessage::BlockingSummary(tx)) => {
+                if tx.send(self.access_service.blocking_summary()).is_err() {
+                    warn!("failed to reply with guards summary, receiver dropped");
+                }
+            }
+            Some(ManagerCtlMessage::ListGuards(tx)) => {
+                if tx.send(self.access_service.list()).is_err() {
+                    warn!("failed to reply with guards list, receiver dropped");
+                }
+            }
+            Some(ManagerCtlMessage::BlockNewGuards(_)) => {
+                // needs manager_rx to drain guards, handled in the main loop
+                unreachable!();
             }
             None => {
                 // can't happen, we're holding the sender
@@ -622,9 +968,11 @@ pub enum Status {
     UpdateWalRemoval,
     UpdatePartialBackup,
     EvictTimeline,
+    UnevictTimeline,
     Wait,
     HandleMessage,
     Exiting,
+    DrainGuards,
     Finished,
 }
 
@@ -663,3 +1011,74 @@ impl AtomicStatus {
         self.inner.store(val as usize, order);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use postgres_ffi::WAL_SEGMENT_SIZE;
+
+    use super::*;
+    use crate::safekeeper::ServerInfo;
+    use crate::timeline::Timeline;
+
+    /// Manager of an empty in-memory timeline, with the receiver of its messages.
+    async fn test_manager() -> (
+        Manager,
+        tokio::sync::mpsc::UnboundedReceiver<ManagerCtlMessage>,
+    ) {
+        let conf = SafeKeeperConf {
+            workdir: camino_tempfile::tempdir().unwrap().into_path(),
+            ..SafeKeeperConf::dummy()
+        };
+        let ttid = TenantTimelineId::generate();
+        let server_info = ServerInfo {
+            pg_version: 160000,
+            system_id: 0,
+            wal_seg_size: WAL_SEGMENT_SIZE as u32,
+        };
+        let tli = Timeline::create_empty(&conf, ttid, server_info, Lsn::INVALID, Lsn::INVALID)
+            .expect("failed to create timeline");
+        let (guard_issuer, rx) = ManagerCtl::new(&ttid).bootstrap_manager();
+        let mgr = Manager::new(
+            ManagerTimeline { tli: Arc::new(tli) },
+            conf,
+            Arc::new(TimelinesSet::default()),
+            guard_issuer,
+            RateLimiter::new(1),
+        )
+        .await;
+        (mgr, rx)
+    }
+
+    #[tokio::test]
+    async fn test_unknown_guard_drop() {
+        let (mut mgr, _rx) = test_manager().await;
+        assert!(!mgr.is_offloaded);
+        mgr.access_service.set_resident();
+
+        let _held = mgr.access_service.create_guard("walsender").unwrap();
+        let dropped = mgr.access_service.create_guard("wal_backup").unwrap();
+        let guards = mgr.access_service.list();
+        let dropped_id = guards
+            .iter()
+            .find(|g| g.purpose == "wal_backup")
+            .unwrap()
+            .id;
+        let held_id = guards.iter().find(|g| g.purpose == "walsender").unwrap().id;
+        drop(dropped);
+        mgr.handle_message(Some(ManagerCtlMessage::GuardDropBatch))
+            .await;
+
+        // the drop is reported again, the manager counts it and goes on
+        let unexpected = MANAGER_UNEXPECTED_GUARD_DROPS.get();
+        mgr.access_service.report_dropped(dropped_id);
+        mgr.handle_message(Some(ManagerCtlMessage::GuardDropBatch))
+            .await;
+        assert_eq!(MANAGER_UNEXPECTED_GUARD_DROPS.get(), unexpected + 1);
+
+        // the timeline stays resident, and the held guard still prevents eviction
+        assert!(!mgr.is_offloaded);
+        let ids: Vec<_> = mgr.access_service.list().iter().map(|g| g.id).collect();
+        assert_eq!(ids, vec![held_id]);
+        assert!(!mgr.access_service.begin_eviction());
+    }
+}
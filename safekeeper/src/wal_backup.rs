@@ -75,7 +75,7 @@ pub(crate) async fn update_task(mgr: &mut Manager, need_backup: bool, state: &St
             let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
 
             let async_task = backup_task_main(
-                mgr.wal_resident_timeline(),
+                mgr.wal_resident_timeline("wal_backup"),
                 mgr.conf.backup_parallel_jobs,
                 shutdown_rx,
             );
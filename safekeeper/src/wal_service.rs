@@ -35,6 +35,7 @@ pub async fn task_main(
         debug!("accepted connection from {}", peer_addr);
         let conf = conf.clone();
         let conn_id = issue_connection_id(&mut connection_count);
+        let allowed_auth_scope = allowed_auth_scope.clone();
 
         tokio::spawn(
             async move {
@@ -188,6 +188,8 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
         delete_offloaded_wal: false,
         control_file_save_interval: Duration::from_secs(1),
         partial_backup_concurrency: 1,
+        guard_hold_warn_threshold: Duration::from_secs(600),
+        max_residence_guards: 1024,
     };
 
     let mut global = GlobalMap::new(disk, conf.clone())?;
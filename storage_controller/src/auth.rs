@@ -1,8 +1,10 @@
 use utils::auth::{AuthError, Claims, Scope};
 
-pub fn check_permission(claims: &Claims, required_scope: Scope) -> Result<(), AuthError> {
-    if claims.scope != required_scope {
-        return Err(AuthError("Scope mismatch. Permission denied".into()));
+pub fn check_permission(claims: &Claims, required_scope: &Scope) -> Result<(), AuthError> {
+    if matches!(claims.scope, Scope::Unknown(_)) || claims.scope != *required_scope {
+        return Err(AuthError::Denied(
+            "Scope mismatch. Permission denied".into(),
+        ));
     }
 
     Ok(())
@@ -694,8 +694,8 @@ where
 /// a token with 'admin' scope then always permit it.
 fn check_permissions(request: &Request<Body>, required_scope: Scope) -> Result<(), ApiError> {
     check_permission_with(request, |claims| {
-        match crate::auth::check_permission(claims, required_scope) {
-            Err(e) => match crate::auth::check_permission(claims, Scope::Admin) {
+        match crate::auth::check_permission(claims, &required_scope) {
+            Err(e) => match crate::auth::check_permission(claims, &Scope::Admin) {
                 Ok(()) => Ok(()),
                 Err(_) => Err(e),
             },
@@ -1870,6 +1870,7 @@ class NeonCli(AbstractNeonCli):
         lsn: Optional[Lsn] = None,
         pageserver_id: Optional[int] = None,
         allow_multiple=False,
+        update_catalog: bool = False,
     ) -> "subprocess.CompletedProcess[str]":
         args = [
             "endpoint",
@@ -1895,6 +1896,8 @@ class NeonCli(AbstractNeonCli):
             args.extend(["--pageserver-id", str(pageserver_id)])
         if allow_multiple:
             args.extend(["--allow-multiple"])
+        if update_catalog:
+            args.extend(["--update-catalog", "true"])
 
         res = self.raw_cli(args)
         res.check_returncode()
@@ -1945,12 +1948,40 @@ class NeonCli(AbstractNeonCli):
             args.extend(["--safekeepers", (",".join(map(str, safekeepers)))])
         return self.raw_cli(args, check_return_code=check_return_code)
 
+    def endpoint_dump(
+        self,
+        endpoint_id: str,
+        dest: Path,
+        db: str = "postgres",
+        globals: bool = False,
+    ) -> str:
+        """
+        Dump a database of the endpoint with pg_dump, returning the hash of the dump.
+        """
+        args = ["endpoint", "dump", endpoint_id, "--dest", str(dest), "--db", db]
+        if globals:
+            args.append("--globals")
+        res = self.raw_cli(args)
+        return res.stdout.split()[0]
+
+    def endpoint_generate_wal(
+        self,
+        endpoint_id: str,
+        mb: int,
+        small_transactions: bool = False,
+    ) -> "subprocess.CompletedProcess[str]":
+        args = ["endpoint", "generate-wal", endpoint_id, "--mb", str(mb)]
+        if small_transactions:
+            args.append("--small-transactions")
+        return self.raw_cli(args)
+
     def endpoint_stop(
         self,
         endpoint_id: str,
         destroy=False,
         check_return_code=True,
         mode: Optional[str] = None,
+        skip_safekeeper_sync: bool = False,
     ) -> "subprocess.CompletedProcess[str]":
         args = [
             "endpoint",
@@ -1958,6 +1989,8 @@ class NeonCli(AbstractNeonCli):
         ]
         if destroy:
             args.append("--destroy")
+        if skip_safekeeper_sync:
+            args.append("--skip-safekeeper-sync")
         if mode is not None:
             args.append(f"--mode={mode}")
         if endpoint_id is not None:
@@ -3691,9 +3724,10 @@ class Endpoint(PgProtocol, LogUtils):
         with open(remote_extensions_spec_path, "w") as file:
             json.dump(spec, file, indent=4)
 
-    def stop(self, mode: str = "fast") -> "Endpoint":
+    def stop(self, mode: str = "fast", skip_safekeeper_sync: bool = False) -> "Endpoint":
         """
-        Stop the Postgres instance if it's running.
+        Stop the Postgres instance if it's running. With `skip_safekeeper_sync`, compute_ctl
+        doesn't sync the safekeepers afterwards, which hangs if a majority of them is down.
 
         Because test teardown might try and stop an endpoint concurrently with test code
         stopping the endpoint, this method is thread safe
@@ -3705,7 +3739,10 @@ class Endpoint(PgProtocol, LogUtils):
         if running:
             assert self.endpoint_id is not None
             self.env.neon_cli.endpoint_stop(
-                self.endpoint_id, check_return_code=self.check_stop_result, mode=mode
+                self.endpoint_id,
+                check_return_code=self.check_stop_result,
+                mode=mode,
+                skip_safekeeper_sync=skip_safekeeper_sync,
             )
 
         return self
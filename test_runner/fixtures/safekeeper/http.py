@@ -17,6 +17,14 @@ class Walreceiver:
     state: str
 
 
+# Residence guard as returned by sk's timeline status endpoint.
+@dataclass
+class ResidenceGuard:
+    id: str
+    purpose: str
+    held_for_secs: float
+
+
 @dataclass
 class SafekeeperTimelineStatus:
     term: int
@@ -29,6 +37,8 @@ class SafekeeperTimelineStatus:
     peer_horizon_lsn: Lsn
     remote_consistent_lsn: Lsn
     walreceivers: List[Walreceiver]
+    # None if the timeline manager didn't reply in time
+    residence_guards: Optional[List[ResidenceGuard]]
 
 
 @dataclass
@@ -156,6 +166,12 @@ class SafekeeperHttpClient(requests.Session):
         res.raise_for_status()
         resj = res.json()
         walreceivers = [Walreceiver(wr["conn_id"], wr["status"]) for wr in resj["walreceivers"]]
+        residence_guards = None
+        if resj.get("residence_guards") is not None:
+            residence_guards = [
+                ResidenceGuard(g["id"], g["purpose"], g["held_for_secs"])
+                for g in resj["residence_guards"]
+            ]
         return SafekeeperTimelineStatus(
             term=resj["acceptor_state"]["term"],
             last_log_term=resj["acceptor_state"]["epoch"],
@@ -167,6 +183,7 @@ class SafekeeperHttpClient(requests.Session):
             peer_horizon_lsn=Lsn(resj["peer_horizon_lsn"]),
             remote_consistent_lsn=Lsn(resj["remote_consistent_lsn"]),
             walreceivers=walreceivers,
+            residence_guards=residence_guards,
         )
 
     def get_commit_lsn(self, tenant_id: TenantId, timeline_id: TimelineId) -> Lsn:
@@ -1,5 +1,10 @@
+import json
+import time
+from pathlib import Path
+
 import pytest
-from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.common_types import Lsn
+from fixtures.neon_fixtures import NeonEnv, NeonEnvBuilder, PgProtocol
 from fixtures.port_distributor import PortDistributor
 
 
@@ -61,3 +66,197 @@ def test_neon_two_primary_endpoints_fail(
     env.neon_cli.endpoint_start("ep2")
     # cleanup
     env.neon_cli.endpoint_stop("ep2")
+
+
+def test_neon_local_generate_wal(neon_simple_env: NeonEnv):
+    """
+    Smoke test of 'neon_local endpoint generate-wal'
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+
+    for small_transactions in [False, True]:
+        res = env.neon_cli.endpoint_generate_wal(
+            endpoint.endpoint_id, mb=1, small_transactions=small_transactions
+        )
+        assert "Generated" in res.stdout
+
+    # The scratch table is gone
+    assert endpoint.safe_psql(
+        "SELECT count(*) FROM pg_tables WHERE tablename LIKE 'neon_local_generate_wal%'"
+    ) == [(0,)]
+
+
+def test_neon_local_dump(neon_simple_env: NeonEnv, test_output_dir: Path):
+    """
+    Dumps taken before and after a restart are the same
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g AS id FROM generate_series(1, 1000) g")
+
+    before = env.neon_cli.endpoint_dump(endpoint.endpoint_id, test_output_dir / "before")
+    globals_before = env.neon_cli.endpoint_dump(
+        endpoint.endpoint_id, test_output_dir / "before", globals=True
+    )
+    endpoint.stop()
+    endpoint.start()
+    after = env.neon_cli.endpoint_dump(endpoint.endpoint_id, test_output_dir / "after")
+    globals_after = env.neon_cli.endpoint_dump(
+        endpoint.endpoint_id, test_output_dir / "after", globals=True
+    )
+
+    assert before == after
+    assert globals_before == globals_after
+
+
+def test_neon_local_catalog_updates(
+    neon_simple_env: NeonEnv, port_distributor: PortDistributor
+):
+    """
+    Start an endpoint with catalog updates enabled, which creates the objects
+    of the availability checks
+    """
+    env = neon_simple_env
+    env.neon_cli.endpoint_create(
+        "main",
+        port_distributor.get_port(),
+        port_distributor.get_port(),
+        endpoint_id="ep-catalog",
+        update_catalog=True,
+    )
+    env.neon_cli.endpoint_start("ep-catalog")
+    try:
+        res = env.neon_cli.raw_cli(["endpoint", "verify-availability", "ep-catalog"])
+        assert "has its availability check objects" in res.stdout
+    finally:
+        env.neon_cli.endpoint_stop("ep-catalog", destroy=True)
+
+
+def test_neon_local_vanilla_endpoint(
+    neon_simple_env: NeonEnv, port_distributor: PortDistributor
+):
+    """
+    A vanilla endpoint runs plain Postgres on a local data directory
+    """
+    env = neon_simple_env
+    pg_port = port_distributor.get_port()
+    env.neon_cli.raw_cli(
+        [
+            "endpoint",
+            "create",
+            "--vanilla",
+            "--pg-port",
+            str(pg_port),
+            "--pg-version",
+            env.pg_version,
+            "ep-vanilla",
+        ]
+    )
+    env.neon_cli.endpoint_start("ep-vanilla")
+    try:
+        pg = PgProtocol(host="localhost", port=pg_port, user="cloud_admin", dbname="postgres")
+        assert pg.safe_psql("SHOW shared_preload_libraries")[0][0] == ""
+        assert "running, vanilla" in env.neon_cli.raw_cli(["endpoint", "list"]).stdout
+
+        res = env.neon_cli.raw_cli(
+            ["endpoint", "reconfigure", "ep-vanilla"], check_return_code=False
+        )
+        assert res.returncode != 0
+        assert "runs vanilla Postgres" in res.stderr
+    finally:
+        env.neon_cli.endpoint_stop("ep-vanilla", destroy=True)
+
+
+def test_neon_local_basebackup_lsn(neon_simple_env: NeonEnv):
+    """
+    The basebackup LSN of each start is recorded in last_run.json
+    """
+    env = neon_simple_env
+    primary = env.endpoints.create_start("main")
+    primary.safe_psql("CREATE TABLE t AS SELECT g AS id FROM generate_series(1, 1000) g")
+    pinned = Lsn(primary.safe_psql("SELECT pg_current_wal_insert_lsn()")[0][0])
+    static = env.endpoints.create_start("main", endpoint_id="ep-static", lsn=pinned)
+
+    def basebackup_lsn(endpoint) -> Lsn:
+        with open(endpoint.endpoint_path() / "last_run.json") as f:
+            return Lsn(json.load(f)["basebackup_lsn"])
+
+    assert basebackup_lsn(static) == pinned
+    assert basebackup_lsn(primary) <= pinned
+
+
+def test_neon_local_stop_skip_safekeeper_sync(neon_simple_env: NeonEnv):
+    """
+    With the safekeepers down, stopping an endpoint with --skip-safekeeper-sync
+    doesn't wait for compute_ctl's sync-safekeepers. Without it, they are
+    synced.
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g AS id FROM generate_series(1, 1000) g")
+    for sk in env.safekeepers:
+        sk.stop()
+    try:
+        started = time.monotonic()
+        endpoint.stop(skip_safekeeper_sync=True)
+        assert time.monotonic() - started < 10
+
+        with open(endpoint.endpoint_path() / "last_run.json") as f:
+            assert json.load(f)["skip_safekeeper_sync"] is True
+    finally:
+        for sk in env.safekeepers:
+            sk.start()
+
+    endpoint.start()
+    endpoint.stop()
+    with open(endpoint.endpoint_path() / "last_run.json") as f:
+        assert json.load(f)["skip_safekeeper_sync"] is False
+
+
+def test_neon_local_add_database(neon_simple_env: NeonEnv):
+    """
+    'endpoint add-database' has compute_ctl create the database and its owner on the
+    running compute
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+    env.neon_cli.raw_cli(
+        [
+            "endpoint",
+            "add-database",
+            endpoint.endpoint_id,
+            "--name",
+            "added",
+            "--owner",
+            "alice",
+            "--create-owner",
+        ]
+    )
+
+    owner = endpoint.safe_psql(
+        "SELECT pg_get_userbyid(datdba) FROM pg_database WHERE datname = current_database()",
+        dbname="added",
+    )[0][0]
+    assert owner == "alice"
+
+    res = env.neon_cli.raw_cli(
+        ["endpoint", "add-database", endpoint.endpoint_id, "--name", "added", "--owner", "alice"],
+        check_return_code=False,
+    )
+    assert res.returncode != 0
+    assert "already exists" in res.stderr
+
+
+def test_neon_local_top(neon_simple_env: NeonEnv):
+    """
+    'endpoint top' shows the running endpoints with their sessions, once per refresh
+    """
+    env = neon_simple_env
+    endpoint = env.endpoints.create_start("main")
+
+    res = env.neon_cli.raw_cli(["endpoint", "top", "--interval", "200ms", "--count", "2"])
+    rows = [line.split() for line in res.stdout.splitlines() if endpoint.endpoint_id in line]
+    assert len(rows) == 2
+    for row in rows:
+        assert row[1] == "running,"
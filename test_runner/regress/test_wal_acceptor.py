@@ -2,6 +2,7 @@ import filecmp
 import logging
 import os
 import random
+import re
 import shutil
 import signal
 import subprocess
@@ -49,7 +50,13 @@ from fixtures.remote_storage import (
 )
 from fixtures.safekeeper.http import SafekeeperHttpClient
 from fixtures.safekeeper.utils import are_walreceivers_absent
-from fixtures.utils import PropagatingThread, get_dir_size, query_scalar, start_in_background
+from fixtures.utils import (
+    PropagatingThread,
+    get_dir_size,
+    query_scalar,
+    start_in_background,
+    wait_until,
+)
 
 
 def wait_lsn_force_checkpoint(
@@ -890,6 +897,39 @@ def test_timeline_status(neon_env_builder: NeonEnvBuilder, auth_enabled: bool):
     assert debug_dump_1["config"]["id"] == env.safekeepers[0].id
 
 
+# Check that residence guards held on the timeline are listed in the status.
+def test_timeline_status_residence_guards(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_timeline_status_residence_guards")
+    started_at = time.monotonic()
+    endpoint = env.endpoints.create_start("test_timeline_status_residence_guards")
+    # walreceiver of the running compute holds a guard for the whole connection
+    endpoint.safe_psql("create table t(i int)")
+    time.sleep(1)
+
+    http_cli = env.safekeepers[0].http_client()
+    tli_status = http_cli.timeline_status(tenant_id, timeline_id)
+    elapsed = time.monotonic() - started_at
+    log.info(f"residence guards: {tli_status.residence_guards}")
+    assert tli_status.residence_guards is not None
+    walreceiver_guards = [g for g in tli_status.residence_guards if g.purpose == "walreceiver"]
+    assert len(walreceiver_guards) >= 1
+    for g in walreceiver_guards:
+        assert 1 <= g.held_for_secs <= elapsed
+
+    # guard is released once compute disconnects
+    endpoint.stop()
+
+    def walreceiver_guard_dropped():
+        guards = http_cli.timeline_status(tenant_id, timeline_id).residence_guards
+        assert guards is not None
+        assert all(g.purpose != "walreceiver" for g in guards)
+
+    wait_until(10, 0.5, walreceiver_guard_dropped)
+
+
 class DummyConsumer(object):
     def __call__(self, msg):
         pass
@@ -2273,3 +2313,58 @@ def test_s3_eviction(
         and sk.log_contains("successfully restored evicted timeline")
         for sk in env.safekeepers
     )
+
+
+# Two concurrent requests needing WAL on an evicted timeline should both be
+# served by a single uneviction.
+def test_s3_eviction_concurrent_guards(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_safekeeper_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            "checkpoint_timeout": "100ms",
+        }
+    )
+    sk = env.safekeepers[0]
+    sk.stop().start(
+        extra_opts=[
+            "--enable-offload",
+            "--partial-backup-timeout",
+            "50ms",
+            "--control-file-save-interval",
+            "1s",
+        ]
+    )
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_s3_eviction_concurrent_guards")
+    endpoint = env.endpoints.create_start("test_s3_eviction_concurrent_guards")
+    endpoint.safe_psql("CREATE TABLE t(i int)")
+    endpoint.safe_psql("INSERT INTO t VALUES (0)")
+    endpoint.stop()
+    env.pageserver.http_client().timeline_checkpoint(
+        tenant_id, timeline_id, wait_until_uploaded=True
+    )
+
+    def evicted():
+        assert sk.log_contains(f"{timeline_id}.*successfully evicted timeline")
+
+    wait_until(60, 0.5, evicted)
+
+    flush_lsn = sk.get_flush_lsn(tenant_id, timeline_id)
+    http_cli = sk.http_client()
+    results: List[Any] = [None, None]
+
+    def get_digest(i: int):
+        results[i] = http_cli.timeline_digest(tenant_id, timeline_id, flush_lsn, flush_lsn)
+
+    threads = [PropagatingThread(target=get_digest, args=(i,)) for i in range(2)]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join()
+    assert results[0] == results[1]
+
+    uneviction_re = re.compile(f"{timeline_id}.*successfully restored evicted timeline")
+    with sk.logfile.open("r") as f:
+        unevictions = [line for line in f if uneviction_re.search(line)]
+    assert len(unevictions) == 1, unevictions